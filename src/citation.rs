@@ -0,0 +1,147 @@
+//! The citation printed by `pare cite`.
+//!
+//! The authors, version and address come from the crate manifest so the citation follows each
+//! release without being edited by hand.
+
+use clap::ValueEnum;
+
+/// The year of the current release, update it along with the version.
+const RELEASE_YEAR: u16 = 2026;
+
+/// The styles a citation can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum CitationFormat {
+    /// a BibTeX `@software` entry
+    Bibtex,
+    /// an RIS record, read by most reference managers
+    Ris,
+    /// a line of text
+    #[default]
+    Plain,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Citation {
+    pub title: String,
+    pub authors: Vec<String>,
+    pub version: String,
+    pub year: u16,
+    pub url: String,
+}
+
+impl Citation {
+    /// The citation for this build of pare.
+    pub fn pare() -> Self {
+        Citation {
+            title: format!(
+                "{}: {}",
+                env!("CARGO_PKG_NAME"),
+                env!("CARGO_PKG_DESCRIPTION")
+            ),
+            authors: env!("CARGO_PKG_AUTHORS")
+                .split(':')
+                .filter(|author| !author.is_empty())
+                .map(str::to_string)
+                .collect(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            year: RELEASE_YEAR,
+            url: env!("CARGO_PKG_REPOSITORY").to_string(),
+        }
+    }
+
+    /// The BibTeX key, the name of the software and the year.
+    pub fn key(&self) -> String {
+        let name: String = self
+            .title
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric())
+            .collect();
+        format!("{}{}", name, self.year)
+    }
+
+    pub fn format(&self, format: CitationFormat) -> String {
+        match format {
+            CitationFormat::Bibtex => format!(
+                concat!(
+                    "@software{{{},\n",
+                    "  author = {{{}}},\n",
+                    "  title = {{{}}},\n",
+                    "  version = {{{}}},\n",
+                    "  year = {{{}}},\n",
+                    "  url = {{{}}},\n",
+                    "}}\n",
+                ),
+                self.key(),
+                self.authors.join(" and "),
+                self.title,
+                self.version,
+                self.year,
+                self.url
+            ),
+            CitationFormat::Ris => {
+                let mut ris = String::from("TY  - COMP\n");
+                for author in &self.authors {
+                    ris.push_str(&format!("AU  - {}\n", author));
+                }
+                ris.push_str(&format!(
+                    "TI  - {}\nET  - {}\nPY  - {}\nUR  - {}\nER  - \n",
+                    self.title, self.version, self.year, self.url
+                ));
+                ris
+            }
+            CitationFormat::Plain => format!(
+                "{} ({}). {} (version {}). {}\n",
+                self.authors.join(", "),
+                self.year,
+                self.title,
+                self.version,
+                self.url
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The value of `field = {value}` in a BibTeX entry.
+    fn bibtex_field<'a>(entry: &'a str, field: &str) -> Option<&'a str> {
+        entry.lines().find_map(|line| {
+            line.trim()
+                .strip_prefix(field)?
+                .trim_start()
+                .strip_prefix("= {")?
+                .strip_suffix("},")
+        })
+    }
+
+    #[test]
+    fn test_bibtex() {
+        let citation = Citation::pare();
+        let entry = citation.format(CitationFormat::Bibtex);
+
+        let key = entry
+            .strip_prefix("@software{")
+            .and_then(|rest| rest.split_once(",\n"))
+            .map(|(key, _)| key);
+        assert_eq!(Some(format!("pare{}", RELEASE_YEAR).as_str()), key);
+        assert_eq!(
+            Some(RELEASE_YEAR.to_string().as_str()),
+            bibtex_field(&entry, "year")
+        );
+        assert_eq!(
+            Some(env!("CARGO_PKG_VERSION")),
+            bibtex_field(&entry, "version")
+        );
+        assert!(entry.ends_with("}\n"));
+    }
+
+    #[test]
+    fn test_ris() {
+        let record = Citation::pare().format(CitationFormat::Ris);
+        assert!(record.starts_with("TY  - COMP\n"));
+        assert!(record.contains(&format!("PY  - {}\n", RELEASE_YEAR)));
+        assert!(record.ends_with("ER  - \n"));
+    }
+}
@@ -0,0 +1,182 @@
+use std::io::{Read, Seek};
+
+use clap::ValueEnum;
+use sha2::Digest;
+
+use crate::compression_models::{CompressionModelError, Result};
+
+/// Incrementally hashes the content of an archive.
+pub trait ContentHasher {
+    fn update(&mut self, data: &[u8]);
+    fn hex_digest(self: Box<Self>) -> String;
+}
+
+impl ContentHasher for xxhash_rust::xxh3::Xxh3 {
+    fn update(&mut self, data: &[u8]) {
+        xxhash_rust::xxh3::Xxh3::update(self, data);
+    }
+
+    fn hex_digest(self: Box<Self>) -> String {
+        format!("{:032x}", self.digest128())
+    }
+}
+
+impl ContentHasher for sha2::Sha256 {
+    fn update(&mut self, data: &[u8]) {
+        Digest::update(self, data);
+    }
+
+    fn hex_digest(self: Box<Self>) -> String {
+        to_hex(&self.finalize())
+    }
+}
+
+impl ContentHasher for blake3::Hasher {
+    fn update(&mut self, data: &[u8]) {
+        blake3::Hasher::update(self, data);
+    }
+
+    fn hex_digest(self: Box<Self>) -> String {
+        self.finalize().to_hex().to_string()
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Which hash is used for the archive content checksum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, ValueEnum)]
+pub enum ChecksumAlgorithm {
+    None,
+    #[default]
+    Xxh3,
+    Sha256,
+    Blake3,
+}
+
+impl ChecksumAlgorithm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::None => "none",
+            ChecksumAlgorithm::Xxh3 => "xxh3",
+            ChecksumAlgorithm::Sha256 => "sha256",
+            ChecksumAlgorithm::Blake3 => "blake3",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "none" => Ok(ChecksumAlgorithm::None),
+            "xxh3" => Ok(ChecksumAlgorithm::Xxh3),
+            "sha256" => Ok(ChecksumAlgorithm::Sha256),
+            "blake3" => Ok(ChecksumAlgorithm::Blake3),
+            _ => Err(CompressionModelError::UnknownChecksumAlgorithm {
+                name: name.to_string(),
+            }),
+        }
+    }
+
+    /// A fresh hasher, or None when checksums are disabled.
+    pub fn hasher(&self) -> Option<Box<dyn ContentHasher>> {
+        match self {
+            ChecksumAlgorithm::None => None,
+            ChecksumAlgorithm::Xxh3 => Some(Box::new(xxhash_rust::xxh3::Xxh3::new())),
+            ChecksumAlgorithm::Sha256 => Some(Box::new(sha2::Sha256::new())),
+            ChecksumAlgorithm::Blake3 => Some(Box::new(blake3::Hasher::new())),
+        }
+    }
+}
+
+/// Feed the whole of `source` to `hasher`, leaving `source` rewound.
+pub fn hash_stream<T: Read + Seek>(hasher: &mut dyn ContentHasher, source: &mut T) -> Result<()> {
+    let mut buffer = [0u8; 64 * 1024];
+
+    source.rewind()?;
+    loop {
+        let n = source.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    source.rewind()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::compression_models::lzma_multi_stream::*;
+    use crate::compression_models::lzma_single_file::*;
+    use crate::compression_models::test_utils::*;
+    use crate::compression_models::{DecoderModel, EncoderModel, EncoderOptions};
+
+    const ALGORITHMS: [ChecksumAlgorithm; 4] = [
+        ChecksumAlgorithm::None,
+        ChecksumAlgorithm::Xxh3,
+        ChecksumAlgorithm::Sha256,
+        ChecksumAlgorithm::Blake3,
+    ];
+
+    fn multi_stream_archive(checksum: ChecksumAlgorithm) -> Result<Vec<u8>> {
+        let mut archive: Vec<u8> = Vec::new();
+        XZMultiStreamWriter::new(&mut archive)
+            .with_options(EncoderOptions::new().with_checksum(checksum))
+            .compress(&mut interleaved_reader(INTERLEAVED_FASTQ))?;
+        Ok(archive)
+    }
+
+    fn single_file_archive(checksum: ChecksumAlgorithm) -> Result<Vec<u8>> {
+        let mut archive: Vec<u8> = Vec::new();
+        XZSingleFileWriter::new(&mut archive)
+            .with_options(EncoderOptions::new().with_checksum(checksum))
+            .compress(&mut interleaved_reader(INTERLEAVED_FASTQ))?;
+        Ok(archive)
+    }
+
+    #[test]
+    fn test_checksum_round_trip() -> Result<()> {
+        for checksum in ALGORITHMS {
+            let archive = multi_stream_archive(checksum)?;
+            let (mut writer, output) = interleaved_writer();
+            XZMultiStreamReader::new(archive.as_slice())?.decompress(&mut writer)?;
+            drop(writer);
+            assert_eq!(INTERLEAVED_FASTQ, output.text());
+
+            let archive = single_file_archive(checksum)?;
+            let (mut writer, output) = interleaved_writer();
+            XZSingleFileReader::new(archive.as_slice())?.decompress(&mut writer)?;
+            drop(writer);
+            assert_eq!(INTERLEAVED_FASTQ, output.text());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_checksum_detects_corruption() -> Result<()> {
+        for checksum in ALGORITHMS.into_iter().skip(1) {
+            let mut archive = multi_stream_archive(checksum)?;
+            corrupt_stream(&mut archive, "titles");
+            let actual = XZMultiStreamReader::new(archive.as_slice())
+                .and_then(|mut reader| reader.decompress(&mut interleaved_writer().0));
+            assert!(matches!(
+                actual.unwrap_err(),
+                CompressionModelError::ContentChecksumMismatch { .. }
+            ));
+
+            let mut archive = single_file_archive(checksum)?;
+            corrupt_stream(&mut archive, "data");
+            let actual = XZSingleFileReader::new(archive.as_slice());
+            assert!(matches!(
+                actual.err().unwrap(),
+                CompressionModelError::ContentChecksumMismatch { .. }
+            ));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,190 @@
+//! How each stream of the multi stream model is compressed, gathered in one place so it can be
+//! read from a JSON file rather than a flag per setting.
+//!
+//! ```json
+//! {
+//!     "titles": { "codec": "xz", "level": 9, "layout": "tokens" },
+//!     "nucleotides": { "level": 6, "packed": true },
+//!     "qualities": { "codec": "brotli", "level": 9, "binning": "illumina8" }
+//! }
+//! ```
+//!
+//! Anything left out is the `pare` default, a level left out is the level of the writer. The config
+//! the writer ended up with is recorded in the metadata of the archive.
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::compression_models::quality_binning::QualityBinning;
+use crate::compression_models::title_encoding::TitleLayout;
+use crate::compression_models::{Codec, CompressionModelError, Result, MAX_XZ_LEVEL};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TitleStreams {
+    pub codec: Codec,
+    /// The xz preset, or what it is mapped to for the other codecs, see `CompressedSpool::new`.
+    /// The level of the writer when `None`.
+    pub level: Option<u32>,
+    pub layout: TitleLayout,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NucleotideStream {
+    pub codec: Codec,
+    pub level: Option<u32>,
+    /// Two bits to a base, see `XZMultiStreamWriter::with_packed_nucleotides`.
+    pub packed: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QualityStream {
+    pub codec: Codec,
+    pub level: Option<u32>,
+    /// Binning and dropping the qualities are both lossy.
+    pub binning: Option<QualityBinning>,
+    pub dropped: bool,
+}
+
+/// The codec, level and transforms of the titles, nucleotides and qualities. The default is what
+/// the multi stream model does without one. The smaller streams, the case of the bases and the `+`
+/// lines, are always xz at the level of the writer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompressionConfig {
+    pub titles: TitleStreams,
+    pub nucleotides: NucleotideStream,
+    pub qualities: QualityStream,
+}
+
+impl CompressionConfig {
+    pub fn from_json(json: &str) -> Result<Self> {
+        let config: CompressionConfig =
+            serde_json::from_str(json).map_err(|e| CompressionModelError::BadConfig {
+                reason: e.to_string(),
+            })?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Check the levels are xz presets, 0 to 9, like the level of the writer.
+    pub fn validate(&self) -> Result<()> {
+        for (stream, level) in [
+            ("titles", self.titles.level),
+            ("nucleotides", self.nucleotides.level),
+            ("qualities", self.qualities.level),
+        ] {
+            if let Some(level) = level.filter(|level| *level > MAX_XZ_LEVEL) {
+                return Err(CompressionModelError::BadConfig {
+                    reason: format!(
+                        "the level of the {} is {}, levels run from 0 to {}",
+                        stream, level, MAX_XZ_LEVEL
+                    ),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Read a config from a JSON file.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        CompressionConfig::from_json(&std::fs::read_to_string(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    use tempfile::NamedTempFile;
+
+    use crate::compression_models::lzma_multi_stream::XZMultiStreamWriter;
+    use crate::compression_models::test_utils::*;
+    use crate::compression_models::*;
+    use crate::{compress_paired, decompress_paired, CompressOptions};
+
+    #[test]
+    fn test_default_config() -> Result<()> {
+        assert_eq!(
+            CompressionConfig::default(),
+            CompressionConfig::from_json("{}")?
+        );
+        let config = CompressionConfig::from_json(r#"{ "qualities": { "level": 2 } }"#)?;
+        assert_eq!(Some(2), config.qualities.level);
+        assert_eq!(None, config.titles.level);
+
+        assert!(matches!(
+            CompressionConfig::from_json(r#"{ "qualities": { "levle": 2 } }"#),
+            Err(CompressionModelError::BadConfig { .. })
+        ));
+        assert!(matches!(
+            CompressionConfig::from_json(r#"{ "titles": { "codec": "gzip" } }"#),
+            Err(CompressionModelError::BadConfig { .. })
+        ));
+        assert!(matches!(
+            CompressionConfig::from_json(r#"{ "qualities": { "level": 12 } }"#),
+            Err(CompressionModelError::BadConfig { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_levels_default_to_the_writer() -> Result<()> {
+        let config = CompressionConfig::from_json(r#"{ "qualities": { "level": 2 } }"#)?;
+        let writer = XZMultiStreamWriter::new(Vec::new())
+            .with_options(EncoderOptions::new().with_level(4))
+            .with_config(config);
+        let config = writer.config();
+        assert_eq!(Some(4), config.titles.level);
+        assert_eq!(Some(4), config.nucleotides.level);
+        assert_eq!(Some(2), config.qualities.level);
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_file() -> Result<()> {
+        let mut file = NamedTempFile::new()?;
+        file.write_all(
+            br#"{
+                "titles": { "level": 1, "layout": "delta" },
+                "nucleotides": { "codec": "bzip2", "level": 9, "packed": true },
+                "qualities": { "codec": "brotli", "level": 3 }
+            }"#,
+        )?;
+        let config = CompressionConfig::open(file.path())?;
+
+        let fastq = SyntheticFastq::default().interleaved(100);
+        let mut archive: Vec<u8> = Vec::new();
+        compress_paired(
+            &mut string_reader(&fastq),
+            &mut archive,
+            CompressOptions::new()
+                .with_model(CompressionModel::LZMAMulti)
+                .with_config(Some(config)),
+        )?;
+
+        let metadata = PareArchiveDecoder::new(archive.as_slice())?.get_metadata()?;
+        let recorded: CompressionConfig =
+            bson::from_document(metadata.get_document("config")?.clone())?;
+        assert_eq!(config, recorded);
+        assert_eq!(Some(1), recorded.titles.level);
+        assert_eq!(Some(9), recorded.nucleotides.level);
+        assert_eq!(Some(3), recorded.qualities.level);
+        let streams = metadata.get_document("streams")?;
+        assert_eq!("bzip2", streams.get_str("nucleotides")?);
+        assert_eq!("brotli", streams.get_str("qualities")?);
+        assert_eq!("packed", metadata.get_str("nucleotides")?);
+        assert_eq!("delta", metadata.get_str("titles")?);
+
+        let (mut writer, output) = interleaved_writer();
+        decompress_paired(archive.as_slice(), &mut writer, None)?;
+        drop(writer);
+        assert_eq!(fastq, output.text());
+        Ok(())
+    }
+}
@@ -0,0 +1,299 @@
+//! Storing the read pairs that are copies of an earlier pair as a reference to it, amplicon runs
+//! have a lot of them.
+//!
+//! Pairs are copies when everything but their titles is the same: the bases, qualities, `+` lines
+//! and case of both reads. Only the first of them is given to the model, every copy after it is a
+//! u64 little endian input position and the u64 index of the stored pair it repeats in the
+//! `duplicates` stream, with its titles a line each in the `duplicate_titles` stream.
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Seek, Write};
+
+use xz2::read::XzDecoder;
+
+use crate::compression_models::{
+    new_xz_spool, ArchiveStream, CompressedSpool, CompressionModelError, DecoderModel, LzmaOptions,
+    PairDecoder, Result, SpoolOptions,
+};
+use crate::seq_files::fastq::FastQRead;
+
+/// What pairs are told apart by, a hash of everything but their titles.
+fn content_hash(reads: &[&FastQRead]) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new();
+    for read in reads {
+        // each field is prefixed by its length so they can't run into each other
+        for field in [&read.letters, &read.qualities, read.sub_title.as_bytes()] {
+            hasher.update(&(field.len() as u64).to_le_bytes());
+            hasher.update(field);
+        }
+        hasher.update(&(read.case_mask.len() as u64).to_le_bytes());
+        for &lower in &read.case_mask {
+            hasher.update(&[lower as u8]);
+        }
+        hasher.update(&(read.other_letters.len() as u64).to_le_bytes());
+        for &(offset, letter) in &read.other_letters {
+            hasher.update(&(offset as u64).to_le_bytes());
+            hasher.update(&[letter]);
+        }
+    }
+    *hasher.finalize().as_bytes()
+}
+
+/// The pairs seen while compressing, and the copies of them found so far.
+pub(crate) struct Dedup {
+    /// The index of the stored pair with each content.
+    seen: HashMap<[u8; 32], u64>,
+    stored: u64,
+    /// The pairs read so far, copies included.
+    position: u64,
+    copies: u64,
+    duplicates: CompressedSpool,
+    titles: CompressedSpool,
+}
+
+impl Dedup {
+    pub(crate) fn new(spool: &SpoolOptions) -> Self {
+        Dedup {
+            seen: HashMap::new(),
+            stored: 0,
+            position: 0,
+            copies: 0,
+            duplicates: new_xz_spool(LzmaOptions::default(), spool),
+            titles: new_xz_spool(LzmaOptions::default(), spool),
+        }
+    }
+
+    /// Whether `reads` are a copy of a pair seen before, it is recorded as one if so and should
+    /// be left out of the model.
+    pub(crate) fn is_duplicate(&mut self, reads: &[&FastQRead]) -> Result<bool> {
+        let position = self.position;
+        self.position += 1;
+
+        let key = content_hash(reads);
+        let stored = match self.seen.get(&key) {
+            Some(&stored) => stored,
+            None => {
+                self.seen.insert(key, self.stored);
+                self.stored += 1;
+                return Ok(false);
+            }
+        };
+
+        self.duplicates.write_all(&position.to_le_bytes())?;
+        self.duplicates.write_all(&stored.to_le_bytes())?;
+        for read in reads {
+            self.titles.write_all(read.title.as_bytes())?;
+            self.titles.write_all(b"\n")?;
+        }
+        self.copies += 1;
+        Ok(true)
+    }
+
+    /// How many pairs were copies.
+    pub(crate) fn copies(&self) -> u64 {
+        self.copies
+    }
+
+    /// The `duplicates` and `duplicate_titles` streams.
+    pub(crate) fn into_streams(self) -> (CompressedSpool, CompressedSpool) {
+        (self.duplicates, self.titles)
+    }
+}
+
+/// The copies recorded in an archive, read back by `PareArchiveDecoder::duplicates`.
+pub(crate) struct Duplicates {
+    /// The input position of each copy and the stored pair it repeats, by position.
+    pub(crate) copies: Vec<(u64, u64)>,
+    pub(crate) titles: ArchiveStream,
+}
+
+impl Duplicates {
+    pub(crate) fn from_streams(mut duplicates: impl Read, titles: ArchiveStream) -> Result<Self> {
+        let mut bytes = Vec::new();
+        duplicates.read_to_end(&mut bytes)?;
+        if bytes.len() % 16 != 0 {
+            return Err(CompressionModelError::CorruptStream {
+                stream: "duplicates".to_string(),
+                read: (bytes.len() / 16) as u64,
+            });
+        }
+        let u64_at = |bytes: &[u8]| u64::from_le_bytes(bytes.try_into().unwrap());
+        let copies = bytes
+            .chunks_exact(16)
+            .map(|copy| (u64_at(&copy[..8]), u64_at(&copy[8..])))
+            .collect();
+        Ok(Duplicates { copies, titles })
+    }
+}
+
+/// Decodes the stored pairs of an archive and puts the copies back between them.
+pub(crate) struct RestoredDuplicates<'a> {
+    pub(crate) model: Box<dyn DecoderModel + 'a>,
+    pub(crate) duplicates: Duplicates,
+}
+
+struct DuplicatedPairs<'a> {
+    pairs: Box<dyn PairDecoder + 'a>,
+    copies: std::iter::Peekable<std::slice::Iter<'a, (u64, u64)>>,
+    titles: BufReader<XzDecoder<&'a mut ArchiveStream>>,
+    /// The copies still to come of each stored pair that has any, and the pairs themselves.
+    remaining: HashMap<u64, usize>,
+    kept: HashMap<u64, (FastQRead, FastQRead)>,
+    position: u64,
+    stored: u64,
+}
+
+impl DuplicatedPairs<'_> {
+    fn corrupt(&self, stream: &str) -> CompressionModelError {
+        CompressionModelError::CorruptStream {
+            stream: stream.to_string(),
+            read: self.position,
+        }
+    }
+
+    fn read_title(&mut self, title: &mut String) -> Result<()> {
+        title.clear();
+        if self.titles.read_line(title)? == 0 || title.pop() != Some('\n') {
+            return Err(self.corrupt("duplicate_titles"));
+        }
+        Ok(())
+    }
+
+    fn copy(&mut self, stored: u64, r1: &mut FastQRead, r2: &mut FastQRead) -> Result<()> {
+        let remaining = match self.remaining.get_mut(&stored) {
+            Some(remaining) if stored < self.stored => remaining,
+            _ => return Err(self.corrupt("duplicates")),
+        };
+        *remaining -= 1;
+        // the last copy can have the pair rather than a clone of it
+        let (s1, s2) = match *remaining {
+            0 => self.kept.remove(&stored),
+            _ => self.kept.get(&stored).cloned(),
+        }
+        .ok_or_else(|| self.corrupt("duplicates"))?;
+        *r1 = s1;
+        *r2 = s2;
+        self.read_title(&mut r1.title)?;
+        self.read_title(&mut r2.title)
+    }
+}
+
+impl PairDecoder for DuplicatedPairs<'_> {
+    fn read_next(&mut self, r1: &mut FastQRead, r2: &mut FastQRead) -> Result<bool> {
+        match self
+            .copies
+            .next_if(|&&(position, _)| position == self.position)
+        {
+            Some(&(_, stored)) => self.copy(stored, r1, r2)?,
+            None => {
+                if !self.pairs.read_next(r1, r2)? {
+                    if self.copies.peek().is_some() {
+                        return Err(self.corrupt("duplicates"));
+                    }
+                    return Ok(false);
+                }
+                if self.remaining.contains_key(&self.stored) {
+                    self.kept.insert(self.stored, (r1.clone(), r2.clone()));
+                }
+                self.stored += 1;
+            }
+        }
+        self.position += 1;
+        Ok(true)
+    }
+}
+
+impl DecoderModel for RestoredDuplicates<'_> {
+    fn decoder(&mut self) -> Result<Box<dyn PairDecoder + '_>> {
+        let mut remaining = HashMap::new();
+        for &(_, stored) in &self.duplicates.copies {
+            *remaining.entry(stored).or_insert(0) += 1;
+        }
+
+        self.duplicates.titles.rewind()?;
+        Ok(Box::new(DuplicatedPairs {
+            pairs: self.model.decoder()?,
+            copies: self.duplicates.copies.iter().peekable(),
+            titles: BufReader::new(XzDecoder::new_multi_decoder(&mut self.duplicates.titles)),
+            remaining,
+            kept: HashMap::new(),
+            position: 0,
+            stored: 0,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::compression_models::read_order::ReadOrder;
+    use crate::compression_models::test_utils::*;
+    use crate::compression_models::*;
+    use crate::{compress_paired, decompress_paired, CompressOptions};
+
+    const DUPLICATED_FASTQ: &str = concat!(
+        "@pair1/1\nACGTACGTAA\n+\nIIIIIIIIII\n",
+        "@pair1/2\nTTGCATTGCA\n+\nIIIIIIIIII\n",
+        "@pair2/1\nGGGGCCCCAA\n+\nIIIII#####\n",
+        "@pair2/2\nCCCCGGGGTT\n+\nIIIII#####\n",
+        "@pair3/1\nACGTACGTAA\n+\nIIIIIIIIII\n",
+        "@pair3/2\nTTGCATTGCA\n+\nIIIIIIIIII\n",
+    );
+
+    #[test]
+    fn test_dedup_round_trip() -> Result<()> {
+        for model in [CompressionModel::LZMASingle, CompressionModel::LZMAMulti] {
+            let mut archive: Vec<u8> = Vec::new();
+            let stats = compress_paired(
+                &mut string_reader(DUPLICATED_FASTQ),
+                &mut archive,
+                CompressOptions::new()
+                    .with_model(model.clone())
+                    .with_dedup(true),
+            )?;
+            assert_eq!(3, stats.pairs);
+            assert_eq!(1, stats.duplicate_pairs);
+
+            // the model only has the two unique pairs
+            let mut arc = PareArchiveDecoder::new(archive.as_slice())?;
+            let metadata = arc.get_metadata()?;
+            assert_eq!(1, metadata.get_i64("duplicates")?);
+            let mut unique = match model {
+                CompressionModel::LZMASingle => {
+                    Box::new(XZSingleFileReader::from_archive(arc)?) as Box<dyn DecoderModel>
+                }
+                _ => Box::new(XZMultiStreamReader::from_archive(arc)?),
+            };
+            let pairs: Vec<(FastQRead, FastQRead)> = unique.pairs()?.collect::<Result<_>>()?;
+            let titles: Vec<&str> = pairs.iter().map(|(r1, _)| r1.title.as_str()).collect();
+            assert_eq!(vec!["pair1/1", "pair2/1"], titles, "{}", model.as_str());
+
+            let (mut writer, output) = interleaved_writer();
+            decompress_paired(archive.as_slice(), &mut writer, None)?;
+            drop(writer);
+            assert_eq!(DUPLICATED_FASTQ, output.text(), "{}", model.as_str());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_sorted_and_deduplicated() -> Result<()> {
+        let fastq = SyntheticFastq::default().interleaved(100).repeat(2);
+        let mut archive: Vec<u8> = Vec::new();
+        let stats = compress_paired(
+            &mut string_reader(&fastq),
+            &mut archive,
+            CompressOptions::new()
+                .with_dedup(true)
+                .with_read_order(ReadOrder::SortedKeepingOrder),
+        )?;
+        assert_eq!(100, stats.duplicate_pairs);
+
+        let (mut writer, output) = interleaved_writer();
+        decompress_paired(archive.as_slice(), &mut writer, None)?;
+        drop(writer);
+        assert_eq!(fastq, output.text());
+        Ok(())
+    }
+}
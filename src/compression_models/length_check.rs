@@ -0,0 +1,109 @@
+//! Counting reads whose length isn't the one the run should have.
+
+use crate::seq_files::fastq::FastQRead;
+
+/// The read length a run is expected to have. Reads further than the tolerance from it are
+/// counted in `CompressionStats::unexpected_lengths`, they are still compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LengthCheck {
+    expected: usize,
+    tolerance: usize,
+}
+
+impl LengthCheck {
+    pub fn new(expected: usize) -> Self {
+        LengthCheck {
+            expected,
+            tolerance: 0,
+        }
+    }
+
+    /// How many bases shorter or longer than expected a read may be.
+    pub fn with_tolerance(mut self, tolerance: usize) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    pub fn expected(&self) -> usize {
+        self.expected
+    }
+
+    pub fn tolerance(&self) -> usize {
+        self.tolerance
+    }
+
+    pub fn is_unexpected(&self, read: &FastQRead) -> bool {
+        read.letters.len().abs_diff(self.expected) > self.tolerance
+    }
+
+    pub fn count_pair(&self, r1: &FastQRead, r2: &FastQRead) -> u64 {
+        self.count(&[r1, r2])
+    }
+
+    pub fn count_fragment(&self, reads: &[FastQRead]) -> u64 {
+        self.count(&reads.iter().collect::<Vec<_>>())
+    }
+
+    fn count(&self, reads: &[&FastQRead]) -> u64 {
+        reads.iter().filter(|read| self.is_unexpected(read)).count() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::compression_models::test_utils::*;
+    use crate::compression_models::*;
+    use crate::seq_files::fastq::*;
+    use crate::{XZSingleFileReader, XZSingleFileWriter};
+
+    fn read_of(length: usize) -> String {
+        format!("{}\n+\n{}\n", "A".repeat(length), "I".repeat(length))
+    }
+
+    #[test]
+    fn test_is_unexpected() {
+        let check = LengthCheck::new(100).with_tolerance(5);
+        let read = |length: usize| FastQRead {
+            letters: vec![b'A'; length],
+            ..FastQRead::default()
+        };
+        assert!(!check.is_unexpected(&read(100)));
+        assert!(!check.is_unexpected(&read(95)));
+        assert!(!check.is_unexpected(&read(105)));
+        assert!(check.is_unexpected(&read(94)));
+        assert!(check.is_unexpected(&read(106)));
+        assert!(LengthCheck::new(100).is_unexpected(&read(99)));
+    }
+
+    #[test]
+    fn test_short_read_is_counted() -> Result<()> {
+        let mut text = String::new();
+        for (i, (r1, r2)) in [(100, 100), (50, 100), (100, 100)].into_iter().enumerate() {
+            text.push_str(&format!("@pair{}/1\n{}", i, read_of(r1)));
+            text.push_str(&format!("@pair{}/2\n{}", i, read_of(r2)));
+        }
+
+        let mut archive: Vec<u8> = Vec::new();
+        let stats = XZSingleFileWriter::new(&mut archive)
+            .with_options(
+                EncoderOptions::new()
+                    .with_length_check(Some(LengthCheck::new(100).with_tolerance(10))),
+            )
+            .compress(&mut string_reader(&text))?;
+        assert_eq!(3, stats.pairs);
+        assert_eq!(1, stats.unexpected_lengths);
+        assert_eq!(
+            Some(stats),
+            PareArchiveDecoder::new(archive.as_slice())?.stats()?
+        );
+
+        // the reads are all stored
+        let (mut writer, output) = interleaved_writer();
+        XZSingleFileReader::new(archive.as_slice())?.decompress(&mut writer)?;
+        drop(writer);
+        assert_eq!(text, output.text());
+        Ok(())
+    }
+}
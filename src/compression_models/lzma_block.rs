@@ -0,0 +1,406 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+use bson::doc;
+
+use crate::compression_models::*;
+use crate::seq_files::fastq::{FastQRead, PairedFastQReader};
+
+/// Default number of read pairs in a block.
+pub const DEFAULT_BLOCK_SIZE: usize = 1024;
+/// The most read pairs in a block, a whole block is held in memory to write and read it.
+pub const MAX_BLOCK_SIZE: usize = 1 << 20;
+
+fn block_path(index: usize) -> String {
+    format!("block_{:08}", index)
+}
+
+/// Stores reads in independently compressed blocks of `block_size` pairs. Inside a block the
+/// reads are laid out by column rather than by record:
+///
+/// ```text
+/// read count      u32 little endian, R1 and R2 alternate
+/// read lengths    one u32 little endian per read
+/// titles          one line per read
+/// nucleotides     every read's letters back to back
+/// qualities       every read's qualities back to back
+/// ```
+///
+/// Each block is its own archive member so any block can be decoded without the others.
+pub struct XZBlockWriter<W: Write> {
+    sink: PareArchiveEncoder<W>,
+    block_size: usize,
+}
+
+impl<W: Write> XZBlockWriter<W> {
+    pub fn new(sink: W) -> Self {
+        XZBlockWriter {
+            sink: PareArchiveEncoder::<W>::new(sink),
+            block_size: DEFAULT_BLOCK_SIZE,
+        }
+    }
+
+    /// The read pairs compressed together, from 1 to `MAX_BLOCK_SIZE`.
+    pub fn with_block_size(mut self, block_size: usize) -> Self {
+        self.block_size = block_size.clamp(1, MAX_BLOCK_SIZE);
+        self
+    }
+
+    /// The settings shared by every model, see `EncoderOptions`.
+    pub fn with_options(mut self, options: EncoderOptions) -> Self {
+        self.sink.set_options(options);
+        self
+    }
+
+    fn write_block(&mut self, reads: &[FastQRead], index: usize) -> Result<()> {
+        let mut spool = new_xz_spool(self.sink.options().lzma, self.sink.spool_options());
+
+        spool.write_all(&(reads.len() as u32).to_le_bytes())?;
+        for read in reads {
+            spool.write_all(&(read.letters.len() as u32).to_le_bytes())?;
+        }
+        for read in reads {
+            spool.write_all(read.title.as_bytes())?;
+            spool.write_all(b"\n")?;
+        }
+        for read in reads {
+            spool.write_all(&read.letters)?;
+        }
+        for read in reads {
+            spool.write_all(&read.qualities)?;
+        }
+
+        self.sink.write_compressed_spool(spool, &block_path(index))
+    }
+}
+
+impl<W: Write> EncoderModel for XZBlockWriter<W> {
+    fn compress(&mut self, reader: &mut Box<dyn PairedFastQReader>) -> Result<CompressionStats> {
+        let mut stats = CompressionStats::default();
+        let mut block: Vec<FastQRead> = Vec::with_capacity(2 * self.block_size);
+        let mut blocks = 0;
+        let filter = self.sink.options().quality_filter;
+        let length_check = self.sink.options().length_check;
+        let binning = self.sink.options().quality_binning;
+
+        loop {
+            let mut r1 = FastQRead::default();
+            let mut r2 = FastQRead::default();
+            let more = reader.read_next(&mut r1, &mut r2)?;
+            if more && filter.is_some_and(|filter| !filter.keep_pair(&r1, &r2)) {
+                stats.filtered_pairs += 1;
+                continue;
+            }
+            if more {
+                if let Some(check) = length_check {
+                    stats.unexpected_lengths += check.count_pair(&r1, &r2);
+                }
+                if let Some(binning) = binning {
+                    binning.bin_read(&mut r1);
+                    binning.bin_read(&mut r2);
+                }
+                check_bare_sub_title(CompressionModel::LZMABlock, &r1)?;
+                check_bare_sub_title(CompressionModel::LZMABlock, &r2)?;
+                check_no_other_letters(CompressionModel::LZMABlock, &r1)?;
+                check_no_other_letters(CompressionModel::LZMABlock, &r2)?;
+                self.sink.note_dropped_case(&r1);
+                self.sink.note_dropped_case(&r2);
+                stats.add_pair(&r1, &r2);
+                if self.sink.is_duplicate(&[&r1, &r2])? {
+                    stats.duplicate_pairs += 1;
+                    continue;
+                }
+                block.push(r1);
+                block.push(r2);
+            }
+
+            if block.len() == 2 * self.block_size || (!more && !block.is_empty()) {
+                self.write_block(&block, blocks)?;
+                block.clear();
+                blocks += 1;
+            }
+
+            if !more {
+                break;
+            }
+        }
+
+        self.sink.set_line_ending(reader.line_ending());
+        self.sink.set_stats(stats);
+
+        self.sink.write_metadata(doc! {
+            "model": CompressionModel::LZMABlock.as_str(),
+            "version": 1,
+            "level": self.sink.options().lzma.level() as i64,
+            "block_size": self.block_size as i64,
+            "blocks": blocks as i64,
+        })?;
+        self.sink.finish()?;
+
+        Ok(stats)
+    }
+}
+
+impl XZBlockWriter<std::io::Stdout> {
+    pub fn to_stdout() -> Self {
+        XZBlockWriter::new(std::io::stdout())
+    }
+}
+
+impl XZBlockWriter<File> {
+    pub fn create<P: AsRef<Path>>(path: &P) -> Result<Self> {
+        let (file, pending) = PendingFile::create(path)?;
+        let mut writer = XZBlockWriter::new(file);
+        writer.sink.set_pending_file(pending);
+        Ok(writer)
+    }
+}
+
+// readers
+pub struct XZBlockReader<R: Read> {
+    arc: PareArchiveDecoder<R>,
+    blocks: usize,
+    block_size: usize,
+}
+
+fn read_u32<T: Read>(source: &mut T) -> Result<u32> {
+    let mut buffer = [0u8; 4];
+    source
+        .read_exact(&mut buffer)
+        .map_err(|_| CompressionModelError::IncompleteRecord)?;
+    Ok(u32::from_le_bytes(buffer))
+}
+
+impl<R: Read> XZBlockReader<R> {
+    pub fn new(source: R) -> Result<Self> {
+        XZBlockReader::from_archive(PareArchiveDecoder::new(source)?)
+    }
+
+    /// Read an archive that has already been opened, e.g. to look at its metadata first.
+    pub fn from_archive(mut arc: PareArchiveDecoder<R>) -> Result<Self> {
+        let metadata = arc.get_metadata()?;
+        CompressionModel::LZMABlock.check_metadata(&metadata)?;
+        arc.verify_checksum(&metadata)?;
+
+        let blocks = metadata.get_i64("blocks")? as usize;
+        let block_size = metadata.get_i64("block_size")?;
+        if !(1..=MAX_BLOCK_SIZE as i64).contains(&block_size) {
+            return Err(CompressionModelError::InvalidMetadata {
+                field: "block_size".to_string(),
+                value: block_size,
+            });
+        }
+        Ok(XZBlockReader {
+            arc,
+            blocks,
+            block_size: block_size as usize,
+        })
+    }
+
+    /// Number of blocks in the archive.
+    pub fn block_count(&self) -> usize {
+        self.blocks
+    }
+
+    /// Decode the reads of a single block into `reads`, R1 and R2 alternate.
+    pub fn read_block(&mut self, index: usize, reads: &mut Vec<FastQRead>) -> Result<()> {
+        let mut source = BufReader::new(self.arc.get_xz_stream(&block_path(index))?);
+
+        // the counts come from the stream, they are only trusted as far as the metadata allows
+        let count = read_u32(&mut source)? as usize;
+        if count > 2 * self.block_size {
+            return Err(CompressionModelError::IncompleteRecord);
+        }
+        reads.resize_with(count, FastQRead::default);
+        let mut lengths = Vec::with_capacity(count);
+        for _ in 0..count {
+            lengths.push(read_u32(&mut source)? as u64);
+        }
+
+        let mut title = Vec::new();
+        for read in reads.iter_mut() {
+            title.clear();
+            source.read_until(b'\n', &mut title)?;
+            if title.pop() != Some(b'\n') {
+                return Err(CompressionModelError::IncompleteRecord);
+            }
+            read.title = String::from_utf8(title.clone())?;
+        }
+
+        for (read, length) in reads.iter_mut().zip(&lengths) {
+            read_exactly(&mut source, *length, &mut read.letters)?;
+        }
+        for (read, length) in reads.iter_mut().zip(&lengths) {
+            read_exactly(&mut source, *length, &mut read.qualities)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Replace the contents of `buffer` with the next `length` bytes of `source`, it only grows as
+/// far as the bytes that are there.
+fn read_exactly<T: Read>(source: &mut T, length: u64, buffer: &mut Vec<u8>) -> Result<()> {
+    buffer.clear();
+    let read = source
+        .take(length)
+        .read_to_end(buffer)
+        .map_err(|_| CompressionModelError::IncompleteRecord)?;
+    if read as u64 != length {
+        return Err(CompressionModelError::IncompleteRecord);
+    }
+    Ok(())
+}
+
+/// Walks the blocks of an archive in order.
+struct BlockPairs<'a, R: Read> {
+    reader: &'a mut XZBlockReader<R>,
+    next_block: usize,
+    reads: std::vec::IntoIter<FastQRead>,
+}
+
+impl<R: Read> PairDecoder for BlockPairs<'_, R> {
+    fn read_next(&mut self, r1: &mut FastQRead, r2: &mut FastQRead) -> Result<bool> {
+        if self.reads.len() == 0 {
+            if self.next_block == self.reader.block_count() {
+                return Ok(false);
+            }
+
+            let mut reads = Vec::new();
+            self.reader.read_block(self.next_block, &mut reads)?;
+            self.next_block += 1;
+            self.reads = reads.into_iter();
+        }
+
+        match (self.reads.next(), self.reads.next()) {
+            (Some(first), Some(second)) => {
+                *r1 = first;
+                *r2 = second;
+                Ok(true)
+            }
+            _ => Err(CompressionModelError::IncompleteRecord),
+        }
+    }
+}
+
+impl<R: Read> DecoderModel for XZBlockReader<R> {
+    fn decoder(&mut self) -> Result<Box<dyn PairDecoder + '_>> {
+        Ok(Box::new(BlockPairs {
+            reader: self,
+            next_block: 0,
+            reads: Vec::new().into_iter(),
+        }))
+    }
+}
+
+impl XZBlockReader<std::io::Stdin> {
+    pub fn from_stdin() -> Result<Self> {
+        XZBlockReader::new(std::io::stdin())
+    }
+}
+
+impl XZBlockReader<File> {
+    pub fn open<P: AsRef<Path>>(path: &P) -> Result<Self> {
+        let file = File::open(path)?;
+        XZBlockReader::new(file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::compression_models::lzma_multi_stream::*;
+    use crate::compression_models::test_utils::*;
+
+    #[test]
+    fn test_block_round_trip() -> Result<()> {
+        let mut archive: Vec<u8> = Vec::new();
+        XZBlockWriter::new(&mut archive)
+            .with_block_size(1)
+            .compress(&mut interleaved_reader(INTERLEAVED_FASTQ))?;
+
+        let (mut writer, output) = interleaved_writer();
+        XZBlockReader::new(archive.as_slice())?.decompress(&mut writer)?;
+        drop(writer);
+        assert_eq!(INTERLEAVED_FASTQ, output.text());
+
+        let mut reader = XZBlockReader::new(archive.as_slice())?;
+        assert_eq!(2, reader.block_count());
+        let mut reads = Vec::new();
+        reader.read_block(1, &mut reads)?;
+        assert_eq!(2, reads.len());
+        assert_eq!(
+            "HWI-EAS209_0006_FC706VJ:5:58:5895:21142#ATCACG/2",
+            reads[1].title
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupt_block_counts() -> Result<()> {
+        let mut archive: Vec<u8> = Vec::new();
+        XZBlockWriter::new(&mut archive)
+            .with_block_size(1)
+            .compress(&mut interleaved_reader(INTERLEAVED_FASTQ))?;
+
+        let arc = PareArchiveDecoder::new(archive.as_slice())?;
+        let path = arc
+            .temp_path()
+            .expect("opened with new")
+            .join(block_path(0));
+        let mut reader = XZBlockReader::from_archive(arc)?;
+        let mut reads = Vec::new();
+
+        // more reads than a block holds, and a read longer than what is left of the block
+        for block in [
+            [
+                u32::MAX.to_le_bytes(),
+                4u32.to_le_bytes(),
+                4u32.to_le_bytes(),
+            ],
+            [
+                2u32.to_le_bytes(),
+                4u32.to_le_bytes(),
+                u32::MAX.to_le_bytes(),
+            ],
+        ] {
+            let mut encoder = XzEncoder::new(Vec::new(), 0);
+            encoder.write_all(&block.concat())?;
+            encoder.write_all(b"a\nb\nACGTACGT")?;
+            std::fs::write(&path, encoder.finish()?)?;
+            assert!(matches!(
+                reader.read_block(0, &mut reads),
+                Err(CompressionModelError::IncompleteRecord)
+            ));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ratio_against_multi_stream() -> Result<()> {
+        let fastq = SyntheticFastq::default().with_length(100).interleaved(2000);
+
+        let mut multi: Vec<u8> = Vec::new();
+        XZMultiStreamWriter::new(&mut multi).compress(&mut string_reader(&fastq))?;
+        let mut block: Vec<u8> = Vec::new();
+        XZBlockWriter::new(&mut block).compress(&mut string_reader(&fastq))?;
+
+        // blocks lose the context of the blocks before them, but not much of the ratio
+        assert!(
+            2 * block.len() < 3 * multi.len(),
+            "block {} bytes, multi stream {} bytes",
+            block.len(),
+            multi.len()
+        );
+
+        let (mut writer, output) = interleaved_writer();
+        XZBlockReader::new(block.as_slice())?.decompress(&mut writer)?;
+        drop(writer);
+        assert_eq!(fastq, output.text());
+        Ok(())
+    }
+}
@@ -0,0 +1,457 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+use bson::doc;
+use xz2::read::XzDecoder;
+
+use crate::compression_models::lzma_block::{DEFAULT_BLOCK_SIZE, MAX_BLOCK_SIZE};
+use crate::compression_models::*;
+use crate::seq_files::fastq::{FastQRead, PairedFastQReader};
+
+/// Blocks with more read lengths than this have their qualities stored a read at a time. R1 and R2
+/// can be sequenced to different lengths, more than that means the reads were trimmed and the
+/// columns no longer line up.
+const MAX_COLUMNAR_LENGTHS: usize = 2;
+
+const LAYOUT_COLUMNS: u8 = b'C';
+const LAYOUT_ROWS: u8 = b'R';
+
+/// An experimental model for fixed length reads. The quality of a cycle is much like the quality
+/// of the same cycle in the reads around it, so the qualities of each block of read pairs are
+/// stored as a matrix per read length, transposed so a column of the same cycle in every read
+/// comes out together:
+///
+/// ```text
+/// read 1  q1 q2 q3 q4      stored   q1 q1 q1  q2 q2 q2  q3 q3 q3  q4 q4 q4
+/// read 2  q1 q2 q3 q4  ->           ^ read 1, 2, 3
+/// read 3  q1 q2 q3 q4
+/// ```
+///
+/// The matrices follow each other in order of length. Blocks of trimmed reads, with more than
+/// `MAX_COLUMNAR_LENGTHS` lengths, are stored a read at a time instead. The rest of the read is in
+/// streams of its own:
+///
+/// ```text
+/// lengths      one u32 little endian per read, R1 and R2 alternate
+/// titles       one line per read
+/// nucleotides  every read's letters back to back
+/// qualities    per block a layout byte, C for columns or R for rows, then the qualities
+/// ```
+pub struct XZColumnarWriter<W: Write> {
+    sink: PareArchiveEncoder<W>,
+    block_size: usize,
+}
+
+impl<W: Write> XZColumnarWriter<W> {
+    pub fn new(sink: W) -> Self {
+        XZColumnarWriter {
+            sink: PareArchiveEncoder::<W>::new(sink),
+            block_size: DEFAULT_BLOCK_SIZE,
+        }
+    }
+
+    /// The read pairs whose qualities are transposed together, from 1 to `MAX_BLOCK_SIZE`.
+    pub fn with_block_size(mut self, block_size: usize) -> Self {
+        self.block_size = block_size.clamp(1, MAX_BLOCK_SIZE);
+        self
+    }
+
+    /// The settings shared by every model, see `EncoderOptions`.
+    pub fn with_options(mut self, options: EncoderOptions) -> Self {
+        self.sink.set_options(options);
+        self
+    }
+}
+
+/// The indices of the reads of each length, in order of length.
+fn reads_by_length(lengths: impl Iterator<Item = usize>) -> BTreeMap<usize, Vec<usize>> {
+    let mut by_length: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for (index, length) in lengths.enumerate() {
+        by_length.entry(length).or_default().push(index);
+    }
+    by_length
+}
+
+fn write_qualities<T: Write>(spool: &mut T, block: &[FastQRead]) -> Result<()> {
+    let by_length = reads_by_length(block.iter().map(|read| read.qualities.len()));
+    if by_length.len() > MAX_COLUMNAR_LENGTHS {
+        spool.write_all(&[LAYOUT_ROWS])?;
+        for read in block {
+            spool.write_all(&read.qualities)?;
+        }
+        return Ok(());
+    }
+
+    spool.write_all(&[LAYOUT_COLUMNS])?;
+    let mut column = Vec::new();
+    for (length, reads) in by_length {
+        for cycle in 0..length {
+            column.clear();
+            column.extend(reads.iter().map(|&index| block[index].qualities[cycle]));
+            spool.write_all(&column)?;
+        }
+    }
+    Ok(())
+}
+
+impl<W: Write> EncoderModel for XZColumnarWriter<W> {
+    fn compress(&mut self, reader: &mut Box<dyn PairedFastQReader>) -> Result<CompressionStats> {
+        let mut stats = CompressionStats::default();
+        let mut block: Vec<FastQRead> = Vec::with_capacity(2 * self.block_size);
+
+        let spool = self.sink.spool_options();
+        let lzma = self.sink.options().lzma;
+        let filter = self.sink.options().quality_filter;
+        let length_check = self.sink.options().length_check;
+        let binning = self.sink.options().quality_binning;
+        let mut lengths = new_xz_spool(lzma, spool);
+        let mut titles = new_xz_spool(lzma, spool);
+        let mut nucleotides = new_xz_spool(lzma, spool);
+        let mut qualities = new_xz_spool(lzma, spool);
+
+        loop {
+            let mut r1 = FastQRead::default();
+            let mut r2 = FastQRead::default();
+            let more = reader.read_next(&mut r1, &mut r2)?;
+            if more && filter.is_some_and(|filter| !filter.keep_pair(&r1, &r2)) {
+                stats.filtered_pairs += 1;
+                continue;
+            }
+            if more {
+                if let Some(check) = length_check {
+                    stats.unexpected_lengths += check.count_pair(&r1, &r2);
+                }
+                if let Some(binning) = binning {
+                    binning.bin_read(&mut r1);
+                    binning.bin_read(&mut r2);
+                }
+                check_bare_sub_title(CompressionModel::LZMAColumnar, &r1)?;
+                check_bare_sub_title(CompressionModel::LZMAColumnar, &r2)?;
+                check_no_other_letters(CompressionModel::LZMAColumnar, &r1)?;
+                check_no_other_letters(CompressionModel::LZMAColumnar, &r2)?;
+                self.sink.note_dropped_case(&r1);
+                self.sink.note_dropped_case(&r2);
+                stats.add_pair(&r1, &r2);
+                if self.sink.is_duplicate(&[&r1, &r2])? {
+                    stats.duplicate_pairs += 1;
+                    continue;
+                }
+
+                for read in [&r1, &r2] {
+                    lengths.write_all(&(read.letters.len() as u32).to_le_bytes())?;
+                    titles.write_all(read.title.as_bytes())?;
+                    titles.write_all(b"\n")?;
+                    nucleotides.write_all(&read.letters)?;
+                }
+                block.push(r1);
+                block.push(r2);
+            }
+
+            if block.len() == 2 * self.block_size || (!more && !block.is_empty()) {
+                write_qualities(&mut qualities, &block)?;
+                block.clear();
+            }
+
+            if !more {
+                break;
+            }
+        }
+
+        self.sink.write_compressed_spool(lengths, "lengths")?;
+        self.sink.write_compressed_spool(titles, "titles")?;
+        self.sink
+            .write_compressed_spool(nucleotides, "nucleotides")?;
+        self.sink.write_compressed_spool(qualities, "qualities")?;
+        self.sink.set_line_ending(reader.line_ending());
+        self.sink.set_stats(stats);
+        self.sink.write_metadata(doc! {
+            "model": CompressionModel::LZMAColumnar.as_str(),
+            "version": 1,
+            "level": lzma.level() as i64,
+            "block_size": self.block_size as i64,
+            "quality_layout": "columns_per_length",
+        })?;
+        self.sink.finish()?;
+
+        Ok(stats)
+    }
+}
+
+impl XZColumnarWriter<std::io::Stdout> {
+    pub fn to_stdout() -> Self {
+        XZColumnarWriter::new(std::io::stdout())
+    }
+}
+
+impl XZColumnarWriter<File> {
+    pub fn create<P: AsRef<Path>>(path: &P) -> Result<Self> {
+        let (file, pending) = PendingFile::create(path)?;
+        let mut writer = XZColumnarWriter::new(file);
+        writer.sink.set_pending_file(pending);
+        Ok(writer)
+    }
+}
+
+// readers
+pub struct XZColumnarReader<R: Read> {
+    arc: PareArchiveDecoder<R>,
+    block_size: usize,
+}
+
+impl<R: Read> XZColumnarReader<R> {
+    pub fn new(source: R) -> Result<Self> {
+        XZColumnarReader::from_archive(PareArchiveDecoder::new(source)?)
+    }
+
+    /// Read an archive that has already been opened, e.g. to look at its metadata first.
+    pub fn from_archive(mut arc: PareArchiveDecoder<R>) -> Result<Self> {
+        let metadata = arc.get_metadata()?;
+        CompressionModel::LZMAColumnar.check_metadata(&metadata)?;
+        arc.verify_checksum(&metadata)?;
+
+        // a block is allocated up front, don't trust a corrupt size
+        let block_size = metadata.get_i64("block_size")?;
+        if !(1..=MAX_BLOCK_SIZE as i64).contains(&block_size) {
+            return Err(CompressionModelError::InvalidMetadata {
+                field: "block_size".to_string(),
+                value: block_size,
+            });
+        }
+        let block_size = block_size as usize;
+        Ok(XZColumnarReader { arc, block_size })
+    }
+}
+
+/// Decodes a block of read pairs at a time, the qualities of a block can't be told apart before
+/// the whole of it is read.
+struct ColumnarPairs {
+    lengths: BufReader<XzDecoder<ArchiveStream>>,
+    titles: BufReader<XzDecoder<ArchiveStream>>,
+    nucleotides: BufReader<XzDecoder<ArchiveStream>>,
+    qualities: BufReader<XzDecoder<ArchiveStream>>,
+    block_size: usize,
+    reads: std::vec::IntoIter<FastQRead>,
+}
+
+impl ColumnarPairs {
+    /// The length of the next read, None at the end of the archive.
+    fn read_length(&mut self) -> Result<Option<usize>> {
+        let mut buffer = [0u8; 4];
+        let mut filled = 0;
+        while filled < buffer.len() {
+            match self.lengths.read(&mut buffer[filled..])? {
+                0 if filled == 0 => return Ok(None),
+                0 => return Err(CompressionModelError::IncompleteRecord),
+                n => filled += n,
+            }
+        }
+        Ok(Some(u32::from_le_bytes(buffer) as usize))
+    }
+
+    fn read_block(&mut self) -> Result<Vec<FastQRead>> {
+        let mut block = Vec::with_capacity(2 * self.block_size);
+        while block.len() < 2 * self.block_size {
+            let length = match self.read_length()? {
+                Some(length) => length,
+                None => break,
+            };
+
+            let mut title = Vec::new();
+            self.titles.read_until(b'\n', &mut title)?;
+            if title.pop() != Some(b'\n') {
+                return Err(CompressionModelError::IncompleteRecord);
+            }
+
+            let mut letters = vec![0; length];
+            self.nucleotides
+                .read_exact(&mut letters)
+                .map_err(|_| CompressionModelError::IncompleteRecord)?;
+
+            block.push(FastQRead {
+                title: String::from_utf8(title)?,
+                letters,
+                qualities: vec![0; length],
+                ..Default::default()
+            });
+        }
+        if block.is_empty() {
+            return Ok(block);
+        }
+
+        let mut layout = [0u8];
+        self.qualities
+            .read_exact(&mut layout)
+            .map_err(|_| CompressionModelError::IncompleteRecord)?;
+        match layout[0] {
+            LAYOUT_ROWS => {
+                for read in block.iter_mut() {
+                    self.qualities
+                        .read_exact(&mut read.qualities)
+                        .map_err(|_| CompressionModelError::IncompleteRecord)?;
+                }
+            }
+            LAYOUT_COLUMNS => {
+                let by_length = reads_by_length(block.iter().map(|read| read.letters.len()));
+                let mut column = Vec::new();
+                for (length, reads) in by_length {
+                    column.resize(reads.len(), 0);
+                    for cycle in 0..length {
+                        self.qualities
+                            .read_exact(&mut column)
+                            .map_err(|_| CompressionModelError::IncompleteRecord)?;
+                        for (&index, &quality) in reads.iter().zip(column.iter()) {
+                            block[index].qualities[cycle] = quality;
+                        }
+                    }
+                }
+            }
+            layout => {
+                return Err(CompressionModelError::UnknownQualityLayout {
+                    name: (layout as char).to_string(),
+                })
+            }
+        }
+
+        Ok(block)
+    }
+}
+
+impl PairDecoder for ColumnarPairs {
+    fn read_next(&mut self, r1: &mut FastQRead, r2: &mut FastQRead) -> Result<bool> {
+        if self.reads.len() == 0 {
+            let block = self.read_block()?;
+            if block.is_empty() {
+                return Ok(false);
+            }
+            self.reads = block.into_iter();
+        }
+
+        match (self.reads.next(), self.reads.next()) {
+            (Some(first), Some(second)) => {
+                *r1 = first;
+                *r2 = second;
+                Ok(true)
+            }
+            _ => Err(CompressionModelError::IncompleteRecord),
+        }
+    }
+}
+
+impl<R: Read> DecoderModel for XZColumnarReader<R> {
+    fn decoder(&mut self) -> Result<Box<dyn PairDecoder + '_>> {
+        Ok(Box::new(ColumnarPairs {
+            lengths: BufReader::new(self.arc.get_xz_stream("lengths")?),
+            titles: BufReader::new(self.arc.get_xz_stream("titles")?),
+            nucleotides: BufReader::new(self.arc.get_xz_stream("nucleotides")?),
+            qualities: BufReader::new(self.arc.get_xz_stream("qualities")?),
+            block_size: self.block_size,
+            reads: Vec::new().into_iter(),
+        }))
+    }
+}
+
+impl XZColumnarReader<std::io::Stdin> {
+    pub fn from_stdin() -> Result<Self> {
+        XZColumnarReader::new(std::io::stdin())
+    }
+}
+
+impl XZColumnarReader<File> {
+    pub fn open<P: AsRef<Path>>(path: &P) -> Result<Self> {
+        let file = File::open(path)?;
+        XZColumnarReader::new(file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::compression_models::lzma_multi_stream::*;
+    use crate::compression_models::test_utils::*;
+
+    #[test]
+    fn test_columnar_round_trip() -> Result<()> {
+        // a pair to a block is a single length a block, two pairs is two lengths
+        for block_size in [1, 2] {
+            let mut archive: Vec<u8> = Vec::new();
+            XZColumnarWriter::new(&mut archive)
+                .with_block_size(block_size)
+                .compress(&mut interleaved_reader(INTERLEAVED_FASTQ))?;
+
+            let (mut writer, output) = interleaved_writer();
+            XZColumnarReader::new(archive.as_slice())?.decompress(&mut writer)?;
+            drop(writer);
+            assert_eq!(INTERLEAVED_FASTQ, output.text());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_trimmed_reads_are_stored_as_rows() -> Result<()> {
+        let trimmed = "@a/1\nACGT\n+\nABCD\n@a/2\nACG\n+\nEFG\n@b/1\nAC\n+\nHI\n@b/2\nA\n+\nJ\n";
+
+        let mut block = Vec::new();
+        let mut reader = string_reader(trimmed);
+        let mut r1 = FastQRead::default();
+        let mut r2 = FastQRead::default();
+        while reader.read_next(&mut r1, &mut r2)? {
+            block.push(r1.clone());
+            block.push(r2.clone());
+        }
+        let mut spool = Vec::new();
+        write_qualities(&mut spool, &block)?;
+        assert_eq!(Some(&LAYOUT_ROWS), spool.first());
+
+        let mut archive: Vec<u8> = Vec::new();
+        XZColumnarWriter::new(&mut archive).compress(&mut string_reader(trimmed))?;
+        let (mut writer, output) = interleaved_writer();
+        XZColumnarReader::new(archive.as_slice())?.decompress(&mut writer)?;
+        drop(writer);
+        assert_eq!(trimmed, output.text());
+
+        Ok(())
+    }
+
+    /// Uniform length reads whose quality falls off along the read, as it does on Illumina runs.
+    fn stream_size(archive: &[u8], path: &str) -> Result<u64> {
+        let streams = PareArchiveDecoder::new(archive)?.list_streams()?;
+        Ok(streams
+            .into_iter()
+            .find(|(name, _)| name == path)
+            .map(|(_, size)| size)
+            .expect("the stream is in the archive"))
+    }
+
+    // The point of the model, uniform reads have smaller qualities in columns than in rows.
+    #[test]
+    fn test_columns_compress_uniform_qualities() -> Result<()> {
+        let fastq = SyntheticFastq::default()
+            .with_length(100)
+            .with_fading_qualities()
+            .interleaved(2_000);
+
+        let mut multi: Vec<u8> = Vec::new();
+        XZMultiStreamWriter::new(&mut multi).compress(&mut string_reader(&fastq))?;
+        let mut columnar: Vec<u8> = Vec::new();
+        XZColumnarWriter::new(&mut columnar).compress(&mut string_reader(&fastq))?;
+
+        let (mut writer, output) = interleaved_writer();
+        XZColumnarReader::new(columnar.as_slice())?.decompress(&mut writer)?;
+        drop(writer);
+        assert_eq!(fastq, output.text());
+
+        let columns = stream_size(&columnar, "qualities")?;
+        let rows = stream_size(&multi, "qualities")?;
+        assert!(
+            columns < rows,
+            "columns {} bytes, rows {} bytes",
+            columns,
+            rows
+        );
+        Ok(())
+    }
+}
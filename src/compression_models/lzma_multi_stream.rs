@@ -1,65 +1,606 @@
 use std::fs::File;
 use std::io::{BufRead, BufReader, Read, Write};
 use std::path::Path;
+use std::sync::mpsc::sync_channel;
+use std::thread;
+
+use bson::{doc, Bson};
+
+use crate::compression_models::config::{
+    CompressionConfig, NucleotideStream, QualityStream, TitleStreams,
+};
+use crate::compression_models::nucleotide_pack::{
+    read_exceptions, read_packed, write_exceptions, write_packed,
+};
+use crate::compression_models::title_encoding::{TitleDecoder, TitleEncoder, TitleLayout};
+use crate::compression_models::*;
+use crate::seq_files::fastq::{
+    FastQRead, LineEnding, MultiFastQReader, MultiFastQWriter, PairedFastQReader,
+};
+
+/// Read pairs are gathered into chunks of about this many bytes of qualities before being
+/// handed to the stream encoders.
+const CHUNK_SIZE: usize = 1 << 20;
+/// Chunks waiting for each encoder thread.
+const CHUNKS_IN_FLIGHT: usize = 4;
+
+/// Where each stream was restarted at a sync point, see `XZMultiStreamWriter::with_index`.
+type SyncPoint = Vec<(&'static str, u64)>;
+
+/// One kind of data, titles, nucleotides, etc., fed to its encoder in chunks.
+trait StreamEncoder: Send {
+    fn write_chunk(&mut self, chunk: &[u8]) -> Result<()>;
+    /// Restart the compression so decoding can start from here.
+    fn restart(&mut self) -> Result<SyncPoint>;
+    fn finish(self: Box<Self>) -> Result<Vec<(&'static str, FinishedSpool)>>;
+}
 
-use bson::doc;
-use tempfile::SpooledTempFile;
-use xz2::read::XzDecoder;
-use xz2::write::XzEncoder;
+struct SpoolStreamEncoder {
+    path: &'static str,
+    spool: CompressedSpool,
+    lzma: LzmaOptions,
+}
 
-use crate::compression_models::*;
-use crate::seq_files::fastq::{FastQRead, PairedFastQReader, PairedFastQWriter};
+impl StreamEncoder for SpoolStreamEncoder {
+    fn write_chunk(&mut self, chunk: &[u8]) -> Result<()> {
+        self.spool.write_all(chunk)?;
+        Ok(())
+    }
+
+    fn restart(&mut self) -> Result<SyncPoint> {
+        Ok(vec![(self.path, self.spool.restart(self.lzma)?)])
+    }
+
+    fn finish(self: Box<Self>) -> Result<Vec<(&'static str, FinishedSpool)>> {
+        Ok(vec![(self.path, self.spool.finish()?)])
+    }
+}
+
+/// Chunks of newline terminated titles.
+struct TitleStreamEncoder {
+    titles: Box<dyn TitleEncoder>,
+    lzma: LzmaOptions,
+}
+
+impl StreamEncoder for TitleStreamEncoder {
+    fn write_chunk(&mut self, chunk: &[u8]) -> Result<()> {
+        for title in std::str::from_utf8(chunk)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?
+            .split_terminator('\n')
+        {
+            self.titles.write_title(title)?;
+        }
+        Ok(())
+    }
+
+    fn restart(&mut self) -> Result<SyncPoint> {
+        self.titles.restart(self.lzma)
+    }
+
+    fn finish(self: Box<Self>) -> Result<Vec<(&'static str, FinishedSpool)>> {
+        let mut spools = Vec::new();
+        for (path, spool) in self.titles.into_spools() {
+            spools.push((path, spool.finish()?));
+        }
+        Ok(spools)
+    }
+}
+
+/// What a call to the `fill` of `encode_streams` left in the chunks.
+enum Filled {
+    /// Full chunks, there are more reads to come.
+    More,
+    /// The reads up to a sync point, every stream is restarted after them.
+    SyncPoint,
+    /// The last reads.
+    Done,
+}
+
+/// Sent to an encoder thread.
+enum EncoderInput {
+    Chunk(Vec<u8>),
+    Restart,
+}
+
+/// The spools written by `encode_streams` and the sync points in them.
+type EncodedStreams = (Vec<(&'static str, FinishedSpool)>, Vec<SyncPoint>);
+
+/// Join up the sync points of each encoder into the sync points of all the streams.
+fn merge_sync_points(per_encoder: Vec<Vec<SyncPoint>>) -> Vec<SyncPoint> {
+    let mut merged: Vec<SyncPoint> = Vec::new();
+    for points in per_encoder {
+        merged.resize(points.len(), Vec::new());
+        for (point, offsets) in merged.iter_mut().zip(points) {
+            point.extend(offsets);
+        }
+    }
+    merged
+}
+
+/// Feed every chunk filled by `fill` to the matching encoder, either on this thread or with each
+/// encoder on its own. The output is the same either way.
+fn encode_streams<F>(
+    encoders: Vec<Box<dyn StreamEncoder>>,
+    parallel: bool,
+    mut fill: F,
+) -> Result<EncodedStreams>
+where
+    F: FnMut(&mut [Vec<u8>]) -> Result<Filled>,
+{
+    let mut chunks: Vec<Vec<u8>> = vec![Vec::new(); encoders.len()];
+
+    if !parallel {
+        let mut encoders = encoders;
+        let mut sync_points = vec![Vec::new(); encoders.len()];
+        loop {
+            let filled = fill(&mut chunks)?;
+            for (encoder, chunk) in encoders.iter_mut().zip(chunks.iter_mut()) {
+                encoder.write_chunk(chunk)?;
+                chunk.clear();
+            }
+            match filled {
+                Filled::More => {}
+                Filled::SyncPoint => {
+                    for (encoder, points) in encoders.iter_mut().zip(sync_points.iter_mut()) {
+                        points.push(encoder.restart()?);
+                    }
+                }
+                Filled::Done => break,
+            }
+        }
+
+        let mut spools = Vec::new();
+        for encoder in encoders {
+            spools.extend(encoder.finish()?);
+        }
+        return Ok((spools, merge_sync_points(sync_points)));
+    }
+
+    thread::scope(|scope| {
+        let mut senders = Vec::new();
+        let mut workers = Vec::new();
+        for mut encoder in encoders {
+            let (sender, receiver) = sync_channel::<EncoderInput>(CHUNKS_IN_FLIGHT);
+            senders.push(sender);
+            workers.push(scope.spawn(move || {
+                let mut points = Vec::new();
+                for input in receiver {
+                    match input {
+                        EncoderInput::Chunk(chunk) => encoder.write_chunk(&chunk)?,
+                        EncoderInput::Restart => points.push(encoder.restart()?),
+                    }
+                }
+                Ok::<_, CompressionModelError>((encoder.finish()?, points))
+            }));
+        }
+
+        // a send only fails when the worker has stopped on an error, which is reported by join
+        let mut result = Ok(());
+        loop {
+            let filled = match fill(&mut chunks) {
+                Ok(filled) => filled,
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            };
+            let mut sent = senders
+                .iter()
+                .zip(chunks.iter_mut())
+                .all(|(sender, chunk)| {
+                    sender
+                        .send(EncoderInput::Chunk(std::mem::take(chunk)))
+                        .is_ok()
+                });
+            match filled {
+                Filled::More => {}
+                Filled::SyncPoint => {
+                    sent = sent
+                        && senders
+                            .iter()
+                            .all(|sender| sender.send(EncoderInput::Restart).is_ok());
+                }
+                Filled::Done => break,
+            }
+            if !sent {
+                break;
+            }
+        }
+        drop(senders);
+
+        let mut spools = Vec::new();
+        let mut sync_points = Vec::new();
+        for worker in workers {
+            match worker.join() {
+                Ok(finished) => {
+                    let (finished, points) = finished?;
+                    spools.extend(finished);
+                    sync_points.push(points);
+                }
+                Err(panic) => std::panic::resume_unwind(panic),
+            }
+        }
+        result?;
+        Ok((spools, merge_sync_points(sync_points)))
+    })
+}
 
 pub struct XZMultiStreamWriter<W: Write> {
     sink: PareArchiveEncoder<W>,
+    title_layout: TitleLayout,
+    pack_nucleotides: bool,
+    nucleotide_codec: Codec,
+    quality_codec: Codec,
+    title_codec: Codec,
+    /// The levels of the titles, nucleotides and qualities when they were configured.
+    config: Option<CompressionConfig>,
+    parallel: bool,
+    index_interval: Option<u64>,
+    drop_qualities: bool,
+    /// The codec of each stream, filled in while compressing.
+    codecs: Document,
+    /// How the `+` lines are stored, decided once every read has been seen.
+    plus_lines: PlusLines,
+    /// Whether a read had a `.` or `-`, decided once every read has been seen.
+    other_letters: bool,
 }
 
 impl<W: Write> XZMultiStreamWriter<W> {
     pub fn new(sink: W) -> Self {
         XZMultiStreamWriter {
             sink: PareArchiveEncoder::<W>::new(sink),
+            title_layout: TitleLayout::default(),
+            pack_nucleotides: false,
+            nucleotide_codec: Codec::default(),
+            quality_codec: Codec::default(),
+            title_codec: Codec::default(),
+            config: None,
+            parallel: true,
+            index_interval: None,
+            drop_qualities: false,
+            codecs: Document::new(),
+            plus_lines: PlusLines::Stored,
+            other_letters: false,
+        }
+    }
+
+    /// The settings shared by every model, see `EncoderOptions`. The quality binning of a config
+    /// takes the place of theirs, so give them before `with_config`.
+    pub fn with_options(mut self, options: EncoderOptions) -> Self {
+        self.sink.set_options(options);
+        self
+    }
+
+    /// Compress the nucleotides with `codec` instead of xz.
+    pub fn with_nucleotide_codec(mut self, codec: Codec) -> Self {
+        self.nucleotide_codec = codec;
+        self
+    }
+
+    /// Compress the qualities with `codec` instead of xz.
+    pub fn with_quality_codec(mut self, codec: Codec) -> Self {
+        self.quality_codec = codec;
+        self
+    }
+
+    /// Compress the titles with `codec` instead of xz, every title stream of the layout uses it.
+    pub fn with_title_codec(mut self, codec: Codec) -> Self {
+        self.title_codec = codec;
+        self
+    }
+
+    /// Store the nucleotides two bits to a base, with anything other than ACGT in a side stream.
+    pub fn with_packed_nucleotides(mut self, pack_nucleotides: bool) -> Self {
+        self.pack_nucleotides = pack_nucleotides;
+        self
+    }
+
+    /// Don't store the qualities at all, this is lossy. The reads are decoded with
+    /// `PLACEHOLDER_PHRED` for every base.
+    pub fn with_dropped_qualities(mut self, drop_qualities: bool) -> Self {
+        if drop_qualities {
+            self.sink.add_lossy("qualities", "dropped");
+        }
+        self.drop_qualities = drop_qualities;
+        self
+    }
+
+    /// Compress each stream on its own thread. On by default.
+    pub fn with_parallel_streams(mut self, parallel: bool) -> Self {
+        self.parallel = parallel;
+        self
+    }
+
+    pub fn with_title_layout(mut self, title_layout: TitleLayout) -> Self {
+        self.title_layout = title_layout;
+        self
+    }
+
+    /// Restart the compression of every stream each `interval` read pairs and write an index of
+    /// where, so `XZMultiStreamReader::read_pair_at` only has to decode from the sync point before
+    /// the pair. Smaller intervals compress a little worse.
+    pub fn with_index(mut self, interval: Option<u64>) -> Self {
+        self.index_interval = interval.filter(|interval| *interval > 0);
+        self
+    }
+
+    /// Take the codec, level and transforms of the titles, nucleotides and qualities from
+    /// `config`, in place of the ones set so far. The config is recorded in the metadata.
+    pub fn with_config(self, config: CompressionConfig) -> Self {
+        let mut writer = self
+            .with_title_codec(config.titles.codec)
+            .with_title_layout(config.titles.layout)
+            .with_nucleotide_codec(config.nucleotides.codec)
+            .with_packed_nucleotides(config.nucleotides.packed)
+            .with_quality_codec(config.qualities.codec)
+            .with_dropped_qualities(config.qualities.dropped);
+        writer.sink.options.quality_binning = config.qualities.binning;
+        writer.config = Some(config);
+        writer
+    }
+
+    /// What the titles, nucleotides and qualities are compressed with, the levels are those of
+    /// `with_config` or else the level of the writer. Every level is set.
+    pub fn config(&self) -> CompressionConfig {
+        let level = |configured: fn(&CompressionConfig) -> Option<u32>| {
+            Some(
+                self.config
+                    .as_ref()
+                    .and_then(configured)
+                    .unwrap_or(self.sink.options().lzma.level()),
+            )
+        };
+        CompressionConfig {
+            titles: TitleStreams {
+                codec: self.title_codec,
+                level: level(|config| config.titles.level),
+                layout: self.title_layout,
+            },
+            nucleotides: NucleotideStream {
+                codec: self.nucleotide_codec,
+                level: level(|config| config.nucleotides.level),
+                packed: self.pack_nucleotides,
+            },
+            qualities: QualityStream {
+                codec: self.quality_codec,
+                level: level(|config| config.qualities.level),
+                binning: self.sink.options().quality_binning,
+                dropped: self.drop_qualities,
+            },
         }
     }
 }
 
 impl<W: Write> EncoderModel for XZMultiStreamWriter<W> {
-    fn compress(&mut self, reader: &mut Box<dyn PairedFastQReader>) -> Result<()> {
-        let mut r1 = FastQRead::default();
-        let mut r2 = FastQRead::default();
+    fn compress(&mut self, reader: &mut Box<dyn PairedFastQReader>) -> Result<CompressionStats> {
+        // the index counts the pairs in the streams, which the copies aren't
+        if self.sink.dedup() && self.index_interval.is_some() {
+            return Err(CompressionModelError::CantDedup {
+                reason: "an archive with an index can't leave copies out".to_string(),
+            });
+        }
+        let stats = self.compress_fragments(2, |reads| {
+            let (r1, r2) = reads.split_at_mut(1);
+            Ok(reader.read_next(&mut r1[0], &mut r2[0])?)
+        })?;
+        self.finish_archive(reader.line_ending(), stats, 2)?;
 
-        let mut title_spool = XzEncoder::new(SpooledTempFile::new(4096), 9);
-        let mut nucleotides_spool = XzEncoder::new(SpooledTempFile::new(4096), 9);
-        let mut qualities_spool = XzEncoder::new(SpooledTempFile::new(4096), 9);
+        Ok(stats)
+    }
+}
 
-        self.sink.write_metadata(doc! {
-            "model": CompressionModel::LZMAMulti.as_str(),
-            "version": 1,
-        })?;
+impl<W: Write> XZMultiStreamWriter<W> {
+    /// Compress fragments of any number of reads, e.g. R1, R2 and the index reads. The reads of a
+    /// fragment follow each other in every stream, as R1 and R2 do for pairs.
+    pub fn compress_multi(
+        &mut self,
+        reader: &mut Box<dyn MultiFastQReader>,
+    ) -> Result<CompressionStats> {
+        let reads_per_fragment = reader.reads_per_fragment();
+        if reads_per_fragment == 0 {
+            return Err(CompressionModelError::NoInputFiles);
+        }
+        if self.sink.dedup() {
+            return Err(CompressionModelError::CantDedup {
+                reason: "only read pairs can be deduplicated".to_string(),
+            });
+        }
+        let stats =
+            self.compress_fragments(reads_per_fragment, |reads| Ok(reader.read_next(reads)?))?;
+        self.finish_archive(reader.line_ending(), stats, reads_per_fragment)?;
 
-        loop {
-            if !reader.read_next(&mut r1, &mut r2)? {
-                break;
-            }
+        Ok(stats)
+    }
 
-            write!(title_spool, "{}\n", r1.title)?;
-            write!(title_spool, "{}\n", r2.title)?;
+    /// Encode the fragments filled in by `read_next` into the staged streams.
+    fn compress_fragments<F>(
+        &mut self,
+        reads_per_fragment: usize,
+        mut read_next: F,
+    ) -> Result<CompressionStats>
+    where
+        F: FnMut(&mut [FastQRead]) -> Result<bool>,
+    {
+        let mut stats = CompressionStats::default();
+        let mut reads = vec![FastQRead::default(); reads_per_fragment];
+
+        let lzma = self.sink.options().lzma;
+        let spool = self.sink.spool_options().clone();
+        let binning = self.sink.options().quality_binning;
+        let filter = self.sink.options().quality_filter;
+        let length_check = self.sink.options().length_check;
+        let pack_nucleotides = self.pack_nucleotides;
+        let drop_qualities = self.drop_qualities;
+        let mut plus_lines = PlusLineCheck::default();
+        let mut has_other_letters = false;
+        // chunks are cut by the bytes of qualities, or of nucleotides when there are none
+        let measured = if drop_qualities { 1 } else { 2 };
+        // the chunks filled below are in this order, after the titles
+        let config = self.config();
+        let stream_lzma = |level: Option<u32>| level.map_or(lzma, |level| lzma.with_level(level));
+        let nucleotide_lzma = stream_lzma(config.nucleotides.level);
+        let mut streams = vec![
+            ("nucleotides", self.nucleotide_codec, nucleotide_lzma),
+            (
+                "qualities",
+                self.quality_codec,
+                stream_lzma(config.qualities.level),
+            ),
+            ("case", Codec::Xz, lzma),
+            ("sub_titles", Codec::Xz, lzma),
+            ("other_letters", Codec::Xz, lzma),
+        ];
+        if pack_nucleotides {
+            streams.push(("nucleotide_exceptions", Codec::Xz, nucleotide_lzma));
+        }
 
-            nucleotides_spool.write(&r1.letters)?;
-            nucleotides_spool.write(b"\n")?;
+        let title_codec = self.title_codec;
+        let title_lzma = stream_lzma(config.titles.level);
+        let mut encoders: Vec<Box<dyn StreamEncoder>> = vec![Box::new(TitleStreamEncoder {
+            titles: self.title_layout.encoder({
+                let spool = spool.clone();
+                move || CompressedSpool::new(title_codec, title_lzma, &spool)
+            }),
+            lzma: title_lzma,
+        })];
+        self.codecs = Document::new();
+        for (path, codec, lzma) in streams {
+            encoders.push(Box::new(SpoolStreamEncoder {
+                path,
+                spool: CompressedSpool::new(codec, lzma, &spool),
+                lzma,
+            }));
+            self.codecs.insert(path, codec.as_str());
+        }
 
-            nucleotides_spool.write(&r2.letters)?;
-            nucleotides_spool.write(b"\n")?;
+        let index_interval = self.index_interval;
+        let sink = &mut self.sink;
+        let (spools, sync_points) = encode_streams(encoders, self.parallel, |chunks| {
+            while chunks[measured].len() < CHUNK_SIZE {
+                if !read_next(&mut reads)? {
+                    return Ok(Filled::Done);
+                }
+                if filter.is_some_and(|filter| !filter.keep_fragment(&reads)) {
+                    stats.filtered_pairs += 1;
+                    continue;
+                }
+                if let Some(check) = length_check {
+                    stats.unexpected_lengths += check.count_fragment(&reads);
+                }
+                if let Some(binning) = binning {
+                    reads.iter_mut().for_each(|read| binning.bin_read(read));
+                }
+                stats.add_fragment(&reads);
+                if sink.dedup() && sink.is_duplicate(&reads.iter().collect::<Vec<_>>())? {
+                    stats.duplicate_pairs += 1;
+                    continue;
+                }
+
+                for read in reads.iter() {
+                    chunks[0].extend_from_slice(read.title.as_bytes());
+                    chunks[0].push(b'\n');
+                    if pack_nucleotides {
+                        let (nucleotides, exceptions) = chunks[1..].split_at_mut(5);
+                        write_packed(&mut nucleotides[0], &mut exceptions[0], &read.letters)?;
+                    } else {
+                        chunks[1].extend_from_slice(&read.letters);
+                        chunks[1].push(b'\n');
+                    }
+                    if !drop_qualities {
+                        chunks[2].extend_from_slice(&read.qualities);
+                    }
+                    write_case_runs(&mut chunks[3], &read.case_mask)?;
+                    plus_lines.check(read);
+                    encode_sub_title(read, &mut chunks[4]);
+                    chunks[4].push(b'\n');
+                    write_exceptions(&mut chunks[5], &read.other_letters)?;
+                    has_other_letters |= !read.other_letters.is_empty();
+                }
+
+                if index_interval.is_some_and(|interval| stats.pairs % interval == 0) {
+                    return Ok(Filled::SyncPoint);
+                }
+            }
+            Ok(Filled::More)
+        })?;
 
-            qualities_spool.write(&r1.qualities)?;
-            qualities_spool.write(&r2.qualities)?;
+        self.plus_lines = plus_lines.plus_lines();
+        self.other_letters = has_other_letters;
+        for (path, spool) in spools {
+            // the qualities stream is left empty, there's no need to store it
+            if path == "qualities" && self.drop_qualities {
+                self.codecs.remove(path);
+                continue;
+            }
+            // nor the `+` lines when the metadata says what they all are
+            if path == "sub_titles" && self.plus_lines != PlusLines::Stored {
+                self.codecs.remove(path);
+                continue;
+            }
+            // or the `.` and `-` letters when there were none
+            if path == "other_letters" && !self.other_letters {
+                self.codecs.remove(path);
+                continue;
+            }
+            // which title streams there are is up to the layout
+            if !self.codecs.contains_key(path) {
+                self.codecs.insert(path, title_codec.as_str());
+            }
+            self.sink.stage_finished_spool(spool, path);
+        }
+        if let Some(interval) = index_interval {
+            self.stage_index(interval, sync_points)?;
         }
+        Ok(stats)
+    }
 
-        self.sink.write_xz_spool(title_spool, "titles")?;
-        self.sink.write_xz_spool(nucleotides_spool, "nucleotides")?;
-        self.sink.write_xz_spool(qualities_spool, "qualities")?;
-        self.sink.finish()?;
+    /// The offset of every stream at each sync point, the first sync point is after `interval`
+    /// pairs.
+    fn stage_index(&mut self, interval: u64, sync_points: Vec<SyncPoint>) -> Result<()> {
+        let points: Vec<Document> = sync_points
+            .into_iter()
+            .map(|point| {
+                point
+                    .into_iter()
+                    .map(|(path, offset)| (path.to_string(), Bson::Int64(offset as i64)))
+                    .collect()
+            })
+            .collect();
+
+        let mut spool = self.sink.spool_options().spool();
+        doc! { "interval": interval as i64, "points": points }.to_writer(&mut spool)?;
+        self.sink.stage_stored_spool(spool, "index")
+    }
 
-        Ok(())
+    fn finish_archive(
+        &mut self,
+        line_ending: LineEnding,
+        stats: CompressionStats,
+        reads_per_fragment: usize,
+    ) -> Result<()> {
+        self.sink.set_line_ending(line_ending);
+        self.sink.set_stats(stats);
+        let mut metadata = doc! {
+            "model": CompressionModel::LZMAMulti.as_str(),
+            "version": 1,
+            "level": self.sink.options().lzma.level() as i64,
+            "titles": self.title_layout.as_str(),
+            "case": true,
+            "other_letters": self.other_letters,
+            "sub_titles": self.plus_lines == PlusLines::Stored,
+            "plus_lines": self.plus_lines.as_str(),
+            "nucleotides": if self.pack_nucleotides { "packed" } else { "text" },
+            "streams": std::mem::take(&mut self.codecs),
+            "reads": reads_per_fragment as i64,
+        };
+        if let Some(interval) = self.index_interval {
+            metadata.insert("index", interval as i64);
+        }
+        if self.config.is_some() {
+            metadata.insert("config", bson::to_document(&self.config())?);
+        }
+        self.sink.write_metadata(metadata)?;
+        self.sink.finish()
     }
 }
 
@@ -71,48 +612,73 @@ impl XZMultiStreamWriter<std::io::Stdout> {
 
 impl XZMultiStreamWriter<File> {
     pub fn create<P: AsRef<Path>>(path: &P) -> Result<Self> {
-        let file = File::create(path)?;
-        Ok(XZMultiStreamWriter::new(file))
+        let (file, pending) = PendingFile::create(path)?;
+        let mut writer = XZMultiStreamWriter::new(file);
+        writer.sink.set_pending_file(pending);
+        Ok(writer)
     }
 }
 
 // readers
 pub struct XZMultiStreamReader<R: Read> {
     arc: PareArchiveDecoder<R>,
+    parallel: bool,
 }
 
-type XzBufReader = BufReader<XzDecoder<File>>;
+type XzBufReader = BufReader<StreamDecoder>;
 
 impl<R: Read> XZMultiStreamReader<R> {
     pub fn new(source: R) -> Result<Self> {
-        let arc = PareArchiveDecoder::<R>::new(source)?;
-
-        Ok(XZMultiStreamReader { arc: arc })
+        XZMultiStreamReader::from_archive(PareArchiveDecoder::new(source)?)
     }
 
-    fn check_magic(&mut self) -> Result<()> {
-        let metadata = self.arc.get_metadata()?;
-        if metadata.get_str("model")? != CompressionModel::LZMAMulti.as_str()
-            || metadata.get_i64("version")? != 1
-        {
-            return Err(CompressionModelError::OpenedWithWrongModel);
-        }
-
-        Ok(())
+    /// Read an archive that has already been opened, e.g. to look at its metadata first.
+    pub fn from_archive(arc: PareArchiveDecoder<R>) -> Result<Self> {
+        Ok(XZMultiStreamReader {
+            arc: arc,
+            parallel: true,
+        })
     }
 
-    fn read_line(&mut self, source: &mut XzBufReader, record: &mut String) -> Result<bool> {
-        record.clear();
-        if source.read_line(record)? == 0 {
-            return Ok(false);
-        }
+    /// Decode each stream on its own thread, the reads are put together from them as they come.
+    /// On by default.
+    pub fn with_parallel_streams(mut self, parallel: bool) -> Self {
+        self.parallel = parallel;
+        self
+    }
 
-        record.pop();
+    fn check_magic(&mut self) -> Result<Document> {
+        let metadata = self.arc.get_metadata()?;
+        CompressionModel::LZMAMulti.check_metadata(&metadata)?;
+        self.arc.verify_checksum(&metadata)?;
 
-        Ok(true)
+        Ok(metadata)
     }
+}
+
+/// The open streams of a multi stream archive.
+struct MultiStreamPairs {
+    titles: Box<dyn TitleDecoder>,
+    nucleotides: XzBufReader,
+    /// Only packed nucleotides have exceptions.
+    nucleotide_exceptions: Option<XzBufReader>,
+    /// Archives made without qualities don't have this stream.
+    qualities: Option<StreamDecoder>,
+    /// Archives written before the case was kept don't have this stream.
+    case: Option<XzBufReader>,
+    case_line: Vec<u8>,
+    /// Only stored when the `+` lines aren't all bare or all repeats of the title.
+    sub_titles: Option<XzBufReader>,
+    sub_title_line: Vec<u8>,
+    plus_lines: PlusLines,
+    /// Only stored when a read has a `.` or `-`.
+    other_letters: Option<XzBufReader>,
+    /// The reads decoded so far, to say where the streams went wrong.
+    reads: u64,
+}
 
-    fn read_u8(&mut self, source: &mut XzBufReader, record: &mut Vec<u8>) -> Result<bool> {
+impl MultiStreamPairs {
+    fn read_u8(source: &mut XzBufReader, record: &mut Vec<u8>) -> Result<bool> {
         record.clear();
         if source.read_until(b'\n', record)? == 0 {
             return Ok(false);
@@ -128,55 +694,316 @@ impl<R: Read> XZMultiStreamReader<R> {
         Ok(true)
     }
 
-    fn read_exact(
-        &mut self,
-        source: &mut XzDecoder<File>,
-        l: usize,
-        record: &mut Vec<u8>,
-    ) -> Result<bool> {
+    fn read_exact(source: &mut StreamDecoder, l: usize, record: &mut Vec<u8>) -> Result<()> {
         record.clear();
         record.resize(l, 0);
 
         source.read_exact(&mut record[..])?;
-        Ok(true)
+        Ok(())
     }
-}
 
-impl<R: Read> DecoderModel for XZMultiStreamReader<R> {
-    fn decompress(&mut self, writer: &mut Box<dyn PairedFastQWriter>) -> Result<()> {
-        let mut r1 = FastQRead::default();
-        let mut r2 = FastQRead::default();
+    fn corrupt(&self, stream: &str) -> CompressionModelError {
+        CompressionModelError::CorruptStream {
+            stream: stream.to_string(),
+            read: self.reads,
+        }
+    }
+}
 
-        self.check_magic()?;
+impl MultiStreamPairs {
+    /// Decode the rest of a read once its title has been, from every other stream.
+    fn read_rest_of(&mut self, read: &mut FastQRead) -> Result<()> {
+        self.reads += 1;
 
-        let mut title_stream = BufReader::new(self.arc.get_xz_stream("titles")?);
-        let mut nuc_stream = BufReader::new(self.arc.get_xz_stream("nucleotides")?);
-        let mut qual_stream = self.arc.get_xz_stream("qualities")?;
+        match &mut self.nucleotide_exceptions {
+            Some(exceptions) => {
+                read_packed(&mut self.nucleotides, exceptions, &mut read.letters)?;
+            }
+            None => required(Self::read_u8(&mut self.nucleotides, &mut read.letters)?)?,
+        }
 
-        loop {
-            if !self.read_line(&mut title_stream, &mut r1.title)? {
-                break;
+        match &mut self.qualities {
+            Some(qualities) => {
+                Self::read_exact(qualities, read.letters.len(), &mut read.qualities)?;
+            }
+            None => fill_qualities(read),
+        }
+        match read.validate() {
+            Ok(()) => {}
+            Err(FastQFileError::InvalidNucleotideLetter { .. }) => {
+                return Err(self.corrupt("nucleotides"))
             }
+            Err(_) => return Err(self.corrupt("qualities")),
+        }
 
-            if !self.read_line(&mut title_stream, &mut r2.title)? {
-                return Err(CompressionModelError::IncompleteRecord);
+        match &mut self.case {
+            Some(case) => {
+                required(Self::read_u8(case, &mut self.case_line)?)?;
+                parse_case_runs(&self.case_line, read.letters.len(), &mut read.case_mask)?;
             }
+            None => read.case_mask.clear(),
+        }
 
-            if !self.read_u8(&mut nuc_stream, &mut r1.letters)? {
-                return Err(CompressionModelError::IncompleteRecord);
+        match &mut self.sub_titles {
+            Some(sub_titles) => {
+                required(Self::read_u8(sub_titles, &mut self.sub_title_line)?)?;
+                decode_sub_title(&self.sub_title_line, read)?;
             }
+            None => self.plus_lines.fill_sub_title(read),
+        }
 
-            if !self.read_u8(&mut nuc_stream, &mut r2.letters)? {
-                return Err(CompressionModelError::IncompleteRecord);
+        match &mut self.other_letters {
+            Some(other_letters) => read_exceptions(other_letters, &mut read.other_letters)?,
+            None => read.other_letters.clear(),
+        }
+
+        Ok(())
+    }
+
+    /// A read after the first of a fragment, its title has to be there.
+    fn read_one(&mut self, read: &mut FastQRead) -> Result<()> {
+        required(self.titles.read_title(&mut read.title)?)?;
+        self.read_rest_of(read)
+    }
+
+    /// Reads any number of reads to a fragment the way `read_fields` reads pairs.
+    fn read_fragment(&mut self, reads: &mut [FastQRead]) -> Result<bool> {
+        let (first, rest) = match reads.split_first_mut() {
+            Some(split) => split,
+            None => return Ok(false),
+        };
+        if !self.read_first_field(first)? {
+            self.check_ended()?;
+            return Ok(false);
+        }
+
+        eof_is_incomplete(
+            self.read_rest_of(first)
+                .and_then(|()| rest.iter_mut().try_for_each(|read| self.read_one(read))),
+        )?;
+        Ok(true)
+    }
+}
+
+impl FieldDecoder for MultiStreamPairs {
+    fn read_first_field(&mut self, r1: &mut FastQRead) -> Result<bool> {
+        self.titles.read_title(&mut r1.title)
+    }
+
+    fn read_rest(&mut self, r1: &mut FastQRead, r2: &mut FastQRead) -> Result<()> {
+        self.read_rest_of(r1)?;
+        self.read_one(r2)
+    }
+
+    fn check_ended(&mut self) -> Result<()> {
+        // data left over means a read was decoded to the wrong length, or the titles are short
+        let reads = self.reads;
+        check_stream_ended(&mut self.nucleotides, "nucleotides", reads)?;
+        if let Some(exceptions) = &mut self.nucleotide_exceptions {
+            check_stream_ended(exceptions, "nucleotide_exceptions", reads)?;
+        }
+        if let Some(qualities) = &mut self.qualities {
+            check_stream_ended(qualities, "qualities", reads)?;
+        }
+        for (stream, source) in [
+            ("case", &mut self.case),
+            ("sub_titles", &mut self.sub_titles),
+            ("other_letters", &mut self.other_letters),
+        ] {
+            if let Some(source) = source {
+                check_stream_ended(source, stream, reads)?;
             }
+        }
+        Ok(())
+    }
+}
 
-            self.read_exact(&mut qual_stream, r1.letters.len(), &mut r1.qualities)?;
-            self.read_exact(&mut qual_stream, r2.letters.len(), &mut r2.qualities)?;
+impl PairDecoder for MultiStreamPairs {
+    fn read_next(&mut self, r1: &mut FastQRead, r2: &mut FastQRead) -> Result<bool> {
+        read_fields(self, r1, r2)
+    }
+}
 
-            writer.write_next(&r1, &r2)?;
+impl<R: Read> XZMultiStreamReader<R> {
+    /// The reads to a fragment, 2 for pairs and for archives from before there could be more.
+    pub fn reads_per_fragment(&mut self) -> Result<usize> {
+        let metadata = self.arc.get_metadata()?;
+        Ok(reads_per_fragment(&metadata))
+    }
+
+    /// Decompress an archive of any number of reads to a fragment, `writer` has to take as many.
+    pub fn decompress_multi(&mut self, writer: &mut Box<dyn MultiFastQWriter>) -> Result<()> {
+        let metadata = self.check_magic()?;
+        let actual = reads_per_fragment(&metadata);
+        if actual != writer.reads_per_fragment() {
+            return Err(CompressionModelError::ReadsPerFragmentMismatch {
+                expected: writer.reads_per_fragment(),
+                actual,
+            });
+        }
+
+        let mut reads = vec![FastQRead::default(); actual];
+        let mut decoder = self.open_streams(&metadata)?;
+        while decoder.read_fragment(&mut reads)? {
+            writer.write_next(&reads)?;
         }
         Ok(())
     }
+
+    /// Read the pair at `index`, counting from 0. With an index in the archive, see
+    /// `XZMultiStreamWriter::with_index`, decoding starts from the sync point before the pair,
+    /// otherwise every pair before it has to be decoded.
+    pub fn read_pair_at(&mut self, index: u64) -> Result<(FastQRead, FastQRead)> {
+        let mut decoder = self.decoder_at(index)?;
+        let mut r1 = FastQRead::default();
+        let mut r2 = FastQRead::default();
+        if !decoder.read_next(&mut r1, &mut r2)? {
+            return Err(CompressionModelError::PairOutOfRange { index });
+        }
+        Ok((r1, r2))
+    }
+
+    /// The metadata of an archive of read pairs.
+    fn check_pairs(&mut self) -> Result<Document> {
+        let metadata = self.check_magic()?;
+        let actual = reads_per_fragment(&metadata);
+        if actual != 2 {
+            return Err(CompressionModelError::ReadsPerFragmentMismatch {
+                expected: 2,
+                actual,
+            });
+        }
+        Ok(metadata)
+    }
+
+    /// The last sync point at or before the pair `index` and the pair it is at, the start of the
+    /// archive when there is no index.
+    fn sync_point_before(
+        &mut self,
+        metadata: &Document,
+        index: u64,
+    ) -> Result<(u64, Option<Document>)> {
+        let interval = match metadata.get_i64("index") {
+            Ok(interval) => interval as u64,
+            Err(_) => return Ok((0, None)),
+        };
+
+        let index_doc = Document::from_reader(self.arc.get_stream("index")?)?;
+        let points = index_doc.get_array("points")?;
+        let point = ((index / interval) as usize).min(points.len());
+        if point == 0 {
+            return Ok((0, None));
+        }
+        Ok((
+            point as u64 * interval,
+            points[point - 1].as_document().cloned(),
+        ))
+    }
+
+    fn open_streams(&mut self, metadata: &Document) -> Result<MultiStreamPairs> {
+        self.open_streams_at(metadata, None)
+    }
+
+    /// Open the streams at a sync point from the index, or at the start.
+    fn open_streams_at(
+        &mut self,
+        metadata: &Document,
+        sync_point: Option<&Document>,
+    ) -> Result<MultiStreamPairs> {
+        let offset = |path: &str| -> u64 {
+            sync_point
+                .and_then(|point| point.get_i64(path).ok())
+                .map_or(0, |offset| offset as u64)
+        };
+
+        self.arc.set_parallel_streams(self.parallel);
+        let xz_stream = |arc: &mut PareArchiveDecoder<R>, path: &str| -> Result<XzBufReader> {
+            Ok(BufReader::new(arc.get_compressed_stream_at(
+                path,
+                Codec::Xz,
+                offset(path),
+            )?))
+        };
+
+        let title_layout = match metadata.get_str("titles") {
+            Ok(name) => TitleLayout::from_name(name)?,
+            Err(_) => TitleLayout::Verbatim,
+        };
+
+        let case = match metadata.get_bool("case") {
+            Ok(true) => Some(xz_stream(&mut self.arc, "case")?),
+            _ => None,
+        };
+
+        let plus_lines = PlusLines::from_metadata(metadata)?;
+        let sub_titles = match plus_lines {
+            PlusLines::Stored => Some(xz_stream(&mut self.arc, "sub_titles")?),
+            _ => None,
+        };
+
+        let other_letters = match metadata.get_bool("other_letters") {
+            Ok(true) => Some(xz_stream(&mut self.arc, "other_letters")?),
+            _ => None,
+        };
+
+        let nucleotide_exceptions = match metadata.get_str("nucleotides") {
+            Ok("packed") => Some(xz_stream(&mut self.arc, "nucleotide_exceptions")?),
+            _ => None,
+        };
+
+        let codec = |path: &str| stream_codec(metadata, path);
+        let nucleotides = BufReader::new(self.arc.get_compressed_stream_at(
+            "nucleotides",
+            codec("nucleotides")?,
+            offset("nucleotides"),
+        )?);
+        let qualities = match qualities_dropped(metadata) {
+            true => None,
+            false => Some(self.arc.get_compressed_stream_at(
+                "qualities",
+                codec("qualities")?,
+                offset("qualities"),
+            )?),
+        };
+
+        Ok(MultiStreamPairs {
+            titles: title_layout.decoder_at(&mut self.arc, codec("titles")?, &offset)?,
+            nucleotides,
+            nucleotide_exceptions,
+            qualities,
+            case,
+            case_line: Vec::new(),
+            sub_titles,
+            sub_title_line: Vec::new(),
+            plus_lines,
+            other_letters,
+            reads: 0,
+        })
+    }
+}
+
+/// Archives from before there could be more than two reads to a fragment don't record it.
+pub(crate) fn reads_per_fragment(metadata: &Document) -> usize {
+    match metadata.get_i64("reads") {
+        Ok(reads) => reads as usize,
+        Err(_) => 2,
+    }
+}
+
+impl<R: Read> DecoderModel for XZMultiStreamReader<R> {
+    fn decoder(&mut self) -> Result<Box<dyn PairDecoder + '_>> {
+        let metadata = self.check_pairs()?;
+        Ok(Box::new(self.open_streams(&metadata)?))
+    }
+
+    /// With an index in the archive decoding starts from the sync point before `start`.
+    fn decoder_at(&mut self, start: u64) -> Result<Box<dyn PairDecoder + '_>> {
+        let metadata = self.check_pairs()?;
+        let (point_start, sync_point) = self.sync_point_before(&metadata, start)?;
+        let mut decoder = self.open_streams_at(&metadata, sync_point.as_ref())?;
+        skip_pairs(&mut decoder, start - point_start)?;
+        Ok(Box::new(decoder))
+    }
 }
 
 impl XZMultiStreamReader<std::io::Stdin> {
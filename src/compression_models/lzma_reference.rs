@@ -0,0 +1,442 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+use bson::doc;
+use xz2::read::XzDecoder;
+
+use crate::compression_models::reference::{Alignment, Reference};
+use crate::compression_models::title_encoding::{TitleDecoder, TitleLayout};
+use crate::compression_models::*;
+use crate::seq_files::fastq::{FastQRead, PairedFastQReader};
+
+/// Stores reads that align to a reference as a position plus the bases that differ, the rest are
+/// stored like the multi stream model. Only the identity of the reference is stored in the
+/// archive, the same reference has to be given to decompress it.
+///
+/// Every read has a line in the `alignments` stream, either `*` for a read stored verbatim in the
+/// `nucleotides` stream or `<contig> <position> <+|-> <length>` with a matching line in `edits`.
+/// Each edit is written as the distance from the previous edit followed by the read base.
+pub struct XZReferenceWriter<W: Write> {
+    sink: PareArchiveEncoder<W>,
+    reference: Reference,
+    title_layout: TitleLayout,
+}
+
+impl<W: Write> XZReferenceWriter<W> {
+    pub fn new(sink: W, reference: Reference) -> Self {
+        XZReferenceWriter {
+            sink: PareArchiveEncoder::<W>::new(sink),
+            reference,
+            title_layout: TitleLayout::default(),
+        }
+    }
+
+    pub fn with_title_layout(mut self, title_layout: TitleLayout) -> Self {
+        self.title_layout = title_layout;
+        self
+    }
+
+    /// The settings shared by every model, see `EncoderOptions`.
+    pub fn with_options(mut self, options: EncoderOptions) -> Self {
+        self.sink.set_options(options);
+        self
+    }
+
+    fn write_nucleotides(
+        &self,
+        read: &FastQRead,
+        alignments: &mut CompressedSpool,
+        edits: &mut CompressedSpool,
+        nucleotides: &mut CompressedSpool,
+    ) -> Result<()> {
+        let alignment = match self.reference.align(&read.letters) {
+            Some(alignment) => alignment,
+            None => {
+                alignments.write_all(b"*\n")?;
+                nucleotides.write_all(&read.letters)?;
+                nucleotides.write_all(b"\n")?;
+                return Ok(());
+            }
+        };
+
+        writeln!(
+            alignments,
+            "{} {} {} {}",
+            alignment.contig,
+            alignment.position,
+            if alignment.reverse { '-' } else { '+' },
+            read.letters.len()
+        )?;
+
+        let mut previous = 0;
+        for (i, (offset, base)) in alignment.edits.iter().enumerate() {
+            if i > 0 {
+                edits.write_all(b" ")?;
+            }
+            write!(edits, "{}{}", offset - previous, *base as char)?;
+            previous = *offset;
+        }
+        edits.write_all(b"\n")?;
+
+        Ok(())
+    }
+}
+
+impl<W: Write> EncoderModel for XZReferenceWriter<W> {
+    fn compress(&mut self, reader: &mut Box<dyn PairedFastQReader>) -> Result<CompressionStats> {
+        let mut stats = CompressionStats::default();
+        let mut r1 = FastQRead::default();
+        let mut r2 = FastQRead::default();
+
+        let spool = self.sink.spool_options().clone();
+        let lzma = self.sink.options().lzma;
+        let filter = self.sink.options().quality_filter;
+        let length_check = self.sink.options().length_check;
+        let binning = self.sink.options().quality_binning;
+        let mut titles = self.title_layout.encoder({
+            let spool = spool.clone();
+            move || CompressedSpool::new(Codec::Xz, lzma, &spool)
+        });
+        let mut alignments_spool = new_xz_spool(lzma, &spool);
+        let mut edits_spool = new_xz_spool(lzma, &spool);
+        let mut nucleotides_spool = new_xz_spool(lzma, &spool);
+        let mut qualities_spool = new_xz_spool(lzma, &spool);
+
+        loop {
+            if !reader.read_next(&mut r1, &mut r2)? {
+                break;
+            }
+            if filter.is_some_and(|filter| !filter.keep_pair(&r1, &r2)) {
+                stats.filtered_pairs += 1;
+                continue;
+            }
+            if let Some(check) = length_check {
+                stats.unexpected_lengths += check.count_pair(&r1, &r2);
+            }
+            if let Some(binning) = binning {
+                binning.bin_read(&mut r1);
+                binning.bin_read(&mut r2);
+            }
+            check_bare_sub_title(CompressionModel::LZMAReference, &r1)?;
+            check_bare_sub_title(CompressionModel::LZMAReference, &r2)?;
+            check_no_other_letters(CompressionModel::LZMAReference, &r1)?;
+            check_no_other_letters(CompressionModel::LZMAReference, &r2)?;
+            self.sink.note_dropped_case(&r1);
+            self.sink.note_dropped_case(&r2);
+            stats.add_pair(&r1, &r2);
+            if self.sink.is_duplicate(&[&r1, &r2])? {
+                stats.duplicate_pairs += 1;
+                continue;
+            }
+
+            for read in [&r1, &r2] {
+                titles.write_title(&read.title)?;
+                self.write_nucleotides(
+                    read,
+                    &mut alignments_spool,
+                    &mut edits_spool,
+                    &mut nucleotides_spool,
+                )?;
+                qualities_spool.write_all(&read.qualities)?;
+            }
+        }
+
+        for (path, spool) in titles.into_spools() {
+            self.sink.write_compressed_spool(spool, path)?;
+        }
+        self.sink
+            .write_compressed_spool(alignments_spool, "alignments")?;
+        self.sink.write_compressed_spool(edits_spool, "edits")?;
+        self.sink
+            .write_compressed_spool(nucleotides_spool, "nucleotides")?;
+        self.sink
+            .write_compressed_spool(qualities_spool, "qualities")?;
+        self.sink.set_line_ending(reader.line_ending());
+        self.sink.set_stats(stats);
+        self.sink.write_metadata(doc! {
+            "model": CompressionModel::LZMAReference.as_str(),
+            "version": 1,
+            "level": lzma.level() as i64,
+            "titles": self.title_layout.as_str(),
+            "reference": self.reference.identity(),
+        })?;
+        self.sink.finish()?;
+
+        Ok(stats)
+    }
+}
+
+impl XZReferenceWriter<std::io::Stdout> {
+    pub fn to_stdout(reference: Reference) -> Self {
+        XZReferenceWriter::new(std::io::stdout(), reference)
+    }
+}
+
+impl XZReferenceWriter<File> {
+    pub fn create<P: AsRef<Path>>(path: &P, reference: Reference) -> Result<Self> {
+        let (file, pending) = PendingFile::create(path)?;
+        let mut writer = XZReferenceWriter::new(file, reference);
+        writer.sink.set_pending_file(pending);
+        Ok(writer)
+    }
+}
+
+// readers
+pub struct XZReferenceReader<R: Read> {
+    arc: PareArchiveDecoder<R>,
+    reference: Reference,
+}
+
+type XzBufReader = BufReader<XzDecoder<ArchiveStream>>;
+
+fn read_line(source: &mut XzBufReader, line: &mut String) -> Result<bool> {
+    line.clear();
+    if source.read_line(line)? == 0 {
+        return Ok(false);
+    }
+
+    if line.pop() != Some('\n') {
+        return Err(CompressionModelError::IncompleteRecord);
+    }
+
+    Ok(true)
+}
+
+fn parse_field<T: std::str::FromStr>(field: Option<&str>) -> Result<T> {
+    field
+        .and_then(|f| f.parse().ok())
+        .ok_or(CompressionModelError::IncompleteRecord)
+}
+
+/// Split an edit into the distance from the previous one and the base it puts there. The base is
+/// a single ascii byte, anything else is a corrupt stream.
+fn parse_edit(edit: &str) -> Result<(u32, u8)> {
+    match edit.as_bytes().split_last() {
+        Some((&base, delta)) if base.is_ascii() => {
+            Ok((parse_field(std::str::from_utf8(delta).ok())?, base))
+        }
+        _ => Err(CompressionModelError::IncompleteRecord),
+    }
+}
+
+impl<R: Read> XZReferenceReader<R> {
+    pub fn new(source: R, reference: Reference) -> Result<Self> {
+        XZReferenceReader::from_archive(PareArchiveDecoder::new(source)?, reference)
+    }
+
+    /// Read an archive that has already been opened, e.g. to look at its metadata first.
+    pub fn from_archive(arc: PareArchiveDecoder<R>, reference: Reference) -> Result<Self> {
+        Ok(XZReferenceReader { arc, reference })
+    }
+
+    fn check_magic(&mut self) -> Result<Document> {
+        let metadata = self.arc.get_metadata()?;
+        CompressionModel::LZMAReference.check_metadata(&metadata)?;
+        self.reference
+            .check_identity(metadata.get_document("reference")?)?;
+        self.arc.verify_checksum(&metadata)?;
+
+        Ok(metadata)
+    }
+}
+
+/// The decoding state of the nucleotide streams.
+struct NucleotideStreams {
+    alignments: XzBufReader,
+    edits: XzBufReader,
+    nucleotides: XzBufReader,
+    line: String,
+    alignment: Alignment,
+}
+
+impl NucleotideStreams {
+    fn read_nucleotides(&mut self, reference: &Reference, letters: &mut Vec<u8>) -> Result<()> {
+        if !read_line(&mut self.alignments, &mut self.line)? {
+            return Err(CompressionModelError::IncompleteRecord);
+        }
+
+        if self.line == "*" {
+            if !read_line(&mut self.nucleotides, &mut self.line)? {
+                return Err(CompressionModelError::IncompleteRecord);
+            }
+            letters.clear();
+            letters.extend_from_slice(self.line.as_bytes());
+            return Ok(());
+        }
+
+        let mut fields = self.line.split(' ');
+        self.alignment.contig = parse_field(fields.next())?;
+        self.alignment.position = parse_field(fields.next())?;
+        self.alignment.reverse = match fields.next() {
+            Some("+") => false,
+            Some("-") => true,
+            _ => return Err(CompressionModelError::IncompleteRecord),
+        };
+        let length: usize = parse_field(fields.next())?;
+
+        if !read_line(&mut self.edits, &mut self.line)? {
+            return Err(CompressionModelError::IncompleteRecord);
+        }
+
+        self.alignment.edits.clear();
+        let mut offset = 0;
+        for edit in self.line.split(' ').filter(|e| !e.is_empty()) {
+            let (delta, base) = parse_edit(edit)?;
+            offset += delta;
+            self.alignment.edits.push((offset, base));
+        }
+
+        reference.reconstruct(&self.alignment, length, letters)
+    }
+}
+
+/// The open streams of a reference archive.
+struct ReferencePairs<'a> {
+    reference: &'a Reference,
+    titles: Box<dyn TitleDecoder>,
+    nucleotides: NucleotideStreams,
+    qualities: XzDecoder<ArchiveStream>,
+}
+
+impl PairDecoder for ReferencePairs<'_> {
+    fn read_next(&mut self, r1: &mut FastQRead, r2: &mut FastQRead) -> Result<bool> {
+        if !self.titles.read_title(&mut r1.title)? {
+            return Ok(false);
+        }
+
+        if !self.titles.read_title(&mut r2.title)? {
+            return Err(CompressionModelError::IncompleteRecord);
+        }
+
+        for read in [r1, r2] {
+            self.nucleotides
+                .read_nucleotides(self.reference, &mut read.letters)?;
+            read.qualities.resize(read.letters.len(), 0);
+            self.qualities.read_exact(&mut read.qualities)?;
+        }
+
+        Ok(true)
+    }
+}
+
+impl<R: Read> DecoderModel for XZReferenceReader<R> {
+    fn decoder(&mut self) -> Result<Box<dyn PairDecoder + '_>> {
+        let metadata = self.check_magic()?;
+        let title_layout = TitleLayout::from_name(metadata.get_str("titles")?)?;
+
+        Ok(Box::new(ReferencePairs {
+            reference: &self.reference,
+            titles: title_layout.decoder(&mut self.arc, Codec::Xz)?,
+            nucleotides: NucleotideStreams {
+                alignments: BufReader::new(self.arc.get_xz_stream("alignments")?),
+                edits: BufReader::new(self.arc.get_xz_stream("edits")?),
+                nucleotides: BufReader::new(self.arc.get_xz_stream("nucleotides")?),
+                line: String::new(),
+                alignment: Alignment::default(),
+            },
+            qualities: self.arc.get_xz_stream("qualities")?,
+        }))
+    }
+}
+
+impl XZReferenceReader<std::io::Stdin> {
+    pub fn from_stdin(reference: Reference) -> Result<Self> {
+        XZReferenceReader::new(std::io::stdin(), reference)
+    }
+}
+
+impl XZReferenceReader<File> {
+    pub fn open<P: AsRef<Path>>(path: &P, reference: Reference) -> Result<Self> {
+        let file = File::open(path)?;
+        XZReferenceReader::new(file, reference)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::compression_models::test_utils::*;
+
+    const REFERENCE: &str = concat!(
+        ">chr1\n",
+        "ACGTTGCAACGTTACGGATCCTAGGCTAGCTAACGGTTCAGTCAGGTACCATGCATGCAAGTC\n",
+        "TTGACCGGTAACCTGAGGTCAATCGGCTTAGCATCCGATGCTAGGTCCAGTTGACGTAGCTA\n",
+    );
+
+    // R1 is on the forward strand with a mismatch, R2 is reverse complemented, the last pair
+    // does not come from the reference at all.
+    const DERIVED_FASTQ: &str = concat!(
+        "@read1/1\n",
+        "GGATCCTAGGATAGCTAACGGTTCAGTCAGGTACC\n",
+        "+\n",
+        "IIIIIIIIIIIIIIIIIIIIIIIIIIIIIIIIIII\n",
+        "@read1/2\n",
+        "GGATGCTAAGCCGATTGACCTCAGGTTACCGGT\n",
+        "+\n",
+        "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF\n",
+        "@read2/1\n",
+        "AAAAAAAAAACCCCCCCCCCGGGGGGGGGGTTTTT\n",
+        "+\n",
+        "#########################!!!!!!!!!!\n",
+        "@read2/2\n",
+        "ACGTNNNN\n",
+        "+\n",
+        "ABCDEFGH\n",
+    );
+
+    #[test]
+    fn test_reference_round_trip() -> Result<()> {
+        let mut archive: Vec<u8> = Vec::new();
+        XZReferenceWriter::new(&mut archive, Reference::from_fasta(REFERENCE.as_bytes())?)
+            .compress(&mut interleaved_reader(DERIVED_FASTQ))?;
+
+        let mut arc = PareArchiveDecoder::new(archive.as_slice())?;
+        let mut alignments = String::new();
+        arc.get_xz_stream("alignments")?
+            .read_to_string(&mut alignments)?;
+        assert_eq!(2, alignments.lines().filter(|l| *l != "*").count());
+
+        let (mut writer, output) = interleaved_writer();
+        XZReferenceReader::new(
+            archive.as_slice(),
+            Reference::from_fasta(REFERENCE.as_bytes())?,
+        )?
+        .decompress(&mut writer)?;
+        drop(writer);
+        assert_eq!(DERIVED_FASTQ, output.text());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_edit() -> Result<()> {
+        assert_eq!((12, b'A'), parse_edit("12A")?);
+        assert_eq!((0, b'N'), parse_edit("0N")?);
+        for edit in ["", "A", "12é", "1é2A"] {
+            assert!(matches!(
+                parse_edit(edit).unwrap_err(),
+                CompressionModelError::IncompleteRecord
+            ));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_wrong_reference() -> Result<()> {
+        let mut archive: Vec<u8> = Vec::new();
+        XZReferenceWriter::new(&mut archive, Reference::from_fasta(REFERENCE.as_bytes())?)
+            .compress(&mut interleaved_reader(DERIVED_FASTQ))?;
+
+        let other = Reference::from_fasta(">chr1\nACGTACGTACGTACGTACGT\n".as_bytes())?;
+        let actual = XZReferenceReader::new(archive.as_slice(), other)?
+            .decompress(&mut interleaved_writer().0);
+        assert!(matches!(
+            actual.unwrap_err(),
+            CompressionModelError::ReferenceMismatch
+        ));
+
+        Ok(())
+    }
+}
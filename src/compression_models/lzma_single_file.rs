@@ -3,66 +3,242 @@ use std::io::{BufRead, BufReader, Read, Write};
 use std::path::Path;
 
 use bson::doc;
+use clap::ValueEnum;
 use xz2::read::XzDecoder;
-use xz2::write::XzEncoder;
 
+use crate::compression_models::nucleotide_pack::{read_exceptions, write_exceptions};
 use crate::compression_models::*;
-use crate::seq_files::fastq::{FastQRead, PairedFastQReader, PairedFastQWriter};
+use crate::seq_files::fastq::{FastQRead, PairedFastQReader};
+
+/// How the titles and sequences of a record are told apart. Qualities need neither, there are as
+/// many as there are bases.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Framing {
+    /// Each field is followed by a 0xFF byte, which no title or sequence can contain.
+    #[default]
+    #[value(name = "delimiter-ff")]
+    DelimiterFF,
+    /// Each field is preceded by its length as an LEB128 varint, any byte can be stored.
+    LengthPrefixed,
+}
+
+impl Framing {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Framing::DelimiterFF => "delimiter_ff",
+            Framing::LengthPrefixed => "length_prefixed",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "delimiter_ff" => Ok(Framing::DelimiterFF),
+            "length_prefixed" => Ok(Framing::LengthPrefixed),
+            _ => Err(CompressionModelError::UnknownFraming {
+                name: name.to_string(),
+            }),
+        }
+    }
+}
+
+fn write_field<T: Write>(sink: &mut T, framing: Framing, field: &[u8]) -> Result<()> {
+    match framing {
+        Framing::DelimiterFF => {
+            sink.write_all(field)?;
+            sink.write_all(b"\xFF")?;
+        }
+        Framing::LengthPrefixed => {
+            let mut length = field.len() as u64;
+            loop {
+                let low = (length & 0x7f) as u8;
+                length >>= 7;
+                if length == 0 {
+                    sink.write_all(&[low])?;
+                    break;
+                }
+                sink.write_all(&[low | 0x80])?;
+            }
+            sink.write_all(field)?;
+        }
+    }
+    Ok(())
+}
+
+/// Read the next field into `field`, false at the end of the stream.
+fn read_field<T: BufRead>(source: &mut T, framing: Framing, field: &mut Vec<u8>) -> Result<bool> {
+    field.clear();
+    match framing {
+        Framing::DelimiterFF => {
+            if source.read_until(b'\xFF', field)? == 0 {
+                return Ok(false);
+            }
+            if field.pop() != Some(b'\xFF') {
+                return Err(CompressionModelError::IncompleteRecord);
+            }
+        }
+        Framing::LengthPrefixed => {
+            let mut length: u64 = 0;
+            let mut shift = 0;
+            loop {
+                let mut byte = [0u8];
+                if source.read(&mut byte)? == 0 {
+                    if shift == 0 {
+                        return Ok(false);
+                    }
+                    return Err(CompressionModelError::IncompleteRecord);
+                }
+                if shift > 63 {
+                    return Err(CompressionModelError::IncompleteRecord);
+                }
+                length |= u64::from(byte[0] & 0x7f) << shift;
+                shift += 7;
+                if byte[0] & 0x80 == 0 {
+                    break;
+                }
+            }
+            source
+                .take(length)
+                .read_to_end(field)
+                .map_err(CompressionModelError::from)?;
+            if field.len() as u64 != length {
+                return Err(CompressionModelError::IncompleteRecord);
+            }
+        }
+    }
+    Ok(true)
+}
 
 pub struct XZSingleFileWriter<W: Write> {
     sink: PareArchiveEncoder<W>,
+    framing: Framing,
+    drop_qualities: bool,
 }
 
 impl<W: Write> XZSingleFileWriter<W> {
     pub fn new(sink: W) -> Self {
         XZSingleFileWriter {
             sink: PareArchiveEncoder::<W>::new(sink),
+            framing: Framing::default(),
+            drop_qualities: false,
         }
     }
 
-    fn write_string(
-        &mut self,
-        spool: &mut XzEncoder<SpooledTempFile>,
-        record: &String,
-    ) -> Result<()> {
-        self.write_u8(spool, record.as_bytes())?;
-        Ok(())
+    /// Length prefix the fields instead of ending them with 0xFF.
+    pub fn with_framing(mut self, framing: Framing) -> Self {
+        self.framing = framing;
+        self
     }
 
-    fn write_u8(&mut self, spool: &mut XzEncoder<SpooledTempFile>, record: &[u8]) -> Result<()> {
-        spool.write(record)?;
-        spool.write(b"\xFF")?;
+    /// The settings shared by every model, see `EncoderOptions`.
+    pub fn with_options(mut self, options: EncoderOptions) -> Self {
+        self.sink.set_options(options);
+        self
+    }
+
+    /// Don't store the qualities at all, this is lossy. The reads are decoded with
+    /// `PLACEHOLDER_PHRED` for every base.
+    pub fn with_dropped_qualities(mut self, drop_qualities: bool) -> Self {
+        if drop_qualities {
+            self.sink.add_lossy("qualities", "dropped");
+        }
+        self.drop_qualities = drop_qualities;
+        self
+    }
 
+    fn write_string(&mut self, spool: &mut CompressedSpool, record: &String) -> Result<()> {
+        self.write_u8(spool, record.as_bytes())?;
         Ok(())
     }
+
+    fn write_u8(&mut self, spool: &mut CompressedSpool, record: &[u8]) -> Result<()> {
+        write_field(spool, self.framing, record)
+    }
 }
 
 impl<W: Write> EncoderModel for XZSingleFileWriter<W> {
-    fn compress(&mut self, reader: &mut Box<dyn PairedFastQReader>) -> Result<()> {
+    fn compress(&mut self, reader: &mut Box<dyn PairedFastQReader>) -> Result<CompressionStats> {
+        let mut stats = CompressionStats::default();
         let mut r1 = FastQRead::default();
         let mut r2 = FastQRead::default();
 
-        let mut spool = XzEncoder::new(SpooledTempFile::new(4096), 9);
-
-        self.sink.write_metadata(doc! {
-            "model": CompressionModel::LZMASingle.as_str(),
-            "version": 1,
-        })?;
+        let lzma = self.sink.options().lzma;
+        let filter = self.sink.options().quality_filter;
+        let length_check = self.sink.options().length_check;
+        let binning = self.sink.options().quality_binning;
+        let mut spool = new_xz_spool(lzma, self.sink.spool_options());
+        // kept out of the data stream so archives from before they were stored read the same
+        let mut sub_titles = new_xz_spool(lzma, self.sink.spool_options());
+        let mut sub_title = Vec::new();
+        let mut plus_lines = PlusLineCheck::default();
+        let mut case = new_xz_spool(lzma, self.sink.spool_options());
+        // only stored when a read has a `.` or `-`
+        let mut other_letters = new_xz_spool(lzma, self.sink.spool_options());
+        let mut has_other_letters = false;
 
         loop {
             if !reader.read_next(&mut r1, &mut r2)? {
                 break;
             }
+            if filter.is_some_and(|filter| !filter.keep_pair(&r1, &r2)) {
+                stats.filtered_pairs += 1;
+                continue;
+            }
+            if let Some(check) = length_check {
+                stats.unexpected_lengths += check.count_pair(&r1, &r2);
+            }
+            if let Some(binning) = binning {
+                binning.bin_read(&mut r1);
+                binning.bin_read(&mut r2);
+            }
+            stats.add_pair(&r1, &r2);
+            if self.sink.is_duplicate(&[&r1, &r2])? {
+                stats.duplicate_pairs += 1;
+                continue;
+            }
 
             self.write_string(&mut spool, &r1.title)?;
             self.write_string(&mut spool, &r2.title)?;
             self.write_u8(&mut spool, &r1.letters)?;
             self.write_u8(&mut spool, &r2.letters)?;
-            spool.write(&r1.qualities)?;
-            spool.write(&r2.qualities)?;
+            if !self.drop_qualities {
+                spool.write_all(&r1.qualities)?;
+                spool.write_all(&r2.qualities)?;
+            }
+
+            for read in [&r1, &r2] {
+                plus_lines.check(read);
+                sub_title.clear();
+                encode_sub_title(read, &mut sub_title);
+                self.write_u8(&mut sub_titles, &sub_title)?;
+                write_case_runs(&mut case, &read.case_mask)?;
+                write_exceptions(&mut other_letters, &read.other_letters)?;
+                has_other_letters |= !read.other_letters.is_empty();
+            }
         }
-        self.sink.write_stream(&mut spool.finish()?, "data")?;
-        Ok(())
+        self.sink.write_compressed_spool(spool, "data")?;
+        let plus_lines = plus_lines.plus_lines();
+        if plus_lines == PlusLines::Stored {
+            self.sink.write_compressed_spool(sub_titles, "sub_titles")?;
+        }
+        self.sink.write_compressed_spool(case, "case")?;
+        if has_other_letters {
+            self.sink
+                .write_compressed_spool(other_letters, "other_letters")?;
+        }
+        self.sink.set_line_ending(reader.line_ending());
+        self.sink.set_stats(stats);
+        self.sink.write_metadata(doc! {
+            "model": CompressionModel::LZMASingle.as_str(),
+            "version": 1,
+            "level": lzma.level() as i64,
+            "sub_titles": plus_lines == PlusLines::Stored,
+            "plus_lines": plus_lines.as_str(),
+            "case": true,
+            "other_letters": has_other_letters,
+            "framing": self.framing.as_str(),
+        })?;
+        self.sink.finish()?;
+        Ok(stats)
     }
 }
 
@@ -74,113 +250,195 @@ impl XZSingleFileWriter<std::io::Stdout> {
 
 impl XZSingleFileWriter<File> {
     pub fn create<P: AsRef<Path>>(path: &P) -> Result<Self> {
-        let file = File::create(path)?;
-        Ok(XZSingleFileWriter::new(file))
+        let (file, pending) = PendingFile::create(path)?;
+        let mut writer = XZSingleFileWriter::new(file);
+        writer.sink.set_pending_file(pending);
+        Ok(writer)
     }
 }
 
 // readers
 pub struct XZSingleFileReader<R: Read> {
     arc: PareArchiveDecoder<R>,
-    decoder: BufReader<XzDecoder<File>>,
+    decoder: BufReader<XzDecoder<ArchiveStream>>,
+    /// Only stored when the `+` lines aren't all bare or all repeats of the title.
+    sub_titles: Option<BufReader<XzDecoder<ArchiveStream>>>,
+    sub_title: Vec<u8>,
+    plus_lines: PlusLines,
+    /// Archives written before the case was kept don't have this stream.
+    case: Option<BufReader<XzDecoder<ArchiveStream>>>,
+    case_line: Vec<u8>,
+    /// Only stored when a read has a `.` or `-`.
+    other_letters: Option<BufReader<XzDecoder<ArchiveStream>>>,
+    framing: Framing,
+    /// Archives made without qualities have none between the fields.
+    qualities: bool,
+    /// The reads decoded so far, to say where the stream went wrong.
+    reads: u64,
 }
 
 impl<R: Read> XZSingleFileReader<R> {
     pub fn new(source: R) -> Result<Self> {
-        let mut arc = PareArchiveDecoder::new(source)?;
+        XZSingleFileReader::from_archive(PareArchiveDecoder::new(source)?)
+    }
 
+    /// Read an archive that has already been opened, e.g. to look at its metadata first.
+    pub fn from_archive(mut arc: PareArchiveDecoder<R>) -> Result<Self> {
         let metadata = arc.get_metadata()?;
-        if metadata.get_str("model")? != CompressionModel::LZMASingle.as_str()
-            || metadata.get_i64("version")? != 1
-        {
-            return Err(CompressionModelError::OpenedWithWrongModel);
-        }
+        CompressionModel::LZMASingle.check_metadata(&metadata)?;
+        arc.verify_checksum(&metadata)?;
 
         let source_stream = arc.get_stream("data")?;
+        // archives from before the framing could be chosen all end fields with 0xFF
+        let framing = match metadata.get_str("framing") {
+            Ok(name) => Framing::from_name(name)?,
+            Err(_) => Framing::DelimiterFF,
+        };
+        let plus_lines = PlusLines::from_metadata(&metadata)?;
+        let sub_titles = match plus_lines {
+            PlusLines::Stored => Some(BufReader::new(arc.get_xz_stream("sub_titles")?)),
+            _ => None,
+        };
+        let case = match metadata.get_bool("case") {
+            Ok(true) => Some(BufReader::new(arc.get_xz_stream("case")?)),
+            _ => None,
+        };
+        let other_letters = match metadata.get_bool("other_letters") {
+            Ok(true) => Some(BufReader::new(arc.get_xz_stream("other_letters")?)),
+            _ => None,
+        };
 
         Ok(XZSingleFileReader {
             arc: arc,
             decoder: BufReader::new(XzDecoder::new(source_stream)),
+            sub_titles,
+            sub_title: Vec::new(),
+            plus_lines,
+            case,
+            case_line: Vec::new(),
+            other_letters,
+            framing,
+            qualities: !qualities_dropped(&metadata),
+            reads: 0,
         })
     }
 
     fn read_string(&mut self, record: &mut String) -> Result<bool> {
-        let mut buffer = vec![];
+        // reuse the allocation of the last title
+        let mut buffer = std::mem::take(record).into_bytes();
 
         let ret = self.read_u8(&mut buffer)?;
-        record.clear();
         *record = String::from_utf8(buffer)?;
         Ok(ret)
     }
 
     fn read_u8(&mut self, record: &mut Vec<u8>) -> Result<bool> {
-        record.clear();
-        if self.decoder.read_until(b'\xFF', record)? == 0 {
-            return Ok(false);
-        }
+        read_field(&mut self.decoder, self.framing, record)
+    }
+}
 
-        match record.pop() {
-            Some(b'\xFF') => {}
-            _ => {
-                return Err(CompressionModelError::IncompleteRecord);
-            }
+/// Read `len` qualities into `qualities`, copying them straight out of the buffer of `source` so
+/// the capacity left from the last read is reused without being zeroed first.
+fn read_qualities<B: BufRead>(source: &mut B, len: usize, qualities: &mut Vec<u8>) -> Result<()> {
+    qualities.clear();
+    qualities.reserve(len);
+    while qualities.len() < len {
+        let available = source.fill_buf()?;
+        if available.is_empty() {
+            return Err(CompressionModelError::IncompleteRecord);
         }
+        let taken = available.len().min(len - qualities.len());
+        qualities.extend_from_slice(&available[..taken]);
+        source.consume(taken);
+    }
+    Ok(())
+}
 
-        Ok(true)
+impl<R: Read> FieldDecoder for XZSingleFileReader<R> {
+    fn read_first_field(&mut self, r1: &mut FastQRead) -> Result<bool> {
+        self.read_string(&mut r1.title)
     }
 
-    fn read_next(&mut self, r1: &mut FastQRead, r2: &mut FastQRead) -> Result<bool> {
-        if !self.read_string(&mut r1.title)? {
-            return Ok(false);
-        }
+    fn read_rest(&mut self, r1: &mut FastQRead, r2: &mut FastQRead) -> Result<()> {
+        required(self.read_string(&mut r2.title)?)?;
+        required(self.read_u8(&mut r1.letters)?)?;
+        required(self.read_u8(&mut r2.letters)?)?;
 
-        if !self.read_string(&mut r2.title)? {
-            return Err(CompressionModelError::IncompleteRecord);
+        for read in [&mut *r1, &mut *r2] {
+            if !self.qualities {
+                fill_qualities(read);
+                continue;
+            }
+            read_qualities(&mut self.decoder, read.letters.len(), &mut read.qualities)?;
         }
 
-        if !self.read_u8(&mut r1.letters)? {
-            return Err(CompressionModelError::IncompleteRecord);
+        // once a field is read to the wrong length the rest of the stream is out of step, which
+        // shows as letters or qualities that no read can have
+        for read in [&*r1, &*r2] {
+            self.reads += 1;
+            if read.validate().is_err() {
+                return Err(CompressionModelError::CorruptStream {
+                    stream: "data".to_string(),
+                    read: self.reads,
+                });
+            }
         }
 
-        if !self.read_u8(&mut r2.letters)? {
-            return Err(CompressionModelError::IncompleteRecord);
-        }
+        for read in [r1, r2] {
+            match &mut self.sub_titles {
+                Some(sub_titles) => {
+                    required(read_field(sub_titles, self.framing, &mut self.sub_title)?)?;
+                    decode_sub_title(&self.sub_title, read)?;
+                }
+                None => self.plus_lines.fill_sub_title(read),
+            }
 
-        r1.qualities.clear();
-        r1.qualities.resize(r1.letters.len(), 0);
+            match &mut self.case {
+                Some(case) => {
+                    self.case_line.clear();
+                    if case.read_until(b'\n', &mut self.case_line)? == 0
+                        || self.case_line.pop() != Some(b'\n')
+                    {
+                        return Err(CompressionModelError::IncompleteRecord);
+                    }
+                    parse_case_runs(&self.case_line, read.letters.len(), &mut read.case_mask)?;
+                }
+                None => read.case_mask.clear(),
+            }
 
-        match self.decoder.read_exact(&mut r1.qualities[..]) {
-            Ok(()) => {}
-            _ => {
-                return Err(CompressionModelError::IncompleteRecord);
+            match &mut self.other_letters {
+                Some(other_letters) => read_exceptions(other_letters, &mut read.other_letters)?,
+                None => read.other_letters.clear(),
             }
         }
 
-        r2.qualities.clear();
-        r2.qualities.resize(r2.letters.len(), 0);
-        match self.decoder.read_exact(&mut r2.qualities[..]) {
-            Ok(()) => {}
-            _ => {
-                return Err(CompressionModelError::IncompleteRecord);
+        Ok(())
+    }
+
+    fn check_ended(&mut self) -> Result<()> {
+        let reads = self.reads;
+        for (stream, source) in [
+            ("sub_titles", &mut self.sub_titles),
+            ("case", &mut self.case),
+            ("other_letters", &mut self.other_letters),
+        ] {
+            if let Some(source) = source {
+                check_stream_ended(source, stream, reads)?;
             }
         }
+        Ok(())
+    }
+}
 
-        Ok(true)
+impl<R: Read> PairDecoder for XZSingleFileReader<R> {
+    fn read_next(&mut self, r1: &mut FastQRead, r2: &mut FastQRead) -> Result<bool> {
+        read_fields(self, r1, r2)
     }
 }
 
 impl<R: Read> DecoderModel for XZSingleFileReader<R> {
-    fn decompress(&mut self, writer: &mut Box<dyn PairedFastQWriter>) -> Result<()> {
-        let mut r1 = FastQRead::default();
-        let mut r2 = FastQRead::default();
-
-        loop {
-            if !self.read_next(&mut r1, &mut r2)? {
-                break;
-            }
-            writer.write_next(&r1, &r2)?;
-        }
-        Ok(())
+    fn decoder(&mut self) -> Result<Box<dyn PairDecoder + '_>> {
+        Ok(Box::new(self))
     }
 }
 
@@ -196,3 +454,94 @@ impl XZSingleFileReader<File> {
         Ok(XZSingleFileReader::new(file)?)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::compression_models::test_utils::*;
+
+    #[test]
+    fn test_length_prefixed_fields() -> Result<()> {
+        // titles are UTF-8 and can't hold 0xFF themselves, the framing can take any byte
+        let long = vec![b'a'; 300];
+        let fields: [&[u8]; 4] = [b"title\xFFwith\xFFbytes", b"", &long, b"\xFF"];
+
+        let mut framed: Vec<u8> = Vec::new();
+        for field in fields {
+            write_field(&mut framed, Framing::LengthPrefixed, field)?;
+        }
+        let mut source = framed.as_slice();
+        let mut field = Vec::new();
+        for expected in fields {
+            assert!(read_field(
+                &mut source,
+                Framing::LengthPrefixed,
+                &mut field
+            )?);
+            assert_eq!(expected, field.as_slice());
+        }
+        assert!(!read_field(
+            &mut source,
+            Framing::LengthPrefixed,
+            &mut field
+        )?);
+
+        // the delimiter splits the field at its 0xFF instead
+        let mut framed: Vec<u8> = Vec::new();
+        write_field(&mut framed, Framing::DelimiterFF, fields[0])?;
+        assert!(read_field(
+            &mut framed.as_slice(),
+            Framing::DelimiterFF,
+            &mut field
+        )?);
+        assert_eq!(b"title", field.as_slice());
+
+        // a length longer than what's left is a truncated record
+        assert!(matches!(
+            read_field(&mut &b"\x05abc"[..], Framing::LengthPrefixed, &mut field),
+            Err(CompressionModelError::IncompleteRecord)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_length_prefixed_round_trip() -> Result<()> {
+        let mut archive: Vec<u8> = Vec::new();
+        XZSingleFileWriter::new(&mut archive)
+            .with_framing(Framing::LengthPrefixed)
+            .compress(&mut interleaved_reader(INTERLEAVED_FASTQ))?;
+
+        let mut decoder = PareArchiveDecoder::new(archive.as_slice())?;
+        assert_eq!(
+            "length_prefixed",
+            decoder.get_metadata()?.get_str("framing")?
+        );
+
+        let (mut writer, output) = interleaved_writer();
+        XZSingleFileReader::from_archive(decoder)?.decompress(&mut writer)?;
+        drop(writer);
+        assert_eq!(INTERLEAVED_FASTQ, output.text());
+        Ok(())
+    }
+
+    fn compress_text(fastq: &str) -> Result<Vec<u8>> {
+        let mut archive: Vec<u8> = Vec::new();
+        XZSingleFileWriter::new(&mut archive)
+            .with_options(EncoderOptions::new().with_level(0))
+            .compress(&mut string_reader(fastq))?;
+        Ok(archive)
+    }
+
+    #[test]
+    fn test_varying_lengths_round_trip() -> Result<()> {
+        let fastq = SyntheticFastq::default().interleaved(2_000);
+        let archive = compress_text(&fastq)?;
+
+        let (mut writer, output) = interleaved_writer();
+        XZSingleFileReader::new(archive.as_slice())?.decompress(&mut writer)?;
+        drop(writer);
+        assert!(fastq == output.text());
+        Ok(())
+    }
+}
@@ -1,45 +1,135 @@
+pub mod checksum;
+pub mod config;
+pub mod dedup;
+pub mod length_check;
+pub mod lzma_block;
+pub mod lzma_columnar;
 pub mod lzma_multi_stream;
+pub mod lzma_reference;
 pub mod lzma_single_file;
+pub mod nucleotide_pack;
+pub mod quality_binning;
+pub mod quality_filter;
+pub mod read_order;
+pub mod reference;
+pub mod samples;
+#[cfg(test)]
+pub(crate) mod test_utils;
+pub mod title_encoding;
+pub mod zstd_single_file;
 
-use std::fs;
-use std::fs::File;
-use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, Cursor, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::string::FromUtf8Error;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::sync::Arc;
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
 
+use brotli::{CompressorWriter, Decompressor};
 use bson::Document;
-use bson::{de, document, ser};
+use bson::{de, doc, document, ser};
+use bzip2::read::MultiBzDecoder;
+use bzip2::write::BzEncoder;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
 use tar::{Archive, Builder, Header};
-use tempfile::{tempdir, SpooledTempFile, TempDir};
+use tempfile::{tempdir_in, SpooledTempFile, TempDir};
 use thiserror::Error;
 use xz2::read::XzDecoder;
+use xz2::stream::{Check, Filters, Stream};
 use xz2::write::XzEncoder;
 
-use crate::seq_files::fastq::{FastQFileError, PairedFastQReader, PairedFastQWriter};
+use crate::compression_models::checksum::{hash_stream, ChecksumAlgorithm};
+use crate::compression_models::dedup::{Dedup, Duplicates, RestoredDuplicates};
+use crate::compression_models::length_check::LengthCheck;
+use crate::compression_models::lzma_block::XZBlockReader;
+use crate::compression_models::lzma_columnar::XZColumnarReader;
+use crate::compression_models::lzma_multi_stream::XZMultiStreamReader;
+use crate::compression_models::lzma_reference::XZReferenceReader;
+use crate::compression_models::lzma_single_file::XZSingleFileReader;
+use crate::compression_models::quality_binning::{QualityBinning, STORED_QUALITY_SHIFT};
+use crate::compression_models::quality_filter::QualityFilter;
+use crate::compression_models::read_order::{InputOrder, RestoredOrder};
+use crate::compression_models::reference::Reference;
+use crate::compression_models::samples::manifest_samples;
+use crate::compression_models::zstd_single_file::ZstdSingleFileReader;
+use crate::seq_files::fastq::{
+    Alphabet, CountingFastQWriter, FastQFileError, FastQRead, InvalidBasePolicy, LineEnding,
+    PairedFastQReader, PairedFastQWriter, PhredOffset,
+};
+use crate::seq_files::peek::PeekReader;
 
 type Result<T> = std::result::Result<T, CompressionModelError>;
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
-enum CompressionModel {
+pub enum CompressionModel {
     LZMASingle,
     LZMAMulti,
+    LZMAReference,
+    LZMABlock,
+    LZMAColumnar,
+    ZstdSingle,
 }
 
 impl CompressionModel {
-    fn as_str(&self) -> &'static str {
+    pub fn as_str(&self) -> &'static str {
         match self {
             CompressionModel::LZMASingle => "lzma_single_stream",
             CompressionModel::LZMAMulti => "lzma_multi_stream",
+            CompressionModel::LZMAReference => "lzma_reference",
+            CompressionModel::LZMABlock => "lzma_block",
+            CompressionModel::LZMAColumnar => "lzma_columnar",
+            CompressionModel::ZstdSingle => "zstd_single_stream",
         }
     }
+
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "lzma_single_stream" => Ok(CompressionModel::LZMASingle),
+            "lzma_multi_stream" => Ok(CompressionModel::LZMAMulti),
+            "lzma_reference" => Ok(CompressionModel::LZMAReference),
+            "lzma_block" => Ok(CompressionModel::LZMABlock),
+            "lzma_columnar" => Ok(CompressionModel::LZMAColumnar),
+            "zstd_single_stream" => Ok(CompressionModel::ZstdSingle),
+            _ => Err(CompressionModelError::OpenedWithWrongModel {
+                model: name.to_string(),
+            }),
+        }
+    }
+
+    /// Make sure an archive's metadata was written by this model.
+    fn check_metadata(&self, metadata: &Document) -> Result<()> {
+        let actual = metadata.get_str("model")?;
+        if actual != self.as_str() {
+            return Err(CompressionModelError::ModelMismatch {
+                requested: self.as_str().to_string(),
+                actual: actual.to_string(),
+            });
+        }
+
+        let version = metadata.get_i32("version")?;
+        if version != 1 {
+            return Err(CompressionModelError::OpenedWithWrongModel {
+                model: format!("{} version {}", actual, version),
+            });
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Error, Debug)]
 pub enum CompressionModelError {
     #[error("IO error while reading fastq file")]
-    IO {
-        #[from]
-        source: std::io::Error,
-    },
+    IO { source: std::io::Error },
     #[error("bad value found file likely corrupted")]
     EncodingError {
         #[from]
@@ -72,93 +162,4025 @@ pub enum CompressionModelError {
 
     #[error("EOF caused Incomplete record")]
     IncompleteRecord,
+    #[error(
+        "The {stream} stream is corrupt at read {read}, the bases and qualities no longer line up"
+    )]
+    CorruptStream { stream: String, read: u64 },
+    #[error("The {model} model drops the text after +, compress with -m lzma-multi or -m lzma to keep it")]
+    SubTitleNotStored { model: String },
+    #[error("The {model} model can't store . or - in a sequence, compress with -m lzma-multi or -m lzma to keep them")]
+    OtherLettersNotStored { model: String },
+    #[error("The {model} model always stores the qualities, compress with -m lzma-multi or -m lzma to drop them")]
+    QualitiesRequired { model: String },
+    #[error("The {model} model can't compress with a dictionary, compress with zstd_single_stream to train one")]
+    DictionaryNotSupported { model: String },
+    #[error("Can't sort the reads, {reason}")]
+    CantSort { reason: String },
+    #[error("Can't deduplicate the reads, {reason}")]
+    CantDedup { reason: String },
     #[error("Could not find the expected version string")]
     MissingVersion,
-    #[error("The wrong model was used to open the file")]
-    OpenedWithWrongModel,
+    #[error("The input is {kind}, not a pare archive")]
+    NotAnArchive { kind: String },
+    #[error("The archive has no metadata, it was not written by pare or is truncated")]
+    MissingMetadata,
+    #[error("The archive was written with the {model} model, which this pare can't read")]
+    OpenedWithWrongModel { model: String },
+    #[error("Asked for the {requested} model but the archive was written with {actual}")]
+    ModelMismatch { requested: String, actual: String },
+    #[error("Unknown checksum algorithm {name}")]
+    UnknownChecksumAlgorithm { name: String },
+    #[error(
+        "The {algorithm} checksum of the archive content does not match, file likely corrupted"
+    )]
+    ContentChecksumMismatch { algorithm: String },
+    #[error("The {path} stream does not match its CRC32, file likely corrupted")]
+    ChecksumMismatch { path: String },
+    #[error("Unknown codec {name}")]
+    UnknownCodec { name: String },
+    #[error("A {codec} stream can't be restarted for an index, use xz or bzip2 with --index")]
+    CodecCantRestart { codec: String },
+    #[error("Unknown alphabet {name}")]
+    UnknownAlphabet { name: String },
+    #[error("Unknown line ending {name}")]
+    UnknownLineEnding { name: String },
+    #[error("Unknown quality offset {name}")]
+    UnknownPhredOffset { name: String },
+    #[error("Unknown framing {name}")]
+    UnknownFraming { name: String },
+    #[error("Unknown quality layout {name}")]
+    UnknownQualityLayout { name: String },
+    #[error("Unknown title layout {name}")]
+    UnknownTitleLayout { name: String },
+    #[error("The archive doesn't keep the case of the bases, it can't be preserved")]
+    CaseNotKept,
+    #[error("Unknown plus line layout {name}")]
+    UnknownPlusLines { name: String },
+    #[error("The {field} in the metadata is {value}, file likely corrupted")]
+    InvalidMetadata { field: String, value: i64 },
+    #[error("The archive has no stream named {path}")]
+    MissingStream { path: String },
+    #[error("The reference is not a FASTA file")]
+    InvalidReference,
+    #[error("The reference does not match the one the archive was compressed against")]
+    ReferenceMismatch,
+    #[error("The reference model needs a reference, pass one with --reference")]
+    MissingReference,
+    #[error("The archive has {actual} reads to a fragment but {expected} were asked for")]
+    ReadsPerFragmentMismatch { expected: usize, actual: usize },
+    #[error(
+        "The {model} model only compresses read pairs, use lzma-multi for more than two files"
+    )]
+    PairsOnlyModel { model: String },
+    #[error("There are no input files, a fragment needs at least one read")]
+    NoInputFiles,
+    #[error("The archive has no read pair {index}")]
+    PairOutOfRange { index: u64 },
+    #[error("Sample names can't be empty nor a path, got {name}")]
+    InvalidSampleName { name: String },
+    #[error("The archive already has a sample named {name}")]
+    DuplicateSample { name: String },
+    #[error("The archive has no sample named {name}")]
+    UnknownSample { name: String },
+    #[error("The archive holds the samples {samples}, choose one with --sample")]
+    SampleRequired { samples: String },
+    #[error("Samples can only be appended to an archive of samples")]
+    NotMultiSample,
+    #[error("The {path} stream was written before the metadata, which has to come first")]
+    MetadataNotFirst { path: String },
+    #[error("Invalid compression config: {reason}")]
+    BadConfig { reason: String },
+    #[error("A compression config is only used by the lzma_multi_stream model, not {model}")]
+    ConfigNotSupported { model: String },
+    #[error("Can't write temporary files to {path}: {source}, pick a directory that can be written to with --temp-dir")]
+    TempDirNotWritable {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("Decoding failed at read pair {pair}: {source}")]
+    DecodeFailed {
+        pair: u64,
+        source: Box<CompressionModelError>,
+    },
 }
 
-pub struct PareArchiveEncoder<W: Write> {
-    arch: Builder<W>,
+impl From<std::io::Error> for CompressionModelError {
+    fn from(source: std::io::Error) -> Self {
+        // a spool that couldn't move to disk passes its error up through io::Write
+        match source
+            .get_ref()
+            .is_some_and(|inner| inner.is::<CompressionModelError>())
+        {
+            true => *source
+                .into_inner()
+                .and_then(|inner| inner.downcast().ok())
+                .expect("checked to be a CompressionModelError"),
+            false => CompressionModelError::IO { source },
+        }
+    }
 }
 
-impl<W: Write> PareArchiveEncoder<W> {
-    pub fn new(sink: W) -> Self {
-        PareArchiveEncoder {
-            arch: Builder::new(sink),
+/// The codec of the stream at `path` in `metadata`, archives from before the codec could be chosen
+/// and the streams not listed are all xz.
+pub(crate) fn stream_codec(metadata: &Document, path: &str) -> Result<Codec> {
+    match metadata
+        .get_document("streams")
+        .and_then(|streams| streams.get_str(path))
+    {
+        Ok(name) => Codec::from_name(name),
+        Err(_) => Ok(Codec::Xz),
+    }
+}
+
+/// The modification time stamped on every entry of an archive. `Zero` makes the output
+/// reproducible, `Now` and `Epoch` record when (or claim when) the archive was made.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ArchiveTimestamp {
+    #[default]
+    Zero,
+    Now,
+    Epoch(u64),
+}
+
+impl ArchiveTimestamp {
+    fn mtime(&self) -> u64 {
+        match self {
+            ArchiveTimestamp::Zero => 0,
+            ArchiveTimestamp::Now => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            ArchiveTimestamp::Epoch(secs) => *secs,
         }
     }
+}
 
-    pub fn write_metadata(&mut self, metadata: Document) -> Result<()> {
-        let mut buffer: Vec<u8> = Vec::new();
-        metadata.to_writer(&mut buffer)?;
+impl fmt::Display for ArchiveTimestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArchiveTimestamp::Zero => write!(f, "zero"),
+            ArchiveTimestamp::Now => write!(f, "now"),
+            ArchiveTimestamp::Epoch(secs) => write!(f, "{}", secs),
+        }
+    }
+}
 
-        self.write_stream(&mut Cursor::new(buffer), "metadata")?;
-        Ok(())
+impl FromStr for ArchiveTimestamp {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "zero" => Ok(ArchiveTimestamp::Zero),
+            "now" => Ok(ArchiveTimestamp::Now),
+            _ => s
+                .parse::<u64>()
+                .map(ArchiveTimestamp::Epoch)
+                .map_err(|_| format!("expected now, zero or seconds since the epoch, got {}", s)),
+        }
     }
+}
 
-    pub fn write_xz_spool(&mut self, spool: XzEncoder<SpooledTempFile>, path: &str) -> Result<()> {
-        let mut finished_spool = spool.finish()?;
-        self.write_stream(&mut finished_spool, &path)?;
+/// The xz preset used when none is given.
+pub const DEFAULT_XZ_LEVEL: u32 = 9;
+/// The highest xz preset, they start at 0.
+pub const MAX_XZ_LEVEL: u32 = 9;
 
-        Ok(())
+/// How much of a spool is kept in memory before it is moved to a temporary file, when none is
+/// given.
+pub const DEFAULT_SPOOL_MEMORY: usize = 64 * 1024 * 1024;
+
+/// The smallest dictionary xz encodes with.
+pub const MIN_LZMA_DICT_SIZE: u32 = 4096;
+/// The largest dictionary xz encodes with.
+pub const MAX_LZMA_DICT_SIZE: u32 = 1536 * 1024 * 1024;
+
+/// How xz streams are encoded, a preset and optionally a dictionary size of its own. None of it is
+/// recorded in the archive, an xz stream has everything needed to decode it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LzmaOptions {
+    level: u32,
+    dict_size: Option<u32>,
+}
+
+impl Default for LzmaOptions {
+    fn default() -> Self {
+        LzmaOptions::new(DEFAULT_XZ_LEVEL)
     }
+}
 
-    pub fn write_stream<T: Read + Seek>(&mut self, source: &mut T, path: &str) -> Result<()> {
-        let mut header = Header::new_gnu();
-        header.set_size(source.seek(SeekFrom::End(0))?);
-        header.set_path(path)?;
-        header.set_mode(0o600);
-        header.set_cksum();
+impl LzmaOptions {
+    /// The xz preset, 0 to 9.
+    pub fn new(level: u32) -> Self {
+        LzmaOptions {
+            level,
+            dict_size: None,
+        }
+    }
 
-        source.rewind()?;
-        self.arch.append(&header, source)?;
+    pub fn with_level(mut self, level: u32) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Use a dictionary of `bytes` rather than the one of the preset, clamped to the sizes xz
+    /// supports. Level 9 has 64 MiB, more than a small file needs and less than a large one could
+    /// use.
+    pub fn with_dict_size(mut self, bytes: u32) -> Self {
+        self.dict_size = Some(bytes.clamp(MIN_LZMA_DICT_SIZE, MAX_LZMA_DICT_SIZE));
+        self
+    }
+
+    pub fn level(&self) -> u32 {
+        self.level
+    }
+
+    pub fn dict_size(&self) -> Option<u32> {
+        self.dict_size
+    }
+
+    /// An xz encoder writing to `sink`. Like `XzEncoder::new` this panics if the encoder can't be
+    /// set up, the options are always valid so only running out of memory does that.
+    pub fn encoder<W: Write>(&self, sink: W) -> XzEncoder<W> {
+        let dict_size = match self.dict_size {
+            Some(dict_size) => dict_size,
+            None => return XzEncoder::new(sink, self.level),
+        };
+
+        let mut options =
+            xz2::stream::LzmaOptions::new_preset(self.level).expect("xz presets run from 0 to 9");
+        options.dict_size(dict_size);
+        let stream = Stream::new_stream_encoder(Filters::new().lzma2(&options), Check::Crc64)
+            .expect("failed to set up the xz encoder");
+        XzEncoder::new_stream(sink, stream)
+    }
+}
+
+/// The `+` line of a read as it is stored: `=` when it repeats the title, otherwise the line
+/// itself. Most files leave it bare or repeat the title, so it is almost always a single letter.
+pub(crate) fn encode_sub_title(read: &FastQRead, sink: &mut Vec<u8>) {
+    if !read.sub_title.is_empty() && read.sub_title == read.title {
+        sink.push(b'=');
+    } else {
+        sink.push(b'+');
+        sink.extend_from_slice(read.sub_title.as_bytes());
+    }
+}
+
+/// How the `+` lines of an archive are stored. When every read in the archive leaves it bare or
+/// every read repeats its title the metadata says so and the sub_titles stream isn't stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PlusLines {
+    Empty,
+    Title,
+    Stored,
+}
+
+impl PlusLines {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            PlusLines::Empty => "empty",
+            PlusLines::Title => "title",
+            PlusLines::Stored => "stored",
+        }
+    }
+
+    pub(crate) fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "empty" => Ok(PlusLines::Empty),
+            "title" => Ok(PlusLines::Title),
+            "stored" => Ok(PlusLines::Stored),
+            _ => Err(CompressionModelError::UnknownPlusLines {
+                name: name.to_string(),
+            }),
+        }
+    }
+
+    /// Archives from before this was recorded store the `+` lines whenever "sub_titles" is set,
+    /// older ones than that don't keep them at all.
+    pub(crate) fn from_metadata(metadata: &Document) -> Result<Self> {
+        match metadata.get_str("plus_lines") {
+            Ok(name) => PlusLines::from_name(name),
+            Err(_) if metadata.get_bool("sub_titles") == Ok(true) => Ok(PlusLines::Stored),
+            Err(_) => Ok(PlusLines::Empty),
+        }
+    }
+
+    /// Fill in the `+` line of `read` when it isn't stored, its title has to be decoded already.
+    pub(crate) fn fill_sub_title(&self, read: &mut FastQRead) {
+        read.sub_title.clear();
+        if *self == PlusLines::Title {
+            read.sub_title.push_str(&read.title);
+        }
+    }
+}
+
+/// Looks at the `+` line of every read compressed to pick the `PlusLines` of the archive.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PlusLineCheck {
+    all_empty: bool,
+    all_title: bool,
+}
+
+impl Default for PlusLineCheck {
+    fn default() -> Self {
+        PlusLineCheck {
+            all_empty: true,
+            all_title: true,
+        }
+    }
+}
+
+impl PlusLineCheck {
+    pub(crate) fn check(&mut self, read: &FastQRead) {
+        self.all_empty &= read.sub_title.is_empty();
+        self.all_title &= read.sub_title == read.title;
+    }
+
+    pub(crate) fn plus_lines(&self) -> PlusLines {
+        if self.all_empty {
+            PlusLines::Empty
+        } else if self.all_title {
+            PlusLines::Title
+        } else {
+            PlusLines::Stored
+        }
+    }
+}
+
+/// Undo `encode_sub_title`, the title of `read` has to be decoded already.
+pub(crate) fn decode_sub_title(stored: &[u8], read: &mut FastQRead) -> Result<()> {
+    read.sub_title.clear();
+    match stored.split_first() {
+        Some((b'=', [])) => read.sub_title.push_str(&read.title),
+        Some((b'+', line)) => read.sub_title = String::from_utf8(line.to_vec())?,
+        _ => return Err(CompressionModelError::IncompleteRecord),
+    }
+    Ok(())
+}
+
+/// Write the case of a read as the lengths of alternating upper and lower case runs, starting
+/// with upper case. A read with no lower case letters is an empty line.
+pub(crate) fn write_case_runs<T: Write>(sink: &mut T, case_mask: &[bool]) -> Result<()> {
+    let mut lower = false;
+    let mut run = 0;
+    let mut first = true;
+    for &is_lower in case_mask {
+        if is_lower != lower {
+            write!(sink, "{}{}", if first { "" } else { " " }, run)?;
+            first = false;
+            lower = is_lower;
+            run = 0;
+        }
+        run += 1;
+    }
+    if !first {
+        write!(sink, " {}", run)?;
+    }
+    sink.write_all(b"\n")?;
+
+    Ok(())
+}
+
+/// Undo `write_case_runs` for a read of `length` letters, `line` is without its newline.
+pub(crate) fn parse_case_runs(line: &[u8], length: usize, case_mask: &mut Vec<bool>) -> Result<()> {
+    case_mask.clear();
+    if line.is_empty() {
+        return Ok(());
+    }
+
+    let mut lower = false;
+    for run in line.split(|b| *b == b' ') {
+        let run: usize = std::str::from_utf8(run)
+            .ok()
+            .and_then(|r| r.parse().ok())
+            .ok_or(CompressionModelError::IncompleteRecord)?;
+        let end = case_mask
+            .len()
+            .checked_add(run)
+            .filter(|end| *end <= length)
+            .ok_or(CompressionModelError::IncompleteRecord)?;
+        case_mask.resize(end, lower);
+        lower = !lower;
+    }
+    if case_mask.len() != length {
+        return Err(CompressionModelError::IncompleteRecord);
+    }
+
+    Ok(())
+}
+
+/// The Phred score every base is given when the archive was made without qualities, `I` in
+/// Phred+33.
+pub const PLACEHOLDER_PHRED: u8 = 40;
+
+/// Whether the archive was made without qualities, see `PLACEHOLDER_PHRED`.
+pub(crate) fn qualities_dropped(metadata: &Document) -> bool {
+    metadata
+        .get_document("lossy")
+        .and_then(|lossy| lossy.get_str("qualities"))
+        .is_ok_and(|setting| setting == "dropped")
+}
+
+/// Give every base of `read` the placeholder quality.
+pub(crate) fn fill_qualities(read: &mut FastQRead) {
+    read.qualities.clear();
+    read.qualities
+        .resize(read.letters.len(), PLACEHOLDER_PHRED + STORED_QUALITY_SHIFT);
+}
+
+/// Models without a stream for the `+` lines can only take reads where it's bare.
+pub(crate) fn check_bare_sub_title(model: CompressionModel, read: &FastQRead) -> Result<()> {
+    if !read.sub_title.is_empty() {
+        return Err(CompressionModelError::SubTitleNotStored {
+            model: model.as_str().to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Nor can they take the `.` and `-` letters of a read parsed in permissive mode.
+pub(crate) fn check_no_other_letters(model: CompressionModel, read: &FastQRead) -> Result<()> {
+    if !read.other_letters.is_empty() {
+        return Err(CompressionModelError::OtherLettersNotStored {
+            model: model.as_str().to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// How much of a spool is held in memory, and where it is moved once it outgrows that.
+#[derive(Debug, Clone)]
+pub struct SpoolOptions {
+    memory: usize,
+    temp_dir: Option<PathBuf>,
+}
+
+impl Default for SpoolOptions {
+    fn default() -> Self {
+        SpoolOptions {
+            memory: DEFAULT_SPOOL_MEMORY,
+            temp_dir: None,
+        }
+    }
+}
+
+impl SpoolOptions {
+    pub fn memory(&self) -> usize {
+        self.memory
+    }
+
+    /// The directory the temporary files are made in, `std::env::temp_dir()` unless one was given.
+    pub fn temp_dir(&self) -> PathBuf {
+        self.temp_dir.clone().unwrap_or_else(std::env::temp_dir)
+    }
+
+    /// An empty spool.
+    pub fn spool(&self) -> SpooledTempFile {
+        SpooledTempFile::new_in(self.memory, self.temp_dir())
+    }
+}
+
+/// A fresh spool compressing with xz.
+pub fn new_xz_spool(lzma: LzmaOptions, spool: &SpoolOptions) -> CompressedSpool {
+    CompressedSpool::new(Codec::Xz, lzma, spool)
+}
+
+/// Finish the xz stream being written to `spool` and start another after it, returning the offset
+/// the new one starts at. Decoding can start from there, but a reader has to decode concatenated
+/// streams to read through it.
+pub fn restart_xz_spool(spool: &mut XzEncoder<SpooledTempFile>, lzma: LzmaOptions) -> Result<u64> {
+    // a level 0 encoder is cheap to set up, it only holds the place of the finished one
+    let finished = std::mem::replace(spool, XzEncoder::new(SpooledTempFile::new(0), 0));
+    let mut file = finished.finish()?;
+    let offset = file.stream_position()?;
+    *spool = lzma.encoder(file);
+    Ok(offset)
+}
+
+fn bzip2_compression(level: u32) -> bzip2::Compression {
+    bzip2::Compression::new(level.clamp(1, 9))
+}
+
+const BROTLI_BUFFER_SIZE: usize = 64 * 1024;
+// a 4 MiB window, brotli's default
+const BROTLI_WINDOW: u32 = 22;
+
+fn brotli_quality(level: u32) -> u32 {
+    (level + 2).min(11)
+}
+
+/// The compression applied to a single stream of an archive.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, ValueEnum, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Codec {
+    #[default]
+    Xz,
+    Bzip2,
+    Brotli,
+}
+
+impl Codec {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Codec::Xz => "xz",
+            Codec::Bzip2 => "bzip2",
+            Codec::Brotli => "brotli",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "xz" => Ok(Codec::Xz),
+            "bzip2" => Ok(Codec::Bzip2),
+            "brotli" => Ok(Codec::Brotli),
+            _ => Err(CompressionModelError::UnknownCodec {
+                name: name.to_string(),
+            }),
+        }
+    }
+}
+
+/// A spool compressing with any of the codecs. The CRC32 of the bytes written to it is kept so that
+/// `verify_stream` can check the decoded stream.
+pub struct CompressedSpool {
+    encoder: SpoolEncoder,
+    crc32: crc32fast::Hasher,
+    length: u64,
+    temp_dir: PathBuf,
+}
+
+enum SpoolEncoder {
+    Xz(XzEncoder<SpooledTempFile>),
+    Bzip2(BzEncoder<SpooledTempFile>),
+    Brotli(Box<CompressorWriter<SpooledTempFile>>),
+}
+
+/// A finished `CompressedSpool` with the CRC32 and the length of everything written to it before
+/// compression.
+pub struct FinishedSpool {
+    pub spool: SpooledTempFile,
+    pub crc32: u32,
+    pub length: u64,
+}
+
+impl CompressedSpool {
+    /// bzip2 takes the level of `lzma` as its block size, they run from 1 to 9 so 0 is taken as 1.
+    /// brotli's quality is the level plus 2, up to its highest of 11.
+    pub fn new(codec: Codec, lzma: LzmaOptions, spool: &SpoolOptions) -> Self {
+        let encoder = match codec {
+            Codec::Xz => SpoolEncoder::Xz(lzma.encoder(spool.spool())),
+            Codec::Bzip2 => SpoolEncoder::Bzip2(BzEncoder::new(
+                spool.spool(),
+                bzip2_compression(lzma.level()),
+            )),
+            Codec::Brotli => SpoolEncoder::Brotli(Box::new(CompressorWriter::new(
+                spool.spool(),
+                BROTLI_BUFFER_SIZE,
+                brotli_quality(lzma.level()),
+                BROTLI_WINDOW,
+            ))),
+        };
+        CompressedSpool {
+            encoder,
+            crc32: crc32fast::Hasher::new(),
+            length: 0,
+            temp_dir: spool.temp_dir(),
+        }
+    }
+
+    pub fn finish(self) -> Result<FinishedSpool> {
+        let finished = match self.encoder {
+            SpoolEncoder::Xz(spool) => spool.finish(),
+            SpoolEncoder::Bzip2(spool) => spool.finish(),
+            SpoolEncoder::Brotli(mut spool) => {
+                // into_inner ends the stream but swallows errors, flushing first surfaces most
+                spool.flush().map(|_| spool.into_inner())
+            }
+        };
+        let spool = finished.map_err(|err| temp_file_error(&self.temp_dir, err))?;
+        Ok(FinishedSpool {
+            spool,
+            crc32: self.crc32.finalize(),
+            length: self.length,
+        })
+    }
+
+    /// Like `restart_xz_spool` for any codec, `lzma` has to be what the spool was made with. The
+    /// CRC32 runs on across the restart, it covers the whole decoded stream.
+    pub fn restart(&mut self, lzma: LzmaOptions) -> Result<u64> {
+        match &mut self.encoder {
+            SpoolEncoder::Xz(spool) => restart_xz_spool(spool, lzma),
+            SpoolEncoder::Bzip2(spool) => {
+                let finished = std::mem::replace(
+                    spool,
+                    BzEncoder::new(SpooledTempFile::new(0), bzip2::Compression::fast()),
+                );
+                let mut file = finished.finish()?;
+                let offset = file.stream_position()?;
+                *spool = BzEncoder::new(file, bzip2_compression(lzma.level()));
+                Ok(offset)
+            }
+            // brotli streams can't be read back to back, a decoder would stop at the first
+            SpoolEncoder::Brotli(_) => Err(CompressionModelError::CodecCantRestart {
+                codec: Codec::Brotli.as_str().to_string(),
+            }),
+        }
+    }
+}
+
+/// Only the temporary file a spool is moved to once it outgrows its memory fails with an OS error,
+/// those are reported as `temp_dir` not being writable.
+fn temp_file_error(temp_dir: &Path, source: std::io::Error) -> std::io::Error {
+    match source.raw_os_error() {
+        Some(_) => std::io::Error::other(CompressionModelError::TempDirNotWritable {
+            path: temp_dir.display().to_string(),
+            source,
+        }),
+        None => source,
+    }
+}
+
+impl Write for CompressedSpool {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = match &mut self.encoder {
+            SpoolEncoder::Xz(spool) => spool.write(buf),
+            SpoolEncoder::Bzip2(spool) => spool.write(buf),
+            SpoolEncoder::Brotli(spool) => spool.write(buf),
+        }
+        .map_err(|err| temp_file_error(&self.temp_dir, err))?;
+        self.crc32.update(&buf[..written]);
+        self.length += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &mut self.encoder {
+            SpoolEncoder::Xz(spool) => spool.flush(),
+            SpoolEncoder::Bzip2(spool) => spool.flush(),
+            SpoolEncoder::Brotli(spool) => spool.flush(),
+        }
+        .map_err(|err| temp_file_error(&self.temp_dir, err))
+    }
+}
+
+/// Fail early when temporary files can't be made in `dir`, rather than part way through an archive.
+pub fn check_temp_dir<P: AsRef<Path>>(dir: P) -> Result<()> {
+    let dir = dir.as_ref();
+    tempfile::tempfile_in(dir).map(|_| ()).map_err(|source| {
+        CompressionModelError::TempDirNotWritable {
+            path: dir.display().to_string(),
+            source,
+        }
+    })
+}
+
+/// Tells apart the temporary files of outputs written at the same time by one process.
+static PENDING_FILES: AtomicU64 = AtomicU64::new(0);
+
+/// An output file written under a temporary name next to where it belongs. `commit` renames it
+/// into place, dropped without that the temporary file is removed, so a failed write never leaves
+/// a partial archive that looks almost valid, nor clobbers the file that was there.
+///
+/// Anything other than a regular file, e.g. a pipe or `/dev/stdout`, is written to directly and
+/// can't be taken back.
+#[derive(Debug)]
+pub struct PendingFile {
+    temp: Option<PathBuf>,
+    path: PathBuf,
+}
+
+impl PendingFile {
+    /// The file to write the output to and the `PendingFile` to commit once it is complete.
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<(File, PendingFile)> {
+        let path = path.as_ref().to_path_buf();
+        let special = std::fs::metadata(&path).is_ok_and(|m| !m.is_file());
+        let name = match path.file_name() {
+            Some(name) if !special => name,
+            _ => {
+                let file = File::create(&path)?;
+                return Ok((file, PendingFile { temp: None, path }));
+            }
+        };
+
+        let mut temp_name = OsString::from(".");
+        temp_name.push(name);
+        temp_name.push(format!(
+            ".{}.{}.partial",
+            std::process::id(),
+            PENDING_FILES.fetch_add(1, Ordering::Relaxed)
+        ));
+        let temp = path.with_file_name(temp_name);
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp)?;
+        Ok((
+            file,
+            PendingFile {
+                temp: Some(temp),
+                path,
+            },
+        ))
+    }
+
+    /// Move the complete output into place.
+    pub fn commit(mut self) -> Result<()> {
+        if let Some(temp) = self.temp.take() {
+            if let Err(e) = std::fs::rename(&temp, &self.path) {
+                let _ = std::fs::remove_file(&temp);
+                return Err(e.into());
+            }
+        }
         Ok(())
     }
+}
 
-    pub fn finish(&mut self) -> Result<()> {
-        self.arch.finish()?;
+impl Drop for PendingFile {
+    fn drop(&mut self) {
+        if let Some(temp) = &self.temp {
+            let _ = std::fs::remove_file(temp);
+        }
+    }
+}
+
+/// A sink that throws away what is written to it and only counts the bytes, to find out how big
+/// an archive would be without keeping it.
+#[derive(Debug, Default)]
+pub struct CountingWriter {
+    bytes: u64,
+}
+
+impl CountingWriter {
+    pub fn new() -> Self {
+        CountingWriter::default()
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+}
+
+impl Write for CountingWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.bytes += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
         Ok(())
     }
 }
 
-pub struct PareArchiveDecoder<R: Read> {
-    arc: Archive<R>,
-    tmpdir: TempDir,
+/// The settings every model shares, a writer takes them with its `with_options`. What only one
+/// model uses is set on its writer.
+#[derive(Debug, Clone, Default)]
+pub struct EncoderOptions {
+    lzma: LzmaOptions,
+    spool: SpoolOptions,
+    checksum: ChecksumAlgorithm,
+    timestamp: ArchiveTimestamp,
+    alphabet: Alphabet,
+    phred: PhredOffset,
+    reverse_r2: bool,
+    invalid_base_policy: InvalidBasePolicy,
+    quality_binning: Option<QualityBinning>,
+    quality_filter: Option<QualityFilter>,
+    length_check: Option<LengthCheck>,
+    input_order: Option<InputOrder>,
+    dedup: bool,
 }
 
-impl<R: Read> PareArchiveDecoder<R> {
-    pub fn new(source: R) -> Result<Self> {
-        let dir = tempdir()?;
-        let mut arc = Archive::<R>::new(source);
+impl EncoderOptions {
+    pub fn new() -> Self {
+        EncoderOptions::default()
+    }
 
-        arc.unpack(dir.path())?;
-        Ok(PareArchiveDecoder {
-            arc: arc,
-            tmpdir: dir,
-        })
+    /// The xz preset, 0 to 9. Ignored by the zstd model.
+    pub fn with_level(mut self, level: u32) -> Self {
+        self.lzma = self.lzma.with_level(level);
+        self
     }
 
-    pub fn get_stream(&mut self, path: &str) -> Result<File> {
-        let file_path = self.tmpdir.path().join(path);
-        Ok(File::open(file_path)?)
+    /// Tune the xz encoder beyond its preset, this replaces the level given to `with_level`.
+    pub fn with_lzma_options(mut self, lzma: LzmaOptions) -> Self {
+        self.lzma = lzma;
+        self
     }
 
-    pub fn get_xz_stream(&mut self, path: &str) -> Result<XzDecoder<File>> {
-        Ok(XzDecoder::new(self.get_stream(path)?))
+    /// The bytes each spool holds in memory before it is moved to a temporary file.
+    pub fn with_spool_memory(mut self, spool_memory: usize) -> Self {
+        self.spool.memory = spool_memory;
+        self
     }
 
-    pub fn get_metadata(&mut self) -> Result<Document> {
-        //TODO: handle not finding the metadata file
-        let cont = fs::read(self.tmpdir.path().join("metadata"))?;
-        Ok(Document::from_reader(&mut Cursor::new(cont))?)
+    /// The directory spools are moved to once they outgrow their memory, rather than
+    /// `std::env::temp_dir()`.
+    pub fn with_temp_dir<P: Into<PathBuf>>(mut self, temp_dir: P) -> Self {
+        self.spool.temp_dir = Some(temp_dir.into());
+        self
+    }
+
+    pub fn with_checksum(mut self, checksum: ChecksumAlgorithm) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    pub fn with_timestamp(mut self, timestamp: ArchiveTimestamp) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    pub fn with_alphabet(mut self, alphabet: Alphabet) -> Self {
+        self.alphabet = alphabet;
+        self
+    }
+
+    /// The quality offset of the input, recorded so the reads are written back with it.
+    pub fn with_phred_offset(mut self, phred: PhredOffset) -> Self {
+        self.phred = phred;
+        self
+    }
+
+    /// Whether R2 was reverse complemented when it was read, recorded so it is undone by default.
+    pub fn with_reverse_r2(mut self, reverse_r2: bool) -> Self {
+        self.reverse_r2 = reverse_r2;
+        self
+    }
+
+    /// How the reader handled reads with invalid bases, noted in the metadata when it was lossy.
+    pub fn with_invalid_base_policy(mut self, policy: InvalidBasePolicy) -> Self {
+        self.invalid_base_policy = policy;
+        self
+    }
+
+    /// Bin the qualities before compressing them, this is lossy.
+    pub fn with_quality_binning(mut self, binning: Option<QualityBinning>) -> Self {
+        self.quality_binning = binning;
+        self
+    }
+
+    /// Leave out the pairs with a low mean quality, this is lossy.
+    pub fn with_quality_filter(mut self, filter: Option<QualityFilter>) -> Self {
+        self.quality_filter = filter;
+        self
+    }
+
+    /// Count the reads of an unexpected length in the stats.
+    pub fn with_length_check(mut self, check: Option<LengthCheck>) -> Self {
+        self.length_check = check;
+        self
+    }
+
+    /// Record that the pairs were sorted before they were compressed, see `sort_pairs`.
+    pub fn with_input_order(mut self, order: Option<InputOrder>) -> Self {
+        self.input_order = order;
+        self
+    }
+
+    /// Store the pairs that are copies of an earlier pair as a reference to it, see `Dedup`.
+    pub fn with_dedup(mut self, dedup: bool) -> Self {
+        self.dedup = dedup;
+        self
+    }
+
+    pub fn quality_filter(&self) -> Option<QualityFilter> {
+        self.quality_filter
+    }
+
+    pub fn dedup(&self) -> bool {
+        self.dedup
     }
 }
 
-pub trait EncoderModel {
-    fn compress(&mut self, reader: &mut Box<dyn PairedFastQReader>) -> Result<()>;
+pub struct PareArchiveEncoder<W: Write> {
+    arch: Builder<W>,
+    options: EncoderOptions,
+    line_ending: LineEnding,
+    stats: Option<CompressionStats>,
+    // resolved once so every entry carries the same time
+    mtime: u64,
+    // finished spools are held back until the metadata has been written so that it is always the
+    // first entry in the archive and can describe them.
+    staged: Vec<(String, SpooledTempFile)>,
+    // the CRC32 of the decoded content of the staged streams that were compressed by pare
+    crc32: Document,
+    // the uncompressed length of the staged streams, for `pare list`
+    lengths: Document,
+    // lossy steps applied to the reads, kept so readers of the archive know it isn't exact.
+    lossy: Document,
+    // the file the archive is going to, only moved into place once it has been finished
+    pending: Option<PendingFile>,
+    // streams can only be appended once the metadata is, readers pick the codecs from it
+    metadata_written: bool,
+    duplicates: Option<Dedup>,
 }
 
-pub trait DecoderModel {
-    fn decompress(&mut self, writer: &mut Box<dyn PairedFastQWriter>) -> Result<()>;
+impl<W: Write> PareArchiveEncoder<W> {
+    pub fn new(sink: W) -> Self {
+        PareArchiveEncoder {
+            arch: Builder::new(sink),
+            options: EncoderOptions::default(),
+            line_ending: LineEnding::default(),
+            stats: None,
+            mtime: ArchiveTimestamp::default().mtime(),
+            staged: Vec::new(),
+            crc32: Document::new(),
+            lengths: Document::new(),
+            lossy: Document::new(),
+            pending: None,
+            metadata_written: false,
+            duplicates: None,
+        }
+    }
+
+    pub fn set_options(&mut self, options: EncoderOptions) {
+        self.mtime = options.timestamp.mtime();
+        self.options = options;
+    }
+
+    pub fn options(&self) -> &EncoderOptions {
+        &self.options
+    }
+
+    /// How the spools of the model are held before they are written to the archive.
+    pub fn spool_options(&self) -> &SpoolOptions {
+        &self.options.spool
+    }
+
+    /// The line ending of the compressed fastq, so it can be written back the same way.
+    pub fn set_line_ending(&mut self, line_ending: LineEnding) {
+        self.line_ending = line_ending;
+    }
+
+    /// Record the counts of what was compressed in the metadata.
+    pub fn set_stats(&mut self, stats: CompressionStats) {
+        self.stats = Some(stats);
+    }
+
+    pub fn dedup(&self) -> bool {
+        self.options.dedup
+    }
+
+    /// Whether `reads` are a copy of a pair already given to the model. Copies are recorded here
+    /// and should be left out of the model, this is always false without `with_dedup`.
+    pub fn is_duplicate(&mut self, reads: &[&FastQRead]) -> Result<bool> {
+        if !self.options.dedup {
+            return Ok(false);
+        }
+        let spool = &self.options.spool;
+        self.duplicates
+            .get_or_insert_with(|| Dedup::new(spool))
+            .is_duplicate(reads)
+    }
+
+    /// Record a lossy step in the metadata.
+    pub fn add_lossy(&mut self, step: &str, setting: &str) {
+        self.lossy.insert(step, setting);
+    }
+
+    /// For the models without a case stream, which write every base back in upper case. The
+    /// metadata notes the loss once a read with lower case bases is seen.
+    pub fn note_dropped_case(&mut self, read: &FastQRead) {
+        if !read.case_mask.is_empty() && !self.lossy.contains_key("case") {
+            self.add_lossy("case", "upper");
+        }
+    }
+
+    /// Write the metadata followed by every staged stream. The checksum of the staged streams and
+    /// the CRC32 of the decoded content of each compressed one are added to the metadata.
+    pub fn write_metadata(&mut self, mut metadata: Document) -> Result<()> {
+        match self.options.input_order.take() {
+            Some(InputOrder::Kept(order)) => {
+                let mut spool = new_xz_spool(LzmaOptions::default(), &self.options.spool);
+                for position in order {
+                    spool.write_all(&position.to_le_bytes())?;
+                }
+                self.write_compressed_spool(spool, "order")?;
+                metadata.insert("order", "kept");
+            }
+            Some(InputOrder::Lost) => self.add_lossy("order", "sorted"),
+            None => {}
+        }
+        if self.options.invalid_base_policy != InvalidBasePolicy::Fail {
+            self.add_lossy("invalid_bases", self.options.invalid_base_policy.as_str());
+        }
+        if let Some(binning) = self.options.quality_binning {
+            self.add_lossy("quality_bins", binning.as_str());
+        }
+        if let Some(filter) = self.options.quality_filter {
+            self.add_lossy("min_mean_quality", &filter.as_str());
+        }
+        if let Some(dedup) = self.duplicates.take().filter(|dedup| dedup.copies() > 0) {
+            metadata.insert("duplicates", dedup.copies() as i64);
+            let (duplicates, titles) = dedup.into_streams();
+            self.write_compressed_spool(duplicates, "duplicates")?;
+            self.write_compressed_spool(titles, "duplicate_titles")?;
+        }
+        metadata.insert("crc32", std::mem::take(&mut self.crc32));
+        metadata.insert("lengths", std::mem::take(&mut self.lengths));
+
+        let mut checksum = doc! { "algorithm": self.options.checksum.as_str() };
+        if let Some(mut hasher) = self.options.checksum.hasher() {
+            for (_, spool) in self.staged.iter_mut() {
+                hash_stream(hasher.as_mut(), spool)?;
+            }
+
+            let paths: Vec<&str> = self.staged.iter().map(|(p, _)| p.as_str()).collect();
+            checksum.insert("streams", paths);
+            checksum.insert("digest", hasher.hex_digest());
+        }
+        metadata.insert("checksum", checksum);
+        metadata.insert("timestamp", self.options.timestamp.to_string());
+        metadata.insert("alphabet", self.options.alphabet.as_str());
+        metadata.insert("line_ending", self.line_ending.as_str());
+        metadata.insert("phred", self.options.phred.as_str());
+        metadata.insert("reverse_r2", self.options.reverse_r2);
+        if let Some(stats) = self.stats {
+            let mut recorded = doc! {
+                "pairs": stats.pairs as i64,
+                "bases_r1": stats.bases_r1 as i64,
+                "bases_r2": stats.bases_r2 as i64,
+            };
+            if stats.bases_extra > 0 {
+                recorded.insert("bases_extra", stats.bases_extra as i64);
+            }
+            if stats.filtered_pairs > 0 {
+                recorded.insert("filtered_pairs", stats.filtered_pairs as i64);
+            }
+            if stats.unexpected_lengths > 0 {
+                recorded.insert("unexpected_lengths", stats.unexpected_lengths as i64);
+            }
+            if stats.duplicate_pairs > 0 {
+                recorded.insert("duplicate_pairs", stats.duplicate_pairs as i64);
+            }
+            metadata.insert("stats", recorded);
+        }
+        if !self.lossy.is_empty() {
+            metadata.insert("lossy", self.lossy.clone());
+        }
+
+        let mut buffer: Vec<u8> = Vec::new();
+        metadata.to_writer(&mut buffer)?;
+
+        self.append(&mut Cursor::new(buffer), "metadata")?;
+        self.metadata_written = true;
+        for (path, mut spool) in std::mem::take(&mut self.staged) {
+            self.write_stream(&mut spool, &path)?;
+        }
+        Ok(())
+    }
+
+    /// Finish compressing a spool and stage it to be written after the metadata.
+    pub fn write_compressed_spool(&mut self, spool: CompressedSpool, path: &str) -> Result<()> {
+        let finished_spool = spool.finish()?;
+        self.stage_finished_spool(finished_spool, path);
+
+        Ok(())
+    }
+
+    /// Stage a finished spool and record its CRC32 for `verify_stream`.
+    pub fn stage_finished_spool(&mut self, finished: FinishedSpool, path: &str) {
+        self.record_crc32(path, finished.crc32);
+        self.record_length(path, finished.length);
+        self.stage_spool(finished.spool, path);
+    }
+
+    /// Stage an already encoded spool to be written after the metadata.
+    pub fn stage_spool(&mut self, spool: SpooledTempFile, path: &str) {
+        self.staged.push((path.to_string(), spool));
+    }
+
+    /// Stage a spool that is stored as it is, uncompressed.
+    pub fn stage_stored_spool(&mut self, mut spool: SpooledTempFile, path: &str) -> Result<()> {
+        let length = spool.seek(SeekFrom::End(0))?;
+        self.record_length(path, length);
+        self.stage_spool(spool, path);
+        Ok(())
+    }
+
+    /// Record the CRC32 of the content of the stream at `path` for `verify_stream`.
+    pub fn record_crc32(&mut self, path: &str, crc32: u32) {
+        self.crc32.insert(path, crc32 as i64);
+    }
+
+    /// Record the uncompressed length of the stream at `path` in the metadata.
+    pub fn record_length(&mut self, path: &str, length: u64) {
+        self.lengths.insert(path, length as i64);
+    }
+
+    /// Append a stream to the archive, an error until the metadata has been written so that it is
+    /// always the first entry. Use `stage_spool` to write a stream before it.
+    pub fn write_stream<T: Read + Seek>(&mut self, source: &mut T, path: &str) -> Result<()> {
+        if !self.metadata_written {
+            return Err(CompressionModelError::MetadataNotFirst {
+                path: path.to_string(),
+            });
+        }
+        self.append(source, path)
+    }
+
+    fn append<T: Read + Seek>(&mut self, source: &mut T, path: &str) -> Result<()> {
+        let mut header = Header::new_gnu();
+        header.set_size(source.seek(SeekFrom::End(0))?);
+        header.set_path(path)?;
+        header.set_mode(0o600);
+        header.set_mtime(self.mtime);
+        header.set_cksum();
+
+        source.rewind()?;
+        self.arch.append(&header, source)?;
+        Ok(())
+    }
+
+    /// Move `pending` into place when the archive is finished, see `PendingFile`.
+    pub fn set_pending_file(&mut self, pending: PendingFile) {
+        self.pending = Some(pending);
+    }
+
+    pub fn finish(&mut self) -> Result<()> {
+        self.arch.finish()?;
+        if let Some(pending) = self.pending.take() {
+            self.arch.get_mut().flush()?;
+            pending.commit()?;
+        }
+        Ok(())
+    }
+}
+
+/// Where the members of an opened archive are kept until they are read.
+enum ArchiveStorage {
+    Disk(TempDir),
+    /// Unpacked to a directory that is left behind, see `PareArchiveDecoder::keep_temp`.
+    Kept(PathBuf),
+    Memory(HashMap<String, Arc<[u8]>>),
+}
+
+/// A decompressing reader for a stream of any of the codecs.
+pub enum StreamDecoder {
+    Xz(XzDecoder<ArchiveStream>),
+    Bzip2(MultiBzDecoder<ArchiveStream>),
+    Brotli(Box<Decompressor<ArchiveStream>>),
+    Threaded(ThreadedDecoder),
+}
+
+impl Read for StreamDecoder {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            StreamDecoder::Xz(stream) => stream.read(buf),
+            StreamDecoder::Bzip2(stream) => stream.read(buf),
+            StreamDecoder::Brotli(stream) => stream.read(buf),
+            StreamDecoder::Threaded(stream) => stream.read(buf),
+        }
+    }
+}
+
+/// The bytes in each chunk a `ThreadedDecoder` decodes ahead.
+const THREADED_CHUNK_SIZE: usize = 256 * 1024;
+/// The chunks decoded ahead before the thread waits for them to be read.
+const THREADED_CHUNKS_IN_FLIGHT: usize = 4;
+
+/// A stream decoded on a thread of its own, ahead of it being read. An empty chunk is the end of
+/// the stream, an error is passed on to the reader in its place.
+pub struct ThreadedDecoder {
+    chunks: Receiver<std::io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    position: usize,
+    done: bool,
+}
+
+impl ThreadedDecoder {
+    pub fn spawn(mut stream: StreamDecoder) -> Self {
+        let (sender, chunks) = sync_channel(THREADED_CHUNKS_IN_FLIGHT);
+        thread::spawn(move || loop {
+            let mut chunk = Vec::with_capacity(THREADED_CHUNK_SIZE);
+            let decoded = (&mut stream)
+                .take(THREADED_CHUNK_SIZE as u64)
+                .read_to_end(&mut chunk);
+            let end = !matches!(decoded, Ok(n) if n > 0);
+            // the reader stops taking chunks when it's dropped
+            if sender.send(decoded.map(|_| chunk)).is_err() || end {
+                break;
+            }
+        });
+
+        ThreadedDecoder {
+            chunks,
+            chunk: Vec::new(),
+            position: 0,
+            done: false,
+        }
+    }
+}
+
+impl Read for ThreadedDecoder {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.position == self.chunk.len() {
+            if self.done {
+                return Ok(0);
+            }
+            match self.chunks.recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk?;
+                    self.position = 0;
+                    self.done = self.chunk.is_empty();
+                }
+                Err(_) => {
+                    return Err(std::io::Error::other(
+                        "the thread decoding the stream stopped",
+                    ))
+                }
+            }
+        }
+
+        let n = buf.len().min(self.chunk.len() - self.position);
+        buf[..n].copy_from_slice(&self.chunk[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+/// An archive member opened for reading.
+pub enum ArchiveStream {
+    File(File),
+    Memory(Cursor<Arc<[u8]>>),
+}
+
+impl Read for ArchiveStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            ArchiveStream::File(file) => file.read(buf),
+            ArchiveStream::Memory(cursor) => cursor.read(buf),
+        }
+    }
+}
+
+impl Seek for ArchiveStream {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        match self {
+            ArchiveStream::File(file) => file.seek(pos),
+            ArchiveStream::Memory(cursor) => cursor.seek(pos),
+        }
+    }
+}
+
+/// The bytes read to tell what a file is before it's read as an archive.
+const SNIFF_LENGTH: usize = 8;
+
+/// What the start of a file says it is, when it's one of the files likely to be given in place of
+/// an archive.
+fn sniff_container(start: &[u8]) -> Option<&'static str> {
+    match start {
+        [0xfd, b'7', b'z', b'X', b'Z', 0, ..] => Some("an xz stream"),
+        [0x1f, 0x8b, ..] => Some("gzip compressed"),
+        [0x28, 0xb5, 0x2f, 0xfd, ..] => Some("zstd compressed"),
+        [b'B', b'Z', b'h', ..] => Some("bzip2 compressed"),
+        [b'@', ..] => Some("a fastq file"),
+        [b'>', ..] => Some("a FASTA file"),
+        _ => None,
+    }
+}
+
+/// Open `source` as a tar, unless its first bytes show it's something else. The tar crate would
+/// only fail on it later with an error about the tar header.
+fn open_tar<R: Read>(source: R) -> Result<Archive<PeekReader<R>>> {
+    let source = PeekReader::new(source, SNIFF_LENGTH)?;
+    if let Some(kind) = sniff_container(source.peeked()) {
+        return Err(CompressionModelError::NotAnArchive {
+            kind: kind.to_string(),
+        });
+    }
+    Ok(Archive::new(source))
+}
+
+/// The recorded uncompressed lengths of the streams in `metadata`, their paths prefixed by `prefix`.
+fn collect_lengths(metadata: &Document, prefix: &str, lengths: &mut HashMap<String, u64>) {
+    if let Ok(recorded) = metadata.get_document("lengths") {
+        for (path, length) in recorded {
+            if let Some(length) = length.as_i64() {
+                lengths.insert(format!("{}{}", prefix, path), length as u64);
+            }
+        }
+    }
+}
+
+/// The metadata of an archive written by one of the models, rather than the manifest of an archive
+/// of samples.
+fn check_model_metadata(metadata: Document) -> Result<Document> {
+    if metadata.get_str("model").is_err() {
+        if metadata.contains_key("samples") {
+            return Err(CompressionModelError::SampleRequired {
+                samples: manifest_samples(&metadata)?.join(", "),
+            });
+        }
+        return Err(CompressionModelError::MissingMetadata);
+    }
+    Ok(metadata)
+}
+
+/// The model an archive was written with. Only the metadata, the first member of every archive, is
+/// read so this is cheap even for a large archive, but `source` can't be read again from the start.
+pub fn detect_model<R: Read>(source: R) -> Result<CompressionModel> {
+    let mut arc = open_tar(source)?;
+    let mut entries = arc.entries()?;
+    let mut entry = match entries.next() {
+        Some(Ok(entry)) => entry,
+        // a bad tar header, or not enough bytes for one
+        Some(Err(_)) | None => {
+            return Err(CompressionModelError::NotAnArchive {
+                kind: "unrecognised data".to_string(),
+            })
+        }
+    };
+    if entry.path()?.to_str() != Some("metadata") {
+        return Err(CompressionModelError::MissingMetadata);
+    }
+    let metadata = check_model_metadata(Document::from_reader(&mut entry)?)?;
+    CompressionModel::from_name(metadata.get_str("model")?)
+}
+
+pub struct PareArchiveDecoder<R: Read> {
+    arc: Archive<PeekReader<R>>,
+    storage: ArchiveStorage,
+    streams: Vec<(String, u64)>,
+    // the members of an archive of samples read are those of this sample
+    sample: Option<String>,
+    parallel_streams: bool,
+}
+
+impl<R: Read> PareArchiveDecoder<R> {
+    /// Unpack the archive to a temporary directory, the streams are then read back from there.
+    pub fn new(source: R) -> Result<Self> {
+        Self::new_in(source, std::env::temp_dir())
+    }
+
+    /// Unpack the archive to a temporary directory made in `temp_dir`.
+    pub fn new_in<P: AsRef<Path>>(source: R, temp_dir: P) -> Result<Self> {
+        Self::unpack_in(source, temp_dir, None)
+    }
+
+    /// Unpack only the sample `name` of an archive of samples and select it, see `select_sample`.
+    /// The members of the other samples are read past.
+    pub fn new_sample(source: R, name: &str) -> Result<Self> {
+        Self::new_sample_in(source, std::env::temp_dir(), name)
+    }
+
+    /// Like `new_sample`, unpacked to a temporary directory made in `temp_dir`.
+    pub fn new_sample_in<P: AsRef<Path>>(source: R, temp_dir: P, name: &str) -> Result<Self> {
+        let mut decoder = Self::unpack_in(source, temp_dir, Some(name))?;
+        decoder.select_sample(name)?;
+        Ok(decoder)
+    }
+
+    /// Unpack the members of the archive, or of one of its samples and the manifest.
+    fn unpack_in<P: AsRef<Path>>(source: R, temp_dir: P, sample: Option<&str>) -> Result<Self> {
+        let dir = tempdir_in(temp_dir)?;
+        let mut arc = open_tar(source)?;
+        let prefix = sample.map(|name| format!("{}/", name));
+
+        let mut streams = Vec::new();
+        for entry in arc.entries()? {
+            let mut entry = entry?;
+            let path = entry.path()?.to_string_lossy().into_owned();
+            let size = entry.header().size()?;
+            let wanted = match &prefix {
+                Some(prefix) => path == "metadata" || path.starts_with(prefix.as_str()),
+                None => true,
+            };
+            if wanted {
+                entry.unpack_in(dir.path())?;
+            }
+            streams.push((path, size));
+        }
+
+        Ok(PareArchiveDecoder {
+            arc,
+            storage: ArchiveStorage::Disk(dir),
+            streams,
+            sample: None,
+            parallel_streams: false,
+        })
+    }
+
+    /// Read the archive straight into memory without touching the disk. The tar is a single pass
+    /// but the models read several of its streams side by side, so every stream has to be held
+    /// until the end: this needs as much memory as the archive is big, in exchange for no temporary
+    /// space and half the IO of `new`.
+    pub fn new_in_memory(source: R) -> Result<Self> {
+        let mut arc = open_tar(source)?;
+
+        let mut streams = Vec::new();
+        let mut contents = HashMap::new();
+        for entry in arc.entries()? {
+            let mut entry = entry?;
+            let path = entry.path()?.to_string_lossy().into_owned();
+            let size = entry.header().size()?;
+            // the header size is only a hint, a corrupt one mustn't allocate before any reading
+            let mut content = Vec::with_capacity(size.min(DEFAULT_SPOOL_MEMORY as u64) as usize);
+            entry.read_to_end(&mut content)?;
+            contents.insert(path.clone(), Arc::from(content));
+            streams.push((path, size));
+        }
+
+        Ok(PareArchiveDecoder {
+            arc,
+            storage: ArchiveStorage::Memory(contents),
+            streams,
+            sample: None,
+            parallel_streams: false,
+        })
+    }
+
+    /// The directory the archive was unpacked to, `None` when it was read into memory.
+    pub fn temp_path(&self) -> Option<&Path> {
+        match &self.storage {
+            ArchiveStorage::Disk(dir) => Some(dir.path()),
+            ArchiveStorage::Kept(path) => Some(path),
+            ArchiveStorage::Memory(_) => None,
+        }
+    }
+
+    /// Leave the unpacked streams behind when the decoder is dropped, e.g. to look at them after
+    /// decoding failed. Returns the directory they are in, `None` when the archive was read into
+    /// memory.
+    pub fn keep_temp(&mut self) -> Option<PathBuf> {
+        let storage = std::mem::replace(&mut self.storage, ArchiveStorage::Memory(HashMap::new()));
+        self.storage = match storage {
+            ArchiveStorage::Disk(dir) => ArchiveStorage::Kept(dir.keep()),
+            storage => storage,
+        };
+        self.temp_path().map(Path::to_path_buf)
+    }
+
+    /// The path and stored size of every member of the archive, in archive order. Once a sample
+    /// is selected, those of the sample with their paths inside it.
+    pub fn list_streams(&self) -> Result<Vec<(String, u64)>> {
+        match &self.sample {
+            Some(sample) => {
+                let prefix = format!("{}/", sample);
+                Ok(self
+                    .streams
+                    .iter()
+                    .filter_map(|(path, size)| {
+                        path.strip_prefix(prefix.as_str())
+                            .map(|path| (path.to_string(), *size))
+                    })
+                    .collect())
+            }
+            None => Ok(self.streams.clone()),
+        }
+    }
+
+    /// The uncompressed length of every stream that `list_streams` gives, None for those of older
+    /// archives that didn't record it. The metadata is stored as it is.
+    pub fn stream_lengths(&mut self) -> Result<Vec<(String, u64, Option<u64>)>> {
+        let samples = match &self.sample {
+            Some(_) => Vec::new(),
+            None => self.samples()?,
+        };
+        let mut lengths = HashMap::new();
+        match samples.is_empty() {
+            true => collect_lengths(&self.get_metadata()?, "", &mut lengths),
+            false => {
+                for sample in samples {
+                    let mut stream = self.get_member(&format!("{}/metadata", sample))?;
+                    let metadata = Document::from_reader(&mut stream)?;
+                    collect_lengths(&metadata, &format!("{}/", sample), &mut lengths);
+                }
+            }
+        }
+
+        Ok(self
+            .list_streams()?
+            .into_iter()
+            .map(|(path, size)| {
+                let length = match path.rsplit('/').next() == Some("metadata") {
+                    true => Some(size),
+                    false => lengths.get(&path).copied(),
+                };
+                (path, size, length)
+            })
+            .collect())
+    }
+
+    /// The samples of an archive of samples, empty for any other archive.
+    pub fn samples(&mut self) -> Result<Vec<String>> {
+        match self.manifest()? {
+            Some(manifest) => manifest_samples(&manifest),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// The list of samples at the front of an archive of samples, see `samples::append_sample`.
+    pub fn manifest(&mut self) -> Result<Option<Document>> {
+        let manifest = match self.get_member("metadata") {
+            Ok(mut stream) => Document::from_reader(&mut stream)?,
+            Err(CompressionModelError::IO { source }) if source.kind() == ErrorKind::NotFound => {
+                return Ok(None)
+            }
+            Err(e) => return Err(e),
+        };
+        match manifest.contains_key("samples") {
+            true => Ok(Some(manifest)),
+            false => Ok(None),
+        }
+    }
+
+    /// Read the sample named `name` of an archive of samples, every stream is then looked up in
+    /// that sample.
+    pub fn select_sample(&mut self, name: &str) -> Result<()> {
+        if !self.samples()?.iter().any(|sample| sample == name) {
+            return Err(CompressionModelError::UnknownSample {
+                name: name.to_string(),
+            });
+        }
+        self.sample = Some(name.to_string());
+        Ok(())
+    }
+
+    pub fn get_stream(&mut self, path: &str) -> Result<ArchiveStream> {
+        match &self.sample {
+            Some(sample) => self.get_member(&format!("{}/{}", sample, path)),
+            None => self.get_member(path),
+        }
+    }
+
+    /// The member at `path` whatever sample is selected.
+    fn get_member(&self, path: &str) -> Result<ArchiveStream> {
+        match &self.storage {
+            ArchiveStorage::Disk(dir) => {
+                Ok(ArchiveStream::File(File::open(dir.path().join(path))?))
+            }
+            ArchiveStorage::Kept(dir) => Ok(ArchiveStream::File(File::open(dir.join(path))?)),
+            ArchiveStorage::Memory(contents) => match contents.get(path) {
+                Some(content) => Ok(ArchiveStream::Memory(Cursor::new(content.clone()))),
+                None => Err(std::io::Error::from(ErrorKind::NotFound).into()),
+            },
+        }
+    }
+
+    /// Streams can be several compressed streams one after the other, see `restart_xz_spool`,
+    /// they are all decoded.
+    pub fn get_xz_stream(&mut self, path: &str) -> Result<XzDecoder<ArchiveStream>> {
+        self.get_xz_stream_at(path, 0)
+    }
+
+    /// Decode a stream starting `offset` bytes in, where one of its compressed streams starts.
+    pub fn get_xz_stream_at(
+        &mut self,
+        path: &str,
+        offset: u64,
+    ) -> Result<XzDecoder<ArchiveStream>> {
+        let mut stream = self.get_stream(path)?;
+        stream.seek(SeekFrom::Start(offset))?;
+        Ok(XzDecoder::new_multi_decoder(stream))
+    }
+
+    pub fn get_compressed_stream(&mut self, path: &str, codec: Codec) -> Result<StreamDecoder> {
+        self.get_compressed_stream_at(path, codec, 0)
+    }
+
+    pub fn get_compressed_stream_at(
+        &mut self,
+        path: &str,
+        codec: Codec,
+        offset: u64,
+    ) -> Result<StreamDecoder> {
+        let mut stream = self.get_stream(path)?;
+        stream.seek(SeekFrom::Start(offset))?;
+        let decoder = match codec {
+            Codec::Xz => StreamDecoder::Xz(XzDecoder::new_multi_decoder(stream)),
+            Codec::Bzip2 => StreamDecoder::Bzip2(MultiBzDecoder::new(stream)),
+            Codec::Brotli => {
+                StreamDecoder::Brotli(Box::new(Decompressor::new(stream, BROTLI_BUFFER_SIZE)))
+            }
+        };
+        Ok(match self.parallel_streams {
+            true => StreamDecoder::Threaded(ThreadedDecoder::spawn(decoder)),
+            false => decoder,
+        })
+    }
+
+    /// Decode each stream opened with `get_compressed_stream_at` on a thread of its own.
+    pub fn set_parallel_streams(&mut self, parallel: bool) {
+        self.parallel_streams = parallel;
+    }
+
+    /// The codec a stream was compressed with, as recorded in the metadata.
+    pub fn stream_codec(&mut self, path: &str) -> Result<Codec> {
+        stream_codec(&self.get_metadata()?, path)
+    }
+
+    /// Copy the raw bytes of an archive member to `sink`, decoding them first if asked.
+    pub fn dump_stream<W: Write>(
+        &mut self,
+        path: &str,
+        decompressed: bool,
+        sink: &mut W,
+    ) -> Result<u64> {
+        let stream = self.get_stream(path).map_err(|e| match e {
+            CompressionModelError::IO { source } if source.kind() == ErrorKind::NotFound => {
+                CompressionModelError::MissingStream {
+                    path: path.to_string(),
+                }
+            }
+            e => e,
+        })?;
+
+        if decompressed {
+            let codec = self.stream_codec(path)?;
+            Ok(std::io::copy(
+                &mut self.get_compressed_stream(path, codec)?,
+                sink,
+            )?)
+        } else {
+            Ok(std::io::copy(&mut BufReader::new(stream), sink)?)
+        }
+    }
+
+    pub fn get_metadata(&mut self) -> Result<Document> {
+        let mut stream = match self.get_stream("metadata") {
+            Ok(stream) => stream,
+            Err(CompressionModelError::IO { source }) if source.kind() == ErrorKind::NotFound => {
+                return Err(CompressionModelError::MissingMetadata)
+            }
+            Err(e) => return Err(e),
+        };
+
+        check_model_metadata(Document::from_reader(&mut stream)?)
+    }
+
+    /// The model named in the archive metadata.
+    pub fn model(&mut self) -> Result<CompressionModel> {
+        CompressionModel::from_name(self.get_metadata()?.get_str("model")?)
+    }
+
+    /// The alphabet of the reads, archives from before RNA was supported are DNA.
+    pub fn alphabet(&mut self) -> Result<Alphabet> {
+        let metadata = self.get_metadata()?;
+        match metadata.get_str("alphabet") {
+            Ok(name) => Alphabet::from_name(name).ok_or(CompressionModelError::UnknownAlphabet {
+                name: name.to_string(),
+            }),
+            Err(_) => Ok(Alphabet::Dna),
+        }
+    }
+
+    /// The line ending of the fastq the archive was made from, archives from before it was
+    /// recorded are taken to be LF.
+    pub fn line_ending(&mut self) -> Result<LineEnding> {
+        let metadata = self.get_metadata()?;
+        match metadata.get_str("line_ending") {
+            Ok(name) => {
+                LineEnding::from_name(name).ok_or(CompressionModelError::UnknownLineEnding {
+                    name: name.to_string(),
+                })
+            }
+            Err(_) => Ok(LineEnding::Lf),
+        }
+    }
+
+    /// The quality offset of the fastq the archive was made from, archives from before it was
+    /// recorded are Phred+33.
+    pub fn phred_offset(&mut self) -> Result<PhredOffset> {
+        let metadata = self.get_metadata()?;
+        match metadata.get_str("phred") {
+            Ok(name) => {
+                PhredOffset::from_name(name).ok_or(CompressionModelError::UnknownPhredOffset {
+                    name: name.to_string(),
+                })
+            }
+            Err(_) => Ok(PhredOffset::Phred33),
+        }
+    }
+
+    /// The counts recorded when the archive was made, `None` for archives from before they were
+    /// recorded.
+    pub fn stats(&mut self) -> Result<Option<CompressionStats>> {
+        let metadata = self.get_metadata()?;
+        let recorded = match metadata.get_document("stats") {
+            Ok(recorded) => recorded,
+            Err(_) => return Ok(None),
+        };
+        let count = |key: &str| recorded.get_i64(key).map(|n| n as u64);
+        Ok(Some(CompressionStats {
+            pairs: count("pairs")?,
+            bases_r1: count("bases_r1")?,
+            bases_r2: count("bases_r2")?,
+            bases_extra: count("bases_extra").unwrap_or(0),
+            filtered_pairs: count("filtered_pairs").unwrap_or(0),
+            unexpected_lengths: count("unexpected_lengths").unwrap_or(0),
+            duplicate_pairs: count("duplicate_pairs").unwrap_or(0),
+        }))
+    }
+
+    /// Whether R2 was reverse complemented when the archive was made, `None` for archives from
+    /// before it was recorded.
+    pub fn reverse_r2(&mut self) -> Result<Option<bool>> {
+        let metadata = self.get_metadata()?;
+        Ok(metadata.get_bool("reverse_r2").ok())
+    }
+
+    /// The input position of each pair of an archive that was sorted with the order kept, in the
+    /// order they were compressed.
+    pub fn input_order(&mut self) -> Result<Option<Vec<u64>>> {
+        let metadata = self.get_metadata()?;
+        if metadata.get_str("order") != Ok("kept") {
+            return Ok(None);
+        }
+
+        let mut positions = Vec::new();
+        self.get_xz_stream("order")?.read_to_end(&mut positions)?;
+        if positions.len() % 8 != 0 {
+            return Err(CompressionModelError::CorruptStream {
+                stream: "order".to_string(),
+                read: (positions.len() / 8) as u64,
+            });
+        }
+        Ok(Some(
+            positions
+                .chunks_exact(8)
+                .map(|position| u64::from_le_bytes(position.try_into().unwrap()))
+                .collect(),
+        ))
+    }
+
+    /// The pairs of an archive compressed with `with_dedup` that are copies of an earlier pair.
+    pub(crate) fn duplicates(&mut self) -> Result<Option<Duplicates>> {
+        let metadata = self.get_metadata()?;
+        if !metadata.contains_key("duplicates") {
+            return Ok(None);
+        }
+        let duplicates = self.get_xz_stream("duplicates")?;
+        let titles = self.get_stream("duplicate_titles")?;
+        Ok(Some(Duplicates::from_streams(duplicates, titles)?))
+    }
+
+    /// The reads to a fragment: 1 for single-end reads, 2 for pairs and for archives of models that
+    /// only store pairs.
+    pub fn reads_per_fragment(&mut self) -> Result<usize> {
+        let metadata = self.get_metadata()?;
+        Ok(lzma_multi_stream::reads_per_fragment(&metadata))
+    }
+
+    /// Whether the archive kept the case of the bases, archives of models that don't are decoded
+    /// upper case.
+    pub fn keeps_case(&mut self) -> Result<bool> {
+        let metadata = self.get_metadata()?;
+        Ok(metadata.get_bool("case") == Ok(true))
+    }
+
+    /// Whether the archive was made without qualities, its reads are decoded with
+    /// `PLACEHOLDER_PHRED` for every base.
+    pub fn qualities_dropped(&mut self) -> Result<bool> {
+        let metadata = self.get_metadata()?;
+        Ok(qualities_dropped(&metadata))
+    }
+
+    /// Decode a stream and compare the CRC32 of its content to the one in the metadata, a stream
+    /// that can't be decoded fails too. Streams without a recorded CRC32, like those pare doesn't
+    /// compress itself or all those of older archives, always pass.
+    pub fn verify_stream(&mut self, path: &str) -> Result<()> {
+        let metadata = self.get_metadata()?;
+        let expected = match metadata
+            .get_document("crc32")
+            .and_then(|crc| crc.get_i64(path))
+        {
+            Ok(expected) => expected,
+            Err(_) => return Ok(()),
+        };
+
+        // the zstd model has streams of its own, not written with a codec
+        let zstd = metadata.get_str("model") == Ok(CompressionModel::ZstdSingle.as_str());
+        let mut stream: Box<dyn Read> = match path {
+            "data" if zstd => Box::new(zstd_single_file::decode_data(self, &metadata)?),
+            "dictionary" if zstd => Box::new(self.get_stream(path)?),
+            _ => Box::new(self.get_compressed_stream(path, stream_codec(&metadata, path)?)?),
+        };
+        let mut hasher = crc32fast::Hasher::new();
+        let mut buffer = [0u8; 64 * 1024];
+        loop {
+            match stream.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => hasher.update(&buffer[..n]),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(_) => {
+                    return Err(CompressionModelError::ChecksumMismatch {
+                        path: path.to_string(),
+                    })
+                }
+            }
+        }
+
+        if hasher.finalize() as i64 != expected {
+            return Err(CompressionModelError::ChecksumMismatch {
+                path: path.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Recompute the content checksum recorded in the metadata. Archives written without a
+    /// checksum always pass.
+    pub fn verify_checksum(&mut self, metadata: &Document) -> Result<()> {
+        let checksum = match metadata.get_document("checksum") {
+            Ok(checksum) => checksum,
+            Err(_) => return Ok(()),
+        };
+
+        let algorithm = ChecksumAlgorithm::from_name(checksum.get_str("algorithm")?)?;
+        let mut hasher = match algorithm.hasher() {
+            Some(hasher) => hasher,
+            None => return Ok(()),
+        };
+
+        for path in checksum.get_array("streams")? {
+            let path = path
+                .as_str()
+                .ok_or(document::ValueAccessError::UnexpectedType)?;
+            hash_stream(hasher.as_mut(), &mut self.get_stream(path)?)?;
+        }
+
+        if hasher.hex_digest() != checksum.get_str("digest")? {
+            return Err(CompressionModelError::ContentChecksumMismatch {
+                algorithm: algorithm.as_str().to_string(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// What was compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CompressionStats {
+    pub pairs: u64,
+    pub bases_r1: u64,
+    pub bases_r2: u64,
+    /// The bases of the reads after R2, when there are more than two reads to a fragment.
+    pub bases_extra: u64,
+    /// Pairs left out by a `QualityFilter`, not counted in `pairs`.
+    pub filtered_pairs: u64,
+    /// Reads outside the range of a `LengthCheck`, these are stored like any other.
+    pub unexpected_lengths: u64,
+    /// Pairs stored as a copy of an earlier pair, counted in `pairs` too.
+    pub duplicate_pairs: u64,
+}
+
+impl CompressionStats {
+    pub fn add_pair(&mut self, r1: &FastQRead, r2: &FastQRead) {
+        self.pairs += 1;
+        self.bases_r1 += r1.letters.len() as u64;
+        self.bases_r2 += r2.letters.len() as u64;
+    }
+
+    /// Count a fragment of any number of reads, `pairs` counts fragments.
+    pub fn add_fragment(&mut self, reads: &[FastQRead]) {
+        self.pairs += 1;
+        for (i, read) in reads.iter().enumerate() {
+            let bases = read.letters.len() as u64;
+            match i {
+                0 => self.bases_r1 += bases,
+                1 => self.bases_r2 += bases,
+                _ => self.bases_extra += bases,
+            }
+        }
+    }
+}
+
+pub trait EncoderModel {
+    fn compress(&mut self, reader: &mut Box<dyn PairedFastQReader>) -> Result<CompressionStats>;
+}
+
+/// Decode and throw away up to `pairs` pairs, fewer when the archive ends first.
+pub(crate) fn skip_pairs(decoder: &mut dyn PairDecoder, pairs: u64) -> Result<()> {
+    let mut r1 = FastQRead::default();
+    let mut r2 = FastQRead::default();
+    for _ in 0..pairs {
+        if !decoder.read_next(&mut r1, &mut r2)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Pulls decoded read pairs out of an archive one pair at a time.
+pub trait PairDecoder {
+    fn read_next(&mut self, r1: &mut FastQRead, r2: &mut FastQRead) -> Result<bool>;
+
+    /// Whether the pairs come out in the order they were compressed. Every model keeps to this,
+    /// interleaved output relies on it. A decoder handing out pairs as they are ready, e.g. from
+    /// blocks decoded in parallel, has to say so here.
+    fn keeps_order(&self) -> bool {
+        true
+    }
+}
+
+impl<T: PairDecoder + ?Sized> PairDecoder for &mut T {
+    fn read_next(&mut self, r1: &mut FastQRead, r2: &mut FastQRead) -> Result<bool> {
+        (**self).read_next(r1, r2)
+    }
+
+    fn keeps_order(&self) -> bool {
+        (**self).keeps_order()
+    }
+}
+
+/// The models storing a read as fields in one or more streams, the single file and multi stream
+/// models, end the same way when an archive is cut short:
+///
+/// - the pairs only end when the first field of a pair is at the end of its stream, and every
+///   other stream is at its end too. A stream with data left over is `CorruptStream`.
+/// - any stream ending after the first field of a pair is `IncompleteRecord`.
+///
+/// `read_fields` reads a pair following this, the decoders only say how to read the fields.
+pub(crate) trait FieldDecoder {
+    /// Read the first field of the next pair, false at the end of its stream.
+    fn read_first_field(&mut self, r1: &mut FastQRead) -> Result<bool>;
+
+    /// Read the rest of the pair. Running out of input here is taken as an incomplete record,
+    /// whether it is reported as one or as an unexpected end of file.
+    fn read_rest(&mut self, r1: &mut FastQRead, r2: &mut FastQRead) -> Result<()>;
+
+    /// Check that the streams other than the one of the first field have ended as well.
+    fn check_ended(&mut self) -> Result<()>;
+}
+
+/// Read the next pair from `decoder`, see `FieldDecoder`.
+pub(crate) fn read_fields<D: FieldDecoder + ?Sized>(
+    decoder: &mut D,
+    r1: &mut FastQRead,
+    r2: &mut FastQRead,
+) -> Result<bool> {
+    if !decoder.read_first_field(r1)? {
+        decoder.check_ended()?;
+        return Ok(false);
+    }
+    eof_is_incomplete(decoder.read_rest(r1, r2))?;
+    Ok(true)
+}
+
+/// A field after the first of a record, which has to be there.
+pub(crate) fn required(read: bool) -> Result<()> {
+    match read {
+        true => Ok(()),
+        false => Err(CompressionModelError::IncompleteRecord),
+    }
+}
+
+/// Report a stream that ended part way through a record as `IncompleteRecord`.
+pub(crate) fn eof_is_incomplete(result: Result<()>) -> Result<()> {
+    match result {
+        Err(CompressionModelError::IO { source })
+            if source.kind() == std::io::ErrorKind::UnexpectedEof =>
+        {
+            Err(CompressionModelError::IncompleteRecord)
+        }
+        result => result,
+    }
+}
+
+/// Fail with `CorruptStream` when `source` has anything left once `reads` reads were decoded.
+pub(crate) fn check_stream_ended<S: Read>(source: &mut S, stream: &str, reads: u64) -> Result<()> {
+    if source.read(&mut [0u8])? != 0 {
+        return Err(CompressionModelError::CorruptStream {
+            stream: stream.to_string(),
+            read: reads,
+        });
+    }
+    Ok(())
+}
+
+pub trait DecoderModel {
+    /// Check the archive and start decoding it.
+    fn decoder(&mut self) -> Result<Box<dyn PairDecoder + '_>>;
+
+    fn decompress(&mut self, writer: &mut Box<dyn PairedFastQWriter>) -> Result<()> {
+        let mut r1 = FastQRead::default();
+        let mut r2 = FastQRead::default();
+
+        let mut decoder = self.decoder()?;
+        while decoder.read_next(&mut r1, &mut r2)? {
+            writer.write_next(&r1, &r2)?;
+        }
+        Ok(())
+    }
+
+    /// Start decoding at the pair `start`, counting from 0. The pairs before it are decoded and
+    /// thrown away, models that can find their way into the archive do better.
+    fn decoder_at(&mut self, start: u64) -> Result<Box<dyn PairDecoder + '_>> {
+        let mut decoder = self.decoder()?;
+        skip_pairs(decoder.as_mut(), start)?;
+        Ok(decoder)
+    }
+
+    /// Decompress only the pairs in `range`, counting from 0. A range running past the end of the
+    /// archive stops with its last pair.
+    fn decompress_range(
+        &mut self,
+        writer: &mut Box<dyn PairedFastQWriter>,
+        range: Range<u64>,
+    ) -> Result<()> {
+        let mut r1 = FastQRead::default();
+        let mut r2 = FastQRead::default();
+
+        let mut decoder = self.decoder_at(range.start)?;
+        for _ in range {
+            if !decoder.read_next(&mut r1, &mut r2)? {
+                break;
+            }
+            writer.write_next(&r1, &r2)?;
+        }
+        Ok(())
+    }
+
+    /// Decode the whole archive, only counting the reads. Every read is checked with
+    /// `FastQRead::validate`. A failure is reported as `DecodeFailed` with the index of the read
+    /// pair that couldn't be decoded.
+    fn verify(&mut self) -> Result<CountingFastQWriter> {
+        let mut r1 = FastQRead::default();
+        let mut r2 = FastQRead::default();
+        let mut counter = CountingFastQWriter::default();
+
+        let mut decoder = self.decoder()?;
+        loop {
+            let decoded = decoder.read_next(&mut r1, &mut r2).and_then(|more| {
+                if more {
+                    r1.validate()?;
+                    r2.validate()?;
+                }
+                Ok(more)
+            });
+            match decoded {
+                Ok(true) => {
+                    counter.write_next(&r1, &r2)?;
+                }
+                Ok(false) => return Ok(counter),
+                Err(source) => {
+                    return Err(CompressionModelError::DecodeFailed {
+                        pair: counter.pairs(),
+                        source: Box::new(source),
+                    })
+                }
+            }
+        }
+    }
+
+    /// The decoded read pairs, for callers that want the reads rather than a fastq file.
+    fn pairs(&mut self) -> Result<DecodedPairs<'_>> {
+        Ok(DecodedPairs {
+            decoder: self.decoder()?,
+            done: false,
+        })
+    }
+}
+
+/// Open the reader for `model`, or for the model named in the archive metadata when `model` is
+/// None. `reference` is only needed by the reference model.
+pub fn open_decoder<'a, R: Read + 'a>(
+    mut arc: PareArchiveDecoder<R>,
+    model: Option<CompressionModel>,
+    reference: Option<Reference>,
+) -> Result<Box<dyn DecoderModel + 'a>> {
+    let model = match model {
+        Some(model) => model,
+        None => arc.model()?,
+    };
+
+    let order = arc.input_order()?;
+    let duplicates = arc.duplicates()?;
+    let decoder: Box<dyn DecoderModel + 'a> = match model {
+        CompressionModel::LZMASingle => Box::new(XZSingleFileReader::from_archive(arc)?),
+        CompressionModel::LZMAMulti => Box::new(XZMultiStreamReader::from_archive(arc)?),
+        CompressionModel::LZMAReference => Box::new(XZReferenceReader::from_archive(
+            arc,
+            reference.ok_or(CompressionModelError::MissingReference)?,
+        )?),
+        CompressionModel::LZMABlock => Box::new(XZBlockReader::from_archive(arc)?),
+        CompressionModel::LZMAColumnar => Box::new(XZColumnarReader::from_archive(arc)?),
+        CompressionModel::ZstdSingle => Box::new(ZstdSingleFileReader::from_archive(arc)?),
+    };
+    // the copies are put back before the order is, it is the order of every pair
+    let decoder: Box<dyn DecoderModel + 'a> = match duplicates {
+        Some(duplicates) => Box::new(RestoredDuplicates {
+            model: decoder,
+            duplicates,
+        }),
+        None => decoder,
+    };
+    // sorted archives are put back in the input order
+    Ok(match order {
+        Some(order) => Box::new(RestoredOrder {
+            model: decoder,
+            order,
+        }),
+        None => decoder,
+    })
+}
+
+/// Iterator over the read pairs of an archive. Iteration stops after the first error.
+pub struct DecodedPairs<'a> {
+    decoder: Box<dyn PairDecoder + 'a>,
+    done: bool,
+}
+
+impl Iterator for DecodedPairs<'_> {
+    type Item = Result<(FastQRead, FastQRead)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let mut r1 = FastQRead::default();
+        let mut r2 = FastQRead::default();
+        match self.decoder.read_next(&mut r1, &mut r2) {
+            Ok(true) => Some(Ok((r1, r2))),
+            Ok(false) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::compression_models::lzma_block::*;
+    use crate::compression_models::lzma_columnar::*;
+    use crate::compression_models::lzma_multi_stream::*;
+    use crate::compression_models::lzma_reference::*;
+    use crate::compression_models::lzma_single_file::*;
+    use crate::compression_models::quality_binning::QualityBinning;
+    use crate::compression_models::test_utils::*;
+    use crate::compression_models::title_encoding::TitleLayout;
+    use crate::compression_models::zstd_single_file::*;
+    use crate::seq_files::fastq::*;
+    use tempfile::tempdir;
+
+    fn archive_with_timestamp(timestamp: ArchiveTimestamp) -> Result<Vec<u8>> {
+        let mut archive: Vec<u8> = Vec::new();
+        XZMultiStreamWriter::new(&mut archive)
+            .with_options(EncoderOptions::new().with_timestamp(timestamp))
+            .compress(&mut interleaved_reader(INTERLEAVED_FASTQ))?;
+        Ok(archive)
+    }
+
+    fn entry_mtimes(archive: &[u8]) -> Result<Vec<u64>> {
+        let mut mtimes = Vec::new();
+        for entry in Archive::new(archive).entries()? {
+            mtimes.push(entry?.header().mtime()?);
+        }
+        Ok(mtimes)
+    }
+
+    #[test]
+    fn test_zero_timestamp_is_reproducible() -> Result<()> {
+        let first = archive_with_timestamp(ArchiveTimestamp::Zero)?;
+        let second = archive_with_timestamp(ArchiveTimestamp::Zero)?;
+
+        assert_eq!(first, second);
+        assert!(entry_mtimes(&first)?.iter().all(|m| *m == 0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_now_timestamp_is_recent() -> Result<()> {
+        let before = ArchiveTimestamp::Now.mtime();
+        let archive = archive_with_timestamp(ArchiveTimestamp::Now)?;
+        let after = ArchiveTimestamp::Now.mtime();
+
+        let mtimes = entry_mtimes(&archive)?;
+        assert_eq!(5, mtimes.len());
+        assert!(mtimes.iter().all(|m| before <= *m && *m <= after));
+
+        let mut decoder = PareArchiveDecoder::new(archive.as_slice())?;
+        assert_eq!("now", decoder.get_metadata()?.get_str("timestamp")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(Ok(ArchiveTimestamp::Zero), "zero".parse());
+        assert_eq!(Ok(ArchiveTimestamp::Now), "now".parse());
+        assert_eq!(
+            Ok(ArchiveTimestamp::Epoch(1670371200)),
+            "1670371200".parse()
+        );
+        assert!("yesterday".parse::<ArchiveTimestamp>().is_err());
+    }
+
+    #[test]
+    fn test_dump_metadata() -> Result<()> {
+        let archive = archive_with_timestamp(ArchiveTimestamp::Zero)?;
+        let mut decoder = PareArchiveDecoder::new(archive.as_slice())?;
+
+        let mut dumped: Vec<u8> = Vec::new();
+        decoder.dump_stream("metadata", false, &mut dumped)?;
+        let metadata = Document::from_reader(&mut dumped.as_slice())?;
+        assert_eq!("lzma_multi_stream", metadata.get_str("model")?);
+
+        let mut titles: Vec<u8> = Vec::new();
+        decoder.dump_stream("titles", true, &mut titles)?;
+        assert!(titles.starts_with(b"HWI-EAS209_0006_FC706VJ:5:58:5894:21141#ATCACG/1\n"));
+
+        assert!(matches!(
+            decoder.dump_stream("missing", false, &mut Vec::new()),
+            Err(CompressionModelError::MissingStream { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_streams() -> Result<()> {
+        let archive = archive_with_timestamp(ArchiveTimestamp::Zero)?;
+        let decoder = PareArchiveDecoder::new(archive.as_slice())?;
+
+        let streams = decoder.list_streams()?;
+        let paths: Vec<&str> = streams.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!("metadata", paths[0]);
+        for expected in ["titles", "nucleotides", "qualities"] {
+            assert!(paths.contains(&expected), "{} not in {:?}", expected, paths);
+        }
+        assert!(streams.iter().all(|(_, size)| *size > 0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_level_round_trip() -> Result<()> {
+        let mut archive: Vec<u8> = Vec::new();
+        XZSingleFileWriter::new(&mut archive)
+            .with_options(EncoderOptions::new().with_level(1))
+            .compress(&mut interleaved_reader(INTERLEAVED_FASTQ))?;
+        let mut reader = XZSingleFileReader::new(archive.as_slice())?;
+        let (mut writer, output) = interleaved_writer();
+        reader.decompress(&mut writer)?;
+        drop(writer);
+        assert_eq!(INTERLEAVED_FASTQ, output.text());
+
+        let mut archive: Vec<u8> = Vec::new();
+        XZMultiStreamWriter::new(&mut archive)
+            .with_options(EncoderOptions::new().with_level(1))
+            .compress(&mut interleaved_reader(INTERLEAVED_FASTQ))?;
+        let (mut writer, output) = interleaved_writer();
+        XZMultiStreamReader::new(archive.as_slice())?.decompress(&mut writer)?;
+        drop(writer);
+        assert_eq!(INTERLEAVED_FASTQ, output.text());
+
+        let mut decoder = PareArchiveDecoder::new(archive.as_slice())?;
+        assert_eq!(1, decoder.get_metadata()?.get_i64("level")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lzma_dict_size_round_trip() -> Result<()> {
+        let lzma = LzmaOptions::new(6).with_dict_size(1024 * 1024);
+        assert_eq!(Some(1024 * 1024), lzma.dict_size());
+        assert_eq!(
+            Some(MIN_LZMA_DICT_SIZE),
+            LzmaOptions::new(6).with_dict_size(1).dict_size()
+        );
+
+        // restarted at every pair so the restarted spools have the dictionary too
+        let mut archive: Vec<u8> = Vec::new();
+        XZMultiStreamWriter::new(&mut archive)
+            .with_options(EncoderOptions::new().with_lzma_options(lzma))
+            .with_index(Some(1))
+            .compress(&mut interleaved_reader(INTERLEAVED_FASTQ))?;
+        let (mut writer, output) = interleaved_writer();
+        XZMultiStreamReader::new(archive.as_slice())?.decompress(&mut writer)?;
+        drop(writer);
+        assert_eq!(INTERLEAVED_FASTQ, output.text());
+
+        let mut decoder = PareArchiveDecoder::new(archive.as_slice())?;
+        assert_eq!(6, decoder.get_metadata()?.get_i64("level")?);
+        Ok(())
+    }
+
+    const LOWERCASE_QUALITY_FASTQ: &str = concat!(
+        "@read1/1\n",
+        "ACGTNACGTNACGTNACGTNACGTNACGTN\n",
+        "+\n",
+        "abcdefghijklmnopqrstuvwxyz{|}~\n",
+        "@read1/2\n",
+        "GGGGCCCCAAAATTTTNNNN\n",
+        "+\n",
+        "~~~~}}}}zzzzaaaannnn\n",
+    );
+
+    // Lower case quality letters are high scores, they must come back exactly as they went in
+    // whatever is done with the case of the nucleotides.
+    #[test]
+    fn test_lowercase_qualities_round_trip() -> Result<()> {
+        let mut archive: Vec<u8> = Vec::new();
+        XZSingleFileWriter::new(&mut archive)
+            .compress(&mut interleaved_reader(LOWERCASE_QUALITY_FASTQ))?;
+        let (mut writer, output) = interleaved_writer();
+        XZSingleFileReader::new(archive.as_slice())?.decompress(&mut writer)?;
+        drop(writer);
+        assert_eq!(LOWERCASE_QUALITY_FASTQ, output.text());
+
+        let mut archive: Vec<u8> = Vec::new();
+        XZMultiStreamWriter::new(&mut archive)
+            .compress(&mut interleaved_reader(LOWERCASE_QUALITY_FASTQ))?;
+        let (mut writer, output) = interleaved_writer();
+        XZMultiStreamReader::new(archive.as_slice())?.decompress(&mut writer)?;
+        drop(writer);
+        assert_eq!(LOWERCASE_QUALITY_FASTQ, output.text());
+
+        Ok(())
+    }
+
+    const MIXED_CASE_FASTQ: &str = concat!(
+        "@masked/1\n",
+        "acGTnNAtcg\n",
+        "+\n",
+        "IIIIIIIIII\n",
+        "@masked/2\n",
+        "ACGTACGTAC\n",
+        "+\n",
+        "IIIIIIIIII\n",
+        "@lower/1\n",
+        "acgtacgtac\n",
+        "+\n",
+        "IIIIIIIIII\n",
+        "@lower/2\n",
+        "ACGTAcgtac\n",
+        "+\n",
+        "IIIIIIIIII\n",
+    );
+
+    // Soft masked (lower case) bases are kept by the multi stream and single file models.
+    #[test]
+    fn test_mixed_case_round_trip() -> Result<()> {
+        let mut archive: Vec<u8> = Vec::new();
+        XZMultiStreamWriter::new(&mut archive)
+            .compress(&mut interleaved_reader(MIXED_CASE_FASTQ))?;
+        let (mut writer, output) = interleaved_writer();
+        XZMultiStreamReader::new(archive.as_slice())?.decompress(&mut writer)?;
+        drop(writer);
+        assert_eq!(MIXED_CASE_FASTQ, output.text());
+
+        let mut decoder = PareArchiveDecoder::new(archive.as_slice())?;
+        let mut case: Vec<u8> = Vec::new();
+        decoder.dump_stream("case", true, &mut case)?;
+        assert_eq!("0 2 2 1 2 3\n\n0 10\n5 5\n", String::from_utf8(case)?);
+
+        Ok(())
+    }
+
+    // The other models write the bases back in upper case, the archive says so.
+    #[test]
+    fn test_mixed_case_is_lossy() -> Result<()> {
+        let mut block: Vec<u8> = Vec::new();
+        XZBlockWriter::new(&mut block).compress(&mut interleaved_reader(MIXED_CASE_FASTQ))?;
+        let mut columnar: Vec<u8> = Vec::new();
+        XZColumnarWriter::new(&mut columnar).compress(&mut interleaved_reader(MIXED_CASE_FASTQ))?;
+        let mut zstd: Vec<u8> = Vec::new();
+        ZstdSingleFileWriter::new(&mut zstd).compress(&mut interleaved_reader(MIXED_CASE_FASTQ))?;
+
+        for archive in [&block, &columnar, &zstd] {
+            let (mut writer, output) = interleaved_writer();
+            open_decoder(PareArchiveDecoder::new(archive.as_slice())?, None, None)?
+                .decompress(&mut writer)?;
+            drop(writer);
+            assert_eq!(
+                MIXED_CASE_FASTQ.to_uppercase(),
+                output.text().to_uppercase()
+            );
+
+            let metadata = PareArchiveDecoder::new(archive.as_slice())?.get_metadata()?;
+            assert_eq!("upper", metadata.get_document("lossy")?.get_str("case")?);
+        }
+
+        let mut upper: Vec<u8> = Vec::new();
+        ZstdSingleFileWriter::new(&mut upper)
+            .compress(&mut string_reader(&MIXED_CASE_FASTQ.to_uppercase()))?;
+        let metadata = PareArchiveDecoder::new(upper.as_slice())?.get_metadata()?;
+        assert!(metadata.get_document("lossy").is_err());
+        Ok(())
+    }
+
+    const LOWERCASE_N_FASTQ: &str =
+        concat!("@n/1\n", "atcgn\n", "+\n", "IIIII\n", "@n/2\n", "NnAtN\n", "+\n", "IIIII\n",);
+
+    #[test]
+    fn test_lowercase_n_round_trip() -> Result<()> {
+        let mut multi: Vec<u8> = Vec::new();
+        XZMultiStreamWriter::new(&mut multi)
+            .compress(&mut interleaved_reader(LOWERCASE_N_FASTQ))?;
+        let mut single: Vec<u8> = Vec::new();
+        XZSingleFileWriter::new(&mut single)
+            .compress(&mut interleaved_reader(LOWERCASE_N_FASTQ))?;
+
+        for archive in [&multi, &single] {
+            let (mut writer, output) = interleaved_writer();
+            open_decoder(PareArchiveDecoder::new(archive.as_slice())?, None, None)?
+                .decompress(&mut writer)?;
+            drop(writer);
+            assert_eq!(LOWERCASE_N_FASTQ, output.text());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dropped_qualities() -> Result<()> {
+        for model in [CompressionModel::LZMASingle, CompressionModel::LZMAMulti] {
+            let mut archive: Vec<u8> = Vec::new();
+            crate::compress_paired(
+                &mut interleaved_reader(INTERLEAVED_FASTQ),
+                &mut archive,
+                crate::CompressOptions::new()
+                    .with_model(model.clone())
+                    .with_dropped_qualities(true),
+            )?;
+
+            let mut decoder = PareArchiveDecoder::new(archive.as_slice())?;
+            assert!(decoder.qualities_dropped()?);
+            assert!(!decoder
+                .list_streams()?
+                .iter()
+                .any(|(path, _)| path == "qualities"));
+
+            let mut expected = interleaved_reader(INTERLEAVED_FASTQ);
+            let (mut e1, mut e2) = (FastQRead::default(), FastQRead::default());
+            let mut model = open_decoder(decoder, None, None)?;
+            let mut pairs = model.pairs()?;
+            while expected.read_next(&mut e1, &mut e2)? {
+                let (r1, r2) = pairs.next().expect("a pair for every one compressed")?;
+                for (read, expected) in [(&r1, &e1), (&r2, &e2)] {
+                    assert_eq!(expected.letters, read.letters);
+                    assert_eq!(vec![b'I' - 32; expected.letters.len()], read.qualities);
+                }
+            }
+            assert!(pairs.next().is_none());
+        }
+
+        let mut archive: Vec<u8> = Vec::new();
+        assert!(matches!(
+            crate::compress_paired(
+                &mut interleaved_reader(INTERLEAVED_FASTQ),
+                &mut archive,
+                crate::CompressOptions::new()
+                    .with_model(CompressionModel::LZMABlock)
+                    .with_dropped_qualities(true),
+            ),
+            Err(CompressionModelError::QualitiesRequired { .. })
+        ));
+
+        Ok(())
+    }
+
+    const SUB_TITLE_FASTQ: &str = concat!(
+        "@plus/1\n",
+        "ACGT\n",
+        "+plus/1\n",
+        "IIII\n",
+        "@plus/2\n",
+        "TTGA\n",
+        "+\n",
+        "IIII\n",
+        "@plus2/1\n",
+        "GGCA\n",
+        "+lane 3 tile 14\n",
+        "IIII\n",
+        "@plus2/2\n",
+        "CCAT\n",
+        "+=\n",
+        "IIII\n",
+    );
+
+    #[test]
+    fn test_sub_title_round_trip() -> Result<()> {
+        let mut multi: Vec<u8> = Vec::new();
+        XZMultiStreamWriter::new(&mut multi).compress(&mut interleaved_reader(SUB_TITLE_FASTQ))?;
+        let mut single: Vec<u8> = Vec::new();
+        XZSingleFileWriter::new(&mut single).compress(&mut interleaved_reader(SUB_TITLE_FASTQ))?;
+
+        for archive in [&multi, &single] {
+            let (mut writer, output) = interleaved_writer();
+            open_decoder(PareArchiveDecoder::new(archive.as_slice())?, None, None)?
+                .decompress(&mut writer)?;
+            drop(writer);
+            assert_eq!(SUB_TITLE_FASTQ, output.text());
+        }
+
+        let mut sub_titles: Vec<u8> = Vec::new();
+        PareArchiveDecoder::new(multi.as_slice())?.dump_stream(
+            "sub_titles",
+            true,
+            &mut sub_titles,
+        )?;
+        assert_eq!(
+            "=\n+\n+lane 3 tile 14\n+=\n",
+            String::from_utf8(sub_titles)?
+        );
+
+        assert!(matches!(
+            XZBlockWriter::new(Vec::new()).compress(&mut interleaved_reader(SUB_TITLE_FASTQ)),
+            Err(CompressionModelError::SubTitleNotStored { .. })
+        ));
+        Ok(())
+    }
+
+    const REPEATED_PLUS_FASTQ: &str = concat!(
+        "@plus/1\n",
+        "ACGT\n",
+        "+plus/1\n",
+        "IIII\n",
+        "@plus/2\n",
+        "TTGA\n",
+        "+plus/2\n",
+        "IIII\n",
+    );
+
+    #[test]
+    fn test_plus_lines() -> Result<()> {
+        for (fastq, plus_lines) in [
+            (INTERLEAVED_FASTQ, "empty"),
+            (REPEATED_PLUS_FASTQ, "title"),
+            (SUB_TITLE_FASTQ, "stored"),
+        ] {
+            let mut multi: Vec<u8> = Vec::new();
+            XZMultiStreamWriter::new(&mut multi).compress(&mut interleaved_reader(fastq))?;
+            let mut single: Vec<u8> = Vec::new();
+            XZSingleFileWriter::new(&mut single).compress(&mut interleaved_reader(fastq))?;
+
+            for archive in [&multi, &single] {
+                let mut decoder = PareArchiveDecoder::new(archive.as_slice())?;
+                let metadata = decoder.get_metadata()?.clone();
+                assert_eq!(plus_lines, metadata.get_str("plus_lines")?);
+                assert_eq!(
+                    plus_lines == "stored",
+                    decoder
+                        .dump_stream("sub_titles", false, &mut Vec::new())
+                        .is_ok()
+                );
+
+                let (mut writer, output) = interleaved_writer();
+                open_decoder(PareArchiveDecoder::new(archive.as_slice())?, None, None)?
+                    .decompress(&mut writer)?;
+                drop(writer);
+                assert_eq!(fastq, output.text(), "{}", plus_lines);
+            }
+        }
+
+        Ok(())
+    }
+
+    const AMBIGUOUS_FASTQ: &str = concat!(
+        "@amb/1\n",
+        "NNACGTRYACGTACGN\n",
+        "+\n",
+        "#!IIIIIIIIIIIII#\n",
+        "@amb/2\n",
+        "ACGTTGCAN\n",
+        "+\n",
+        "IIIIIIII#\n",
+    );
+
+    #[test]
+    fn test_packed_nucleotides_round_trip() -> Result<()> {
+        for fastq in [AMBIGUOUS_FASTQ, MIXED_CASE_FASTQ, INTERLEAVED_FASTQ] {
+            let mut archive: Vec<u8> = Vec::new();
+            XZMultiStreamWriter::new(&mut archive)
+                .with_packed_nucleotides(true)
+                .compress(&mut interleaved_reader(fastq))?;
+            let (mut writer, output) = interleaved_writer();
+            XZMultiStreamReader::new(archive.as_slice())?.decompress(&mut writer)?;
+            drop(writer);
+            assert_eq!(fastq, output.text());
+
+            let mut decoder = PareArchiveDecoder::new(archive.as_slice())?;
+            assert_eq!(Ok("packed"), decoder.get_metadata()?.get_str("nucleotides"));
+        }
+
+        Ok(())
+    }
+
+    const THREE_FILES: [&str; 3] = [
+        "@frag1 1:N:0\nACGTTGCAACGTNACG\n+\nIIIIHHHHGGGG!!#I\n@frag2 1:N:0\nttGCA\n+\nABCDE\n",
+        "@frag1 2:N:0\nGGGCCCAAATTTNNNA\n+\nABCDEFGHIJKLMNOP\n@frag2 2:N:0\nCCGTA\n+\nIIIII\n",
+        "@frag1 3:N:0\nATCACG\n+\nFFFFFF\n@frag2 3:N:0\nTTAGGC\n+\nFF:FF,\n",
+    ];
+
+    #[test]
+    fn test_three_files_round_trip() -> Result<()> {
+        let readers: Vec<Box<dyn FastQFileReaderTrait>> = THREE_FILES
+            .iter()
+            .map(|fastq| -> Box<dyn FastQFileReaderTrait> {
+                Box::new(FastQFileReader::new(BufReader::new(fastq.as_bytes())))
+            })
+            .collect();
+        let mut reader: Box<dyn MultiFastQReader> =
+            Box::new(FastQMultiFilesReader::new(readers, false, true));
+
+        let mut archive: Vec<u8> = Vec::new();
+        let stats = XZMultiStreamWriter::new(&mut archive).compress_multi(&mut reader)?;
+        assert_eq!(2, stats.pairs);
+        assert_eq!(12, stats.bases_extra);
+
+        let buffers: Vec<SharedBuffer> = (0..3).map(|_| SharedBuffer::default()).collect();
+        let writers: Vec<Box<dyn FastQFileWriterTrait>> = buffers
+            .iter()
+            .map(|buffer| -> Box<dyn FastQFileWriterTrait> {
+                Box::new(FastQFileWriter::new(std::io::BufWriter::new(
+                    buffer.clone(),
+                )))
+            })
+            .collect();
+        let mut writer: Box<dyn MultiFastQWriter> =
+            Box::new(FastQMultiFilesWriter::new(writers, false));
+        let mut reader = XZMultiStreamReader::new(archive.as_slice())?;
+        assert_eq!(3, reader.reads_per_fragment()?);
+        reader.decompress_multi(&mut writer)?;
+        drop(writer);
+        for (expected, buffer) in THREE_FILES.iter().zip(buffers) {
+            assert_eq!(*expected, buffer.text());
+        }
+
+        // the pair decoder would mix index reads in with R2
+        let (mut writer, _) = interleaved_writer();
+        assert!(matches!(
+            XZMultiStreamReader::new(archive.as_slice())?.decompress(&mut writer),
+            Err(CompressionModelError::ReadsPerFragmentMismatch {
+                expected: 2,
+                actual: 3
+            })
+        ));
+
+        Ok(())
+    }
+
+    const SINGLE_END_FASTQ: &str = concat!(
+        "@single1\n",
+        "ACGTTGCAAC\n",
+        "+\n",
+        "IIIIHHHHGG\n",
+        "@single2\n",
+        "TTGCA\n",
+        "+\n",
+        "ABCDE\n",
+        "@single3\n",
+        "GGGCCCAAATTT\n",
+        "+\n",
+        "FFFFFFFFFFFF\n",
+    );
+
+    #[test]
+    fn test_single_end_round_trip() -> Result<()> {
+        let mut reader: Box<dyn MultiFastQReader> = Box::new(SingleEndReader::new(Box::new(
+            FastQFileReader::new(BufReader::new(SINGLE_END_FASTQ.as_bytes())),
+        )));
+        let mut archive: Vec<u8> = Vec::new();
+        let stats = XZMultiStreamWriter::new(&mut archive).compress_multi(&mut reader)?;
+        assert_eq!(3, stats.pairs);
+
+        let mut decoder = PareArchiveDecoder::new(archive.as_slice())?;
+        assert_eq!(1, decoder.reads_per_fragment()?);
+
+        let buffer = SharedBuffer::default();
+        let mut writer: Box<dyn MultiFastQWriter> = Box::new(SingleEndWriter::new(Box::new(
+            FastQFileWriter::new(std::io::BufWriter::new(buffer.clone())),
+        )));
+        XZMultiStreamReader::from_archive(decoder)?.decompress_multi(&mut writer)?;
+        writer.finish()?;
+        assert_eq!(SINGLE_END_FASTQ, buffer.text());
+
+        // an odd number of reads can't be decoded as pairs
+        let (mut writer, _) = interleaved_writer();
+        assert!(matches!(
+            XZMultiStreamReader::new(archive.as_slice())?.decompress(&mut writer),
+            Err(CompressionModelError::ReadsPerFragmentMismatch {
+                expected: 2,
+                actual: 1
+            })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_files_is_an_error() {
+        let mut reader: Box<dyn MultiFastQReader> =
+            Box::new(FastQMultiFilesReader::new(Vec::new(), false, true));
+
+        let mut archive: Vec<u8> = Vec::new();
+        assert!(matches!(
+            XZMultiStreamWriter::new(&mut archive).compress_multi(&mut reader),
+            Err(CompressionModelError::NoInputFiles)
+        ));
+    }
+
+    #[test]
+    fn test_pair_archive_is_two_reads_per_fragment() -> Result<()> {
+        let mut archive: Vec<u8> = Vec::new();
+        XZMultiStreamWriter::new(&mut archive)
+            .compress(&mut interleaved_reader(INTERLEAVED_FASTQ))?;
+        assert_eq!(
+            2,
+            XZMultiStreamReader::new(archive.as_slice())?.reads_per_fragment()?
+        );
+
+        // archives from before the number of reads was recorded
+        let old_archive = edit_metadata(&archive, |metadata| {
+            metadata.remove("reads");
+        })?;
+        let mut reader = XZMultiStreamReader::new(old_archive.as_slice())?;
+        assert_eq!(2, reader.reads_per_fragment()?);
+        let (mut writer, output) = interleaved_writer();
+        reader.decompress(&mut writer)?;
+        drop(writer);
+        assert_eq!(INTERLEAVED_FASTQ, output.text());
+
+        let mut reads: Box<dyn MultiFastQReader> = Box::new(FastQMultiFilesReader::new(
+            vec![Box::new(FastQFileReader::new(BufReader::new(
+                INTERLEAVED_FASTQ.as_bytes(),
+            )))],
+            false,
+            false,
+        ));
+        assert!(matches!(
+            crate::compress_multi(
+                &mut reads,
+                Vec::new(),
+                crate::CompressOptions::new().with_model(CompressionModel::LZMABlock)
+            ),
+            Err(CompressionModelError::PairsOnlyModel { model }) if model == "lzma_block"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bzip2_qualities_round_trip() -> Result<()> {
+        let mut archive: Vec<u8> = Vec::new();
+        XZMultiStreamWriter::new(&mut archive)
+            .with_quality_codec(Codec::Bzip2)
+            .compress(&mut interleaved_reader(INTERLEAVED_FASTQ))?;
+
+        let mut decoder = PareArchiveDecoder::new(archive.as_slice())?;
+        let streams = decoder.get_metadata()?.get_document("streams")?.clone();
+        assert_eq!("bzip2", streams.get_str("qualities")?);
+        assert_eq!("xz", streams.get_str("nucleotides")?);
+
+        // bzip2 streams start with BZh
+        let mut qualities: Vec<u8> = Vec::new();
+        decoder.dump_stream("qualities", false, &mut qualities)?;
+        assert_eq!(b"BZh", &qualities[..3]);
+
+        let (mut writer, output) = interleaved_writer();
+        XZMultiStreamReader::new(archive.as_slice())?.decompress(&mut writer)?;
+        drop(writer);
+        assert_eq!(INTERLEAVED_FASTQ, output.text());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_brotli_titles_round_trip() -> Result<()> {
+        for layout in [
+            TitleLayout::Verbatim,
+            TitleLayout::IlluminaColumns,
+            TitleLayout::Tokens,
+        ] {
+            let mut archive: Vec<u8> = Vec::new();
+            XZMultiStreamWriter::new(&mut archive)
+                .with_title_layout(layout)
+                .with_title_codec(Codec::Brotli)
+                .compress(&mut interleaved_reader(INTERLEAVED_FASTQ))?;
+
+            let mut decoder = PareArchiveDecoder::new(archive.as_slice())?;
+            let streams = decoder.get_metadata()?.get_document("streams")?.clone();
+            assert_eq!("brotli", streams.get_str("titles")?);
+            assert_eq!("xz", streams.get_str("nucleotides")?);
+            assert_eq!("xz", streams.get_str("qualities")?);
+
+            let (mut writer, output) = interleaved_writer();
+            XZMultiStreamReader::new(archive.as_slice())?.decompress(&mut writer)?;
+            drop(writer);
+            assert_eq!(INTERLEAVED_FASTQ, output.text(), "{}", layout.as_str());
+        }
+
+        // brotli streams can't be restarted at the sync points of an index
+        assert!(matches!(
+            XZMultiStreamWriter::new(Vec::new())
+                .with_title_codec(Codec::Brotli)
+                .with_index(Some(1))
+                .compress(&mut interleaved_reader(INTERLEAVED_FASTQ)),
+            Err(CompressionModelError::CodecCantRestart { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_titles_without_recorded_codec() -> Result<()> {
+        let mut archive: Vec<u8> = Vec::new();
+        XZMultiStreamWriter::new(&mut archive)
+            .compress(&mut interleaved_reader(INTERLEAVED_FASTQ))?;
+        // archives from before the title codec could be chosen, their titles are all xz
+        let old_archive = edit_metadata(&archive, |metadata| {
+            metadata
+                .get_document_mut("streams")
+                .unwrap()
+                .remove("titles");
+        })?;
+
+        let (mut writer, output) = interleaved_writer();
+        XZMultiStreamReader::new(old_archive.as_slice())?.decompress(&mut writer)?;
+        drop(writer);
+        assert_eq!(INTERLEAVED_FASTQ, output.text());
+        Ok(())
+    }
+
+    const RNA_FASTQ: &str = concat!(
+        "@rna/1\n",
+        "ACGUNUGCAU\n",
+        "+\n",
+        "IIIIIIIIII\n",
+        "@rna/2\n",
+        "UUUUAAAACC\n",
+        "+\n",
+        "IIIIIIIIII\n",
+    );
+
+    #[test]
+    fn test_rna_round_trip() -> Result<()> {
+        let mut reader: Box<dyn PairedFastQReader> = Box::new(FastQInterleavedFileReader::new(
+            Box::new(
+                FastQFileReader::new(BufReader::new(RNA_FASTQ.as_bytes()))
+                    .with_alphabet(Alphabet::Rna),
+            ),
+            false,
+        ));
+        let mut archive: Vec<u8> = Vec::new();
+        XZSingleFileWriter::new(&mut archive)
+            .with_options(EncoderOptions::new().with_alphabet(Alphabet::Rna))
+            .compress(&mut reader)?;
+
+        let mut arc = PareArchiveDecoder::new(archive.as_slice())?;
+        assert_eq!(Alphabet::Rna, arc.alphabet()?);
+
+        let output = SharedBuffer::default();
+        let mut writer: Box<dyn PairedFastQWriter> = Box::new(FastQInterleavedFileWriter::new(
+            Box::new(
+                FastQFileWriter::new(std::io::BufWriter::new(output.clone()))
+                    .with_alphabet(Alphabet::Rna),
+            ),
+            false,
+        ));
+        XZSingleFileReader::from_archive(arc)?.decompress(&mut writer)?;
+        drop(writer);
+        assert_eq!(RNA_FASTQ, output.text());
+
+        // T isn't an RNA base
+        let mut reader = FastQFileReader::new(BufReader::new(INTERLEAVED_FASTQ.as_bytes()))
+            .with_alphabet(Alphabet::Rna);
+        assert!(matches!(
+            reader
+                .read_next(&mut FastQRead::default())
+                .unwrap_err()
+                .cause(),
+            FastQFileError::InvalidNucleotideLetter { c: 'T' }
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_decompress_range() -> Result<()> {
+        let mut indexed: Vec<u8> = Vec::new();
+        XZMultiStreamWriter::new(&mut indexed)
+            .with_index(Some(4))
+            .compress(&mut SyntheticFastq::default().with_illumina_reads().reader(10))?;
+        let mut single: Vec<u8> = Vec::new();
+        XZSingleFileWriter::new(&mut single)
+            .compress(&mut SyntheticFastq::default().with_illumina_reads().reader(10))?;
+
+        for archive in [&indexed, &single] {
+            let expected: Vec<(FastQRead, FastQRead)> =
+                open_decoder(PareArchiveDecoder::new(archive.as_slice())?, None, None)?
+                    .pairs()?
+                    .collect::<Result<_>>()?;
+            assert_eq!(10, expected.len());
+
+            for (range, slice) in [(5..8, 5..8), (0..2, 0..2), (8..20, 8..10), (12..14, 0..0)] {
+                let (mut writer, output) = interleaved_writer();
+                open_decoder(PareArchiveDecoder::new(archive.as_slice())?, None, None)?
+                    .decompress_range(&mut writer, range.clone())?;
+                drop(writer);
+
+                let (mut writer, slice_output) = interleaved_writer();
+                for (r1, r2) in &expected[slice] {
+                    writer.write_next(r1, r2)?;
+                }
+                drop(writer);
+                assert_eq!(slice_output.text(), output.text(), "{:?}", range);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_pair_at() -> Result<()> {
+        let mut archive: Vec<u8> = Vec::new();
+        XZMultiStreamWriter::new(&mut archive)
+            .compress(&mut SyntheticFastq::default().with_illumina_reads().reader(1000))?;
+        let expected: Vec<(FastQRead, FastQRead)> = XZMultiStreamReader::new(archive.as_slice())?
+            .pairs()?
+            .collect::<Result<_>>()?;
+
+        // without an index it decodes from the start
+        let mut reader = XZMultiStreamReader::new(archive.as_slice())?;
+        assert_eq!(expected[500], reader.read_pair_at(500)?);
+
+        for (layout, codec, parallel) in [
+            (TitleLayout::Verbatim, Codec::Xz, true),
+            (TitleLayout::Delta, Codec::Bzip2, false),
+            (TitleLayout::IlluminaColumns, Codec::Xz, false),
+            (TitleLayout::Tokens, Codec::Bzip2, true),
+        ] {
+            let mut archive: Vec<u8> = Vec::new();
+            XZMultiStreamWriter::new(&mut archive)
+                .with_title_layout(layout)
+                .with_quality_codec(codec)
+                .with_packed_nucleotides(!parallel)
+                .with_parallel_streams(parallel)
+                .with_index(Some(64))
+                .compress(&mut SyntheticFastq::default().with_illumina_reads().reader(1000))?;
+
+            let mut reader = XZMultiStreamReader::new(archive.as_slice())?;
+            for index in [500, 0, 63, 64, 999] {
+                assert_eq!(
+                    expected[index],
+                    reader.read_pair_at(index as u64)?,
+                    "{}",
+                    index
+                );
+            }
+            assert!(matches!(
+                reader.read_pair_at(1000),
+                Err(CompressionModelError::PairOutOfRange { index: 1000 })
+            ));
+
+            // a full decode runs through the sync points
+            let decoded: Vec<(FastQRead, FastQRead)> = reader.pairs()?.collect::<Result<_>>()?;
+            assert!(expected == decoded, "{}", layout.as_str());
+        }
+
+        Ok(())
+    }
+
+    // enough reads to fill several chunks
+    #[test]
+    fn test_parallel_streams_match_serial() -> Result<()> {
+        for layout in [
+            TitleLayout::Verbatim,
+            TitleLayout::IlluminaColumns,
+            TitleLayout::Tokens,
+        ] {
+            let mut serial: Vec<u8> = Vec::new();
+            XZMultiStreamWriter::new(&mut serial)
+                .with_options(EncoderOptions::new().with_level(0))
+                .with_title_layout(layout)
+                .with_parallel_streams(false)
+                .compress(
+                    &mut SyntheticFastq::default()
+                        .with_illumina_reads()
+                        .reader(12_000),
+                )?;
+
+            let mut parallel: Vec<u8> = Vec::new();
+            XZMultiStreamWriter::new(&mut parallel)
+                .with_options(EncoderOptions::new().with_level(0))
+                .with_title_layout(layout)
+                .compress(
+                    &mut SyntheticFastq::default()
+                        .with_illumina_reads()
+                        .reader(12_000),
+                )?;
+
+            assert!(serial == parallel, "{}", layout.as_str());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parallel_streams_reader_error() {
+        let mut archive: Vec<u8> = Vec::new();
+        let actual = XZMultiStreamWriter::new(&mut archive).compress(&mut interleaved_reader(
+            "@read/1\nACGT\n+\nIIII\n@read/2\nACGT\n+\nIII\n",
+        ));
+        assert!(matches!(
+            actual,
+            Err(CompressionModelError::FastQError {
+                source: FastQFileError::AtLine { line: 5, source }
+            }) if matches!(*source, FastQFileError::MismatchedSequenceLength)
+        ));
+    }
+
+    #[test]
+    fn test_parallel_decode_matches_serial() -> Result<()> {
+        let mut archive: Vec<u8> = Vec::new();
+        XZMultiStreamWriter::new(&mut archive)
+            .with_options(
+                EncoderOptions::new()
+                    .with_level(0)
+                    .with_checksum(ChecksumAlgorithm::None),
+            )
+            .with_title_layout(TitleLayout::Tokens)
+            .with_packed_nucleotides(true)
+            .compress(
+                &mut SyntheticFastq::default()
+                    .with_illumina_reads()
+                    .reader(12_000),
+            )?;
+
+        let decode = |archive: &[u8], parallel: bool| -> Result<String> {
+            let (mut writer, output) = interleaved_writer();
+            XZMultiStreamReader::new(archive)?
+                .with_parallel_streams(parallel)
+                .decompress(&mut writer)?;
+            drop(writer);
+            Ok(output.text())
+        };
+        let serial = decode(&archive, false)?;
+        assert!(serial == decode(&archive, true)?);
+
+        // a stream that fails to decode on its thread fails the decompression the same way
+        corrupt_stream(&mut archive, "nucleotides");
+        let serial = decode(&archive, false).unwrap_err();
+        let parallel = decode(&archive, true).unwrap_err();
+        assert_eq!(format!("{:?}", serial), format!("{:?}", parallel));
+
+        let garbage = ArchiveStream::Memory(Cursor::new(Arc::from(&b"not an xz stream"[..])));
+        let mut threaded = ThreadedDecoder::spawn(StreamDecoder::Xz(XzDecoder::new(garbage)));
+        assert!(threaded.read_to_end(&mut Vec::new()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_quality_binning_is_recorded() -> Result<()> {
+        let mut archive: Vec<u8> = Vec::new();
+        XZBlockWriter::new(&mut archive)
+            .with_options(
+                EncoderOptions::new().with_quality_binning(Some(QualityBinning::Illumina8)),
+            )
+            .compress(&mut interleaved_reader(LOWERCASE_QUALITY_FASTQ))?;
+
+        let mut decoder = PareArchiveDecoder::new(archive.as_slice())?;
+        let metadata = decoder.get_metadata()?;
+        assert_eq!(
+            "illumina8",
+            metadata.get_document("lossy")?.get_str("quality_bins")?
+        );
+
+        let (mut writer, output) = interleaved_writer();
+        XZBlockReader::new(archive.as_slice())?.decompress(&mut writer)?;
+        drop(writer);
+        let text = output.text();
+        let lines: Vec<&str> = text.lines().collect();
+        // lower case letters are all well above Q40 and land in the top bin
+        assert_eq!("IIIIIIIIIIIIIIIIIIIIIIIIIIIIII", lines[3]);
+        assert_eq!(
+            LOWERCASE_QUALITY_FASTQ.lines().nth(1),
+            lines.get(1).copied()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_decoded_pairs() -> Result<()> {
+        let expected: Vec<&str> = INTERLEAVED_FASTQ.lines().collect();
+
+        let mut archive: Vec<u8> = Vec::new();
+        XZMultiStreamWriter::new(&mut archive)
+            .compress(&mut interleaved_reader(INTERLEAVED_FASTQ))?;
+        let mut reader = XZMultiStreamReader::new(archive.as_slice())?;
+        assert!(reader.decoder()?.keeps_order());
+        let pairs: Vec<(FastQRead, FastQRead)> = reader.pairs()?.collect::<Result<_>>()?;
+        assert_eq!(2, pairs.len());
+        assert_eq!(expected[0][1..], pairs[0].0.title);
+        assert_eq!(expected[4][1..], pairs[0].1.title);
+        assert_eq!(expected[12][1..], pairs[1].1.title);
+        assert_eq!(b"ggg", &pairs[1].1.letters[..3]);
+
+        let mut archive: Vec<u8> = Vec::new();
+        XZSingleFileWriter::new(&mut archive)
+            .compress(&mut interleaved_reader(INTERLEAVED_FASTQ))?;
+        let mut reader = XZSingleFileReader::new(archive.as_slice())?;
+        let titles: Vec<String> = reader
+            .pairs()?
+            .map(|pair| pair.map(|(r1, _)| r1.title))
+            .collect::<Result<_>>()?;
+        assert_eq!(vec![&expected[0][1..], &expected[8][1..]], titles);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupt_block_size() -> Result<()> {
+        let mut archive: Vec<u8> = Vec::new();
+        XZColumnarWriter::new(&mut archive).compress(&mut interleaved_reader(INTERLEAVED_FASTQ))?;
+
+        for block_size in [0, i64::MAX] {
+            let corrupt = edit_metadata(&archive, |metadata| {
+                metadata.insert("block_size", block_size);
+            })?;
+            assert!(matches!(
+                XZColumnarReader::new(corrupt.as_slice()),
+                Err(CompressionModelError::InvalidMetadata { value, .. }) if value == block_size
+            ));
+        }
+        Ok(())
+    }
+
+    /// The same archive with `edit` applied to its metadata.
+    fn edit_metadata(archive: &[u8], edit: impl FnOnce(&mut Document)) -> Result<Vec<u8>> {
+        let mut edit = Some(edit);
+        let mut entries: Vec<(String, Vec<u8>)> = Vec::new();
+        for entry in Archive::new(archive).entries()? {
+            let mut entry = entry?;
+            let path = entry.path()?.to_string_lossy().into_owned();
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data)?;
+            if path == "metadata" {
+                let mut metadata = Document::from_reader(data.as_slice())?;
+                edit.take().expect("a single metadata member")(&mut metadata);
+                data.clear();
+                metadata.to_writer(&mut data)?;
+            }
+            entries.push((path, data));
+        }
+        let entries: Vec<(&str, &[u8])> = entries
+            .iter()
+            .map(|(path, data)| (path.as_str(), data.as_slice()))
+            .collect();
+        archive_of(&entries)
+    }
+
+    /// Rewrite the xz member `path` of `archive` with its decompressed content edited.
+    fn edit_xz_stream(
+        archive: &[u8],
+        path: &str,
+        edit: impl FnOnce(&mut Vec<u8>),
+    ) -> Result<Vec<u8>> {
+        let mut edit = Some(edit);
+        let mut entries: Vec<(String, Vec<u8>)> = Vec::new();
+        for entry in Archive::new(archive).entries()? {
+            let mut entry = entry?;
+            let name = entry.path()?.to_string_lossy().into_owned();
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data)?;
+            if name == path {
+                let mut content = Vec::new();
+                XzDecoder::new_multi_decoder(data.as_slice()).read_to_end(&mut content)?;
+                edit.take().expect("a single member named path")(&mut content);
+                let mut encoder = XzEncoder::new(Vec::new(), 0);
+                encoder.write_all(&content)?;
+                data = encoder.finish()?;
+            }
+            entries.push((name, data));
+        }
+        let entries: Vec<(&str, &[u8])> = entries
+            .iter()
+            .map(|(path, data)| (path.as_str(), data.as_slice()))
+            .collect();
+        archive_of(&entries)
+    }
+
+    fn archive_of(entries: &[(&str, &[u8])]) -> Result<Vec<u8>> {
+        let mut builder = Builder::new(Vec::new());
+        for (path, data) in entries {
+            let mut header = Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, path, *data)?;
+        }
+        Ok(builder.into_inner()?)
+    }
+
+    #[test]
+    fn test_verify() -> Result<()> {
+        let mut archive: Vec<u8> = Vec::new();
+        XZSingleFileWriter::new(&mut archive)
+            .with_options(EncoderOptions::new().with_checksum(ChecksumAlgorithm::None))
+            .compress(&mut interleaved_reader(INTERLEAVED_FASTQ))?;
+
+        let counter = XZSingleFileReader::new(archive.as_slice())?.verify()?;
+        assert_eq!(2, counter.pairs());
+        assert_eq!(4, counter.records());
+        assert_eq!(232, counter.bases());
+
+        // cut the data short in the middle of the second pair
+        let mut decoder = PareArchiveDecoder::new(archive.as_slice())?;
+        let mut metadata: Vec<u8> = Vec::new();
+        decoder.dump_stream("metadata", false, &mut metadata)?;
+        let mut case: Vec<u8> = Vec::new();
+        decoder.dump_stream("case", false, &mut case)?;
+        let mut data: Vec<u8> = Vec::new();
+        decoder.dump_stream("data", true, &mut data)?;
+        data.truncate(data.len() - 20);
+        let mut encoder = XzEncoder::new(Vec::new(), 0);
+        encoder.write_all(&data)?;
+        let truncated = archive_of(&[
+            ("metadata", &metadata),
+            ("data", &encoder.finish()?),
+            ("case", &case),
+        ])?;
+
+        assert!(matches!(
+            XZSingleFileReader::new(truncated.as_slice())?.verify(),
+            Err(CompressionModelError::DecodeFailed { pair: 1, .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_qualities_out_of_step() -> Result<()> {
+        let mut archive: Vec<u8> = Vec::new();
+        XZMultiStreamWriter::new(&mut archive)
+            .with_options(EncoderOptions::new().with_checksum(ChecksumAlgorithm::None))
+            .compress(&mut interleaved_reader(INTERLEAVED_FASTQ))?;
+
+        // too few qualities for the last read, then some left over after it
+        let truncated = edit_xz_stream(&archive, "qualities", |qualities| {
+            qualities.truncate(qualities.len() - 5)
+        })?;
+        let extended = edit_xz_stream(&archive, "qualities", |qualities| {
+            qualities.extend_from_slice(&[40; 5])
+        })?;
+
+        let (mut writer, _) = interleaved_writer();
+        assert!(matches!(
+            XZMultiStreamReader::new(truncated.as_slice())?.decompress(&mut writer),
+            Err(CompressionModelError::IncompleteRecord)
+        ));
+        let (mut writer, _) = interleaved_writer();
+        assert!(matches!(
+            XZMultiStreamReader::new(extended.as_slice())?.decompress(&mut writer),
+            Err(CompressionModelError::CorruptStream { stream, read: 4 }) if stream == "qualities"
+        ));
+
+        // a quality no read can have, the stream got ahead of the bases
+        let shifted = edit_xz_stream(&archive, "qualities", |qualities| qualities[150] = 0)?;
+        let (mut writer, _) = interleaved_writer();
+        assert!(matches!(
+            XZMultiStreamReader::new(shifted.as_slice())?.decompress(&mut writer),
+            Err(CompressionModelError::CorruptStream { stream, read: 2 }) if stream == "qualities"
+        ));
+
+        // the single file model keeps the qualities between the fields of the data stream
+        let mut archive: Vec<u8> = Vec::new();
+        XZSingleFileWriter::new(&mut archive)
+            .with_options(EncoderOptions::new().with_checksum(ChecksumAlgorithm::None))
+            .compress(&mut interleaved_reader(INTERLEAVED_FASTQ))?;
+        let shifted = edit_xz_stream(&archive, "data", |data| {
+            let fields = data.iter().rposition(|b| *b == 0xFF).unwrap();
+            data[fields + 1] = 0;
+        })?;
+        let (mut writer, _) = interleaved_writer();
+        assert!(matches!(
+            XZSingleFileReader::new(shifted.as_slice())?.decompress(&mut writer),
+            Err(CompressionModelError::CorruptStream { stream, read: 3 }) if stream == "data"
+        ));
+
+        Ok(())
+    }
+
+    /// Where each field of the last pair starts in the data stream of the single file model, the
+    /// titles and sequences end at a 0xFF and the qualities follow them without one.
+    fn last_pair_fields(data: &[u8]) -> Vec<usize> {
+        let mut start = 0;
+        loop {
+            let mut fields = vec![start];
+            for _ in 0..4 {
+                let from = *fields.last().unwrap();
+                fields.push(from + data[from..].iter().position(|b| *b == 0xFF).unwrap() + 1);
+            }
+            let r1 = fields[3] - fields[2] - 1;
+            let r2 = fields[4] - fields[3] - 1;
+            fields.push(fields[4] + r1);
+            let end = fields[5] + r2;
+            if end == data.len() {
+                return fields;
+            }
+            start = end;
+        }
+    }
+
+    #[test]
+    fn test_truncated_fields() -> Result<()> {
+        let mut single: Vec<u8> = Vec::new();
+        XZSingleFileWriter::new(&mut single)
+            .with_options(EncoderOptions::new().with_checksum(ChecksumAlgorithm::None))
+            .compress(&mut interleaved_reader(INTERLEAVED_FASTQ))?;
+        let mut multi: Vec<u8> = Vec::new();
+        XZMultiStreamWriter::new(&mut multi)
+            .with_options(EncoderOptions::new().with_checksum(ChecksumAlgorithm::None))
+            .compress(&mut interleaved_reader(INTERLEAVED_FASTQ))?;
+
+        let lengths: Vec<usize> = INTERLEAVED_FASTQ
+            .lines()
+            .skip(1)
+            .step_by(4)
+            .map(str::len)
+            .collect();
+        let (r1, r2) = (lengths[lengths.len() - 2], lengths[lengths.len() - 1]);
+        let drop_lines = |lines: usize| {
+            move |stream: &mut Vec<u8>| {
+                for _ in 0..lines {
+                    stream.pop();
+                    let start = stream
+                        .iter()
+                        .rposition(|b| *b == b'\n')
+                        .map_or(0, |i| i + 1);
+                    stream.truncate(start);
+                }
+            }
+        };
+
+        // the last pair cut short from each of its fields on, the first is a pair missing
+        type StreamCut = Box<dyn Fn(&mut Vec<u8>)>;
+        let cuts: [(&str, StreamCut); 6] = [
+            ("titles", Box::new(drop_lines(2))),
+            ("titles", Box::new(drop_lines(1))),
+            ("nucleotides", Box::new(drop_lines(2))),
+            ("nucleotides", Box::new(drop_lines(1))),
+            (
+                "qualities",
+                Box::new(move |q| q.truncate(q.len() - r1 - r2)),
+            ),
+            ("qualities", Box::new(move |q| q.truncate(q.len() - r2))),
+        ];
+        for (field, (stream, cut)) in cuts.iter().enumerate() {
+            let single = edit_xz_stream(&single, "data", |data| {
+                data.truncate(last_pair_fields(data)[field])
+            })?;
+            let multi = edit_xz_stream(&multi, stream, cut)?;
+
+            let (mut writer, _) = interleaved_writer();
+            let single = XZSingleFileReader::new(single.as_slice())?
+                .decompress(&mut writer)
+                .unwrap_err();
+            let (mut writer, _) = interleaved_writer();
+            let multi = XZMultiStreamReader::new(multi.as_slice())?
+                .decompress(&mut writer)
+                .unwrap_err();
+
+            assert_eq!(
+                std::mem::discriminant(&single),
+                std::mem::discriminant(&multi),
+                "field {}: {:?} and {:?}",
+                field,
+                single,
+                multi
+            );
+            match field {
+                // the other streams have the pair
+                0 => assert!(matches!(multi, CompressionModelError::CorruptStream { .. })),
+                _ => assert!(matches!(multi, CompressionModelError::IncompleteRecord)),
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_stream() -> Result<()> {
+        let mut archive: Vec<u8> = Vec::new();
+        write_with(&CompressionModel::LZMAMulti, &mut archive)?;
+
+        let mut decoder = PareArchiveDecoder::new(archive.as_slice())?;
+        let streams = decoder.list_streams()?;
+        for (path, _) in &streams {
+            decoder.verify_stream(path)?;
+        }
+
+        let path = decoder
+            .temp_path()
+            .expect("opened with new")
+            .join("nucleotides");
+        let mut stream = std::fs::read(&path)?;
+        stream[20] ^= 0x04;
+        std::fs::write(&path, stream)?;
+
+        assert!(matches!(
+            decoder.verify_stream("nucleotides"),
+            Err(CompressionModelError::ChecksumMismatch { path }) if path == "nucleotides"
+        ));
+        decoder.verify_stream("qualities")?;
+
+        // a stream that decodes fine but to something else fails too
+        let path = decoder
+            .temp_path()
+            .expect("opened with new")
+            .join("qualities");
+        let mut encoder = XzEncoder::new(Vec::new(), 0);
+        encoder.write_all(b"IIII\n")?;
+        std::fs::write(&path, encoder.finish()?)?;
+        assert!(matches!(
+            decoder.verify_stream("qualities"),
+            Err(CompressionModelError::ChecksumMismatch { path }) if path == "qualities"
+        ));
+
+        // the zstd model's data and dictionary streams are checked too
+        let fastq = SyntheticFastq::default().interleaved(2_000);
+        for train in [false, true] {
+            let mut archive: Vec<u8> = Vec::new();
+            ZstdSingleFileWriter::new(&mut archive)
+                .with_trained_dictionary(train)
+                .compress(&mut string_reader(&fastq))?;
+
+            let mut decoder = PareArchiveDecoder::new(archive.as_slice())?;
+            let crc32 = decoder.get_metadata()?.get_document("crc32")?.clone();
+            assert!(crc32.contains_key("data"));
+            assert_eq!(train, crc32.contains_key("dictionary"));
+            for (path, _) in decoder.list_streams()? {
+                decoder.verify_stream(&path)?;
+            }
+
+            let stream = match train {
+                true => "dictionary",
+                false => "data",
+            };
+            let path = decoder.temp_path().expect("opened with new").join(stream);
+            match train {
+                true => {
+                    let mut dictionary = std::fs::read(&path)?;
+                    dictionary[20] ^= 0x04;
+                    std::fs::write(&path, dictionary)?;
+                }
+                false => std::fs::write(&path, zstd::encode_all(&b"other"[..], 0)?)?,
+            }
+            assert!(matches!(
+                decoder.verify_stream(stream),
+                Err(CompressionModelError::ChecksumMismatch { path }) if path == stream
+            ));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_case_runs() -> Result<()> {
+        let mask = vec![false, false, true, true, true, false];
+        let mut line = Vec::new();
+        write_case_runs(&mut line, &mask)?;
+
+        let mut parsed = Vec::new();
+        parse_case_runs(line.trim_ascii_end(), mask.len(), &mut parsed)?;
+        assert_eq!(mask, parsed);
+
+        // runs longer than the read are corrupt, however long they claim to be
+        for line in [&b"2 99999999999"[..], b"2 18446744073709551615", b"2 3 2"] {
+            assert!(matches!(
+                parse_case_runs(line, mask.len(), &mut parsed),
+                Err(CompressionModelError::IncompleteRecord)
+            ));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_metadata() -> Result<()> {
+        let archive = archive_of(&[("data", b"not a pare archive")])?;
+        let mut decoder = PareArchiveDecoder::new(archive.as_slice())?;
+        assert!(matches!(
+            decoder.get_metadata(),
+            Err(CompressionModelError::MissingMetadata)
+        ));
+        assert!(matches!(
+            XZSingleFileReader::new(archive.as_slice()),
+            Err(CompressionModelError::MissingMetadata)
+        ));
+
+        // a metadata document that doesn't name its model
+        let mut metadata: Vec<u8> = Vec::new();
+        doc! { "version": 1 }.to_writer(&mut metadata)?;
+        let archive = archive_of(&[("metadata", &metadata)])?;
+        let mut decoder = PareArchiveDecoder::new(archive.as_slice())?;
+        assert!(matches!(
+            decoder.get_metadata(),
+            Err(CompressionModelError::MissingMetadata)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_stream_before_metadata() -> Result<()> {
+        let mut archive: Vec<u8> = Vec::new();
+        let mut encoder = PareArchiveEncoder::new(&mut archive);
+        assert!(matches!(
+            encoder.write_stream(&mut Cursor::new(b"early"), "data"),
+            Err(CompressionModelError::MetadataNotFirst { path }) if path == "data"
+        ));
+
+        encoder.stage_spool(SpooledTempFile::new(1024), "staged");
+        encoder.write_metadata(doc! { "model": CompressionModel::LZMASingle.as_str() })?;
+        encoder.write_stream(&mut Cursor::new(b"late"), "data")?;
+        encoder.finish()?;
+        drop(encoder);
+
+        let entries: Vec<String> = tar::Archive::new(archive.as_slice())
+            .entries()?
+            .map(|entry| Ok(entry?.path()?.to_string_lossy().into_owned()))
+            .collect::<Result<_>>()?;
+        assert_eq!(vec!["metadata", "staged", "data"], entries);
+        Ok(())
+    }
+
+    const REFERENCE_FASTA: &str = ">chr1\nTTAATTGGTAAATAAATCTCCTAATAGCTTAGATATTACCTT\n";
+
+    fn reference() -> Result<Reference> {
+        Reference::from_fasta(REFERENCE_FASTA.as_bytes())
+    }
+
+    fn write_with(model: &CompressionModel, archive: &mut Vec<u8>) -> Result<CompressionStats> {
+        let mut reader = interleaved_reader(INTERLEAVED_FASTQ);
+        match model {
+            CompressionModel::LZMASingle => XZSingleFileWriter::new(archive).compress(&mut reader),
+            CompressionModel::LZMAMulti => XZMultiStreamWriter::new(archive).compress(&mut reader),
+            CompressionModel::LZMAReference => {
+                XZReferenceWriter::new(archive, reference()?).compress(&mut reader)
+            }
+            CompressionModel::LZMABlock => XZBlockWriter::new(archive).compress(&mut reader),
+            CompressionModel::LZMAColumnar => XZColumnarWriter::new(archive).compress(&mut reader),
+            CompressionModel::ZstdSingle => {
+                ZstdSingleFileWriter::new(archive).compress(&mut reader)
+            }
+        }
+    }
+
+    #[test]
+    fn test_detect_model() -> Result<()> {
+        for model in [
+            CompressionModel::LZMASingle,
+            CompressionModel::LZMAMulti,
+            CompressionModel::LZMAReference,
+            CompressionModel::LZMABlock,
+            CompressionModel::LZMAColumnar,
+            CompressionModel::ZstdSingle,
+        ] {
+            let mut archive: Vec<u8> = Vec::new();
+            write_with(&model, &mut archive)?;
+            assert_eq!(model, detect_model(archive.as_slice())?);
+        }
+
+        let mut state: u32 = 11;
+        let random: Vec<u8> = (0..4096)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (state >> 16) as u8
+            })
+            .collect();
+        let err = detect_model(random.as_slice()).unwrap_err();
+        assert_eq!(
+            "The input is unrecognised data, not a pare archive",
+            err.to_string()
+        );
+        assert!(matches!(
+            detect_model(&b"short"[..]),
+            Err(CompressionModelError::NotAnArchive { .. })
+        ));
+        assert!(matches!(
+            detect_model(&[0xfd, b'7', b'z', b'X', b'Z', 0, 0, 4][..]),
+            Err(CompressionModelError::NotAnArchive { kind }) if kind == "an xz stream"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_decoder_detects_model() -> Result<()> {
+        for model in [
+            CompressionModel::LZMASingle,
+            CompressionModel::LZMAMulti,
+            CompressionModel::LZMAReference,
+            CompressionModel::LZMABlock,
+            CompressionModel::LZMAColumnar,
+            CompressionModel::ZstdSingle,
+        ] {
+            let mut archive: Vec<u8> = Vec::new();
+            write_with(&model, &mut archive)?;
+
+            let mut arc = PareArchiveDecoder::new(archive.as_slice())?;
+            assert_eq!(model, arc.model()?);
+
+            let (mut writer, output) = interleaved_writer();
+            open_decoder(arc, None, Some(reference()?))?.decompress(&mut writer)?;
+            drop(writer);
+            assert_eq!(INTERLEAVED_FASTQ, output.text(), "{}", model.as_str());
+        }
+        Ok(())
+    }
+
+    /// Reads like a pipe: a few bytes at a time and no seeking.
+    struct Pipe<'a>(&'a [u8]);
+
+    impl Read for Pipe<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = buf.len().min(self.0.len()).min(7);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    impl Seek for Pipe<'_> {
+        fn seek(&mut self, _: SeekFrom) -> std::io::Result<u64> {
+            Err(std::io::Error::new(ErrorKind::Unsupported, "illegal seek"))
+        }
+    }
+
+    #[test]
+    fn test_unseekable_source() -> Result<()> {
+        let mut archive: Vec<u8> = Vec::new();
+        XZMultiStreamWriter::new(&mut archive)
+            .compress(&mut interleaved_reader(INTERLEAVED_FASTQ))?;
+
+        for decoder in [
+            PareArchiveDecoder::new(Pipe(&archive))?,
+            PareArchiveDecoder::new_in_memory(Pipe(&archive))?,
+        ] {
+            let (mut writer, output) = interleaved_writer();
+            open_decoder(decoder, None, None)?.decompress(&mut writer)?;
+            drop(writer);
+            assert_eq!(INTERLEAVED_FASTQ, output.text());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_in_memory_matches_disk() -> Result<()> {
+        for model in [
+            CompressionModel::LZMASingle,
+            CompressionModel::LZMAMulti,
+            CompressionModel::LZMAReference,
+            CompressionModel::LZMABlock,
+            CompressionModel::LZMAColumnar,
+            CompressionModel::ZstdSingle,
+        ] {
+            let mut archive: Vec<u8> = Vec::new();
+            write_with(&model, &mut archive)?;
+
+            let (mut writer, on_disk) = interleaved_writer();
+            let arc = PareArchiveDecoder::new(archive.as_slice())?;
+            open_decoder(arc, None, Some(reference()?))?.decompress(&mut writer)?;
+            drop(writer);
+
+            let (mut writer, in_memory) = interleaved_writer();
+            let mut arc = PareArchiveDecoder::new_in_memory(archive.as_slice())?;
+            assert_eq!(
+                PareArchiveDecoder::new(archive.as_slice())?.list_streams()?,
+                arc.list_streams()?
+            );
+            for (path, _) in arc.list_streams()? {
+                arc.verify_stream(&path)?;
+            }
+            open_decoder(arc, None, Some(reference()?))?.decompress(&mut writer)?;
+            drop(writer);
+
+            assert_eq!(on_disk.text(), in_memory.text(), "{}", model.as_str());
+            assert_eq!(INTERLEAVED_FASTQ, in_memory.text());
+        }
+
+        let archive = archive_of(&[("data", b"not a pare archive")])?;
+        assert!(matches!(
+            PareArchiveDecoder::new_in_memory(archive.as_slice())?.get_metadata(),
+            Err(CompressionModelError::MissingMetadata)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_phred64_round_trip() -> Result<()> {
+        let phred64 = "@old/1\nACGTNA\n+\nhh`O@B\n@old/2\nTTGCA\n+\nhhhhh\n";
+        let mut reader: Box<dyn PairedFastQReader> = Box::new(FastQInterleavedFileReader::new(
+            Box::new(
+                FastQFileReader::new(BufReader::new(phred64.as_bytes()))
+                    .with_phred_offset(PhredOffset::Phred64),
+            ),
+            false,
+        ));
+        let mut archive: Vec<u8> = Vec::new();
+        XZSingleFileWriter::new(&mut archive)
+            .with_options(EncoderOptions::new().with_phred_offset(PhredOffset::Phred64))
+            .compress(&mut reader)?;
+
+        let mut arc = PareArchiveDecoder::new(archive.as_slice())?;
+        assert_eq!(PhredOffset::Phred64, arc.phred_offset()?);
+
+        let buffer = SharedBuffer::default();
+        let mut writer: Box<dyn PairedFastQWriter> = Box::new(FastQInterleavedFileWriter::new(
+            Box::new(
+                FastQFileWriter::new(std::io::BufWriter::new(buffer.clone()))
+                    .with_phred_offset(arc.phred_offset()?),
+            ),
+            false,
+        ));
+        open_decoder(arc, None, None)?.decompress(&mut writer)?;
+        drop(writer);
+        assert_eq!(phred64, buffer.text());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_crlf_round_trip() -> Result<()> {
+        let crlf = INTERLEAVED_FASTQ.replace('\n', "\r\n");
+        for model in [
+            CompressionModel::LZMASingle,
+            CompressionModel::LZMAMulti,
+            CompressionModel::LZMABlock,
+            CompressionModel::LZMAColumnar,
+            CompressionModel::ZstdSingle,
+        ] {
+            let mut reader: Box<dyn PairedFastQReader> = Box::new(FastQInterleavedFileReader::new(
+                Box::new(FastQFileReader::new(BufReader::new(Cursor::new(
+                    crlf.clone(),
+                )))),
+                false,
+            ));
+            let mut archive: Vec<u8> = Vec::new();
+            crate::compress_paired(
+                &mut reader,
+                &mut archive,
+                crate::CompressOptions::new().with_model(model.clone()),
+            )?;
+
+            let mut arc = PareArchiveDecoder::new(archive.as_slice())?;
+            assert_eq!(LineEnding::Crlf, arc.line_ending()?);
+
+            let (mut writer, output) = interleaved_writer_with_line_ending(arc.line_ending()?);
+            open_decoder(arc, None, None)?.decompress(&mut writer)?;
+            drop(writer);
+            assert_eq!(crlf, output.text(), "{}", model.as_str());
+        }
+
+        let mut archive: Vec<u8> = Vec::new();
+        write_with(&CompressionModel::LZMASingle, &mut archive)?;
+        assert_eq!(
+            LineEnding::Lf,
+            PareArchiveDecoder::new(archive.as_slice())?.line_ending()?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compression_stats() -> Result<()> {
+        let records = INTERLEAVED_FASTQ
+            .lines()
+            .filter(|l| l.starts_with('@'))
+            .count();
+        for model in [
+            CompressionModel::LZMASingle,
+            CompressionModel::LZMAMulti,
+            CompressionModel::LZMAReference,
+            CompressionModel::LZMABlock,
+            CompressionModel::LZMAColumnar,
+            CompressionModel::ZstdSingle,
+        ] {
+            let mut archive: Vec<u8> = Vec::new();
+            let stats = write_with(&model, &mut archive)?;
+            assert_eq!(records as u64, 2 * stats.pairs, "{}", model.as_str());
+            assert_eq!(116, stats.bases_r1);
+            assert_eq!(116, stats.bases_r2);
+
+            let metadata = PareArchiveDecoder::new(archive.as_slice())?.get_metadata()?;
+            let recorded = metadata.get_document("stats")?;
+            assert_eq!(2, recorded.get_i64("pairs")?);
+            assert_eq!(116, recorded.get_i64("bases_r1")?);
+            assert_eq!(116, recorded.get_i64("bases_r2")?);
+            assert_eq!(
+                Some(stats),
+                PareArchiveDecoder::new(archive.as_slice())?.stats()?
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_model() -> Result<()> {
+        let mut metadata: Vec<u8> = Vec::new();
+        doc! { "model": "lzma_sideways", "version": 1 }.to_writer(&mut metadata)?;
+        let archive = archive_of(&[("metadata", &metadata)])?;
+
+        let arc = PareArchiveDecoder::new(archive.as_slice())?;
+        assert!(matches!(
+            open_decoder(arc, None, None),
+            Err(CompressionModelError::OpenedWithWrongModel { model }) if model == "lzma_sideways"
+        ));
+        Ok(())
+    }
+
+    fn assert_model_mismatch<T>(actual: Result<T>, requested: &str, stored: &str) {
+        match actual {
+            Err(CompressionModelError::ModelMismatch {
+                requested: r,
+                actual: a,
+            }) => {
+                assert_eq!(requested, r);
+                assert_eq!(stored, a);
+            }
+            Err(e) => panic!("expected ModelMismatch, got {:?}", e),
+            Ok(_) => panic!("expected ModelMismatch"),
+        }
+    }
+
+    #[test]
+    fn test_single_reader_on_multi_archive() -> Result<()> {
+        let mut archive: Vec<u8> = Vec::new();
+        XZMultiStreamWriter::new(&mut archive)
+            .compress(&mut interleaved_reader(INTERLEAVED_FASTQ))?;
+
+        assert_model_mismatch(
+            XZSingleFileReader::new(archive.as_slice()),
+            "lzma_single_stream",
+            "lzma_multi_stream",
+        );
+        Ok(())
+    }
+
+    const BAD_RECORD_FASTQ: &str = concat!(
+        "@good/1\n",
+        "ACGT\n",
+        "+\n",
+        "IIII\n",
+        "@good/2\n",
+        "TTGA\n",
+        "+\n",
+        "IIII\n",
+        "@bad/1\n",
+        "AC!T\n",
+        "+\n",
+        "IIII\n",
+        "@bad/2\n",
+        "TTGA\n",
+        "+\n",
+        "IIII\n",
+    );
+
+    #[test]
+    fn test_failed_compress_leaves_no_archive() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("reads.pare");
+        let entries = || std::fs::read_dir(dir.path()).unwrap().count();
+
+        assert!(matches!(
+            XZMultiStreamWriter::create(&path)?.compress(&mut interleaved_reader(BAD_RECORD_FASTQ)),
+            Err(CompressionModelError::FastQError { .. })
+        ));
+        assert!(!path.exists());
+        assert_eq!(0, entries());
+
+        // a file that was already there is kept
+        std::fs::write(&path, b"earlier archive")?;
+        assert!(XZSingleFileWriter::create(&path)?
+            .compress(&mut interleaved_reader(BAD_RECORD_FASTQ))
+            .is_err());
+        assert_eq!(b"earlier archive".to_vec(), std::fs::read(&path)?);
+        assert_eq!(1, entries());
+
+        XZSingleFileWriter::create(&path)?.compress(&mut interleaved_reader(INTERLEAVED_FASTQ))?;
+        let (mut writer, output) = interleaved_writer();
+        XZSingleFileReader::open(&path)?.decompress(&mut writer)?;
+        drop(writer);
+        assert_eq!(INTERLEAVED_FASTQ, output.text());
+        assert_eq!(1, entries());
+        Ok(())
+    }
+
+    #[test]
+    fn test_decoder_temp_dir() -> Result<()> {
+        let mut archive: Vec<u8> = Vec::new();
+        XZSingleFileWriter::new(&mut archive)
+            .compress(&mut interleaved_reader(INTERLEAVED_FASTQ))?;
+
+        let scratch = tempdir()?;
+        check_temp_dir(scratch.path())?;
+        let entries = || std::fs::read_dir(scratch.path()).unwrap().count();
+
+        let decoder = PareArchiveDecoder::new_in(archive.as_slice(), scratch.path())?;
+        assert_eq!(1, entries());
+        let (mut writer, output) = interleaved_writer();
+        let mut reader = XZSingleFileReader::from_archive(decoder)?;
+        reader.decompress(&mut writer)?;
+        drop((reader, writer));
+        assert_eq!(INTERLEAVED_FASTQ, output.text());
+        assert_eq!(0, entries());
+
+        let missing = scratch.path().join("missing");
+        let err = check_temp_dir(&missing).unwrap_err();
+        assert!(matches!(
+            err,
+            CompressionModelError::TempDirNotWritable { .. }
+        ));
+        assert!(err.to_string().contains("--temp-dir"));
+        assert!(PareArchiveDecoder::new_in(archive.as_slice(), &missing).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_encoder_temp_dir() -> Result<()> {
+        let scratch = tempdir()?;
+        let compress = |temp_dir: &Path| {
+            let mut archive: Vec<u8> = Vec::new();
+            XZSingleFileWriter::new(&mut archive)
+                .with_options(
+                    EncoderOptions::new()
+                        .with_spool_memory(0)
+                        .with_temp_dir(temp_dir),
+                )
+                .compress(&mut interleaved_reader(INTERLEAVED_FASTQ))?;
+            Ok::<_, CompressionModelError>(archive)
+        };
+
+        let archive = compress(scratch.path())?;
+        let (mut writer, output) = interleaved_writer();
+        XZSingleFileReader::from_archive(PareArchiveDecoder::new(archive.as_slice())?)?
+            .decompress(&mut writer)?;
+        drop(writer);
+        assert_eq!(INTERLEAVED_FASTQ, output.text());
+
+        // every spool goes to disk at once, so they can't without the directory
+        assert!(matches!(
+            compress(&scratch.path().join("missing")),
+            Err(CompressionModelError::TempDirNotWritable { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_not_an_archive() -> Result<()> {
+        let mut xz = Vec::new();
+        XzEncoder::new(&mut xz, 1).write_all(INTERLEAVED_FASTQ.as_bytes())?;
+        let mut gzip = Vec::new();
+        flate2::write::GzEncoder::new(&mut gzip, flate2::Compression::fast())
+            .write_all(INTERLEAVED_FASTQ.as_bytes())?;
+
+        for (input, kind) in [
+            (xz.as_slice(), "an xz stream"),
+            (gzip.as_slice(), "gzip compressed"),
+            (INTERLEAVED_FASTQ.as_bytes(), "a fastq file"),
+        ] {
+            for opened in [
+                PareArchiveDecoder::new(input).map(|_| ()),
+                PareArchiveDecoder::new_in_memory(input).map(|_| ()),
+                XZMultiStreamReader::new(input).map(|_| ()),
+                XZSingleFileReader::new(input).map(|_| ()),
+            ] {
+                match opened {
+                    Err(CompressionModelError::NotAnArchive { kind: k }) => assert_eq!(kind, k),
+                    Err(e) => panic!("expected NotAnArchive, got {:?}", e),
+                    Ok(_) => panic!("expected NotAnArchive"),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_in_memory_truncated_entry() {
+        // a header claiming far more than the archive holds, and than memory does
+        let mut header = Header::new_gnu();
+        header.set_path("metadata").unwrap();
+        header.set_size(1 << 50);
+        header.set_mode(0o644);
+        header.set_cksum();
+        let mut archive = header.as_bytes().to_vec();
+        archive.extend_from_slice(&[0u8; 512]);
+
+        assert!(matches!(
+            PareArchiveDecoder::new_in_memory(archive.as_slice()),
+            Err(CompressionModelError::IO { .. })
+        ));
+    }
+
+    #[test]
+    fn test_multi_reader_on_single_archive() -> Result<()> {
+        let mut archive: Vec<u8> = Vec::new();
+        XZSingleFileWriter::new(&mut archive)
+            .compress(&mut interleaved_reader(INTERLEAVED_FASTQ))?;
+
+        assert_model_mismatch(
+            XZMultiStreamReader::new(archive.as_slice())?.decompress(&mut interleaved_writer().0),
+            "lzma_multi_stream",
+            "lzma_single_stream",
+        );
+        Ok(())
+    }
 }
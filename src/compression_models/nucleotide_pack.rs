@@ -0,0 +1,197 @@
+//! Two bit packing of nucleotides.
+//!
+//! Four bases are packed into each byte, first base in the low bits. Only a, c, g and t have a
+//! code, every other letter (n and the IUPAC codes) is packed as an a and listed as an exception
+//! with its offset in the read so it can be put back.
+//!
+//! In an archive a packed read is its length as a u32 little endian followed by the packed
+//! bytes. Its exceptions go to a separate stream as a u32 count followed by a u32 offset and the
+//! letter for each.
+
+use std::io::{Read, Write};
+
+use crate::compression_models::{CompressionModelError, Result};
+
+fn base_code(base: u8) -> Option<u8> {
+    match base {
+        b'a' => Some(0),
+        b'c' => Some(1),
+        b'g' => Some(2),
+        b't' => Some(3),
+        _ => None,
+    }
+}
+
+const CODE_BASES: [u8; 4] = [b'a', b'c', b'g', b't'];
+
+/// Pack `letters` two bits to a base. Returns the packed bytes and the (offset, letter) of every
+/// letter that isn't a, c, g or t.
+pub fn pack(letters: &[u8]) -> (Vec<u8>, Vec<(usize, u8)>) {
+    let mut packed = vec![0u8; letters.len().div_ceil(4)];
+    let mut exceptions = Vec::new();
+
+    for (i, letter) in letters.iter().enumerate() {
+        let code = match base_code(*letter) {
+            Some(code) => code,
+            None => {
+                exceptions.push((i, *letter));
+                0
+            }
+        };
+        packed[i / 4] |= code << (2 * (i % 4));
+    }
+
+    (packed, exceptions)
+}
+
+/// Rebuild `length` letters from their packed bytes and exceptions.
+pub fn unpack(packed: &[u8], length: usize, exceptions: &[(usize, u8)]) -> Result<Vec<u8>> {
+    if packed.len() != length.div_ceil(4) {
+        return Err(CompressionModelError::IncompleteRecord);
+    }
+
+    let mut letters: Vec<u8> = (0..length)
+        .map(|i| CODE_BASES[((packed[i / 4] >> (2 * (i % 4))) & 0b11) as usize])
+        .collect();
+
+    for (offset, letter) in exceptions {
+        *letters
+            .get_mut(*offset)
+            .ok_or(CompressionModelError::IncompleteRecord)? = *letter;
+    }
+
+    Ok(letters)
+}
+
+/// Write a packed read to `sink` and its exceptions to `exceptions_sink`.
+pub fn write_packed<W: Write, E: Write>(
+    sink: &mut W,
+    exceptions_sink: &mut E,
+    letters: &[u8],
+) -> Result<()> {
+    let (packed, exceptions) = pack(letters);
+
+    sink.write_all(&(letters.len() as u32).to_le_bytes())?;
+    sink.write_all(&packed)?;
+    write_exceptions(exceptions_sink, &exceptions)
+}
+
+/// Write the (offset, letter) exceptions of one read, a u32 count followed by each of them.
+pub fn write_exceptions<E: Write>(sink: &mut E, exceptions: &[(usize, u8)]) -> Result<()> {
+    sink.write_all(&(exceptions.len() as u32).to_le_bytes())?;
+    for &(offset, letter) in exceptions {
+        sink.write_all(&(offset as u32).to_le_bytes())?;
+        sink.write_all(&[letter])?;
+    }
+
+    Ok(())
+}
+
+/// Read back the exceptions of one read written by `write_exceptions`.
+pub fn read_exceptions<E: Read>(source: &mut E, exceptions: &mut Vec<(usize, u8)>) -> Result<()> {
+    let count = read_u32(source)? as usize;
+    exceptions.clear();
+    for _ in 0..count {
+        let offset = read_u32(source)? as usize;
+        let mut letter = [0u8; 1];
+        source
+            .read_exact(&mut letter)
+            .map_err(|_| CompressionModelError::IncompleteRecord)?;
+        exceptions.push((offset, letter[0]));
+    }
+
+    Ok(())
+}
+
+fn read_u32<T: Read>(source: &mut T) -> Result<u32> {
+    let mut buffer = [0u8; 4];
+    source
+        .read_exact(&mut buffer)
+        .map_err(|_| CompressionModelError::IncompleteRecord)?;
+    Ok(u32::from_le_bytes(buffer))
+}
+
+/// Read back a read written by `write_packed`.
+pub fn read_packed<R: Read, E: Read>(
+    source: &mut R,
+    exceptions_source: &mut E,
+    letters: &mut Vec<u8>,
+) -> Result<()> {
+    let length = read_u32(source)? as usize;
+    // the length is only trusted as far as there are bytes for it
+    let packed_length = length.div_ceil(4);
+    let mut packed = Vec::new();
+    let read = source
+        .take(packed_length as u64)
+        .read_to_end(&mut packed)
+        .map_err(|_| CompressionModelError::IncompleteRecord)?;
+    if read != packed_length {
+        return Err(CompressionModelError::IncompleteRecord);
+    }
+
+    let mut exceptions = Vec::new();
+    read_exceptions(exceptions_source, &mut exceptions)?;
+
+    *letters = unpack(&packed, length, &exceptions)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pack_round_trip() -> Result<()> {
+        let letters = b"acgtnnacgtrtgcan".to_vec();
+        let (packed, exceptions) = pack(&letters);
+
+        assert_eq!(4, packed.len());
+        // a=0 c=1 g=2 t=3, first base in the low bits
+        assert_eq!(0b11_10_01_00, packed[0]);
+        assert_eq!(
+            vec![(4, b'n'), (5, b'n'), (10, b'r'), (15, b'n')],
+            exceptions
+        );
+        assert_eq!(letters, unpack(&packed, letters.len(), &exceptions)?);
+
+        // lengths that don't fill the last byte
+        for length in 0..letters.len() {
+            let (packed, exceptions) = pack(&letters[..length]);
+            assert_eq!(letters[..length], unpack(&packed, length, &exceptions)?);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_packed_streams() -> Result<()> {
+        let reads: [&[u8]; 3] = [b"nnnnacgt", b"", b"ggggccccaaaattttn"];
+
+        let mut stream: Vec<u8> = Vec::new();
+        let mut exceptions: Vec<u8> = Vec::new();
+        for read in reads {
+            write_packed(&mut stream, &mut exceptions, read)?;
+        }
+
+        let mut stream = stream.as_slice();
+        let mut exceptions = exceptions.as_slice();
+        let mut letters = Vec::new();
+        for read in reads {
+            read_packed(&mut stream, &mut exceptions, &mut letters)?;
+            assert_eq!(read, &letters[..]);
+        }
+        assert!(matches!(
+            read_packed(&mut stream, &mut exceptions, &mut letters),
+            Err(CompressionModelError::IncompleteRecord)
+        ));
+
+        // a corrupt length longer than the stream
+        let mut stream: &[u8] = &[0xff, 0xff, 0xff, 0xff, 0x1b];
+        assert!(matches!(
+            read_packed(&mut stream, &mut exceptions, &mut letters),
+            Err(CompressionModelError::IncompleteRecord)
+        ));
+
+        Ok(())
+    }
+}
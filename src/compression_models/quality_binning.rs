@@ -0,0 +1,198 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::compression_models::Result;
+use crate::seq_files::fastq::{FastQFileReaderTrait, FastQFileWriterTrait, FastQRead, LineEnding};
+
+/// The fastq reader stores qualities as the ascii value minus 32, which is one higher than the
+/// Phred+33 score they represent.
+pub(crate) const STORED_QUALITY_SHIFT: u8 = 1;
+
+/// Lossy schemes for collapsing Phred scores into a smaller set of values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QualityBinning {
+    /// Illumina's 8 level binning (0, 6, 15, 22, 27, 33, 37, 40)
+    Illumina8,
+}
+
+impl QualityBinning {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QualityBinning::Illumina8 => "illumina8",
+        }
+    }
+
+    /// Map a single Phred score into its bin.
+    pub fn bin(&self, q: u8) -> u8 {
+        match self {
+            QualityBinning::Illumina8 => match q {
+                0..=1 => 0,
+                2..=9 => 6,
+                10..=19 => 15,
+                20..=24 => 22,
+                25..=29 => 27,
+                30..=34 => 33,
+                35..=39 => 37,
+                _ => 40,
+            },
+        }
+    }
+
+    /// Bin the qualities of a read in place.
+    pub fn bin_read(&self, read: &mut FastQRead) {
+        read.qualities.iter_mut().for_each(|q| {
+            let phred = q.saturating_sub(STORED_QUALITY_SHIFT);
+            *q = self.bin(phred) + STORED_QUALITY_SHIFT;
+        });
+    }
+}
+
+/// Copy every record from `reader` to a writer binning the qualities on the way through. The
+/// writer is made by `create` once the first record has been read, so it can be given the line
+/// ending of the input, and is finished at the end. Returns the number of records copied.
+pub fn requality<W: FastQFileWriterTrait>(
+    reader: &mut dyn FastQFileReaderTrait,
+    create: impl FnOnce(LineEnding) -> Result<W>,
+    binning: QualityBinning,
+) -> Result<u64> {
+    let mut read = FastQRead::default();
+    let mut more = reader.read_next(&mut read)?;
+    let mut writer = create(reader.line_ending())?;
+    let mut count = 0;
+
+    while more {
+        binning.bin_read(&mut read);
+        writer.write_next(&read, false)?;
+        count += 1;
+        more = reader.read_next(&mut read)?;
+    }
+
+    writer.finish()?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{BufReader, BufWriter, Read, Write};
+
+    use flate2::read::MultiGzDecoder;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    use crate::seq_files::fastq::{FastQFileReader, FastQFileWriter, PhredOffset};
+
+    #[test]
+    fn test_illumina8_buckets() {
+        let bins: Vec<u8> = (0..=93).map(|q| QualityBinning::Illumina8.bin(q)).collect();
+
+        let mut buckets = bins.clone();
+        buckets.dedup();
+        assert_eq!(vec![0, 6, 15, 22, 27, 33, 37, 40], buckets);
+
+        for (q, bin) in bins.iter().enumerate() {
+            let expected = match q {
+                0..=1 => 0,
+                2..=9 => 6,
+                10..=19 => 15,
+                20..=24 => 22,
+                25..=29 => 27,
+                30..=34 => 33,
+                35..=39 => 37,
+                _ => 40,
+            };
+            assert_eq!(expected, *bin, "q {}", q);
+        }
+    }
+
+    const FASTQ_RECORDS: &str = concat!(
+        "@HWI-EAS209_0006_FC706VJ:5:58:5894:21141#ATCACG/1\n",
+        "TTAATTGGTAAATAAATCTCCTAATAGCTTAGATNTTACCTTNNNNNNNNNNTAGTTTCTTGAGATTTGTTGGGGGAGACATTTTTGTGATTGCCTTGAT\n",
+        "+\n",
+        "efcfffffcfeefffcffffffddf`feed]`]_B__^__[YBBBBBBBBBBRTT\\]][]dddd`ddd^dddadd^BBBBBBBBBBBBBBBBBBBBBBBB\n",
+        "@HWI-EAS209_0006_FC706VJ:5:58:5894:21141#ATCACG/2\n",
+        "ACGTNACGTN\n",
+        "+\n",
+        "!+5?ABCDEI\n",
+    );
+
+    #[test]
+    fn test_requality_preserves_non_quality_fields() -> Result<()> {
+        let crlf = FASTQ_RECORDS.replace('\n', "\r\n");
+        let phred64 = "@read1/1\nACGT\n+\nhhBB\n";
+        let inputs = [
+            (FASTQ_RECORDS, PhredOffset::Phred33, 2),
+            (crlf.as_str(), PhredOffset::Phred33, 2),
+            (phred64, PhredOffset::Phred64, 1),
+        ];
+        for (input, phred, records) in inputs {
+            let mut gz_input = GzEncoder::new(Vec::new(), Compression::default());
+            gz_input.write_all(input.as_bytes())?;
+            let gz_input = gz_input.finish()?;
+
+            let mut reader =
+                FastQFileReader::new(BufReader::new(MultiGzDecoder::new(gz_input.as_slice())))
+                    .with_phred_offset(phred);
+            let mut output: Vec<u8> = Vec::new();
+            let sink = &mut output;
+            let create = move |line_ending| {
+                Ok(FastQFileWriter::gzip(sink)
+                    .with_line_ending(line_ending)
+                    .with_phred_offset(phred))
+            };
+            assert_eq!(
+                records,
+                requality(&mut reader, create, QualityBinning::Illumina8)?
+            );
+
+            let mut plain = String::new();
+            MultiGzDecoder::new(output.as_slice()).read_to_string(&mut plain)?;
+
+            // split on \n alone so a \r left at the end of a line is compared too
+            let expected: Vec<&str> = input.split('\n').collect();
+            let actual: Vec<&str> = plain.split('\n').collect();
+            assert_eq!(expected.len(), actual.len());
+            for (i, (e, a)) in expected.iter().zip(actual.iter()).enumerate() {
+                if i % 4 == 3 {
+                    assert_eq!(e.len(), a.len());
+                } else {
+                    assert_eq!(e, a);
+                }
+            }
+        }
+
+        // !=0 +=10 5=20 ?=30 A=32 B=33 C=34 D=35 E=36 I=40
+        let mut output: Vec<u8> = Vec::new();
+        let mut reader = FastQFileReader::new(BufReader::new(FASTQ_RECORDS.as_bytes()));
+        requality(
+            &mut reader,
+            |_| Ok(FastQFileWriter::new(BufWriter::new(&mut output))),
+            QualityBinning::Illumina8,
+        )?;
+        assert_eq!(
+            String::from_utf8_lossy(&output).lines().nth(7),
+            Some("!07BBBBFFI")
+        );
+
+        // in Phred+64 h=40 stays, B=2 goes to 6
+        let mut output: Vec<u8> = Vec::new();
+        let mut reader = FastQFileReader::new(BufReader::new(phred64.as_bytes()))
+            .with_phred_offset(PhredOffset::Phred64);
+        requality(
+            &mut reader,
+            |_| {
+                Ok(FastQFileWriter::new(BufWriter::new(&mut output))
+                    .with_phred_offset(PhredOffset::Phred64))
+            },
+            QualityBinning::Illumina8,
+        )?;
+        assert_eq!(
+            String::from_utf8_lossy(&output).lines().nth(3),
+            Some("hhFF")
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,138 @@
+//! Dropping low quality reads before they are stored.
+
+use crate::seq_files::fastq::FastQRead;
+
+/// Drop the pairs, or fragments of more reads, whose mean Phred score is below a threshold. By
+/// default a pair goes when either read is below it. This is lossy.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QualityFilter {
+    min_mean: f64,
+    both: bool,
+}
+
+impl QualityFilter {
+    pub fn new(min_mean: f64) -> Self {
+        QualityFilter {
+            min_mean,
+            both: false,
+        }
+    }
+
+    /// Only drop a pair when every read of it is below the threshold.
+    pub fn with_both(mut self, both: bool) -> Self {
+        self.both = both;
+        self
+    }
+
+    pub fn min_mean(&self) -> f64 {
+        self.min_mean
+    }
+
+    /// The setting recorded in the metadata.
+    pub fn as_str(&self) -> String {
+        format!(
+            "{} {}",
+            self.min_mean,
+            if self.both { "both" } else { "either" }
+        )
+    }
+
+    pub fn keep_pair(&self, r1: &FastQRead, r2: &FastQRead) -> bool {
+        self.keep(&[r1, r2])
+    }
+
+    pub fn keep_fragment(&self, reads: &[FastQRead]) -> bool {
+        self.keep(&reads.iter().collect::<Vec<_>>())
+    }
+
+    fn keep(&self, reads: &[&FastQRead]) -> bool {
+        let passes = |read: &&FastQRead| read.mean_quality() >= self.min_mean;
+        match self.both {
+            true => reads.iter().any(passes),
+            false => reads.iter().all(passes),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::BufReader;
+
+    use crate::compression_models::test_utils::*;
+    use crate::compression_models::*;
+    use crate::seq_files::fastq::*;
+    use crate::{XZSingleFileReader, XZSingleFileWriter};
+
+    const MIXED_QUALITY_FASTQ: &str = concat!(
+        "@good/1\n",
+        "ACGTACGT\n",
+        "+\n",
+        "IIIIIIII\n",
+        "@good/2\n",
+        "TTGACCAA\n",
+        "+\n",
+        "IIIIIIII\n",
+        "@poor/1\n",
+        "ACGTACGT\n",
+        "+\n",
+        "IIIIIIII\n",
+        "@poor/2\n",
+        "TTGACCAA\n",
+        "+\n",
+        "########\n",
+    );
+
+    fn compress_filtered(filter: QualityFilter) -> Result<(CompressionStats, String)> {
+        let mut archive: Vec<u8> = Vec::new();
+        let stats = XZSingleFileWriter::new(&mut archive)
+            .with_options(EncoderOptions::new().with_quality_filter(Some(filter)))
+            .compress(&mut interleaved_reader(MIXED_QUALITY_FASTQ))?;
+
+        let mut decoder = PareArchiveDecoder::new(archive.as_slice())?;
+        assert_eq!(Some(stats), decoder.stats()?);
+        let metadata = decoder.get_metadata()?;
+        assert_eq!(
+            filter.as_str(),
+            metadata
+                .get_document("lossy")?
+                .get_str("min_mean_quality")?
+        );
+
+        let (mut writer, output) = interleaved_writer();
+        XZSingleFileReader::new(archive.as_slice())?.decompress(&mut writer)?;
+        drop(writer);
+        Ok((stats, output.text()))
+    }
+
+    #[test]
+    fn test_low_quality_pair_is_dropped() -> Result<()> {
+        let (stats, text) = compress_filtered(QualityFilter::new(20.0))?;
+        assert_eq!(1, stats.pairs);
+        assert_eq!(1, stats.filtered_pairs);
+        assert_eq!(
+            MIXED_QUALITY_FASTQ.lines().take(8).collect::<Vec<_>>(),
+            text.lines().collect::<Vec<_>>()
+        );
+
+        // the first read of the poor pair is fine
+        let (stats, text) = compress_filtered(QualityFilter::new(20.0).with_both(true))?;
+        assert_eq!(2, stats.pairs);
+        assert_eq!(0, stats.filtered_pairs);
+        assert_eq!(MIXED_QUALITY_FASTQ, text);
+        Ok(())
+    }
+
+    #[test]
+    fn test_phred64_b_reads_are_low() -> Result<()> {
+        // '#' above is Q2, the same as the all B reads of old Phred+64 files
+        let mut reader = FastQFileReader::new(BufReader::new("@old\nACGT\n+\nBBBB\n".as_bytes()))
+            .with_phred_offset(PhredOffset::Phred64);
+        let mut read = FastQRead::default();
+        assert!(reader.read_next(&mut read)?);
+        assert_eq!(2.0, read.mean_quality());
+        assert!(!QualityFilter::new(20.0).keep_pair(&read, &read));
+        Ok(())
+    }
+}
@@ -0,0 +1,217 @@
+//! Sorting the read pairs by their bases before they are compressed, similar reads next to each
+//! other compress better.
+
+use crate::compression_models::{CompressionModelError, DecoderModel, PairDecoder, Result};
+use crate::seq_files::fastq::{
+    FastQFileError, FastQRead, LineEnding, PairedFastQReader, SalvageCounts,
+};
+
+/// The most bytes of reads `sort_pairs` holds in memory. Larger inputs fail rather than being
+/// sorted on disk.
+pub const SORT_MEMORY_LIMIT: u64 = 4 << 30;
+
+/// The order the pairs are compressed in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReadOrder {
+    #[default]
+    Input,
+    /// Sorted by the bases of R1 then R2, the input order is lost. This is lossy.
+    Sorted,
+    /// Sorted, with the input order stored so the reader can put the pairs back.
+    SortedKeepingOrder,
+}
+
+/// What is stored of the order of the pairs before they were sorted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputOrder {
+    Lost,
+    /// The input position of each pair, in the order they were compressed.
+    Kept(Vec<u64>),
+}
+
+/// The pairs of an input, read ahead and sorted.
+struct SortedPairs {
+    pairs: std::vec::IntoIter<(FastQRead, FastQRead)>,
+    salvage: SalvageCounts,
+    line_ending: LineEnding,
+}
+
+impl PairedFastQReader for SortedPairs {
+    fn read_next(
+        &mut self,
+        r1: &mut FastQRead,
+        r2: &mut FastQRead,
+    ) -> std::result::Result<bool, FastQFileError> {
+        match self.pairs.next() {
+            Some((s1, s2)) => {
+                *r1 = s1;
+                *r2 = s2;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn salvage(&self) -> SalvageCounts {
+        self.salvage
+    }
+
+    fn line_ending(&self) -> LineEnding {
+        self.line_ending
+    }
+}
+
+fn read_bytes(read: &FastQRead) -> u64 {
+    (read.letters.len() + read.qualities.len() + read.title.len() + read.sub_title.len()) as u64
+}
+
+/// Read every pair from `reader` and sort them by the bases of R1 then R2, pairs with the same
+/// bases stay in input order. Returns a reader of the sorted pairs and the input position of each.
+pub fn sort_pairs(
+    reader: &mut Box<dyn PairedFastQReader>,
+    memory_limit: u64,
+) -> Result<(Box<dyn PairedFastQReader>, Vec<u64>)> {
+    let mut pairs = Vec::new();
+    let mut held = 0;
+    let mut r1 = FastQRead::default();
+    let mut r2 = FastQRead::default();
+    while reader.read_next(&mut r1, &mut r2)? {
+        held += read_bytes(&r1) + read_bytes(&r2);
+        if held > memory_limit {
+            return Err(CompressionModelError::CantSort {
+                reason: format!(
+                    "the reads don't fit in the {} bytes of memory",
+                    memory_limit
+                ),
+            });
+        }
+        pairs.push((r1.clone(), r2.clone()));
+    }
+
+    let mut order: Vec<u64> = (0..pairs.len() as u64).collect();
+    order.sort_by(|&a, &b| {
+        let (a1, a2) = &pairs[a as usize];
+        let (b1, b2) = &pairs[b as usize];
+        (&a1.letters, &a2.letters).cmp(&(&b1.letters, &b2.letters))
+    });
+    let mut slots: Vec<Option<(FastQRead, FastQRead)>> = pairs.into_iter().map(Some).collect();
+    let sorted: Vec<(FastQRead, FastQRead)> = order
+        .iter()
+        .filter_map(|&i| slots[i as usize].take())
+        .collect();
+
+    let sorted = SortedPairs {
+        pairs: sorted.into_iter(),
+        salvage: reader.salvage(),
+        line_ending: reader.line_ending(),
+    };
+    Ok((Box::new(sorted), order))
+}
+
+/// Decodes the pairs of a sorted archive and hands them out in their input order.
+pub(crate) struct RestoredOrder<'a> {
+    pub(crate) model: Box<dyn DecoderModel + 'a>,
+    pub(crate) order: Vec<u64>,
+}
+
+struct RestoredPairs {
+    pairs: std::vec::IntoIter<Option<(FastQRead, FastQRead)>>,
+}
+
+impl PairDecoder for RestoredPairs {
+    fn read_next(&mut self, r1: &mut FastQRead, r2: &mut FastQRead) -> Result<bool> {
+        match self.pairs.next() {
+            Some(Some((s1, s2))) => {
+                *r1 = s1;
+                *r2 = s2;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+}
+
+impl DecoderModel for RestoredOrder<'_> {
+    fn decoder(&mut self) -> Result<Box<dyn PairDecoder + '_>> {
+        let corrupt = |read: usize| CompressionModelError::CorruptStream {
+            stream: "order".to_string(),
+            read: read as u64,
+        };
+
+        let mut pairs: Vec<Option<(FastQRead, FastQRead)>> = vec![None; self.order.len()];
+        let mut decoder = self.model.decoder()?;
+        let mut r1 = FastQRead::default();
+        let mut r2 = FastQRead::default();
+        let mut decoded = 0;
+        while decoder.read_next(&mut r1, &mut r2)? {
+            let slot = self
+                .order
+                .get(decoded)
+                .and_then(|&position| pairs.get_mut(position as usize))
+                .filter(|slot| slot.is_none())
+                .ok_or_else(|| corrupt(decoded))?;
+            *slot = Some((r1.clone(), r2.clone()));
+            decoded += 1;
+        }
+        if decoded != self.order.len() {
+            return Err(corrupt(decoded));
+        }
+
+        Ok(Box::new(RestoredPairs {
+            pairs: pairs.into_iter(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::compression_models::test_utils::*;
+    use crate::compression_models::*;
+    use crate::{compress_paired, decompress_paired, CompressOptions};
+
+    fn compress_sorted(model: &CompressionModel, order: ReadOrder) -> Result<Vec<u8>> {
+        let mut archive: Vec<u8> = Vec::new();
+        compress_paired(
+            &mut string_reader(&SyntheticFastq::default().interleaved(300)),
+            &mut archive,
+            CompressOptions::new()
+                .with_model(model.clone())
+                .with_read_order(order),
+        )?;
+        Ok(archive)
+    }
+
+    #[test]
+    fn test_sorted_round_trip() -> Result<()> {
+        let fastq = SyntheticFastq::default().interleaved(300);
+        for model in [CompressionModel::LZMASingle, CompressionModel::LZMAMulti] {
+            let archive = compress_sorted(&model, ReadOrder::SortedKeepingOrder)?;
+            let (mut writer, output) = interleaved_writer();
+            decompress_paired(archive.as_slice(), &mut writer, None)?;
+            drop(writer);
+            assert_eq!(fastq, output.text(), "{}", model.as_str());
+
+            // without the order the pairs come out sorted
+            let archive = compress_sorted(&model, ReadOrder::Sorted)?;
+            let metadata = PareArchiveDecoder::new(archive.as_slice())?.get_metadata()?;
+            assert_eq!("sorted", metadata.get_document("lossy")?.get_str("order")?);
+            let mut decoder =
+                open_decoder(PareArchiveDecoder::new(archive.as_slice())?, None, None)?;
+            let pairs: Vec<(FastQRead, FastQRead)> = decoder.pairs()?.collect::<Result<_>>()?;
+            assert_eq!(300, pairs.len());
+            assert!(pairs.windows(2).all(|w| w[0].0.letters <= w[1].0.letters));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_sort_memory_limit() {
+        let mut reader = string_reader(&SyntheticFastq::default().interleaved(300));
+        assert!(matches!(
+            sort_pairs(&mut reader, 1000),
+            Err(CompressionModelError::CantSort { .. })
+        ));
+    }
+}
@@ -0,0 +1,335 @@
+//! Reference genomes and the small seed and extend aligner used by the reference model.
+//!
+//! Reads are aligned without gaps, every `SEED_LENGTH` mer of the read is looked up in an index of
+//! the reference and each hit is extended over the whole read counting mismatches. The best hit
+//! with few enough mismatches wins, anything else is stored verbatim by the model.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use bson::{doc, Document};
+
+use crate::compression_models::{CompressionModelError, Result};
+use crate::seq_files::fastq::reverse_complement_nucleotides;
+
+/// Length of the k-mers used to seed alignments. 16 bases pack into the low 32 bits of a u64.
+const SEED_LENGTH: usize = 16;
+/// Seeds that occur more often than this are repeats and are not worth extending.
+const MAX_SEED_HITS: usize = 32;
+/// At most one in this many bases of an aligned read may differ from the reference.
+const MISMATCH_RATIO: usize = 8;
+
+struct Contig {
+    name: String,
+    bases: Vec<u8>,
+}
+
+/// Where a read lies on the reference and how it differs from it.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Alignment {
+    pub contig: u32,
+    pub position: u32,
+    pub reverse: bool,
+    /// (offset into the read, read base) for every base that differs from the reference, in the
+    /// orientation of the reference.
+    pub edits: Vec<(u32, u8)>,
+}
+
+pub struct Reference {
+    contigs: Vec<Contig>,
+    index: HashMap<u64, Vec<(u32, u32)>>,
+    digest: String,
+}
+
+fn normalize_base(b: u8) -> u8 {
+    match b {
+        b'a' | b'A' => b'a',
+        b'c' | b'C' => b'c',
+        b'g' | b'G' => b'g',
+        b't' | b'T' => b't',
+        _ => b'n',
+    }
+}
+
+fn base_code(b: u8) -> Option<u64> {
+    match b {
+        b'a' => Some(0),
+        b'c' => Some(1),
+        b'g' => Some(2),
+        b't' => Some(3),
+        _ => None,
+    }
+}
+
+fn seed(bases: &[u8]) -> Option<u64> {
+    bases
+        .iter()
+        .try_fold(0u64, |acc, b| Some((acc << 2) | base_code(*b)?))
+}
+
+impl Reference {
+    /// Load every record of a FASTA file. Bases other than ACGT are stored as n.
+    pub fn from_fasta<R: BufRead>(source: R) -> Result<Self> {
+        let mut contigs: Vec<Contig> = Vec::new();
+
+        for line in source.lines() {
+            let line = line?;
+            let line = line.trim_end();
+            if let Some(title) = line.strip_prefix('>') {
+                let name = title.split_whitespace().next().unwrap_or("");
+                contigs.push(Contig {
+                    name: name.to_string(),
+                    bases: Vec::new(),
+                });
+            } else if !line.is_empty() {
+                match contigs.last_mut() {
+                    Some(contig) => contig.bases.extend(line.bytes().map(normalize_base)),
+                    None => return Err(CompressionModelError::InvalidReference),
+                }
+            }
+        }
+
+        if contigs.is_empty() {
+            return Err(CompressionModelError::InvalidReference);
+        }
+
+        Ok(Reference::new(contigs))
+    }
+
+    pub fn open<P: AsRef<Path>>(path: &P) -> Result<Self> {
+        Reference::from_fasta(BufReader::new(File::open(path)?))
+    }
+
+    fn new(contigs: Vec<Contig>) -> Self {
+        let mut hasher = xxhash_rust::xxh3::Xxh3::new();
+        let mut index: HashMap<u64, Vec<(u32, u32)>> = HashMap::new();
+
+        for (i, contig) in contigs.iter().enumerate() {
+            hasher.update(contig.name.as_bytes());
+            hasher.update(b"\n");
+            hasher.update(&contig.bases);
+            hasher.update(b"\n");
+
+            for (pos, window) in contig.bases.windows(SEED_LENGTH).enumerate() {
+                if let Some(s) = seed(window) {
+                    let hits = index.entry(s).or_default();
+                    if hits.len() <= MAX_SEED_HITS {
+                        hits.push((i as u32, pos as u32));
+                    }
+                }
+            }
+        }
+
+        Reference {
+            contigs,
+            index,
+            digest: format!("{:032x}", hasher.digest128()),
+        }
+    }
+
+    /// What is recorded in the archive metadata to identify the reference.
+    pub fn identity(&self) -> Document {
+        let names: Vec<&str> = self.contigs.iter().map(|c| c.name.as_str()).collect();
+        let lengths: Vec<i64> = self.contigs.iter().map(|c| c.bases.len() as i64).collect();
+        doc! {
+            "digest": self.digest.as_str(),
+            "contigs": names,
+            "lengths": lengths,
+        }
+    }
+
+    /// Fail unless `identity` describes this reference.
+    pub fn check_identity(&self, identity: &Document) -> Result<()> {
+        if identity.get_str("digest")? != self.digest {
+            return Err(CompressionModelError::ReferenceMismatch);
+        }
+
+        Ok(())
+    }
+
+    /// Find the best ungapped alignment of `read` on either strand.
+    pub fn align(&self, read: &[u8]) -> Option<Alignment> {
+        if read.len() < SEED_LENGTH {
+            return None;
+        }
+
+        let forward = self.align_strand(read, false);
+        if matches!(&forward, Some(a) if a.edits.is_empty()) {
+            return forward;
+        }
+
+        let mut reversed = read.to_vec();
+        if reverse_complement_nucleotides(&mut reversed).is_err() {
+            return forward;
+        }
+        let reverse = self.align_strand(&reversed, true);
+
+        match (forward, reverse) {
+            (Some(f), Some(r)) if r.edits.len() < f.edits.len() => Some(r),
+            (Some(f), _) => Some(f),
+            (None, r) => r,
+        }
+    }
+
+    fn align_strand(&self, read: &[u8], reverse: bool) -> Option<Alignment> {
+        let max_mismatches = read.len() / MISMATCH_RATIO;
+        let mut best: Option<(u32, u32, usize)> = None;
+
+        let last_seed = read.len() - SEED_LENGTH;
+        let offsets = (0..last_seed)
+            .step_by(SEED_LENGTH)
+            .chain(std::iter::once(last_seed));
+
+        for offset in offsets {
+            let hits =
+                match seed(&read[offset..offset + SEED_LENGTH]).and_then(|s| self.index.get(&s)) {
+                    Some(hits) if hits.len() <= MAX_SEED_HITS => hits,
+                    _ => continue,
+                };
+
+            for (contig, pos) in hits {
+                let start = match (*pos as usize).checked_sub(offset) {
+                    Some(start) => start,
+                    None => continue,
+                };
+                if matches!(best, Some((c, p, _)) if c == *contig && p as usize == start) {
+                    continue;
+                }
+
+                let bases = &self.contigs[*contig as usize].bases;
+                let target = match bases.get(start..start + read.len()) {
+                    Some(target) => target,
+                    None => continue,
+                };
+
+                let limit = best.map_or(max_mismatches, |(_, _, m)| m.saturating_sub(1));
+                if let Some(mismatches) = count_mismatches(read, target, limit) {
+                    best = Some((*contig, start as u32, mismatches));
+                }
+            }
+
+            if matches!(best, Some((_, _, 0))) {
+                break;
+            }
+        }
+
+        let (contig, position, _) = best?;
+        let target = &self.contigs[contig as usize].bases[position as usize..][..read.len()];
+        let edits = read
+            .iter()
+            .zip(target)
+            .enumerate()
+            .filter(|(_, (r, t))| r != t)
+            .map(|(i, (r, _))| (i as u32, *r))
+            .collect();
+
+        Some(Alignment {
+            contig,
+            position,
+            reverse,
+            edits,
+        })
+    }
+
+    /// Rebuild a read of `length` bases from its alignment.
+    pub fn reconstruct(
+        &self,
+        alignment: &Alignment,
+        length: usize,
+        read: &mut Vec<u8>,
+    ) -> Result<()> {
+        let target = self
+            .contigs
+            .get(alignment.contig as usize)
+            .and_then(|c| {
+                let start = alignment.position as usize;
+                c.bases.get(start..start + length)
+            })
+            .ok_or(CompressionModelError::IncompleteRecord)?;
+
+        read.clear();
+        read.extend_from_slice(target);
+        for (offset, base) in &alignment.edits {
+            *read
+                .get_mut(*offset as usize)
+                .ok_or(CompressionModelError::IncompleteRecord)? = *base;
+        }
+
+        if alignment.reverse {
+            reverse_complement_nucleotides(read)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Count the positions where `read` and `target` differ, giving up once there are more than
+/// `limit`.
+fn count_mismatches(read: &[u8], target: &[u8], limit: usize) -> Option<usize> {
+    let mut mismatches = 0;
+    for (r, t) in read.iter().zip(target) {
+        if r != t {
+            mismatches += 1;
+            if mismatches > limit {
+                return None;
+            }
+        }
+    }
+
+    Some(mismatches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REFERENCE: &str = concat!(
+        ">chr1 test contig\n",
+        "ACGTTGCAACGTTACGGATCCTAGGCTAGCTAACGGTTCAGTCAGGTACCATGCATGCAAGTC\n",
+        "TTGACCGGTAACCTGAGGTCAATCGGCTTAGCATCCGATGCTAGGTCCAGTTGACGTAGCTA\n",
+        ">chr2\n",
+        "GGATTCCAAGTTGGCCAATTGCGCGATATCCGGTTAACCGGTTCAGCTAGTCGATGCATCGA\n",
+    );
+
+    #[test]
+    fn test_align_and_reconstruct() -> Result<()> {
+        let reference = Reference::from_fasta(REFERENCE.as_bytes())?;
+
+        // exact forward hit on chr1
+        let read = b"ggatcctaggctagctaacggttcagtcagg".to_vec();
+        let alignment = reference.align(&read).unwrap();
+        assert_eq!(
+            (0, 15, false),
+            (alignment.contig, alignment.position, alignment.reverse)
+        );
+        assert!(alignment.edits.is_empty());
+
+        // a mismatch and an n on chr2
+        let read = b"ccaagttggccaattgcgcgatatcnggtaaacc".to_vec();
+        let alignment = reference.align(&read).unwrap();
+        assert_eq!(
+            (1, 5, false),
+            (alignment.contig, alignment.position, alignment.reverse)
+        );
+        assert_eq!(vec![(25, b'n'), (29, b'a')], alignment.edits);
+
+        // reverse strand
+        let mut read = b"accggtaacctgaggtcaatcggcttagc".to_vec();
+        reverse_complement_nucleotides(&mut read)?;
+        let alignment = reference.align(&read).unwrap();
+        assert!(alignment.reverse);
+
+        let mut rebuilt = Vec::new();
+        reference.reconstruct(&alignment, read.len(), &mut rebuilt)?;
+        assert_eq!(read, rebuilt);
+
+        // not from the reference
+        assert_eq!(
+            None,
+            reference.align(b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa")
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,288 @@
+//! Archives holding several samples.
+//!
+//! Each sample is a complete archive of its own, written by any of the models, with the paths of
+//! its members put under the sample name. A manifest listing the samples takes the place of the
+//! metadata at the front of the archive:
+//!
+//! ```text
+//! metadata          {"samples": ["sampleA", "sampleB"]}
+//! sampleA/metadata
+//! sampleA/titles
+//! ...
+//! sampleB/metadata
+//! sampleB/titles
+//! ...
+//! ```
+//!
+//! The manifest has to stay the first member and a tar can't be added to once its end blocks are
+//! written, so a sample is appended by writing the archive again: the new manifest, the members of
+//! the samples already there copied unchanged and then the new sample. `append_sample_to_file`
+//! does this to a `PendingFile` next to the archive, renamed over the archive once it is complete,
+//! so an append that fails leaves the archive as it was.
+
+use std::fs::File;
+use std::io::{Cursor, Read, Write};
+use std::path::Path;
+
+use bson::{doc, Document};
+use tar::{Archive, Builder, Header};
+
+use crate::compression_models::{CompressionModelError, PendingFile, Result};
+
+/// The member holding the list of samples, and of every sample the one holding its metadata.
+const METADATA_PATH: &str = "metadata";
+
+/// Sample names are directories in the archive, they can't be paths themselves.
+fn check_sample_name(name: &str) -> Result<()> {
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+        return Err(CompressionModelError::InvalidSampleName {
+            name: name.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// The samples listed in a manifest.
+pub(crate) fn manifest_samples(manifest: &Document) -> Result<Vec<String>> {
+    let mut samples = Vec::new();
+    for sample in manifest.get_array("samples")? {
+        let name = sample
+            .as_str()
+            .ok_or(bson::document::ValueAccessError::UnexpectedType)?;
+        samples.push(name.to_string());
+    }
+    Ok(samples)
+}
+
+fn append_entry<W: Write, R: Read>(
+    builder: &mut Builder<W>,
+    header: &Header,
+    path: &str,
+    content: R,
+) -> Result<()> {
+    let mut header = header.clone();
+    header.set_path(path)?;
+    header.set_cksum();
+    builder.append(&header, content)?;
+    Ok(())
+}
+
+/// Write the samples of `existing`, when there is one, and `sample` named `name` to `sink` as a
+/// single archive. `existing` has to be an archive of samples, `sample` a plain single sample
+/// archive.
+pub fn append_sample<R: Read, S: Read, W: Write>(
+    existing: Option<R>,
+    name: &str,
+    sample: S,
+    sink: W,
+) -> Result<()> {
+    check_sample_name(name)?;
+
+    let mut existing = existing.map(Archive::new);
+    let mut existing_entries = match existing.as_mut() {
+        Some(archive) => Some(archive.entries()?),
+        None => None,
+    };
+
+    let mut samples = Vec::new();
+    if let Some(entries) = existing_entries.as_mut() {
+        let mut manifest = match entries.next() {
+            Some(entry) => entry?,
+            None => return Err(CompressionModelError::NotMultiSample),
+        };
+        if manifest.path()?.to_str() != Some(METADATA_PATH) {
+            return Err(CompressionModelError::NotMultiSample);
+        }
+        let manifest = Document::from_reader(&mut manifest)?;
+        if !manifest.contains_key("samples") {
+            return Err(CompressionModelError::NotMultiSample);
+        }
+        samples = manifest_samples(&manifest)?;
+    }
+    if samples.iter().any(|sample| sample == name) {
+        return Err(CompressionModelError::DuplicateSample {
+            name: name.to_string(),
+        });
+    }
+    samples.push(name.to_string());
+
+    let mut sample = Archive::new(sample);
+    let mut sample_entries = sample.entries()?.peekable();
+    // the manifest is stamped like the metadata of the new sample so that a reproducible sample
+    // makes a reproducible archive
+    let mut manifest_header = Header::new_gnu();
+    manifest_header.set_mode(0o600);
+    if let Some(Ok(first)) = sample_entries.peek() {
+        manifest_header.set_mtime(first.header().mtime()?);
+    }
+
+    let mut manifest = Vec::new();
+    doc! { "samples": &samples }.to_writer(&mut manifest)?;
+    manifest_header.set_size(manifest.len() as u64);
+
+    let mut builder = Builder::new(sink);
+    append_entry(
+        &mut builder,
+        &manifest_header,
+        METADATA_PATH,
+        Cursor::new(manifest),
+    )?;
+    if let Some(entries) = existing_entries {
+        for entry in entries {
+            let entry = entry?;
+            let header = entry.header().clone();
+            let path = entry.path()?.to_string_lossy().into_owned();
+            append_entry(&mut builder, &header, &path, entry)?;
+        }
+    }
+    for entry in sample_entries {
+        let entry = entry?;
+        let header = entry.header().clone();
+        let path = format!("{}/{}", name, entry.path()?.to_string_lossy());
+        append_entry(&mut builder, &header, &path, entry)?;
+    }
+    builder.into_inner()?.flush()?;
+    Ok(())
+}
+
+/// Append `sample` to the archive of samples at `path`, see the module documentation for how the
+/// archive is rewritten.
+pub fn append_sample_to_file<P: AsRef<Path>, S: Read>(
+    path: P,
+    name: &str,
+    sample: S,
+) -> Result<()> {
+    let existing = File::open(&path)?;
+    let (mut appended, pending) = PendingFile::create(&path)?;
+    append_sample(Some(existing), name, sample, &mut appended)?;
+    pending.commit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::compression_models::lzma_multi_stream::*;
+    use crate::compression_models::lzma_single_file::*;
+    use crate::compression_models::test_utils::*;
+    use crate::compression_models::*;
+
+    const SAMPLE_B_FASTQ: &str = concat!(
+        "@sampleB:1/1\n",
+        "ACGTACGT\n",
+        "+\n",
+        "IIIIIIII\n",
+        "@sampleB:1/2\n",
+        "TTGGCCAA\n",
+        "+\n",
+        "HHHHHHHH\n",
+    );
+
+    fn decompress_sample(archive: &[u8], name: Option<&str>) -> Result<String> {
+        let mut decoder = PareArchiveDecoder::new(archive)?;
+        if let Some(name) = name {
+            decoder.select_sample(name)?;
+        }
+        let (mut writer, buffer) = interleaved_writer();
+        open_decoder(decoder, None, None)?.decompress(&mut writer)?;
+        drop(writer);
+        Ok(buffer.text())
+    }
+
+    #[test]
+    fn test_two_samples() -> Result<()> {
+        let mut sample_a: Vec<u8> = Vec::new();
+        XZSingleFileWriter::new(&mut sample_a)
+            .compress(&mut interleaved_reader(INTERLEAVED_FASTQ))?;
+        let mut sample_b: Vec<u8> = Vec::new();
+        XZMultiStreamWriter::new(&mut sample_b)
+            .compress(&mut interleaved_reader(SAMPLE_B_FASTQ))?;
+
+        let mut one_sample: Vec<u8> = Vec::new();
+        append_sample(
+            None::<&[u8]>,
+            "sampleA",
+            sample_a.as_slice(),
+            &mut one_sample,
+        )?;
+        let mut archive: Vec<u8> = Vec::new();
+        append_sample(
+            Some(one_sample.as_slice()),
+            "sampleB",
+            sample_b.as_slice(),
+            &mut archive,
+        )?;
+
+        let mut decoder = PareArchiveDecoder::new(archive.as_slice())?;
+        assert_eq!(vec!["sampleA", "sampleB"], decoder.samples()?);
+        assert!(matches!(
+            decoder.select_sample("sampleC"),
+            Err(CompressionModelError::UnknownSample { .. })
+        ));
+
+        assert_eq!(
+            SAMPLE_B_FASTQ,
+            decompress_sample(&archive, Some("sampleB"))?
+        );
+        assert_eq!(
+            INTERLEAVED_FASTQ,
+            decompress_sample(&archive, Some("sampleA"))?
+        );
+        assert!(matches!(
+            decompress_sample(&archive, None),
+            Err(CompressionModelError::SampleRequired { .. })
+        ));
+
+        // the uncompressed lengths are in the metadata of each sample
+        let lengths = PareArchiveDecoder::new(archive.as_slice())?.stream_lengths()?;
+        assert!(lengths.iter().all(|(_, _, length)| length.is_some()));
+        let nucleotides = lengths
+            .iter()
+            .find(|(path, _, _)| path == "sampleB/nucleotides")
+            .and_then(|(_, _, length)| *length);
+        assert_eq!(Some(18), nucleotides);
+
+        // only the chosen sample is unpacked, its streams are listed by their path in it
+        let decoder = PareArchiveDecoder::new_sample(archive.as_slice(), "sampleB")?;
+        let unpacked = decoder.temp_path().expect("unpacked to disk");
+        assert!(unpacked.join("sampleB").exists());
+        assert!(!unpacked.join("sampleA").exists());
+        assert!(decoder
+            .list_streams()?
+            .iter()
+            .any(|(path, _)| path == "metadata"));
+        let (mut writer, buffer) = interleaved_writer();
+        open_decoder(decoder, None, None)?.decompress(&mut writer)?;
+        drop(writer);
+        assert_eq!(SAMPLE_B_FASTQ, buffer.text());
+        assert!(matches!(
+            PareArchiveDecoder::new_sample(archive.as_slice(), "sampleC"),
+            Err(CompressionModelError::UnknownSample { .. })
+        ));
+
+        assert!(matches!(
+            append_sample(
+                Some(archive.as_slice()),
+                "sampleA",
+                sample_b.as_slice(),
+                Vec::new()
+            ),
+            Err(CompressionModelError::DuplicateSample { .. })
+        ));
+        assert!(matches!(
+            append_sample(
+                Some(sample_a.as_slice()),
+                "sampleB",
+                sample_b.as_slice(),
+                Vec::new()
+            ),
+            Err(CompressionModelError::NotMultiSample)
+        ));
+        assert!(matches!(
+            append_sample(None::<&[u8]>, "../up", sample_b.as_slice(), Vec::new()),
+            Err(CompressionModelError::InvalidSampleName { .. })
+        ));
+
+        Ok(())
+    }
+}
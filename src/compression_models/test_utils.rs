@@ -0,0 +1,197 @@
+//! Fixtures shared by the compression model tests.
+
+use std::cell::RefCell;
+use std::io::{BufReader, BufWriter, Cursor, Write};
+use std::rc::Rc;
+
+use tar::{Archive, Builder};
+
+use crate::seq_files::fastq::*;
+
+pub const INTERLEAVED_FASTQ: &str = concat!(
+    "@HWI-EAS209_0006_FC706VJ:5:58:5894:21141#ATCACG/1\n",
+    "TTAATTGGTAAATAAATCTCCTAATAGCTTAGATNTTACCTTNNNNNNNNNNTAGTTTCTTGAGATTTGTTGGGGGAGACATTTTTGTGATTGCCTTGAT\n",
+    "+\n",
+    "efcfffffcfeefffcffffffddf`feed]`]_B__^__[YBBBBBBBBBBRTT\\]][]dddd`ddd^dddadd^BBBBBBBBBBBBBBBBBBBBBBBB\n",
+    "@HWI-EAS209_0006_FC706VJ:5:58:5894:21141#ATCACG/2\n",
+    "TTAATTGGTAAATAAATCTCCTAATAGCTTAGATNTTACCTTNNNNNNNNNNTAGTTTCTTGAGATTTGTTGGGGGAGACATTTTTGTGATTGCCTTGAT\n",
+    "+\n",
+    "efcfffffcfeefffcffffffddf`feed]`]_B__^__[YBBBBBBBBBBRTT\\]][]dddd`ddd^dddadd^BBBBBBBBBBBBBBBBBBBBBBBB\n",
+    "@HWI-EAS209_0006_FC706VJ:5:58:5895:21142#ATCACG/1\n",
+    "ACGTTGCAACGTNACG\n",
+    "+\n",
+    "IIIIHHHHGGGG!!#I\n",
+    "@HWI-EAS209_0006_FC706VJ:5:58:5895:21142#ATCACG/2\n",
+    "GGGCCCAAATTTNNNA\n",
+    "+\n",
+    "ABCDEFGHIJKLMNOP\n",
+);
+
+pub fn interleaved_reader(data: &'static str) -> Box<dyn PairedFastQReader> {
+    Box::new(FastQInterleavedFileReader::new(
+        Box::new(FastQFileReader::new(BufReader::new(data.as_bytes()))),
+        false,
+    ))
+}
+
+/// Like `interleaved_reader` for text built by the test.
+pub fn string_reader(fastq: &str) -> Box<dyn PairedFastQReader> {
+    Box::new(FastQInterleavedFileReader::new(file_reader(fastq), false))
+}
+
+/// A reader of the records of a single file built by the test.
+pub fn file_reader(fastq: &str) -> Box<dyn FastQFileReaderTrait + Send> {
+    Box::new(FastQFileReader::new(BufReader::new(Cursor::new(
+        fastq.as_bytes().to_vec(),
+    ))))
+}
+
+/// A `Write` sink that can still be read after the writer owning it is dropped.
+#[derive(Clone, Default)]
+pub struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl SharedBuffer {
+    pub fn bytes(&self) -> Vec<u8> {
+        self.0.borrow().clone()
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8(self.bytes()).unwrap()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// An interleaved writer and the buffer it writes to. Drop the writer to flush it.
+pub fn interleaved_writer() -> (Box<dyn PairedFastQWriter>, SharedBuffer) {
+    interleaved_writer_with_line_ending(LineEnding::Lf)
+}
+
+pub fn interleaved_writer_with_line_ending(
+    line_ending: LineEnding,
+) -> (Box<dyn PairedFastQWriter>, SharedBuffer) {
+    let buffer = SharedBuffer::default();
+    let writer = Box::new(FastQInterleavedFileWriter::new(
+        Box::new(
+            FastQFileWriter::new(BufWriter::new(buffer.clone())).with_line_ending(line_ending),
+        ),
+        false,
+    ));
+    (writer, buffer)
+}
+
+/// Pseudo-random read pairs, the same on every run. By default the reads are 50 to 150 upper case
+/// letters with qualities from all over the Phred range and titles like `@read7/2`.
+#[derive(Clone, Copy, Default)]
+pub struct SyntheticFastq {
+    length: Option<usize>,
+    fading_qualities: bool,
+    illumina: bool,
+}
+
+impl SyntheticFastq {
+    /// Every read `length` letters long.
+    pub fn with_length(mut self, length: usize) -> Self {
+        self.length = Some(length);
+        self
+    }
+
+    /// Qualities that drop along the read, like those of a sequencer, rather than at random.
+    pub fn with_fading_qualities(mut self) -> Self {
+        self.fading_qualities = true;
+        self
+    }
+
+    /// Illumina titles and soft masked, lower case, letters.
+    pub fn with_illumina_reads(mut self) -> Self {
+        self.illumina = true;
+        self
+    }
+
+    /// `pairs` interleaved pairs.
+    pub fn interleaved(&self, pairs: usize) -> String {
+        self.reads(pairs, None)
+    }
+
+    /// The `mate` reads, 1 or 2, of `pairs` pairs.
+    pub fn mate(&self, pairs: usize, mate: usize) -> String {
+        self.reads(pairs, Some(mate))
+    }
+
+    /// A reader of `pairs` interleaved pairs.
+    pub fn reader(&self, pairs: usize) -> Box<dyn PairedFastQReader> {
+        string_reader(&self.interleaved(pairs))
+    }
+
+    fn reads(&self, pairs: usize, mate: Option<usize>) -> String {
+        let letters: &[u8] = match self.illumina {
+            true => b"ACGTacgt",
+            false => b"ACGT",
+        };
+        let mut fastq = String::new();
+        let mut state: u32 = 17;
+        for i in 0..2 * pairs {
+            let length = self.length.unwrap_or(50 + i * 37 % 101);
+            let mut read = match self.illumina {
+                true => format!(
+                    "@EAS139:136:FC706VJ:2:2104:{}:{} {}:N:18:ATCACG\n",
+                    i / 2,
+                    i % 1000,
+                    i % 2 + 1
+                ),
+                false => format!("@read{}/{}\n", i / 2, i % 2 + 1),
+            };
+            let mut qualities = String::new();
+            for cycle in 0..length {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                read.push(letters[(state >> 16) as usize % letters.len()] as char);
+                let quality = match self.fading_qualities {
+                    true => b'I' - (cycle / 10) as u8 - (state >> 20) as u8 % 3,
+                    false => b'#' + (state >> 20) as u8 % 40,
+                };
+                qualities.push(quality as char);
+            }
+            if mate.is_none_or(|mate| mate == i % 2 + 1) {
+                fastq.push_str(&read);
+                fastq.push_str("\n+\n");
+                fastq.push_str(&qualities);
+                fastq.push('\n');
+            }
+        }
+        fastq
+    }
+}
+
+/// A copy of `archive` without the tar entry named `path`.
+pub fn without_stream(archive: &[u8], path: &str) -> Vec<u8> {
+    let mut builder = Builder::new(Vec::new());
+    for entry in Archive::new(archive).entries().unwrap() {
+        let mut entry = entry.unwrap();
+        if entry.path().unwrap().to_str() != Some(path) {
+            let header = entry.header().clone();
+            builder.append(&header, &mut entry).unwrap();
+        }
+    }
+    builder.into_inner().unwrap()
+}
+
+/// Flip a byte in the middle of the content of the tar entry named `path`.
+pub fn corrupt_stream(archive: &mut [u8], path: &str) {
+    let (offset, size) = Archive::new(&archive[..])
+        .entries()
+        .unwrap()
+        .map(|e| e.unwrap())
+        .find(|e| e.path().unwrap().to_str() == Some(path))
+        .map(|e| (e.raw_file_position() as usize, e.size() as usize))
+        .expect("stream not found in archive");
+
+    archive[offset + size / 2] ^= 0x55;
+}
@@ -0,0 +1,960 @@
+use std::io::{BufRead, BufReader, Read, Write};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::compression_models::{
+    Codec, CompressedSpool, CompressionModelError, LzmaOptions, PareArchiveDecoder, Result,
+    StreamDecoder,
+};
+
+type StreamBufReader = BufReader<StreamDecoder>;
+
+/// How the read titles are laid out in the archive.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, ValueEnum, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum TitleLayout {
+    /// one title per line in the titles stream
+    #[default]
+    Verbatim,
+    /// split Illumina names into a stream per field, other titles are stored verbatim
+    IlluminaColumns,
+    /// each title as the length of the prefix it shares with the previous title and the rest
+    Delta,
+    /// split titles at ':' and ' ' into a stream per column, titles shaped differently than the
+    /// first are stored verbatim
+    Tokens,
+}
+
+impl TitleLayout {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TitleLayout::Verbatim => "verbatim",
+            TitleLayout::IlluminaColumns => "illumina_columns",
+            TitleLayout::Delta => "delta",
+            TitleLayout::Tokens => "tokens",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "verbatim" => Ok(TitleLayout::Verbatim),
+            "illumina_columns" => Ok(TitleLayout::IlluminaColumns),
+            "delta" => Ok(TitleLayout::Delta),
+            "tokens" => Ok(TitleLayout::Tokens),
+            _ => Err(CompressionModelError::UnknownTitleLayout {
+                name: name.to_string(),
+            }),
+        }
+    }
+
+    /// `new_spool` is kept by layouts that only know how many streams they need once they've
+    /// seen some titles.
+    pub fn encoder<F>(&self, new_spool: F) -> Box<dyn TitleEncoder>
+    where
+        F: Fn() -> CompressedSpool + Send + 'static,
+    {
+        match self {
+            TitleLayout::Verbatim => Box::new(VerbatimTitleEncoder { spool: new_spool() }),
+            TitleLayout::IlluminaColumns => Box::new(IlluminaTitleEncoder::new(&new_spool)),
+            TitleLayout::Delta => Box::new(TitleDeltaEncoder {
+                spool: new_spool(),
+                previous: String::new(),
+            }),
+            TitleLayout::Tokens => Box::new(TokenTitleEncoder::new(Box::new(new_spool))),
+        }
+    }
+
+    pub fn decoder<R: Read>(
+        &self,
+        arc: &mut PareArchiveDecoder<R>,
+        codec: Codec,
+    ) -> Result<Box<dyn TitleDecoder>> {
+        self.decoder_at(arc, codec, &|_| 0)
+    }
+
+    /// Start decoding from a point the encoder was restarted at, `offset` looks up what
+    /// `TitleEncoder::restart` returned: where each stream was restarted and so on. Anything not
+    /// there is 0. Every title stream is compressed with `codec`.
+    pub fn decoder_at<R: Read>(
+        &self,
+        arc: &mut PareArchiveDecoder<R>,
+        codec: Codec,
+        offset: &dyn Fn(&str) -> u64,
+    ) -> Result<Box<dyn TitleDecoder>> {
+        let mut stream = |path: &str| arc.get_compressed_stream_at(path, codec, offset(path));
+        Ok(match self {
+            TitleLayout::Verbatim => Box::new(VerbatimTitleDecoder {
+                stream: BufReader::new(stream("titles")?),
+            }),
+            TitleLayout::IlluminaColumns => {
+                Box::new(IlluminaTitleDecoder::new(&mut stream, offset)?)
+            }
+            TitleLayout::Delta => Box::new(TitleDeltaDecoder {
+                stream: BufReader::new(stream("titles")?),
+                previous: String::new(),
+                line: String::new(),
+            }),
+            TitleLayout::Tokens => Box::new(TokenTitleDecoder::new(&mut stream, offset)?),
+        })
+    }
+}
+
+pub trait TitleEncoder: Send {
+    fn write_title(&mut self, title: &str) -> Result<()>;
+    /// Restart every spool, so the titles after this can be decoded on their own. Returns where
+    /// each spool was restarted, along with anything else a decoder starting here needs.
+    fn restart(&mut self, lzma: LzmaOptions) -> Result<Vec<(&'static str, u64)>>;
+    /// The spools holding the encoded titles along with the stream names to store them under.
+    fn into_spools(self: Box<Self>) -> Vec<(&'static str, CompressedSpool)>;
+}
+
+pub trait TitleDecoder {
+    /// Read the next title, returning false at the end of the titles.
+    fn read_title(&mut self, title: &mut String) -> Result<bool>;
+}
+
+fn read_line(source: &mut StreamBufReader, record: &mut String) -> Result<bool> {
+    record.clear();
+    if source.read_line(record)? == 0 {
+        return Ok(false);
+    }
+
+    if record.pop() != Some('\n') {
+        return Err(CompressionModelError::IncompleteRecord);
+    }
+
+    Ok(true)
+}
+
+struct VerbatimTitleEncoder {
+    spool: CompressedSpool,
+}
+
+impl TitleEncoder for VerbatimTitleEncoder {
+    fn write_title(&mut self, title: &str) -> Result<()> {
+        writeln!(self.spool, "{}", title)?;
+        Ok(())
+    }
+
+    fn restart(&mut self, lzma: LzmaOptions) -> Result<Vec<(&'static str, u64)>> {
+        Ok(vec![("titles", self.spool.restart(lzma)?)])
+    }
+
+    fn into_spools(self: Box<Self>) -> Vec<(&'static str, CompressedSpool)> {
+        vec![("titles", self.spool)]
+    }
+}
+
+struct VerbatimTitleDecoder {
+    stream: StreamBufReader,
+}
+
+impl TitleDecoder for VerbatimTitleDecoder {
+    fn read_title(&mut self, title: &mut String) -> Result<bool> {
+        read_line(&mut self.stream, title)
+    }
+}
+
+/// The length in bytes of the longest common prefix of `a` and `b` that ends on a char boundary.
+fn common_prefix_len(a: &str, b: &str) -> usize {
+    let mut len = a.bytes().zip(b.bytes()).take_while(|(x, y)| x == y).count();
+    while !a.is_char_boundary(len) {
+        len -= 1;
+    }
+    len
+}
+
+/// Writes each title as "PREFIX SUFFIX", where PREFIX is how many bytes it shares with the start
+/// of the previous title and SUFFIX is the rest of it.
+struct TitleDeltaEncoder {
+    spool: CompressedSpool,
+    previous: String,
+}
+
+impl TitleEncoder for TitleDeltaEncoder {
+    fn write_title(&mut self, title: &str) -> Result<()> {
+        let prefix = common_prefix_len(&self.previous, title);
+        writeln!(self.spool, "{} {}", prefix, &title[prefix..])?;
+
+        self.previous.clear();
+        self.previous.push_str(title);
+        Ok(())
+    }
+
+    fn restart(&mut self, lzma: LzmaOptions) -> Result<Vec<(&'static str, u64)>> {
+        self.previous.clear();
+        Ok(vec![("titles", self.spool.restart(lzma)?)])
+    }
+
+    fn into_spools(self: Box<Self>) -> Vec<(&'static str, CompressedSpool)> {
+        vec![("titles", self.spool)]
+    }
+}
+
+struct TitleDeltaDecoder {
+    stream: StreamBufReader,
+    previous: String,
+    line: String,
+}
+
+impl TitleDecoder for TitleDeltaDecoder {
+    fn read_title(&mut self, title: &mut String) -> Result<bool> {
+        if !read_line(&mut self.stream, &mut self.line)? {
+            return Ok(false);
+        }
+
+        let (prefix, suffix) = self
+            .line
+            .split_once(' ')
+            .ok_or(CompressionModelError::IncompleteRecord)?;
+        let prefix = prefix
+            .parse::<usize>()
+            .map_err(|_| CompressionModelError::IncompleteRecord)?;
+        if !self.previous.is_char_boundary(prefix) {
+            return Err(CompressionModelError::IncompleteRecord);
+        }
+
+        self.previous.truncate(prefix);
+        self.previous.push_str(suffix);
+        title.clear();
+        title.push_str(&self.previous);
+        Ok(true)
+    }
+}
+
+// Illumina names look like INSTRUMENT:RUN:FLOWCELL:LANE:TILE:X:Y optionally followed by a
+// comment, the Casava 1.8 read/filter/control/index fields.
+const ILLUMINA_FIELDS: usize = 7;
+const ILLUMINA_NUMERIC: [bool; ILLUMINA_FIELDS] = [false, true, false, true, true, true, true];
+const ILLUMINA_STREAMS: [&str; ILLUMINA_FIELDS] = [
+    "title_instrument",
+    "title_run",
+    "title_flowcell",
+    "title_lane",
+    "title_tile",
+    "title_x",
+    "title_y",
+];
+
+/// Names the last value of each field is recorded under at a restart.
+const ILLUMINA_PREVIOUS: [&str; ILLUMINA_FIELDS] = [
+    "title_instrument_previous",
+    "title_run_previous",
+    "title_flowcell_previous",
+    "title_lane_previous",
+    "title_tile_previous",
+    "title_x_previous",
+    "title_y_previous",
+];
+
+const KIND_ILLUMINA: u8 = b'I';
+const KIND_VERBATIM: u8 = b'V';
+
+/// A number that prints back exactly as it was written.
+fn canonical_number(field: &str) -> Option<i64> {
+    field.parse::<i64>().ok().filter(|n| n.to_string() == field)
+}
+
+/// Split an Illumina title into its name fields and the rest of the title, including the
+/// whitespace separating them.
+fn split_illumina_title(title: &str) -> Option<([&str; ILLUMINA_FIELDS], &str)> {
+    let (name, rest) = title.split_at(title.find(char::is_whitespace).unwrap_or(title.len()));
+
+    let mut fields = [""; ILLUMINA_FIELDS];
+    let mut parts = name.split(':');
+    for (i, field) in fields.iter_mut().enumerate() {
+        *field = parts.next()?;
+        if ILLUMINA_NUMERIC[i] {
+            canonical_number(field)?;
+        } else if field.is_empty() {
+            return None;
+        }
+    }
+
+    if parts.next().is_some() {
+        return None;
+    }
+
+    Some((fields, rest))
+}
+
+/// Stores each field of an Illumina name in its own stream, numeric fields as the difference
+/// from the previous read.
+struct IlluminaTitleEncoder {
+    kinds: CompressedSpool,
+    verbatim: CompressedSpool,
+    fields: Vec<CompressedSpool>,
+    comments: CompressedSpool,
+    previous: [i64; ILLUMINA_FIELDS],
+}
+
+impl IlluminaTitleEncoder {
+    fn new(new_spool: &dyn Fn() -> CompressedSpool) -> Self {
+        IlluminaTitleEncoder {
+            kinds: new_spool(),
+            verbatim: new_spool(),
+            fields: (0..ILLUMINA_FIELDS).map(|_| new_spool()).collect(),
+            comments: new_spool(),
+            previous: [0; ILLUMINA_FIELDS],
+        }
+    }
+}
+
+impl TitleEncoder for IlluminaTitleEncoder {
+    fn write_title(&mut self, title: &str) -> Result<()> {
+        let (fields, rest) = match split_illumina_title(title) {
+            Some(split) => split,
+            None => {
+                self.kinds.write_all(&[KIND_VERBATIM])?;
+                writeln!(self.verbatim, "{}", title)?;
+                return Ok(());
+            }
+        };
+
+        self.kinds.write_all(&[KIND_ILLUMINA])?;
+        for (i, field) in fields.iter().enumerate() {
+            if ILLUMINA_NUMERIC[i] {
+                let value = canonical_number(field).expect("checked by split_illumina_title");
+                writeln!(self.fields[i], "{}", value.wrapping_sub(self.previous[i]))?;
+                self.previous[i] = value;
+            } else {
+                writeln!(self.fields[i], "{}", field)?;
+            }
+        }
+        writeln!(self.comments, "{}", rest)?;
+
+        Ok(())
+    }
+
+    fn restart(&mut self, lzma: LzmaOptions) -> Result<Vec<(&'static str, u64)>> {
+        // the numbers carry on from the previous read so a decoder reading straight through
+        // doesn't need to know about the restart, one starting here is given them instead
+        let mut offsets = vec![
+            ("title_kinds", self.kinds.restart(lzma)?),
+            ("titles", self.verbatim.restart(lzma)?),
+            ("title_comments", self.comments.restart(lzma)?),
+        ];
+        for (path, spool) in ILLUMINA_STREAMS.into_iter().zip(self.fields.iter_mut()) {
+            offsets.push((path, spool.restart(lzma)?));
+        }
+        for (name, previous) in ILLUMINA_PREVIOUS.into_iter().zip(self.previous) {
+            offsets.push((name, previous as u64));
+        }
+        Ok(offsets)
+    }
+
+    fn into_spools(self: Box<Self>) -> Vec<(&'static str, CompressedSpool)> {
+        let mut spools = vec![
+            ("title_kinds", self.kinds),
+            ("titles", self.verbatim),
+            ("title_comments", self.comments),
+        ];
+        spools.extend(ILLUMINA_STREAMS.into_iter().zip(self.fields));
+        spools
+    }
+}
+
+struct IlluminaTitleDecoder {
+    kinds: StreamDecoder,
+    verbatim: StreamBufReader,
+    fields: Vec<StreamBufReader>,
+    comments: StreamBufReader,
+    previous: [i64; ILLUMINA_FIELDS],
+    line: String,
+}
+
+impl IlluminaTitleDecoder {
+    fn new(
+        stream: &mut dyn FnMut(&str) -> Result<StreamDecoder>,
+        offset: &dyn Fn(&str) -> u64,
+    ) -> Result<Self> {
+        let mut fields = Vec::with_capacity(ILLUMINA_FIELDS);
+        for path in ILLUMINA_STREAMS {
+            fields.push(BufReader::new(stream(path)?));
+        }
+
+        Ok(IlluminaTitleDecoder {
+            kinds: stream("title_kinds")?,
+            verbatim: BufReader::new(stream("titles")?),
+            fields,
+            comments: BufReader::new(stream("title_comments")?),
+            previous: ILLUMINA_PREVIOUS.map(|name| offset(name) as i64),
+            line: String::new(),
+        })
+    }
+}
+
+impl TitleDecoder for IlluminaTitleDecoder {
+    fn read_title(&mut self, title: &mut String) -> Result<bool> {
+        let mut kind = [0u8];
+        if self.kinds.read(&mut kind)? == 0 {
+            return Ok(false);
+        }
+
+        match kind[0] {
+            KIND_VERBATIM => {
+                if !read_line(&mut self.verbatim, title)? {
+                    return Err(CompressionModelError::IncompleteRecord);
+                }
+            }
+            KIND_ILLUMINA => {
+                title.clear();
+                for (i, &numeric) in ILLUMINA_NUMERIC.iter().enumerate() {
+                    if !read_line(&mut self.fields[i], &mut self.line)? {
+                        return Err(CompressionModelError::IncompleteRecord);
+                    }
+
+                    if i > 0 {
+                        title.push(':');
+                    }
+
+                    if numeric {
+                        let delta = self
+                            .line
+                            .parse::<i64>()
+                            .map_err(|_| CompressionModelError::IncompleteRecord)?;
+                        self.previous[i] = self.previous[i].wrapping_add(delta);
+                        title.push_str(&self.previous[i].to_string());
+                    } else {
+                        title.push_str(&self.line);
+                    }
+                }
+
+                if !read_line(&mut self.comments, &mut self.line)? {
+                    return Err(CompressionModelError::IncompleteRecord);
+                }
+                title.push_str(&self.line);
+            }
+            _ => return Err(CompressionModelError::IncompleteRecord),
+        }
+
+        Ok(true)
+    }
+}
+
+/// The most columns `NameTokenizer` splits a name into, longer names are stored verbatim.
+pub const MAX_NAME_TOKENS: usize = 16;
+
+const TOKEN_STREAMS: [&str; MAX_NAME_TOKENS] = [
+    "title_token_0",
+    "title_token_1",
+    "title_token_2",
+    "title_token_3",
+    "title_token_4",
+    "title_token_5",
+    "title_token_6",
+    "title_token_7",
+    "title_token_8",
+    "title_token_9",
+    "title_token_10",
+    "title_token_11",
+    "title_token_12",
+    "title_token_13",
+    "title_token_14",
+    "title_token_15",
+];
+
+/// Names the last value of each numeric column is recorded under at a restart.
+const TOKEN_PREVIOUS: [&str; MAX_NAME_TOKENS] = [
+    "title_token_0_previous",
+    "title_token_1_previous",
+    "title_token_2_previous",
+    "title_token_3_previous",
+    "title_token_4_previous",
+    "title_token_5_previous",
+    "title_token_6_previous",
+    "title_token_7_previous",
+    "title_token_8_previous",
+    "title_token_9_previous",
+    "title_token_10_previous",
+    "title_token_11_previous",
+    "title_token_12_previous",
+    "title_token_13_previous",
+    "title_token_14_previous",
+    "title_token_15_previous",
+];
+
+const KIND_TOKENS: u8 = b'T';
+
+fn is_name_separator(c: char) -> bool {
+    c == ':' || c == ' '
+}
+
+/// Splits read names into columns at every ':' and ' '. The shape of the names is learnt from an
+/// example: which separators come in which order and which columns hold numbers. Only names of
+/// that shape can be split, so the columns of every name line up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NameTokenizer {
+    separators: Vec<char>,
+    numeric: Vec<bool>,
+}
+
+impl NameTokenizer {
+    /// Learn the shape of the names from `name`, None when it has more than `MAX_NAME_TOKENS`
+    /// columns.
+    pub fn learn(name: &str) -> Option<Self> {
+        let separators: Vec<char> = name.chars().filter(|&c| is_name_separator(c)).collect();
+        if separators.len() >= MAX_NAME_TOKENS {
+            return None;
+        }
+
+        let numeric = name
+            .split(is_name_separator)
+            .map(|token| canonical_number(token).is_some())
+            .collect();
+        Some(NameTokenizer {
+            separators,
+            numeric,
+        })
+    }
+
+    pub fn columns(&self) -> usize {
+        self.numeric.len()
+    }
+
+    /// Whether `column` holds a number in every name this tokenizer splits.
+    pub fn is_numeric(&self, column: usize) -> bool {
+        self.numeric[column]
+    }
+
+    /// Split `name` into its columns, None when it isn't the shape this tokenizer learnt.
+    pub fn encode<'a>(&self, name: &'a str) -> Option<Vec<&'a str>> {
+        let mut tokens = Vec::with_capacity(self.columns());
+        let mut separators = self.separators.iter();
+        let mut start = 0;
+        for (i, c) in name.char_indices().filter(|&(_, c)| is_name_separator(c)) {
+            if separators.next() != Some(&c) {
+                return None;
+            }
+            tokens.push(&name[start..i]);
+            start = i + c.len_utf8();
+        }
+        if separators.next().is_some() {
+            return None;
+        }
+        tokens.push(&name[start..]);
+
+        let numbers_match = tokens
+            .iter()
+            .zip(&self.numeric)
+            .all(|(token, &numeric)| !numeric || canonical_number(token).is_some());
+        numbers_match.then_some(tokens)
+    }
+
+    /// Join the columns `encode` split a name into back together.
+    pub fn decode<S: AsRef<str>>(&self, tokens: &[S], name: &mut String) {
+        name.clear();
+        for (i, token) in tokens.iter().enumerate() {
+            if i > 0 {
+                name.push(self.separators[i - 1]);
+            }
+            name.push_str(token.as_ref());
+        }
+    }
+}
+
+/// Stores each column of the titles in its own stream, numeric columns as the difference from
+/// the previous read. The first title that can be tokenized sets the shape and is kept in
+/// "title_pattern" for the decoder to learn it from too.
+struct TokenTitleEncoder {
+    new_spool: Box<dyn Fn() -> CompressedSpool + Send>,
+    tokenizer: Option<NameTokenizer>,
+    pattern: CompressedSpool,
+    kinds: CompressedSpool,
+    verbatim: CompressedSpool,
+    columns: Vec<CompressedSpool>,
+    previous: [i64; MAX_NAME_TOKENS],
+}
+
+impl TokenTitleEncoder {
+    fn new(new_spool: Box<dyn Fn() -> CompressedSpool + Send>) -> Self {
+        TokenTitleEncoder {
+            pattern: new_spool(),
+            kinds: new_spool(),
+            verbatim: new_spool(),
+            new_spool,
+            tokenizer: None,
+            columns: Vec::new(),
+            previous: [0; MAX_NAME_TOKENS],
+        }
+    }
+}
+
+impl TitleEncoder for TokenTitleEncoder {
+    fn write_title(&mut self, title: &str) -> Result<()> {
+        if self.tokenizer.is_none() {
+            if let Some(tokenizer) = NameTokenizer::learn(title) {
+                writeln!(self.pattern, "{}", title)?;
+                self.columns = (0..tokenizer.columns())
+                    .map(|_| (self.new_spool)())
+                    .collect();
+                self.tokenizer = Some(tokenizer);
+            }
+        }
+
+        let split = self
+            .tokenizer
+            .as_ref()
+            .and_then(|tokenizer| Some((tokenizer, tokenizer.encode(title)?)));
+        let (tokenizer, tokens) = match split {
+            Some(split) => split,
+            None => {
+                self.kinds.write_all(&[KIND_VERBATIM])?;
+                writeln!(self.verbatim, "{}", title)?;
+                return Ok(());
+            }
+        };
+
+        self.kinds.write_all(&[KIND_TOKENS])?;
+        for (i, token) in tokens.iter().enumerate() {
+            if tokenizer.is_numeric(i) {
+                let value = canonical_number(token).expect("checked by NameTokenizer::encode");
+                writeln!(self.columns[i], "{}", value.wrapping_sub(self.previous[i]))?;
+                self.previous[i] = value;
+            } else {
+                writeln!(self.columns[i], "{}", token)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn restart(&mut self, lzma: LzmaOptions) -> Result<Vec<(&'static str, u64)>> {
+        // the pattern is only ever written once, a decoder starting here reads it from the start
+        let mut offsets = vec![
+            ("title_kinds", self.kinds.restart(lzma)?),
+            ("titles", self.verbatim.restart(lzma)?),
+        ];
+        for (path, spool) in TOKEN_STREAMS.into_iter().zip(self.columns.iter_mut()) {
+            offsets.push((path, spool.restart(lzma)?));
+        }
+        for (name, previous) in TOKEN_PREVIOUS
+            .into_iter()
+            .zip(self.previous)
+            .take(self.columns.len())
+        {
+            offsets.push((name, previous as u64));
+        }
+        Ok(offsets)
+    }
+
+    fn into_spools(self: Box<Self>) -> Vec<(&'static str, CompressedSpool)> {
+        let mut spools = vec![
+            ("title_pattern", self.pattern),
+            ("title_kinds", self.kinds),
+            ("titles", self.verbatim),
+        ];
+        spools.extend(TOKEN_STREAMS.into_iter().zip(self.columns));
+        spools
+    }
+}
+
+struct TokenTitleDecoder {
+    tokenizer: Option<NameTokenizer>,
+    kinds: StreamDecoder,
+    verbatim: StreamBufReader,
+    columns: Vec<StreamBufReader>,
+    previous: [i64; MAX_NAME_TOKENS],
+    tokens: Vec<String>,
+}
+
+impl TokenTitleDecoder {
+    fn new(
+        stream: &mut dyn FnMut(&str) -> Result<StreamDecoder>,
+        offset: &dyn Fn(&str) -> u64,
+    ) -> Result<Self> {
+        // no pattern means none of the titles could be tokenized
+        let mut pattern = String::new();
+        let tokenizer = if read_line(&mut BufReader::new(stream("title_pattern")?), &mut pattern)? {
+            Some(NameTokenizer::learn(&pattern).ok_or(CompressionModelError::IncompleteRecord)?)
+        } else {
+            None
+        };
+
+        let columns = tokenizer.as_ref().map_or(0, NameTokenizer::columns);
+        let mut streams = Vec::with_capacity(columns);
+        for path in &TOKEN_STREAMS[..columns] {
+            streams.push(BufReader::new(stream(path)?));
+        }
+
+        Ok(TokenTitleDecoder {
+            tokenizer,
+            kinds: stream("title_kinds")?,
+            verbatim: BufReader::new(stream("titles")?),
+            columns: streams,
+            previous: TOKEN_PREVIOUS.map(|name| offset(name) as i64),
+            tokens: vec![String::new(); columns],
+        })
+    }
+}
+
+impl TitleDecoder for TokenTitleDecoder {
+    fn read_title(&mut self, title: &mut String) -> Result<bool> {
+        let mut kind = [0u8];
+        if self.kinds.read(&mut kind)? == 0 {
+            return Ok(false);
+        }
+
+        match (kind[0], &self.tokenizer) {
+            (KIND_VERBATIM, _) => {
+                if !read_line(&mut self.verbatim, title)? {
+                    return Err(CompressionModelError::IncompleteRecord);
+                }
+            }
+            (KIND_TOKENS, Some(tokenizer)) => {
+                for (i, token) in self.tokens.iter_mut().enumerate() {
+                    if !read_line(&mut self.columns[i], token)? {
+                        return Err(CompressionModelError::IncompleteRecord);
+                    }
+
+                    if tokenizer.is_numeric(i) {
+                        let delta = token
+                            .parse::<i64>()
+                            .map_err(|_| CompressionModelError::IncompleteRecord)?;
+                        self.previous[i] = self.previous[i].wrapping_add(delta);
+                        token.clear();
+                        token.push_str(&self.previous[i].to_string());
+                    }
+                }
+                tokenizer.decode(&self.tokens, title);
+            }
+            _ => return Err(CompressionModelError::IncompleteRecord),
+        }
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::compression_models::lzma_multi_stream::*;
+    use crate::compression_models::test_utils::*;
+    use crate::compression_models::{DecoderModel, EncoderModel};
+
+    const ILLUMINA_FASTQ: &str = concat!(
+        "@EAS139:136:FC706VJ:2:2104:15343:197393 1:Y:18:ATCACG\n",
+        "ACGTACGTAC\n",
+        "+\n",
+        "IIIIIIIIII\n",
+        "@EAS139:136:FC706VJ:2:2104:15343:197393 2:Y:18:ATCACG\n",
+        "TTGCATGCAA\n",
+        "+\n",
+        "IIIIIIIIII\n",
+        "@EAS139:136:FC706VJ:2:2104:15361:197250 1:N:18:ATCACG\n",
+        "ACGTACGTAC\n",
+        "+\n",
+        "IIIIIIIIII\n",
+        "@EAS139:136:FC706VJ:2:2104:15361:197250 2:N:18:ATCACG\n",
+        "TTGCATGCAA\n",
+        "+\n",
+        "IIIIIIIIII\n",
+        "@SRR001666.1 071112_SLXA-EAS1_s_7:5:1:817:345 length=10\n",
+        "ACGTACGTAC\n",
+        "+\n",
+        "IIIIIIIIII\n",
+        "@SRR001666.1 071112_SLXA-EAS1_s_7:5:1:817:345 length=10\n",
+        "TTGCATGCAA\n",
+        "+\n",
+        "IIIIIIIIII\n",
+        "@EAS139:136:FC706VJ:3:1101:1203:2007\n",
+        "ACGTACGTAC\n",
+        "+\n",
+        "IIIIIIIIII\n",
+        "@EAS139:136:FC706VJ:3:1101:01203:2007\n",
+        "TTGCATGCAA\n",
+        "+\n",
+        "IIIIIIIIII\n",
+    );
+
+    #[test]
+    fn test_split_illumina_title() {
+        let (fields, rest) =
+            split_illumina_title("EAS139:136:FC706VJ:2:2104:15343:197393 1:Y:18:ATCACG").unwrap();
+        assert_eq!(
+            ["EAS139", "136", "FC706VJ", "2", "2104", "15343", "197393"],
+            fields
+        );
+        assert_eq!(" 1:Y:18:ATCACG", rest);
+
+        assert!(split_illumina_title("HWI-EAS209_0006_FC706VJ:5:58:5894:21141#ATCACG/1").is_none());
+        assert!(split_illumina_title("EAS139:136:FC706VJ:2:2104:15343:197393:9").is_none());
+        assert!(split_illumina_title("EAS139:136:FC706VJ:2:2104:015343:197393").is_none());
+    }
+
+    #[test]
+    fn test_common_prefix_len() {
+        assert_eq!(0, common_prefix_len("", "EAS139"));
+        assert_eq!(3, common_prefix_len("EAS139", "EAS2"));
+        assert_eq!(6, common_prefix_len("EAS139", "EAS139"));
+        // é and è share their first byte
+        assert_eq!(1, common_prefix_len("aé", "aè"));
+    }
+
+    const COORDINATE_FASTQ: &str = concat!(
+        "@EAS139:136:FC706VJ:2:2104:15343:197393 1:N:18:ATCACG\n",
+        "ACGTACGTAC\n",
+        "+\n",
+        "IIIIIIIIII\n",
+        "@EAS139:136:FC706VJ:2:2104:15343:197393 2:N:18:ATCACG\n",
+        "TTGCATGCAA\n",
+        "+\n",
+        "IIIIIIIIII\n",
+        "@EAS139:136:FC706VJ:2:2104:15343:197401 1:N:18:ATCACG\n",
+        "ACGTACGTAC\n",
+        "+\n",
+        "IIIIIIIIII\n",
+        "@EAS139:136:FC706VJ:2:2104:15343:197401 2:N:18:ATCACG\n",
+        "TTGCATGCAA\n",
+        "+\n",
+        "IIIIIIIIII\n",
+        "@EAS139:136:FC706VJ:2:2104:9871:1022 1:N:18:ATCACG\n",
+        "ACGTACGTAC\n",
+        "+\n",
+        "IIIIIIIIII\n",
+        "@EAS139:136:FC706VJ:2:2104:9871:1022 2:N:18:ATCACG\n",
+        "TTGCATGCAA\n",
+        "+\n",
+        "IIIIIIIIII\n",
+    );
+
+    #[test]
+    fn test_delta_round_trip() -> Result<()> {
+        let mut archive: Vec<u8> = Vec::new();
+        XZMultiStreamWriter::new(&mut archive)
+            .with_title_layout(TitleLayout::Delta)
+            .compress(&mut interleaved_reader(COORDINATE_FASTQ))?;
+
+        let mut decoder = PareArchiveDecoder::new(archive.as_slice())?;
+        assert_eq!("delta", decoder.get_metadata()?.get_str("titles")?);
+
+        let mut titles: Vec<u8> = Vec::new();
+        decoder.dump_stream("titles", true, &mut titles)?;
+        assert_eq!(
+            concat!(
+                "0 EAS139:136:FC706VJ:2:2104:15343:197393 1:N:18:ATCACG\n",
+                "39 2:N:18:ATCACG\n",
+                "35 401 1:N:18:ATCACG\n",
+                "39 2:N:18:ATCACG\n",
+                "26 9871:1022 1:N:18:ATCACG\n",
+                "36 2:N:18:ATCACG\n",
+            ),
+            String::from_utf8(titles)?
+        );
+
+        let (mut writer, output) = interleaved_writer();
+        XZMultiStreamReader::new(archive.as_slice())?.decompress(&mut writer)?;
+        drop(writer);
+        assert_eq!(COORDINATE_FASTQ, output.text());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_illumina_columns_round_trip() -> Result<()> {
+        let mut archive: Vec<u8> = Vec::new();
+        XZMultiStreamWriter::new(&mut archive)
+            .with_title_layout(TitleLayout::IlluminaColumns)
+            .compress(&mut interleaved_reader(ILLUMINA_FASTQ))?;
+
+        let mut decoder = PareArchiveDecoder::new(archive.as_slice())?;
+        assert_eq!(
+            "illumina_columns",
+            decoder.get_metadata()?.get_str("titles")?
+        );
+
+        let (mut writer, output) = interleaved_writer();
+        XZMultiStreamReader::new(archive.as_slice())?.decompress(&mut writer)?;
+        drop(writer);
+        assert_eq!(ILLUMINA_FASTQ, output.text());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_name_tokenizer() {
+        let tokenizer =
+            NameTokenizer::learn("EAS139:136:FC706VJ:2:2104:15343:197393 1:Y:18:ATCACG").unwrap();
+        assert_eq!(11, tokenizer.columns());
+        assert_eq!(
+            [false, true, false, true, true, true, true, true, false, true, false],
+            *(0..11).map(|i| tokenizer.is_numeric(i)).collect::<Vec<_>>()
+        );
+
+        let name = "EAS139:136:FC706VJ:2:2104:15361:197250 2:N:18:ATCACG";
+        let tokens = tokenizer.encode(name).unwrap();
+        assert_eq!(
+            vec![
+                "EAS139", "136", "FC706VJ", "2", "2104", "15361", "197250", "2", "N", "18",
+                "ATCACG"
+            ],
+            tokens
+        );
+        let mut decoded = String::new();
+        tokenizer.decode(&tokens, &mut decoded);
+        assert_eq!(name, decoded);
+
+        // no comment, separators in a different order, a number that wouldn't print back
+        assert!(tokenizer
+            .encode("EAS139:136:FC706VJ:2:2104:15343:197393")
+            .is_none());
+        assert!(tokenizer
+            .encode("EAS139:136:FC706VJ:2:2104:15343 197393:1:Y:18")
+            .is_none());
+        assert!(tokenizer
+            .encode("EAS139:136:FC706VJ:2:2104:015343:197393 1:Y:18:ATCACG")
+            .is_none());
+        assert!(tokenizer
+            .encode("EAS139:136:FC706VJ:2:2104:x:197393 1:Y:18:ATCACG")
+            .is_none());
+
+        // text columns take anything
+        assert!(tokenizer
+            .encode("EAS140:136:FC706VK:2:2104:15343:197393 1:Y:7:AT")
+            .is_some());
+
+        assert!(NameTokenizer::learn(&":".repeat(MAX_NAME_TOKENS - 1)).is_some());
+        assert!(NameTokenizer::learn(&":".repeat(MAX_NAME_TOKENS)).is_none());
+    }
+
+    #[test]
+    fn test_tokens_round_trip() -> Result<()> {
+        let mut archive: Vec<u8> = Vec::new();
+        XZMultiStreamWriter::new(&mut archive)
+            .with_title_layout(TitleLayout::Tokens)
+            .compress(&mut interleaved_reader(ILLUMINA_FASTQ))?;
+
+        let mut decoder = PareArchiveDecoder::new(archive.as_slice())?;
+        assert_eq!("tokens", decoder.get_metadata()?.get_str("titles")?);
+
+        let mut dump = |path: &str| -> Result<String> {
+            let mut stream: Vec<u8> = Vec::new();
+            decoder.dump_stream(path, true, &mut stream)?;
+            Ok(String::from_utf8(stream)?)
+        };
+        assert_eq!("TTTTVVVV", dump("title_kinds")?);
+        assert_eq!("15343\n0\n18\n0\n", dump("title_token_5")?);
+        assert_eq!("ATCACG\nATCACG\nATCACG\nATCACG\n", dump("title_token_10")?);
+        assert_eq!(
+            concat!(
+                "SRR001666.1 071112_SLXA-EAS1_s_7:5:1:817:345 length=10\n",
+                "SRR001666.1 071112_SLXA-EAS1_s_7:5:1:817:345 length=10\n",
+                "EAS139:136:FC706VJ:3:1101:1203:2007\n",
+                "EAS139:136:FC706VJ:3:1101:01203:2007\n",
+            ),
+            dump("titles")?
+        );
+        assert!(dump("title_token_11").is_err());
+
+        let (mut writer, output) = interleaved_writer();
+        XZMultiStreamReader::new(archive.as_slice())?.decompress(&mut writer)?;
+        drop(writer);
+        assert_eq!(ILLUMINA_FASTQ, output.text());
+
+        Ok(())
+    }
+}
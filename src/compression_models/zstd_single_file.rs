@@ -0,0 +1,415 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::path::Path;
+
+use bson::doc;
+use tempfile::SpooledTempFile;
+use zstd::stream::read::Decoder as ZstdDecoder;
+use zstd::stream::write::Encoder as ZstdEncoder;
+
+use crate::compression_models::*;
+use crate::seq_files::fastq::{FastQRead, PairedFastQReader};
+
+/// Default zstd level, slow to compress but still quick to decompress.
+pub const DEFAULT_ZSTD_LEVEL: i32 = 19;
+/// The largest dictionary `with_trained_dictionary` stores.
+pub const DICTIONARY_SIZE: usize = 16 * 1024;
+/// How much of the input the dictionary is trained on, the pairs are held until then.
+const DICTIONARY_SAMPLE_BYTES: usize = 100 * DICTIONARY_SIZE;
+
+type ZstdSpool = ZstdEncoder<'static, SpooledTempFile>;
+
+/// The same framing as the lzma single file model, compressed with zstd.
+pub struct ZstdSingleFileWriter<W: Write> {
+    sink: PareArchiveEncoder<W>,
+    level: i32,
+    train_dictionary: bool,
+    dictionary: bool,
+}
+
+impl<W: Write> ZstdSingleFileWriter<W> {
+    pub fn new(sink: W) -> Self {
+        ZstdSingleFileWriter {
+            sink: PareArchiveEncoder::<W>::new(sink),
+            level: DEFAULT_ZSTD_LEVEL,
+            train_dictionary: false,
+            dictionary: false,
+        }
+    }
+
+    /// The settings shared by every model, see `EncoderOptions`.
+    pub fn with_options(mut self, options: EncoderOptions) -> Self {
+        self.sink.set_options(options);
+        self
+    }
+
+    /// Train a zstd dictionary on the start of the input and compress with it, this helps the
+    /// ratio of small inputs. The dictionary is stored in the archive, when the input is too small
+    /// to train one the pairs are compressed without.
+    pub fn with_trained_dictionary(mut self, train_dictionary: bool) -> Self {
+        self.train_dictionary = train_dictionary;
+        self
+    }
+
+    /// Start the data stream, trained on `samples` when there are any, and write them to it.
+    fn start_spool(&mut self, samples: &[Vec<u8>]) -> Result<ZstdSpool> {
+        let spool = self.sink.spool_options().spool();
+        let dictionary = match samples.is_empty() {
+            true => None,
+            false => zstd::dict::from_samples(samples, DICTIONARY_SIZE).ok(),
+        };
+        let mut spool = match dictionary {
+            Some(dictionary) => {
+                let mut stored = self.sink.spool_options().spool();
+                stored.write_all(&dictionary)?;
+                self.sink.stage_stored_spool(stored, "dictionary")?;
+                self.sink
+                    .record_crc32("dictionary", crc32fast::hash(&dictionary));
+                self.dictionary = true;
+                ZstdEncoder::with_dictionary(spool, self.level, &dictionary)?
+            }
+            None => ZstdEncoder::new(spool, self.level)?,
+        };
+        for sample in samples {
+            spool.write_all(sample)?;
+        }
+        Ok(spool)
+    }
+}
+
+fn encode_pair(r1: &FastQRead, r2: &FastQRead, record: &mut Vec<u8>) {
+    record.clear();
+    for field in [
+        r1.title.as_bytes(),
+        r2.title.as_bytes(),
+        &r1.letters,
+        &r2.letters,
+    ] {
+        record.extend_from_slice(field);
+        record.push(b'\xFF');
+    }
+    record.extend_from_slice(&r1.qualities);
+    record.extend_from_slice(&r2.qualities);
+}
+
+impl<W: Write> EncoderModel for ZstdSingleFileWriter<W> {
+    fn compress(&mut self, reader: &mut Box<dyn PairedFastQReader>) -> Result<CompressionStats> {
+        let mut stats = CompressionStats::default();
+        let mut r1 = FastQRead::default();
+        let mut r2 = FastQRead::default();
+
+        let mut record = Vec::new();
+        let mut data_length = 0;
+        let mut data_crc32 = crc32fast::Hasher::new();
+
+        // when training, the pairs are held as samples until there are enough of them
+        let mut spool = match self.train_dictionary {
+            true => None,
+            false => Some(self.start_spool(&[])?),
+        };
+        let mut samples = Vec::new();
+        let mut sample_bytes = 0;
+        let filter = self.sink.options().quality_filter;
+        let length_check = self.sink.options().length_check;
+        let binning = self.sink.options().quality_binning;
+
+        loop {
+            if !reader.read_next(&mut r1, &mut r2)? {
+                break;
+            }
+            if filter.is_some_and(|filter| !filter.keep_pair(&r1, &r2)) {
+                stats.filtered_pairs += 1;
+                continue;
+            }
+            if let Some(check) = length_check {
+                stats.unexpected_lengths += check.count_pair(&r1, &r2);
+            }
+            if let Some(binning) = binning {
+                binning.bin_read(&mut r1);
+                binning.bin_read(&mut r2);
+            }
+            check_bare_sub_title(CompressionModel::ZstdSingle, &r1)?;
+            check_bare_sub_title(CompressionModel::ZstdSingle, &r2)?;
+            check_no_other_letters(CompressionModel::ZstdSingle, &r1)?;
+            check_no_other_letters(CompressionModel::ZstdSingle, &r2)?;
+            self.sink.note_dropped_case(&r1);
+            self.sink.note_dropped_case(&r2);
+            stats.add_pair(&r1, &r2);
+            if self.sink.is_duplicate(&[&r1, &r2])? {
+                stats.duplicate_pairs += 1;
+                continue;
+            }
+
+            encode_pair(&r1, &r2, &mut record);
+            data_length += record.len() as u64;
+            data_crc32.update(&record);
+            match spool.as_mut() {
+                Some(spool) => spool.write_all(&record)?,
+                None => {
+                    sample_bytes += record.len();
+                    samples.push(record.clone());
+                    if sample_bytes >= DICTIONARY_SAMPLE_BYTES {
+                        spool = Some(self.start_spool(&samples)?);
+                        samples.clear();
+                    }
+                }
+            }
+        }
+        let spool = match spool {
+            Some(spool) => spool,
+            None => self.start_spool(&samples)?,
+        };
+        self.sink.stage_spool(spool.finish()?, "data");
+        self.sink.record_length("data", data_length);
+        self.sink.record_crc32("data", data_crc32.finalize());
+        self.sink.set_line_ending(reader.line_ending());
+        self.sink.set_stats(stats);
+        self.sink.write_metadata(doc! {
+            "model": CompressionModel::ZstdSingle.as_str(),
+            "version": 1,
+            "level": self.level as i64,
+            "dictionary": self.dictionary,
+        })?;
+        self.sink.finish()?;
+        Ok(stats)
+    }
+}
+
+impl ZstdSingleFileWriter<std::io::Stdout> {
+    pub fn to_stdout() -> Self {
+        ZstdSingleFileWriter::new(std::io::stdout())
+    }
+}
+
+impl ZstdSingleFileWriter<File> {
+    pub fn create<P: AsRef<Path>>(path: &P) -> Result<Self> {
+        let (file, pending) = PendingFile::create(path)?;
+        let mut writer = ZstdSingleFileWriter::new(file);
+        writer.sink.set_pending_file(pending);
+        Ok(writer)
+    }
+}
+
+// readers
+type DataDecoder = ZstdDecoder<'static, BufReader<ArchiveStream>>;
+
+/// The dictionary the data stream was compressed with, if it was.
+fn read_dictionary<R: Read>(
+    arc: &mut PareArchiveDecoder<R>,
+    metadata: &Document,
+) -> Result<Option<Vec<u8>>> {
+    // archives from before dictionaries don't say
+    if !metadata.get_bool("dictionary").unwrap_or(false) {
+        return Ok(None);
+    }
+    let mut dictionary = Vec::new();
+    arc.get_stream("dictionary")
+        .map_err(|e| match e {
+            CompressionModelError::IO { source } if source.kind() == ErrorKind::NotFound => {
+                CompressionModelError::MissingStream {
+                    path: "dictionary".to_string(),
+                }
+            }
+            e => e,
+        })?
+        .read_to_end(&mut dictionary)?;
+    Ok(Some(dictionary))
+}
+
+fn open_data<R: Read>(
+    arc: &mut PareArchiveDecoder<R>,
+    dictionary: Option<&[u8]>,
+) -> Result<DataDecoder> {
+    let source_stream = BufReader::new(arc.get_stream("data")?);
+    Ok(match dictionary {
+        Some(dictionary) => ZstdDecoder::with_dictionary(source_stream, dictionary)?,
+        None => ZstdDecoder::with_buffer(source_stream)?,
+    })
+}
+
+/// The decoded data stream of a zstd archive, for `verify_stream`.
+pub(crate) fn decode_data<R: Read>(
+    arc: &mut PareArchiveDecoder<R>,
+    metadata: &Document,
+) -> Result<DataDecoder> {
+    let dictionary = read_dictionary(arc, metadata)?;
+    open_data(arc, dictionary.as_deref())
+}
+
+pub struct ZstdSingleFileReader<R: Read> {
+    // owns the directory the streams were unpacked into
+    #[allow(dead_code)]
+    arc: PareArchiveDecoder<R>,
+    decoder: BufReader<DataDecoder>,
+}
+
+impl<R: Read> ZstdSingleFileReader<R> {
+    pub fn new(source: R) -> Result<Self> {
+        ZstdSingleFileReader::from_archive(PareArchiveDecoder::new(source)?)
+    }
+
+    /// Read an archive that has already been opened, e.g. to look at its metadata first.
+    pub fn from_archive(mut arc: PareArchiveDecoder<R>) -> Result<Self> {
+        let metadata = arc.get_metadata()?;
+        CompressionModel::ZstdSingle.check_metadata(&metadata)?;
+
+        let dictionary = read_dictionary(&mut arc, &metadata)?;
+        arc.verify_checksum(&metadata)?;
+        let decoder = open_data(&mut arc, dictionary.as_deref())?;
+
+        Ok(ZstdSingleFileReader {
+            arc,
+            decoder: BufReader::new(decoder),
+        })
+    }
+
+    fn read_string(&mut self, record: &mut String) -> Result<bool> {
+        let mut buffer = vec![];
+
+        let ret = self.read_u8(&mut buffer)?;
+        record.clear();
+        *record = String::from_utf8(buffer)?;
+        Ok(ret)
+    }
+
+    fn read_u8(&mut self, record: &mut Vec<u8>) -> Result<bool> {
+        record.clear();
+        if self.decoder.read_until(b'\xFF', record)? == 0 {
+            return Ok(false);
+        }
+
+        match record.pop() {
+            Some(b'\xFF') => {}
+            _ => {
+                return Err(CompressionModelError::IncompleteRecord);
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+impl<R: Read> PairDecoder for ZstdSingleFileReader<R> {
+    fn read_next(&mut self, r1: &mut FastQRead, r2: &mut FastQRead) -> Result<bool> {
+        if !self.read_string(&mut r1.title)? {
+            return Ok(false);
+        }
+
+        if !self.read_string(&mut r2.title)? {
+            return Err(CompressionModelError::IncompleteRecord);
+        }
+
+        if !self.read_u8(&mut r1.letters)? {
+            return Err(CompressionModelError::IncompleteRecord);
+        }
+
+        if !self.read_u8(&mut r2.letters)? {
+            return Err(CompressionModelError::IncompleteRecord);
+        }
+
+        r1.qualities.clear();
+        r1.qualities.resize(r1.letters.len(), 0);
+
+        match self.decoder.read_exact(&mut r1.qualities[..]) {
+            Ok(()) => {}
+            _ => {
+                return Err(CompressionModelError::IncompleteRecord);
+            }
+        }
+
+        r2.qualities.clear();
+        r2.qualities.resize(r2.letters.len(), 0);
+        match self.decoder.read_exact(&mut r2.qualities[..]) {
+            Ok(()) => {}
+            _ => {
+                return Err(CompressionModelError::IncompleteRecord);
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+impl<R: Read> DecoderModel for ZstdSingleFileReader<R> {
+    fn decoder(&mut self) -> Result<Box<dyn PairDecoder + '_>> {
+        Ok(Box::new(self))
+    }
+}
+
+impl ZstdSingleFileReader<std::io::Stdin> {
+    pub fn from_stdin() -> Result<Self> {
+        ZstdSingleFileReader::new(std::io::stdin())
+    }
+}
+
+impl ZstdSingleFileReader<File> {
+    pub fn open<P: AsRef<Path>>(path: &P) -> Result<Self> {
+        let file = File::open(path)?;
+        ZstdSingleFileReader::new(file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::compression_models::lzma_single_file::XZSingleFileReader;
+    use crate::compression_models::test_utils::*;
+
+    #[test]
+    fn test_zstd_round_trip() -> Result<()> {
+        let mut archive: Vec<u8> = Vec::new();
+        ZstdSingleFileWriter::new(&mut archive)
+            .compress(&mut interleaved_reader(INTERLEAVED_FASTQ))?;
+
+        let (mut writer, output) = interleaved_writer();
+        ZstdSingleFileReader::new(archive.as_slice())?.decompress(&mut writer)?;
+        drop(writer);
+        assert_eq!(INTERLEAVED_FASTQ, output.text());
+
+        assert!(matches!(
+            XZSingleFileReader::new(archive.as_slice()).err().unwrap(),
+            CompressionModelError::ModelMismatch { .. }
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_trained_dictionary() -> Result<()> {
+        let fastq = SyntheticFastq::default().interleaved(2_000);
+        let mut archive: Vec<u8> = Vec::new();
+        ZstdSingleFileWriter::new(&mut archive)
+            .with_trained_dictionary(true)
+            .compress(&mut string_reader(&fastq))?;
+        let metadata = PareArchiveDecoder::new(archive.as_slice())?.get_metadata()?;
+        assert!(metadata.get_bool("dictionary")?);
+
+        let (mut writer, output) = interleaved_writer();
+        ZstdSingleFileReader::new(archive.as_slice())?.decompress(&mut writer)?;
+        drop(writer);
+        assert_eq!(fastq, output.text());
+
+        let archive = without_stream(&archive, "dictionary");
+        assert!(matches!(
+            ZstdSingleFileReader::new(archive.as_slice()).err().unwrap(),
+            CompressionModelError::MissingStream { path } if path == "dictionary"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_too_small_to_train() -> Result<()> {
+        let mut archive: Vec<u8> = Vec::new();
+        ZstdSingleFileWriter::new(&mut archive)
+            .with_trained_dictionary(true)
+            .compress(&mut interleaved_reader(INTERLEAVED_FASTQ))?;
+        let metadata = PareArchiveDecoder::new(archive.as_slice())?.get_metadata()?;
+        assert!(!metadata.get_bool("dictionary")?);
+
+        let (mut writer, output) = interleaved_writer();
+        ZstdSingleFileReader::new(archive.as_slice())?.decompress(&mut writer)?;
+        drop(writer);
+        assert_eq!(INTERLEAVED_FASTQ, output.text());
+        Ok(())
+    }
+}
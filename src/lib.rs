@@ -0,0 +1,460 @@
+//! pare compresses paired end FASTQ files.
+//!
+//! The `pare` binary is a thin wrapper over this crate, the same compression is available to other
+//! programs through [`compress_paired`] and [`decompress_paired`]:
+//!
+//! ```
+//! use std::io::{BufReader, BufWriter};
+//!
+//! use pare::{
+//!     compress_paired, decompress_paired, CompressOptions, CompressionModel, FastQFileReader,
+//!     FastQFileWriter, FastQInterleavedFileReader, FastQInterleavedFileWriter, PairedFastQReader,
+//!     PairedFastQWriter,
+//! };
+//!
+//! let fastq: &'static [u8] = b"@read/1\nACGTN\n+\nIIII#\n@read/2\nTTGCA\n+\nIIIII\n";
+//! let mut reader: Box<dyn PairedFastQReader> = Box::new(FastQInterleavedFileReader::new(
+//!     Box::new(FastQFileReader::new(BufReader::new(fastq))),
+//!     false,
+//! ));
+//!
+//! let mut archive: Vec<u8> = Vec::new();
+//! let options = CompressOptions::new().with_model(CompressionModel::LZMAMulti);
+//! compress_paired(&mut reader, &mut archive, options)?;
+//!
+//! let mut writer: Box<dyn PairedFastQWriter> = Box::new(FastQInterleavedFileWriter::new(
+//!     Box::new(FastQFileWriter::new(BufWriter::new(Vec::new()))),
+//!     false,
+//! ));
+//! decompress_paired(archive.as_slice(), &mut writer, None)?;
+//! # Ok::<(), pare::CompressionModelError>(())
+//! ```
+
+pub mod citation;
+pub mod compression_models;
+pub mod progress;
+pub mod seq_files;
+
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+pub use crate::compression_models::checksum::ChecksumAlgorithm;
+pub use crate::compression_models::config::{
+    CompressionConfig, NucleotideStream, QualityStream, TitleStreams,
+};
+pub use crate::compression_models::length_check::LengthCheck;
+pub use crate::compression_models::lzma_block::{XZBlockReader, XZBlockWriter, DEFAULT_BLOCK_SIZE};
+pub use crate::compression_models::lzma_columnar::{XZColumnarReader, XZColumnarWriter};
+pub use crate::compression_models::lzma_multi_stream::{XZMultiStreamReader, XZMultiStreamWriter};
+pub use crate::compression_models::lzma_reference::{XZReferenceReader, XZReferenceWriter};
+pub use crate::compression_models::lzma_single_file::{
+    Framing, XZSingleFileReader, XZSingleFileWriter,
+};
+pub use crate::compression_models::quality_binning::QualityBinning;
+pub use crate::compression_models::quality_filter::QualityFilter;
+pub use crate::compression_models::read_order::{
+    sort_pairs, InputOrder, ReadOrder, SORT_MEMORY_LIMIT,
+};
+pub use crate::compression_models::reference::Reference;
+pub use crate::compression_models::samples::{append_sample, append_sample_to_file};
+pub use crate::compression_models::title_encoding::{NameTokenizer, TitleLayout};
+pub use crate::compression_models::zstd_single_file::{ZstdSingleFileReader, ZstdSingleFileWriter};
+pub use crate::compression_models::{
+    check_temp_dir, detect_model, open_decoder, ArchiveTimestamp, Codec, CompressionModel,
+    CompressionModelError, CompressionStats, CountingWriter, DecoderModel, EncoderModel,
+    EncoderOptions, LzmaOptions, PairDecoder, PareArchiveDecoder, PendingFile,
+    DEFAULT_SPOOL_MEMORY, DEFAULT_XZ_LEVEL, MAX_LZMA_DICT_SIZE, MAX_XZ_LEVEL, MIN_LZMA_DICT_SIZE,
+    PLACEHOLDER_PHRED,
+};
+#[cfg(feature = "bam")]
+pub use crate::seq_files::bam::UnalignedBamWriter;
+pub use crate::seq_files::fastq::{
+    Alphabet, FastQFileError, FastQFileReader, FastQFileReaderTrait, FastQFileWriter,
+    FastQFileWriterTrait, FastQInterleavedFileReader, FastQInterleavedFileWriter,
+    FastQMultiFilesReader, FastQMultiFilesWriter, FastQPairedFilesReader, FastQPairedFilesWriter,
+    FastQRead, FastQReads, InvalidBasePolicy, LetterCase, LineEnding, MultiFastQReader,
+    MultiFastQWriter, PairedFastQReader, PairedFastQWriter, PhredOffset, ReadPairs,
+    SingleEndReader, SingleEndWriter,
+};
+
+/// How `compress_paired` should compress. Everything not set is the same as the `pare` default.
+pub struct CompressOptions {
+    model: CompressionModel,
+    level: u32,
+    lzma_dict_size: Option<u32>,
+    // what every model is given, the level and dictionary size are added when compressing
+    encoder: EncoderOptions,
+    drop_qualities: bool,
+    train_dictionary: bool,
+    read_order: ReadOrder,
+    title_layout: TitleLayout,
+    pack_nucleotides: bool,
+    quality_codec: Codec,
+    title_codec: Codec,
+    config: Option<CompressionConfig>,
+    index_interval: Option<u64>,
+    block_size: usize,
+    framing: Framing,
+    reference: Option<Reference>,
+}
+
+impl Default for CompressOptions {
+    fn default() -> Self {
+        CompressOptions {
+            model: CompressionModel::LZMASingle,
+            level: DEFAULT_XZ_LEVEL,
+            lzma_dict_size: None,
+            encoder: EncoderOptions::default(),
+            drop_qualities: false,
+            train_dictionary: false,
+            read_order: ReadOrder::Input,
+            title_layout: TitleLayout::default(),
+            pack_nucleotides: false,
+            quality_codec: Codec::default(),
+            title_codec: Codec::default(),
+            config: None,
+            index_interval: None,
+            block_size: DEFAULT_BLOCK_SIZE,
+            framing: Framing::default(),
+            reference: None,
+        }
+    }
+}
+
+impl CompressOptions {
+    pub fn new() -> Self {
+        CompressOptions::default()
+    }
+
+    pub fn with_model(mut self, model: CompressionModel) -> Self {
+        self.model = model;
+        self
+    }
+
+    /// The xz preset, ignored by the zstd model.
+    pub fn with_level(mut self, level: u32) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// The xz dictionary size in bytes, the one of the preset when `None`. Ignored by the zstd
+    /// model.
+    pub fn with_lzma_dict_size(mut self, bytes: Option<u32>) -> Self {
+        self.lzma_dict_size = bytes;
+        self
+    }
+
+    /// What every model is given, with the xz options of the level and dictionary size.
+    fn encoder_options(&self) -> EncoderOptions {
+        let lzma = LzmaOptions::new(self.level);
+        let lzma = match self.lzma_dict_size {
+            Some(bytes) => lzma.with_dict_size(bytes),
+            None => lzma,
+        };
+        self.encoder.clone().with_lzma_options(lzma)
+    }
+
+    pub fn with_checksum(mut self, checksum: ChecksumAlgorithm) -> Self {
+        self.encoder = self.encoder.with_checksum(checksum);
+        self
+    }
+
+    pub fn with_timestamp(mut self, timestamp: ArchiveTimestamp) -> Self {
+        self.encoder = self.encoder.with_timestamp(timestamp);
+        self
+    }
+
+    pub fn with_alphabet(mut self, alphabet: Alphabet) -> Self {
+        self.encoder = self.encoder.with_alphabet(alphabet);
+        self
+    }
+
+    /// The quality offset the reads were read with, see `FastQFileReader::with_phred_offset`.
+    pub fn with_phred_offset(mut self, phred: PhredOffset) -> Self {
+        self.encoder = self.encoder.with_phred_offset(phred);
+        self
+    }
+
+    /// Whether the reader reverse complemented R2, recorded so decompressing undoes it by default.
+    pub fn with_reverse_r2(mut self, reverse_r2: bool) -> Self {
+        self.encoder = self.encoder.with_reverse_r2(reverse_r2);
+        self
+    }
+
+    pub fn with_invalid_base_policy(mut self, policy: InvalidBasePolicy) -> Self {
+        self.encoder = self.encoder.with_invalid_base_policy(policy);
+        self
+    }
+
+    pub fn with_quality_binning(mut self, binning: Option<QualityBinning>) -> Self {
+        self.encoder = self.encoder.with_quality_binning(binning);
+        self
+    }
+
+    pub fn with_quality_filter(mut self, filter: Option<QualityFilter>) -> Self {
+        self.encoder = self.encoder.with_quality_filter(filter);
+        self
+    }
+
+    /// Count the reads of an unexpected length in the stats.
+    pub fn with_length_check(mut self, check: Option<LengthCheck>) -> Self {
+        self.encoder = self.encoder.with_length_check(check);
+        self
+    }
+
+    /// Leave the qualities out of the archive, only the single file and multi stream models can.
+    pub fn with_dropped_qualities(mut self, drop_qualities: bool) -> Self {
+        self.drop_qualities = drop_qualities;
+        self
+    }
+
+    /// Sort the pairs by their bases before compressing them, see `ReadOrder`.
+    pub fn with_read_order(mut self, read_order: ReadOrder) -> Self {
+        self.read_order = read_order;
+        self
+    }
+
+    /// Store the pairs that are copies of an earlier pair, but for their titles, as a reference to
+    /// it. Fragments of other than two reads can't be.
+    pub fn with_dedup(mut self, dedup: bool) -> Self {
+        self.encoder = self.encoder.with_dedup(dedup);
+        self
+    }
+
+    /// Train a dictionary on the input and compress with it, only the zstd model can.
+    pub fn with_trained_dictionary(mut self, train_dictionary: bool) -> Self {
+        self.train_dictionary = train_dictionary;
+        self
+    }
+
+    /// Only used by the multi stream and reference models.
+    pub fn with_title_layout(mut self, title_layout: TitleLayout) -> Self {
+        self.title_layout = title_layout;
+        self
+    }
+
+    /// Only used by the multi stream model.
+    pub fn with_packed_nucleotides(mut self, pack_nucleotides: bool) -> Self {
+        self.pack_nucleotides = pack_nucleotides;
+        self
+    }
+
+    /// Only used by the multi stream model.
+    pub fn with_quality_codec(mut self, codec: Codec) -> Self {
+        self.quality_codec = codec;
+        self
+    }
+
+    /// Only used by the multi stream model.
+    pub fn with_title_codec(mut self, codec: Codec) -> Self {
+        self.title_codec = codec;
+        self
+    }
+
+    /// Only used by the multi stream model, see `XZMultiStreamWriter::with_config`. It takes the
+    /// place of the title layout, packed nucleotides, codecs, quality binning and dropped qualities.
+    pub fn with_config(mut self, config: Option<CompressionConfig>) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Only used by the multi stream model, see `XZMultiStreamWriter::with_index`.
+    pub fn with_index(mut self, interval: Option<u64>) -> Self {
+        self.index_interval = interval;
+        self
+    }
+
+    /// Only used by the block and columnar models.
+    pub fn with_block_size(mut self, block_size: usize) -> Self {
+        self.block_size = block_size;
+        self
+    }
+
+    /// Only used by the single file model.
+    pub fn with_framing(mut self, framing: Framing) -> Self {
+        self.framing = framing;
+        self
+    }
+
+    /// The bytes each spool holds in memory before it is moved to a temporary file.
+    pub fn with_spool_memory(mut self, spool_memory: usize) -> Self {
+        self.encoder = self.encoder.with_spool_memory(spool_memory);
+        self
+    }
+
+    /// Where spools outgrowing their memory are moved, `std::env::temp_dir()` when not given.
+    pub fn with_temp_dir<P: Into<PathBuf>>(mut self, temp_dir: P) -> Self {
+        self.encoder = self.encoder.with_temp_dir(temp_dir);
+        self
+    }
+
+    /// Required by the reference model.
+    pub fn with_reference(mut self, reference: Reference) -> Self {
+        self.reference = Some(reference);
+        self
+    }
+}
+
+/// Compress every read pair from `reader` into an archive written to `sink`.
+pub fn compress_paired<W: Write>(
+    reader: &mut Box<dyn PairedFastQReader>,
+    sink: W,
+    options: CompressOptions,
+) -> Result<CompressionStats, CompressionModelError> {
+    if options.drop_qualities
+        && !matches!(
+            options.model,
+            CompressionModel::LZMASingle | CompressionModel::LZMAMulti
+        )
+    {
+        return Err(CompressionModelError::QualitiesRequired {
+            model: options.model.as_str().to_string(),
+        });
+    }
+    if options.train_dictionary && options.model != CompressionModel::ZstdSingle {
+        return Err(CompressionModelError::DictionaryNotSupported {
+            model: options.model.as_str().to_string(),
+        });
+    }
+    check_config(&options)?;
+
+    // the stored order counts every pair read
+    if options.read_order == ReadOrder::SortedKeepingOrder
+        && options.encoder.quality_filter().is_some()
+    {
+        return Err(CompressionModelError::CantSort {
+            reason: "the order can't be kept when pairs are left out by quality".to_string(),
+        });
+    }
+    let mut sorted;
+    let (reader, input_order) = match options.read_order {
+        ReadOrder::Input => (reader, None),
+        read_order => {
+            let order;
+            (sorted, order) = sort_pairs(reader, SORT_MEMORY_LIMIT)?;
+            let input_order = match read_order {
+                ReadOrder::SortedKeepingOrder => InputOrder::Kept(order),
+                _ => InputOrder::Lost,
+            };
+            (&mut sorted, Some(input_order))
+        }
+    };
+
+    let encoder = options.encoder_options().with_input_order(input_order);
+    let mut writer: Box<dyn EncoderModel + '_> = match options.model {
+        CompressionModel::LZMASingle => Box::new(
+            XZSingleFileWriter::new(sink)
+                .with_options(encoder)
+                .with_dropped_qualities(options.drop_qualities)
+                .with_framing(options.framing),
+        ),
+        CompressionModel::LZMAMulti => Box::new(multi_stream_writer(sink, encoder, options)),
+        CompressionModel::LZMAReference => Box::new(
+            XZReferenceWriter::new(
+                sink,
+                options
+                    .reference
+                    .ok_or(CompressionModelError::MissingReference)?,
+            )
+            .with_options(encoder)
+            .with_title_layout(options.title_layout),
+        ),
+        CompressionModel::LZMABlock => Box::new(
+            XZBlockWriter::new(sink)
+                .with_options(encoder)
+                .with_block_size(options.block_size),
+        ),
+        CompressionModel::LZMAColumnar => Box::new(
+            XZColumnarWriter::new(sink)
+                .with_options(encoder)
+                .with_block_size(options.block_size),
+        ),
+        CompressionModel::ZstdSingle => Box::new(
+            ZstdSingleFileWriter::new(sink)
+                .with_options(encoder)
+                .with_trained_dictionary(options.train_dictionary),
+        ),
+    };
+
+    writer.compress(reader)
+}
+
+/// Compress fragments of any other number of reads than two from `reader` into an archive
+/// written to `sink`, e.g. single-end reads or R1, R2 and the index reads. Only the multi stream
+/// model can store these.
+pub fn compress_multi<W: Write>(
+    reader: &mut Box<dyn MultiFastQReader>,
+    sink: W,
+    options: CompressOptions,
+) -> Result<CompressionStats, CompressionModelError> {
+    if options.model != CompressionModel::LZMAMulti {
+        return Err(CompressionModelError::PairsOnlyModel {
+            model: options.model.as_str().to_string(),
+        });
+    }
+    if options.train_dictionary {
+        return Err(CompressionModelError::DictionaryNotSupported {
+            model: options.model.as_str().to_string(),
+        });
+    }
+    if options.read_order != ReadOrder::Input {
+        return Err(CompressionModelError::CantSort {
+            reason: "only read pairs can be sorted".to_string(),
+        });
+    }
+    if options.encoder.dedup() {
+        return Err(CompressionModelError::CantDedup {
+            reason: "only read pairs can be deduplicated".to_string(),
+        });
+    }
+    check_config(&options)?;
+
+    multi_stream_writer(sink, options.encoder_options(), options).compress_multi(reader)
+}
+
+/// A config is only used by the multi stream model, and its levels have to be xz presets.
+fn check_config(options: &CompressOptions) -> Result<(), CompressionModelError> {
+    let config = match &options.config {
+        Some(config) => config,
+        None => return Ok(()),
+    };
+    if options.model != CompressionModel::LZMAMulti {
+        return Err(CompressionModelError::ConfigNotSupported {
+            model: options.model.as_str().to_string(),
+        });
+    }
+    config.validate()
+}
+
+fn multi_stream_writer<W: Write>(
+    sink: W,
+    encoder: EncoderOptions,
+    options: CompressOptions,
+) -> XZMultiStreamWriter<W> {
+    let writer = XZMultiStreamWriter::new(sink)
+        .with_options(encoder)
+        .with_dropped_qualities(options.drop_qualities)
+        .with_title_layout(options.title_layout)
+        .with_packed_nucleotides(options.pack_nucleotides)
+        .with_quality_codec(options.quality_codec)
+        .with_title_codec(options.title_codec)
+        .with_index(options.index_interval);
+    match options.config {
+        Some(config) => writer.with_config(config),
+        None => writer,
+    }
+}
+
+/// Decompress the archive read from `source` into `writer`, detecting the model from the archive.
+/// `reference` is only needed for archives written by the reference model. The alphabet of the
+/// archive isn't applied to `writer`, see `PareArchiveDecoder::alphabet`, nor are its line ending,
+/// quality offset and whether R2 was reverse complemented, see `PareArchiveDecoder::line_ending`,
+/// `PareArchiveDecoder::phred_offset` and `PareArchiveDecoder::reverse_r2`.
+pub fn decompress_paired<R: Read>(
+    source: R,
+    writer: &mut Box<dyn PairedFastQWriter>,
+    reference: Option<Reference>,
+) -> Result<(), CompressionModelError> {
+    let archive = PareArchiveDecoder::new(source)?;
+    open_decoder(archive, None, reference)?.decompress(writer)
+}
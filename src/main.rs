@@ -1,15 +1,23 @@
-mod compression_models;
-mod seq_files;
-
 use std::ffi::OsString;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, Write};
+use std::ops::Range;
+#[cfg(unix)]
+use std::os::unix::io::{BorrowedFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
-use clap::{Parser, Subcommand, ValueEnum};
-
-use crate::compression_models::lzma_multi_stream::*;
-use crate::compression_models::lzma_single_file::*;
-use crate::compression_models::*;
+use bson::Bson;
+use clap::error::ErrorKind;
+use clap::{ArgAction, Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use flate2::read::MultiGzDecoder;
+use tempfile::SpooledTempFile;
 
-use crate::seq_files::fastq::*;
+use pare::citation::{Citation, CitationFormat};
+use pare::compression_models::quality_binning::*;
+use pare::progress::{PairProgressWriter, ProgressBar, ProgressReader};
+use pare::seq_files::fastq::*;
+use pare::*;
 
 #[derive(Debug, Parser)]
 #[command(name = "pare")]
@@ -17,180 +25,1683 @@ use crate::seq_files::fastq::*;
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    #[arg(
+        long,
+        global = true,
+        value_name = "DIR",
+        help = "Where to put temporary files, archives are unpacked here to decompress them"
+    )]
+    temp_dir: Option<PathBuf>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+#[allow(clippy::upper_case_acronyms)]
 enum Model {
     LZMA,
     LZMAMulti,
+    LZMAReference,
+    LZMABlock,
+    LZMAColumnar,
+    Zstd,
+}
+
+impl Model {
+    fn compression_model(&self) -> CompressionModel {
+        match self {
+            Model::LZMA => CompressionModel::LZMASingle,
+            Model::LZMAMulti => CompressionModel::LZMAMulti,
+            Model::LZMAReference => CompressionModel::LZMAReference,
+            Model::LZMABlock => CompressionModel::LZMABlock,
+            Model::LZMAColumnar => CompressionModel::LZMAColumnar,
+            Model::Zstd => CompressionModel::ZstdSingle,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+enum Format {
+    #[default]
+    Fastq,
+    /// unaligned BAM, both reads of a pair in a single output
+    Bam,
+}
+
+/// How the reads of a pair are laid out in fastq files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Layout {
+    /// both reads of a pair one after the other in one file
+    Interleaved,
+    /// R1 and R2 in files of their own
+    Paired,
+}
+
+impl Layout {
+    fn files(&self) -> usize {
+        match self {
+            Layout::Interleaved => 1,
+            Layout::Paired => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Args)]
+struct CompressArgs {
+    #[arg(short, long, default_value = "-")]
+    output: Option<OsString>,
+    #[arg(
+        required_unless_present = "manifest",
+        num_args(1..),
+        help = "An interleaved file, the R1 and R2 files, or one file per read e.g. R1 R2 I1 I2"
+    )]
+    files: Vec<OsString>,
+    #[arg(short, long, action, help = "Don't reverse complement R2")]
+    reverse_r2: bool,
+    #[arg(
+        long,
+        action,
+        conflicts_with_all = ["reverse_r2", "check_mate_order", "validate_pair_names", "manifest"],
+        help = "The single file holds single-end reads rather than interleaved pairs, stored with the lzma-multi model"
+    )]
+    single_end: bool,
+    #[arg(
+        long,
+        action,
+        help = "Fail if the first file, or the first read of each interleaved pair, looks like R2"
+    )]
+    check_mate_order: bool,
+    #[arg(long, action, help = "Fail if paired reads don't have the same name")]
+    validate_pair_names: bool,
+    #[arg(
+        long,
+        default_value_t = 1,
+        help = "Threads to read with, 2 or more parses paired files concurrently"
+    )]
+    threads: usize,
+    #[arg(short, long, value_enum, help = "which model to use")]
+    model: Option<Model>,
+    #[arg(
+        short,
+        long,
+        default_value_t = DEFAULT_XZ_LEVEL,
+        value_parser = clap::value_parser!(u32).range(0..=MAX_XZ_LEVEL as i64),
+        help = "xz compression level for the lzma models"
+    )]
+    level: u32,
+    #[arg(
+        long,
+        value_name = "BYTES",
+        value_parser = clap::value_parser!(u32).range(MIN_LZMA_DICT_SIZE as i64..=MAX_LZMA_DICT_SIZE as i64),
+        help = "xz dictionary size, the one of the level when not given"
+    )]
+    lzma_dict: Option<u32>,
+    #[arg(long, value_enum, default_value_t = ChecksumAlgorithm::default(), help = "hash used to checksum the archive content")]
+    checksum: ChecksumAlgorithm,
+    #[arg(
+        long,
+        default_value_t = ArchiveTimestamp::default(),
+        help = "mtime of the archive entries: now, zero or seconds since the epoch"
+    )]
+    timestamp: ArchiveTimestamp,
+    #[arg(
+        long,
+        action,
+        help = "Pad or truncate qualities that don't match the sequence length (lossy)"
+    )]
+    repair_lengths: bool,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = InvalidBasePolicy::default(),
+        help = "What to do with reads containing letters other than ACGTN and IUPAC codes"
+    )]
+    on_invalid_base: InvalidBasePolicy,
+    #[arg(
+        long,
+        help = "Accept . and - in sequences and store them so they are written back, only -m lzma and -m lzma-multi can"
+    )]
+    permissive: bool,
+    #[arg(
+        long,
+        value_enum,
+        help = "Bin the qualities with this scheme before compressing (lossy)"
+    )]
+    quality_bins: Option<QualityBinning>,
+    #[arg(
+        long,
+        value_name = "PHRED",
+        help = "Leave out pairs with a read whose mean quality is below this (lossy)"
+    )]
+    min_mean_qual: Option<f64>,
+    #[arg(
+        long,
+        action,
+        help = "Don't store the qualities, they are decompressed as Phred 40 (lossy)"
+    )]
+    no_qualities: bool,
+    #[arg(
+        long,
+        action,
+        help = "Train a dictionary on the input and compress with it, for the zstd model"
+    )]
+    train_dict: bool,
+    #[arg(
+        long,
+        action,
+        conflicts_with = "single_end",
+        help = "Sort the read pairs by their bases, which compresses better but loses their order unless --preserve-order is given. The reads are sorted in memory, up to 4 GiB of them"
+    )]
+    sort: bool,
+    #[arg(
+        long,
+        action,
+        requires = "sort",
+        conflicts_with = "min_mean_qual",
+        help = "Store the input order of the sorted pairs, they are decompressed in it"
+    )]
+    preserve_order: bool,
+    #[arg(
+        long,
+        action,
+        conflicts_with = "single_end",
+        help = "Store read pairs that are copies of an earlier pair, but for their titles, as a reference to it"
+    )]
+    dedup: bool,
+    #[arg(
+        long,
+        requires = "min_mean_qual",
+        help = "Only leave out a pair when both reads are below --min-mean-qual"
+    )]
+    min_mean_qual_both: bool,
+    #[arg(
+        long,
+        value_name = "BASES",
+        help = "Warn about reads that aren't this long, they are still compressed"
+    )]
+    expect_length: Option<usize>,
+    #[arg(
+        long,
+        value_name = "BASES",
+        default_value_t = 0,
+        requires = "expect_length",
+        help = "How many bases shorter or longer than --expect-length a read may be"
+    )]
+    length_tolerance: usize,
+    #[arg(long, value_enum, default_value_t = Alphabet::default(), help = "Whether the reads are DNA (T) or RNA (U)")]
+    alphabet: Alphabet,
+    #[arg(
+        long,
+        value_enum,
+        help = "Quality offset of the input, detected from the first reads of a file when not given"
+    )]
+    phred: Option<PhredOffset>,
+    #[cfg(unix)]
+    #[arg(
+        long,
+        value_name = "FD",
+        value_parser = parse_progress_fd,
+        conflicts_with = "manifest",
+        help = "Write newline delimited JSON progress events to this open file descriptor"
+    )]
+    progress_fd: Option<RawFd>,
+    #[arg(long, value_enum, default_value_t = TitleLayout::default(), help = "how titles are stored by the lzma-multi model")]
+    title_layout: TitleLayout,
+    #[arg(
+        long,
+        action,
+        help = "pack nucleotides two bits to a base in the lzma-multi model"
+    )]
+    pack_nucleotides: bool,
+    #[arg(long, value_enum, default_value_t = Codec::default(), help = "codec for the qualities in the lzma-multi model")]
+    quality_codec: Codec,
+    #[arg(long, value_enum, default_value_t = Codec::default(), help = "codec for the titles in the lzma-multi model")]
+    title_codec: Codec,
+    #[arg(
+        long,
+        value_name = "JSON",
+        conflicts_with_all = ["title_layout", "pack_nucleotides", "quality_codec", "title_codec", "quality_bins", "no_qualities"],
+        help = "codec, level and transforms of the titles, nucleotides and qualities in the lzma-multi model"
+    )]
+    config: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "PAIRS",
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "index the lzma-multi archive for random access with a sync point every PAIRS read pairs"
+    )]
+    index_interval: Option<u64>,
+    #[arg(long, default_value_t = DEFAULT_BLOCK_SIZE, help = "read pairs per block for the lzma-block and lzma-columnar models")]
+    block_size: usize,
+    #[arg(long, value_enum, default_value_t = Framing::default(), help = "how the lzma model separates the fields of a record")]
+    framing: Framing,
+    #[arg(
+        long,
+        value_name = "BYTES",
+        default_value_t = DEFAULT_SPOOL_MEMORY,
+        help = "bytes of each compressed stream kept in memory before it is moved to a temporary file in $TMPDIR"
+    )]
+    spool_memory: usize,
+    #[arg(long, help = "FASTA reference for the lzma-reference model")]
+    reference: Option<OsString>,
+    #[arg(
+        short,
+        long,
+        action,
+        help = "Don't show a progress bar or the summary of what was compressed"
+    )]
+    quiet: bool,
+    #[arg(
+        long,
+        action,
+        help = "Don't write the archive, print how many bytes it would be"
+    )]
+    dry_run: bool,
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "Write the reads as a sample of an archive of samples"
+    )]
+    sample_name: Option<String>,
+    #[arg(
+        long,
+        value_name = "ARCHIVE",
+        requires = "sample_name",
+        conflicts_with = "output",
+        help = "Add the sample to this archive of samples, it is rewritten in place"
+    )]
+    append: Option<OsString>,
+    #[arg(
+        long,
+        value_name = "TSV",
+        conflicts_with_all = ["files", "output", "append"],
+        help = "Compress each line of R1 path, R2 path and output path, separated by tabs, to an archive of its own"
+    )]
+    manifest: Option<OsString>,
+    #[arg(
+        long,
+        action,
+        requires = "manifest",
+        help = "Carry on with the rest of the manifest when a line fails"
+    )]
+    keep_going: bool,
+}
+
+impl CompressArgs {
+    /// Progress events go to stderr, where nothing else should be printed.
+    fn progress_on_stderr(&self) -> bool {
+        #[cfg(unix)]
+        return self.progress_fd == Some(2);
+        #[cfg(not(unix))]
+        false
+    }
+}
+
+#[derive(Debug, Args)]
+struct DecompressArgs {
+    #[arg(default_value = "-")]
+    file: OsString,
+    #[arg(
+        default_value = "-",
+        num_args(1..),
+        help = "where to write the reads, files ending in .gz are gzipped"
+    )]
+    outputs: Vec<OsString>,
+    #[arg(
+        short,
+        long,
+        num_args(0..=1),
+        require_equals = true,
+        default_missing_value = "true",
+        help = "Reverse complement R2, as recorded in the archive when not given"
+    )]
+    reverse_r2: Option<bool>,
+    #[arg(
+        short,
+        long,
+        value_enum,
+        help = "which model to use, read from the archive when not given"
+    )]
+    model: Option<Model>,
+    #[arg(long, help = "FASTA reference the archive was compressed against")]
+    reference: Option<OsString>,
+    #[arg(
+        long,
+        value_enum,
+        help = "line ending to write, the one of the compressed fastq when not given"
+    )]
+    line_ending: Option<LineEnding>,
+    #[arg(long, value_enum, default_value_t = Format::default(), help = "what to write the reads as")]
+    format: Format,
+    #[arg(short, long, action, help = "Don't show a progress bar")]
+    quiet: bool,
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "The sample to decompress from an archive of samples"
+    )]
+    sample: Option<String>,
+    #[arg(
+        long,
+        value_name = "LETTER",
+        value_parser = parse_quality_letter,
+        help = "quality letter to write for an archive made with --no-qualities, Phred 40 when not given"
+    )]
+    quality_fill: Option<u8>,
+    #[arg(
+        long,
+        value_name = "START:END",
+        value_parser = parse_range,
+        help = "only write the read pairs from START up to but not including END, counting from 0"
+    )]
+    range: Option<Range<u64>>,
+    #[arg(
+        long,
+        value_enum,
+        help = "case to write the bases in, as they were compressed when not given"
+    )]
+    case: Option<LetterCase>,
+    #[arg(
+        long,
+        help = "Leave the unpacked archive in the temporary directory and print where, for debugging"
+    )]
+    keep_temp: bool,
+}
+
+fn parse_range(range: &str) -> Result<Range<u64>, String> {
+    let parsed = range
+        .split_once(':')
+        .and_then(|(start, end)| Some(start.parse::<u64>().ok()?..end.parse::<u64>().ok()?));
+    match parsed {
+        Some(range) if range.start <= range.end => Ok(range),
+        _ => Err("a range is START:END with START no more than END".to_string()),
+    }
+}
+
+fn parse_quality_letter(letter: &str) -> Result<u8, String> {
+    match letter.as_bytes() {
+        [c] if c.is_ascii_graphic() => Ok(*c),
+        _ => Err("a quality is a single letter from ! to ~".to_string()),
+    }
 }
 
 #[derive(Debug, Subcommand)]
 enum Commands {
     /// compress fastq file(s)
     #[command(arg_required_else_help = true)]
-    Compress {
-        #[arg(short, long, default_value = "-")]
-        output: Option<OsString>,
-        #[arg(required = true, num_args(1..3))]
-        files: Vec<OsString>,
-        #[arg(short, long, action, help = "Don't reverse complement R2")]
-        reverse_r2: bool,
-        #[arg(short, long, value_enum, help = "which model to use")]
+    Compress(CompressArgs),
+    #[command(arg_required_else_help = true)]
+    Decompress(DecompressArgs),
+    /// decode an archive without writing it out to check that it is intact
+    Verify {
+        #[arg(default_value = "-")]
+        file: OsString,
+        #[arg(
+            short,
+            long,
+            value_enum,
+            help = "which model to use, read from the archive when not given"
+        )]
         model: Option<Model>,
+        #[arg(long, help = "FASTA reference the archive was compressed against")]
+        reference: Option<OsString>,
+        #[arg(
+            long,
+            value_name = "NAME",
+            help = "The sample to verify of an archive of samples"
+        )]
+        sample: Option<String>,
+    },
+    /// parse fastq input to the end to check it is well formed, without compressing it
+    #[command(arg_required_else_help = true)]
+    Check {
+        #[arg(
+            required = true,
+            num_args(1..=2),
+            help = "an interleaved fastq or the R1 and R2 files, gzipped or not"
+        )]
+        inputs: Vec<OsString>,
     },
+    /// rewrite a gzipped fastq file with binned qualities
     #[command(arg_required_else_help = true)]
-    Decompress {
+    Requality {
+        #[arg(short, long, value_enum, help = "which binning scheme to apply")]
+        bins: QualityBinning,
+        #[arg(
+            long,
+            value_enum,
+            help = "Quality offset of the input, detected from the first reads of a file when not given"
+        )]
+        phred: Option<PhredOffset>,
+        #[arg(default_value = "-")]
+        input: OsString,
+        #[arg(default_value = "-")]
+        output: OsString,
+    },
+    /// write a single member of an archive to stdout, for debugging
+    #[command(arg_required_else_help = true)]
+    Dump {
+        archive: OsString,
+        stream: String,
+        #[arg(
+            short,
+            long,
+            action,
+            help = "decode the stream with its codec before writing it"
+        )]
+        decompressed: bool,
+        #[arg(
+            long,
+            value_name = "NAME",
+            help = "The sample the stream is in of an archive of samples"
+        )]
+        sample: Option<String>,
+    },
+    /// list the streams of an archive and print its metadata
+    #[command(arg_required_else_help = true)]
+    List {
         #[arg(default_value = "-")]
         file: OsString,
-        #[arg(default_value = "-", num_args(1..3))]
+    },
+    /// rewrite fastq reads from one layout to the other without compressing them
+    #[command(arg_required_else_help = true)]
+    Relayout {
+        #[arg(short, long, value_enum, help = "the layout to write")]
+        layout: Layout,
+        #[arg(
+            short,
+            long,
+            required = true,
+            action = ArgAction::Append,
+            help = "the interleaved file or the R1 and R2 files to write, give R1 first"
+        )]
         outputs: Vec<OsString>,
-        #[arg(short, long, action, help = "Don't reverse complement R2")]
-        reverse_r2: bool,
-        #[arg(short, long, value_enum, help = "which model to use")]
-        model: Option<Model>,
+        #[arg(
+            required = true,
+            num_args(1..=2),
+            help = "an interleaved fastq or the R1 and R2 files, gzipped or not"
+        )]
+        inputs: Vec<OsString>,
+    },
+    /// compress and decompress a sample with every model to compare them
+    #[command(arg_required_else_help = true)]
+    Benchmark {
+        #[arg(
+            required = true,
+            num_args(1..=2),
+            help = "an interleaved fastq or the R1 and R2 files, read again for each model"
+        )]
+        inputs: Vec<OsString>,
+        #[arg(long, help = "FASTA reference, lzma-reference is left out without one")]
+        reference: Option<OsString>,
+        #[arg(long, action, help = "print the results as JSON")]
+        json: bool,
     },
+    /// print how to cite pare
     #[command()]
-    Cite {},
+    Cite {
+        #[arg(short, long, value_enum, default_value_t = CitationFormat::default(), help = "the style to print the citation in")]
+        format: CitationFormat,
+    },
 }
 
-fn compress(
-    files: &Vec<OsString>,
-    output: Option<OsString>,
-    reverse_r2: bool,
-    model: Option<Model>,
-) -> Result<(), CompressionModelError> {
+fn configure_fastq<R: Read>(
+    reader: FastQFileReader<R>,
+    args: &CompressArgs,
+    phred: PhredOffset,
+) -> FastQFileReader<R> {
+    let reader = reader
+        .with_invalid_base_policy(args.on_invalid_base)
+        .with_permissive(args.permissive)
+        .with_alphabet(args.alphabet)
+        .with_phred_offset(phred);
+    match args.repair_lengths {
+        true => reader.with_repair_lengths(),
+        false => reader,
+    }
+}
+
+/// Open a fastq file, the bytes read from it are counted on `progress`.
+fn open_fastq(
+    path: &OsString,
+    args: &CompressArgs,
+    phred: PhredOffset,
+    progress: &ProgressBar,
+) -> Result<Box<dyn FastQFileReaderTrait + Send>, CompressionModelError> {
+    let source = match path.to_str() {
+        Some("-") => progress.wrap_read(std::io::stdin()),
+        _ => progress.wrap_read(File::open(path)?),
+    };
+    Ok(Box::new(configure_fastq(
+        FastQFileReader::new(BufReader::new(maybe_decompress(source)?)),
+        args,
+        phred,
+    )))
+}
+
+/// A bar following the bytes read from the input files, out of their size when none is stdin.
+fn input_progress(args: &CompressArgs) -> Result<ProgressBar, CompressionModelError> {
+    // progress events sent to stderr would be drawn over
+    if args.quiet || args.progress_on_stderr() {
+        return Ok(ProgressBar::hidden());
+    }
+
+    let mut total = Some(0);
+    for file in &args.files {
+        total = match is_stream(file) {
+            true => None,
+            false => total.map(|t| t + file_len(file)),
+        };
+    }
+    Ok(ProgressBar::bytes(total))
+}
+
+fn file_len(path: &OsString) -> u64 {
+    std::fs::metadata(path).map_or(0, |m| m.len())
+}
+
+/// Stdin, or a path that isn't a regular file such as the named pipe of a process substitution.
+/// These can only be read once and their size isn't known up front.
+fn is_stream(path: &OsString) -> bool {
+    match path.to_str() {
+        Some("-") => true,
+        _ => std::fs::metadata(path).is_ok_and(|m| !m.is_file()),
+    }
+}
+
+/// The --phred given, or the offset detected from the start of the first file. Stdin and pipes
+/// can't be read twice so they're taken to be Phred+33.
+fn resolve_phred(args: &CompressArgs) -> Result<PhredOffset, CompressionModelError> {
+    if let Some(phred) = args.phred {
+        return Ok(phred);
+    }
+
+    match is_stream(&args.files[0]) {
+        true => Ok(PhredOffset::Phred33),
+        false => {
+            let mut reader = open_fastq(
+                &args.files[0],
+                args,
+                PhredOffset::Phred33,
+                &ProgressBar::hidden(),
+            )?;
+            Ok(detect_phred_offset(reader.as_mut(), PHRED_DETECTION_READS)?)
+        }
+    }
+}
+
+fn open_reference(path: &Option<OsString>) -> Result<Reference, CompressionModelError> {
+    match path {
+        Some(path) => Reference::open(path),
+        None => Err(CompressionModelError::MissingReference),
+    }
+}
+
+/// Stderr or a descriptor above it, stdin and stdout can carry reads.
+#[cfg(unix)]
+fn parse_progress_fd(value: &str) -> Result<RawFd, String> {
+    match value.parse::<RawFd>() {
+        Ok(fd) if fd >= 2 => Ok(fd),
+        Ok(_) => Err("use 2 for stderr or a descriptor above it".to_string()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+/// A copy of the progress descriptor, the caller's own is left open. It's taken before pare opens
+/// any files, which could otherwise be given the same number.
+#[cfg(unix)]
+fn progress_sink(fd: RawFd) -> File {
+    // SAFETY: the descriptor is only borrowed to duplicate it, which fails if it isn't open
+    let borrowed = unsafe { BorrowedFd::borrow_raw(fd) };
+    match borrowed.try_clone_to_owned() {
+        Ok(owned) => File::from(owned),
+        Err(err) => Cli::command()
+            .error(
+                ErrorKind::ValueValidation,
+                format!("--progress-fd {} isn't an open descriptor: {}", fd, err),
+            )
+            .exit(),
+    }
+}
+
+fn compress(args: &CompressArgs, temp_dir: &Path) -> Result<(), CompressionModelError> {
+    let files = &args.files;
+    let reverse_r2 = args.reverse_r2;
+    #[cfg(unix)]
+    let progress_sink = args.progress_fd.map(progress_sink);
+    let phred = resolve_phred(args)?;
+    let progress = input_progress(args)?;
+
     let mut sequence_reader: Box<dyn PairedFastQReader>;
     match files.len() {
+        1 if args.single_end => {
+            let in_file = open_fastq(&files[0], args, phred, &progress)?;
+            return compress_fragments(
+                args,
+                temp_dir,
+                phred,
+                progress,
+                Box::new(SingleEndReader::new(in_file)),
+            );
+        }
         1 => {
             //println!("interleaved {:?} {:?} {}", files[0], output, reverse_r2);
-            let in_file: Box<dyn FastQFileReaderTrait> = match files[0].to_str() {
-                Some("-") => Box::new(FastQFileReader::from_stdin()),
-                _ => Box::new(FastQFileReader::open(&files[0])?),
-            };
+            let in_file = open_fastq(&files[0], args, phred, &progress)?;
 
-            sequence_reader = Box::new(FastQInterleavedFileReader::new(in_file, reverse_r2));
+            let mut interleaved_reader = FastQInterleavedFileReader::new(in_file, reverse_r2);
+            if args.check_mate_order {
+                interleaved_reader = interleaved_reader.with_mate_order_check();
+            }
+            sequence_reader = Box::new(interleaved_reader);
         }
         2 => {
             //eprintln!("paired files {:?} {:?} {}", files, output, reverse_r2);
-            let in_file_r1: Box<dyn FastQFileReaderTrait> = match files[0].to_str() {
-                Some("-") => Box::new(FastQFileReader::from_stdin()),
-                _ => Box::new(FastQFileReader::open(&files[0])?),
-            };
+            let in_file_r1 = open_fastq(&files[0], args, phred, &progress)?;
 
-            let in_file_r2: Box<dyn FastQFileReaderTrait> = match files[1].to_str() {
-                Some("-") => Box::new(FastQFileReader::from_stdin()),
-                _ => Box::new(FastQFileReader::open(&files[1])?),
+            let in_file_r2 = open_fastq(&files[1], args, phred, &progress)?;
+
+            let mut paired_reader = match args.threads {
+                0 | 1 => FastQPairedFilesReader::new_with_validation(
+                    in_file_r1,
+                    in_file_r2,
+                    reverse_r2,
+                    args.validate_pair_names,
+                ),
+                _ => FastQPairedFilesReader::new_threaded(
+                    in_file_r1,
+                    in_file_r2,
+                    reverse_r2,
+                    args.validate_pair_names,
+                ),
+            };
+            if args.check_mate_order {
+                paired_reader = paired_reader.with_mate_order_check();
+            }
+            sequence_reader = Box::new(paired_reader);
+        }
+        _ => {
+            let mut readers = Vec::new();
+            for file in files {
+                readers.push(open_fastq(file, args, phred, &progress)?);
+            }
+            let mut reader = match args.threads {
+                0 | 1 => FastQMultiFilesReader::new(
+                    readers
+                        .into_iter()
+                        .map(|reader| reader as Box<dyn FastQFileReaderTrait>)
+                        .collect(),
+                    reverse_r2,
+                    args.validate_pair_names,
+                ),
+                _ => FastQMultiFilesReader::new_threaded(
+                    readers,
+                    reverse_r2,
+                    args.validate_pair_names,
+                ),
             };
+            if args.check_mate_order {
+                reader = reader.with_mate_order_check();
+            }
+            return compress_fragments(args, temp_dir, phred, progress, Box::new(reader));
+        }
+    }
+
+    #[cfg(unix)]
+    if let Some(sink) = progress_sink {
+        sequence_reader = Box::new(ProgressReader::new(sequence_reader, sink));
+    }
 
-            sequence_reader = Box::new(FastQPairedFilesReader::new(
-                in_file_r1, in_file_r2, reverse_r2,
-            ));
+    let options = compress_options(args, temp_dir, phred)?;
+    let stats = write_archive(args, temp_dir, |sink| {
+        compress_paired(&mut sequence_reader, sink, options)
+    })?;
+    progress.finish();
+    report_compression(args, stats, sequence_reader.salvage());
+    Ok(())
+}
+
+/// The R1, R2 and output paths on each line of a manifest. Blank lines and lines starting with #
+/// are skipped.
+fn read_manifest(path: &OsString) -> Result<Vec<[OsString; 3]>, CompressionModelError> {
+    let mut rows = Vec::new();
+    for (i, line) in std::fs::read_to_string(path)?.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.split('\t').collect::<Vec<_>>()[..] {
+            [r1, r2, output] => rows.push([r1.into(), r2.into(), output.into()]),
+            _ => Cli::command()
+                .error(
+                    ErrorKind::InvalidValue,
+                    format!(
+                        "line {} of the manifest should be an R1 path, an R2 path and an output path separated by tabs",
+                        i + 1
+                    ),
+                )
+                .exit(),
         }
-        _ => panic!("Too many input files! programming error."),
     }
+    Ok(rows)
+}
 
-    let mut writer: Box<dyn EncoderModel>;
-    match (output.as_ref().and_then(|o| o.to_str()), model) {
-        (Some("-") | None, Some(Model::LZMA) | None) => {
-            writer = Box::new(XZSingleFileWriter::to_stdout());
+/// Compress each row of the manifest as if it had been given on its own. With --keep-going a
+/// failed row is reported and the rest are still compressed.
+fn compress_manifest(
+    args: &CompressArgs,
+    manifest: &OsString,
+    temp_dir: &Path,
+) -> Result<(), CompressionModelError> {
+    let rows = read_manifest(manifest)?;
+    let mut failed = 0;
+    for [r1, r2, output] in &rows {
+        let row = CompressArgs {
+            files: vec![r1.clone(), r2.clone()],
+            output: Some(output.clone()),
+            manifest: None,
+            ..args.clone()
+        };
+        if let Err(err) = compress(&row, temp_dir) {
+            eprintln!("Failed to compress {:?} and {:?}: {}", r1, r2, err);
+            if !args.keep_going {
+                return Err(err);
+            }
+            failed += 1;
         }
-        (_, Some(Model::LZMA) | None) => {
-            writer = Box::new(XZSingleFileWriter::create(
-                &output.expect("Programming error! output should be Some"),
-            )?);
+    }
+
+    if failed > 0 {
+        eprintln!(
+            "{} of the {} rows of the manifest failed",
+            failed,
+            rows.len()
+        );
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Compress fragments of other than two reads, single-end reads or one file per read.
+fn compress_fragments(
+    args: &CompressArgs,
+    temp_dir: &Path,
+    phred: PhredOffset,
+    progress: ProgressBar,
+    mut sequence_reader: Box<dyn MultiFastQReader>,
+) -> Result<(), CompressionModelError> {
+    let options = compress_options(args, temp_dir, phred)?;
+    let stats = write_archive(args, temp_dir, |sink| {
+        compress_multi(&mut sequence_reader, sink, options)
+    })?;
+    progress.finish();
+    report_compression(args, stats, sequence_reader.salvage());
+    Ok(())
+}
+
+/// The model given, or the default for the input.
+fn compression_model(args: &CompressArgs) -> CompressionModel {
+    // only the multi stream model stores single-end reads
+    let default_model = match args.single_end {
+        true => CompressionModel::LZMAMulti,
+        false => CompressionModel::LZMASingle,
+    };
+    args.model
+        .as_ref()
+        .map_or(default_model, |m| m.compression_model())
+}
+
+fn compress_options(
+    args: &CompressArgs,
+    temp_dir: &Path,
+    phred: PhredOffset,
+) -> Result<CompressOptions, CompressionModelError> {
+    let mut options = CompressOptions::new()
+        .with_model(compression_model(args))
+        .with_level(args.level)
+        .with_lzma_dict_size(args.lzma_dict)
+        .with_checksum(args.checksum)
+        .with_timestamp(args.timestamp)
+        .with_alphabet(args.alphabet)
+        .with_phred_offset(phred)
+        .with_reverse_r2(args.reverse_r2)
+        .with_invalid_base_policy(args.on_invalid_base)
+        .with_quality_binning(args.quality_bins)
+        .with_quality_filter(
+            args.min_mean_qual
+                .map(|min_mean| QualityFilter::new(min_mean).with_both(args.min_mean_qual_both)),
+        )
+        .with_length_check(
+            args.expect_length
+                .map(|expected| LengthCheck::new(expected).with_tolerance(args.length_tolerance)),
+        )
+        .with_dropped_qualities(args.no_qualities)
+        .with_trained_dictionary(args.train_dict)
+        .with_dedup(args.dedup)
+        .with_read_order(match (args.sort, args.preserve_order) {
+            (false, _) => ReadOrder::Input,
+            (true, false) => ReadOrder::Sorted,
+            (true, true) => ReadOrder::SortedKeepingOrder,
+        })
+        .with_title_layout(args.title_layout)
+        .with_packed_nucleotides(args.pack_nucleotides)
+        .with_quality_codec(args.quality_codec)
+        .with_title_codec(args.title_codec)
+        .with_config(
+            args.config
+                .as_ref()
+                .map(CompressionConfig::open)
+                .transpose()?,
+        )
+        .with_index(args.index_interval)
+        .with_block_size(args.block_size)
+        .with_framing(args.framing)
+        .with_spool_memory(args.spool_memory)
+        .with_temp_dir(temp_dir);
+    if args.model == Some(Model::LZMAReference) {
+        options = options.with_reference(open_reference(&args.reference)?);
+    }
+    Ok(options)
+}
+
+/// The output, along with the file to commit once it's complete when it isn't stdout.
+fn create_output(
+    args: &CompressArgs,
+) -> Result<(Box<dyn Write>, Option<PendingFile>), CompressionModelError> {
+    match args.output.as_ref().and_then(|o| o.to_str()) {
+        Some("-") | None => Ok((Box::new(std::io::stdout()), None)),
+        _ => {
+            let (file, pending) = PendingFile::create(
+                args.output
+                    .as_ref()
+                    .expect("Programming error! output should be Some"),
+            )?;
+            Ok((Box::new(file), Some(pending)))
         }
-        (Some("-") | None, Some(Model::LZMAMulti)) => {
-            writer = Box::new(XZMultiStreamWriter::to_stdout());
+    }
+}
+
+/// The output, or `counter` when it's a dry run.
+fn archive_sink<'a>(
+    args: &CompressArgs,
+    counter: &'a mut CountingWriter,
+) -> Result<(Box<dyn Write + 'a>, Option<PendingFile>), CompressionModelError> {
+    if args.dry_run {
+        Ok((Box::new(counter), None))
+    } else {
+        create_output(args)
+    }
+}
+
+/// Run `compress` on the sink the archive goes to. A sample is compressed to a spool of its own
+/// first, then written out with the samples it's appended to.
+fn write_archive<F>(
+    args: &CompressArgs,
+    temp_dir: &Path,
+    compress: F,
+) -> Result<CompressionStats, CompressionModelError>
+where
+    F: for<'a> FnOnce(Box<dyn Write + 'a>) -> Result<CompressionStats, CompressionModelError>,
+{
+    let mut counter = CountingWriter::new();
+    // a partly written output is removed when this returns early with an error
+    let mut pending = None;
+    let stats = match &args.sample_name {
+        None => {
+            let (sink, output) = archive_sink(args, &mut counter)?;
+            pending = output;
+            compress(sink)?
         }
-        (_, Some(Model::LZMAMulti)) => {
-            writer = Box::new(XZMultiStreamWriter::create(
-                &output.expect("Programming error! output should be Some"),
-            )?);
+        Some(name) => {
+            let mut sample = SpooledTempFile::new_in(args.spool_memory, temp_dir);
+            let stats = compress(Box::new(&mut sample))?;
+            sample.rewind()?;
+            match &args.append {
+                Some(path) if !args.dry_run => append_sample_to_file(path, name, sample)?,
+                Some(path) => append_sample(Some(File::open(path)?), name, sample, &mut counter)?,
+                None => {
+                    let (sink, output) = archive_sink(args, &mut counter)?;
+                    pending = output;
+                    append_sample(None::<File>, name, sample, sink)?
+                }
+            }
+            stats
         }
+    };
+    if let Some(pending) = pending {
+        pending.commit()?;
     }
 
-    writer.compress(&mut sequence_reader)?;
-    Ok(())
+    if args.dry_run {
+        println!("{} bytes", counter.bytes());
+    }
+    Ok(stats)
 }
 
-fn decompress(
-    file: OsString,
-    outputs: Vec<OsString>,
+fn report_compression(args: &CompressArgs, stats: CompressionStats, salvage: SalvageCounts) {
+    // progress events sent to stderr shouldn't have a summary mixed in
+    if !args.quiet && !args.progress_on_stderr() {
+        eprintln!(
+            "Compressed {} read pairs, {} R1 bases and {} R2 bases",
+            stats.pairs, stats.bases_r1, stats.bases_r2
+        );
+        if stats.bases_extra > 0 {
+            eprintln!("and {} bases of further reads", stats.bases_extra);
+        }
+        if stats.duplicate_pairs > 0 {
+            eprintln!(
+                "{} of the pairs were stored as copies of an earlier pair",
+                stats.duplicate_pairs
+            );
+        }
+    }
+
+    if stats.filtered_pairs > 0 {
+        eprintln!(
+            "Left out {} read pairs below the minimum mean quality",
+            stats.filtered_pairs
+        );
+    }
+    if stats.unexpected_lengths > 0 {
+        eprintln!(
+            "Warning: {} reads were not within {} bases of the expected length {}",
+            stats.unexpected_lengths,
+            args.length_tolerance,
+            args.expect_length.unwrap_or_default()
+        );
+    }
+    if salvage.repaired_records > 0 {
+        eprintln!(
+            "Repaired the quality length of {} records, qualities of these reads are not original",
+            salvage.repaired_records
+        );
+    }
+    if salvage.replaced_bases > 0 {
+        eprintln!("Replaced {} invalid bases with N", salvage.replaced_bases);
+    }
+    if salvage.skipped_reads > 0 {
+        eprintln!(
+            "Skipped {} reads containing invalid bases",
+            salvage.skipped_reads
+        );
+    }
+}
+
+fn create_fastq(
+    path: &OsString,
+    alphabet: Alphabet,
+    line_ending: LineEnding,
+    phred: PhredOffset,
+    quality_fill: Option<u8>,
+    case: LetterCase,
+) -> Result<Box<dyn FastQFileWriterTrait>, CompressionModelError> {
+    match path.to_str() {
+        Some("-") => Ok(Box::new(
+            FastQFileWriter::to_stdout()
+                .with_alphabet(alphabet)
+                .with_line_ending(line_ending)
+                .with_phred_offset(phred)
+                .with_quality_fill(quality_fill)
+                .with_case(case),
+        )),
+        _ => Ok(Box::new(
+            FastQFileWriter::create_maybe_compressed(path)?
+                .with_alphabet(alphabet)
+                .with_line_ending(line_ending)
+                .with_phred_offset(phred)
+                .with_quality_fill(quality_fill)
+                .with_case(case),
+        )),
+    }
+}
+
+#[cfg(feature = "bam")]
+fn create_bam(
+    path: &OsString,
     reverse_r2: bool,
-    model: Option<Model>,
-) -> Result<(), CompressionModelError> {
-    let mut sequence_writer: Box<dyn PairedFastQWriter>;
-    match outputs.len() {
-        1 => {
-            eprintln!("interleaved {:?} {:?} {:?}", file, outputs, reverse_r2);
-            let out_file: Box<dyn FastQFileWriterTrait> = match outputs[0].to_str() {
-                Some("-") => Box::new(FastQFileWriter::to_stdout()),
-                _ => Box::new(FastQFileWriter::create(&outputs[0])?),
-            };
+) -> Result<Box<dyn PairedFastQWriter>, CompressionModelError> {
+    match path.to_str() {
+        Some("-") => Ok(Box::new(UnalignedBamWriter::to_stdout(reverse_r2)?)),
+        _ => Ok(Box::new(UnalignedBamWriter::create(path, reverse_r2)?)),
+    }
+}
+
+#[cfg(not(feature = "bam"))]
+fn create_bam(
+    _path: &OsString,
+    _reverse_r2: bool,
+) -> Result<Box<dyn PairedFastQWriter>, CompressionModelError> {
+    Cli::command()
+        .error(
+            ErrorKind::InvalidValue,
+            "pare was built without BAM support, rebuild it with --features bam",
+        )
+        .exit()
+}
+
+/// Open the archive at `file`, stdin for `-`. Of an archive of samples only `sample` is unpacked.
+fn open_archive(
+    file: &OsString,
+    sample: Option<&str>,
+    temp_dir: &Path,
+) -> Result<PareArchiveDecoder<Box<dyn Read>>, CompressionModelError> {
+    let source: Box<dyn Read> = match file.to_str() {
+        Some("-") | None => Box::new(std::io::stdin()),
+        _ => Box::new(File::open(file)?),
+    };
+    match sample {
+        Some(sample) => PareArchiveDecoder::new_sample_in(source, temp_dir, sample),
+        None => PareArchiveDecoder::new_in(source, temp_dir),
+    }
+}
 
-            sequence_writer = Box::new(FastQInterleavedFileWriter::new(out_file, reverse_r2));
+fn decompress(args: &DecompressArgs, temp_dir: &Path) -> Result<(), CompressionModelError> {
+    let file = &args.file;
+    let outputs = &args.outputs;
+    let mut archive = open_archive(file, args.sample.as_deref(), temp_dir)?;
+    if args.keep_temp {
+        if let Some(path) = archive.keep_temp() {
+            eprintln!("Kept the unpacked archive in {}", path.display());
         }
-        2 => {
-            eprintln!("paired files {:?} {:?} {:?}", file, outputs, reverse_r2);
-            let out_file_r1: Box<dyn FastQFileWriterTrait> = match outputs[0].to_str() {
-                Some("-") => Box::new(FastQFileWriter::to_stdout()),
-                _ => Box::new(FastQFileWriter::create(&outputs[0])?),
-            };
+    }
+    let alphabet = archive.alphabet()?;
+    let line_ending = match args.line_ending {
+        Some(line_ending) => line_ending,
+        None => archive.line_ending()?,
+    };
+    let phred = archive.phred_offset()?;
+    let reverse_r2 = match (args.reverse_r2, archive.reverse_r2()?) {
+        (Some(given), Some(recorded)) if given != recorded => {
+            eprintln!(
+                "Warning: --reverse-r2={} but R2 was {}reverse complemented when compressed",
+                given,
+                if recorded { "" } else { "not " }
+            );
+            given
+        }
+        (Some(given), _) => given,
+        (None, recorded) => recorded.unwrap_or(false),
+    };
+    let quality_fill = match (args.quality_fill, archive.qualities_dropped()?) {
+        (Some(_), false) => {
+            eprintln!("Warning: --quality-fill is ignored, the archive has its qualities");
+            None
+        }
+        (fill, _) => fill,
+    };
+    let case = match args.case {
+        Some(LetterCase::Preserve) if !archive.keeps_case()? => {
+            return Err(CompressionModelError::CaseNotKept)
+        }
+        case => case.unwrap_or_default(),
+    };
+    let create = |path| create_fastq(path, alphabet, line_ending, phred, quality_fill, case);
+    let single_end = archive.reads_per_fragment()? == 1;
 
-            let out_file_r2: Box<dyn FastQFileWriterTrait> = match outputs[1].to_str() {
-                Some("-") => Box::new(FastQFileWriter::to_stdout()),
-                _ => Box::new(FastQFileWriter::create(&outputs[1])?),
-            };
+    let sequence_writer: Box<dyn PairedFastQWriter> = match outputs.len() {
+        _ if args.format == Format::Bam => create_bam(&outputs[0], reverse_r2)?,
+        1 if !single_end => {
+            if !args.quiet {
+                eprintln!("interleaved {:?} {:?} {:?}", file, outputs, reverse_r2);
+            }
+            let out_file = create(&outputs[0])?;
 
-            sequence_writer = Box::new(FastQPairedFilesWriter::new(
+            Box::new(FastQInterleavedFileWriter::new(out_file, reverse_r2))
+        }
+        2 if !single_end => {
+            if !args.quiet {
+                eprintln!("paired files {:?} {:?} {:?}", file, outputs, reverse_r2);
+            }
+            let out_file_r1 = create(&outputs[0])?;
+
+            let out_file_r2 = create(&outputs[1])?;
+
+            Box::new(FastQPairedFilesWriter::new(
                 out_file_r1,
                 out_file_r2,
                 reverse_r2,
-            ));
+            ))
+        }
+        _ => {
+            if args.range.is_some() {
+                Cli::command()
+                    .error(
+                        ErrorKind::ArgumentConflict,
+                        "--range is only supported for interleaved or paired outputs",
+                    )
+                    .exit();
+            }
+            if args.reference.is_some()
+                || args
+                    .model
+                    .as_ref()
+                    .is_some_and(|model| *model != Model::LZMAMulti)
+            {
+                Cli::command()
+                    .error(
+                        ErrorKind::ArgumentConflict,
+                        "an archive of other than two reads to a fragment is always lzma-multi, without a reference",
+                    )
+                    .exit();
+            }
+            let mut sequence_writer: Box<dyn MultiFastQWriter> = match outputs.as_slice() {
+                [output] if single_end => Box::new(SingleEndWriter::new(create(output)?)),
+                _ => {
+                    let mut writers = Vec::new();
+                    for output in outputs {
+                        writers.push(create(output)?);
+                    }
+                    Box::new(FastQMultiFilesWriter::new(writers, reverse_r2))
+                }
+            };
+
+            // only the multi stream model has other than two reads to a fragment
+            XZMultiStreamReader::from_archive(archive)?.decompress_multi(&mut sequence_writer)?;
+            return Ok(sequence_writer.finish()?);
+        }
+    };
+
+    let reference = match &args.reference {
+        Some(path) => Some(Reference::open(path)?),
+        None => None,
+    };
+
+    let bar = match args.quiet {
+        true => ProgressBar::hidden(),
+        false => ProgressBar::pairs(archive.stats()?.map(|stats| match &args.range {
+            Some(range) => stats.pairs.min(range.end).saturating_sub(range.start),
+            None => stats.pairs,
+        })),
+    };
+    let mut sequence_writer: Box<dyn PairedFastQWriter> =
+        Box::new(PairProgressWriter::new(sequence_writer, bar.clone()));
+
+    let mut writer = open_decoder(
+        archive,
+        args.model.as_ref().map(|m| m.compression_model()),
+        reference,
+    )?;
+    match &args.range {
+        Some(range) => writer.decompress_range(&mut sequence_writer, range.clone())?,
+        None => writer.decompress(&mut sequence_writer)?,
+    }
+    sequence_writer.finish()?;
+    bar.finish();
+
+    Ok(())
+}
+
+fn verify(
+    file: OsString,
+    model: Option<Model>,
+    reference: Option<OsString>,
+    sample: Option<&str>,
+    temp_dir: &Path,
+) -> Result<(), CompressionModelError> {
+    let mut archive = open_archive(&file, sample, temp_dir)?;
+    for (path, _) in archive.list_streams()? {
+        if let Err(err) = archive.verify_stream(&path) {
+            eprintln!("{:?}: {}", file, err);
+            return Err(err);
         }
-        _ => panic!("Too many output files! programming error."),
     }
 
-    let mut writer: Box<dyn DecoderModel>;
-    match (file.to_str(), model) {
-        (Some("-") | None, Some(Model::LZMA) | None) => {
-            writer = Box::new(XZSingleFileReader::from_stdin()?);
+    let reference = match reference {
+        Some(path) => Some(Reference::open(&path)?),
+        None => None,
+    };
+
+    let mut decoder = open_decoder(archive, model.map(|m| m.compression_model()), reference)?;
+    match decoder.verify() {
+        Ok(counter) => {
+            println!(
+                "{:?}: OK, {} records, {} bases",
+                file,
+                counter.records(),
+                counter.bases()
+            );
+            Ok(())
         }
-        (_, Some(Model::LZMA) | None) => {
-            writer = Box::new(XZSingleFileReader::open(&file)?);
+        Err(err) => {
+            eprintln!("{:?}: {}", file, err);
+            Err(err)
         }
-        (Some("-") | None, Some(Model::LZMAMulti)) => {
-            writer = Box::new(XZMultiStreamReader::from_stdin()?);
+    }
+}
+
+fn dump(
+    archive: OsString,
+    stream: &str,
+    decompressed: bool,
+    sample: Option<&str>,
+    temp_dir: &Path,
+) -> Result<(), CompressionModelError> {
+    let mut decoder = open_archive(&archive, sample, temp_dir)?;
+
+    let mut stdout = std::io::stdout().lock();
+    decoder.dump_stream(stream, decompressed, &mut stdout)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+fn list(file: OsString, temp_dir: &Path) -> Result<(), CompressionModelError> {
+    let source: Box<dyn Read> = match file.to_str() {
+        Some("-") => Box::new(std::io::stdin()),
+        _ => Box::new(File::open(&file)?),
+    };
+    let mut decoder = PareArchiveDecoder::new_in(source, temp_dir)?;
+
+    println!("stream\tcompressed\tuncompressed");
+    for (path, size, length) in decoder.stream_lengths()? {
+        let length = length.map_or("-".to_string(), |length| length.to_string());
+        println!("{}\t{}\t{}", path, size, length);
+    }
+
+    let metadata = match decoder.manifest()? {
+        Some(manifest) => manifest,
+        None => decoder.get_metadata()?,
+    };
+    let metadata = Bson::Document(metadata).into_relaxed_extjson();
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&metadata).map_err(std::io::Error::from)?
+    );
+    Ok(())
+}
+
+fn open_gzipped_fastq(
+    path: &OsString,
+    phred: PhredOffset,
+) -> Result<Box<dyn FastQFileReaderTrait>, CompressionModelError> {
+    let source: Box<dyn Read> = match path.to_str() {
+        Some("-") => Box::new(std::io::stdin()),
+        _ => Box::new(File::open(path)?),
+    };
+    Ok(Box::new(
+        FastQFileReader::new(BufReader::new(MultiGzDecoder::new(source))).with_phred_offset(phred),
+    ))
+}
+
+/// Bin the qualities of a gzipped fastq, the rest of each record, its line endings and quality
+/// offset are written back as they were read.
+fn requality_file(
+    input: OsString,
+    output: OsString,
+    bins: QualityBinning,
+    phred: Option<PhredOffset>,
+) -> Result<(), CompressionModelError> {
+    let phred = match phred {
+        Some(phred) => phred,
+        None if is_stream(&input) => PhredOffset::Phred33,
+        None => detect_phred_offset(
+            open_gzipped_fastq(&input, PhredOffset::Phred33)?.as_mut(),
+            PHRED_DETECTION_READS,
+        )?,
+    };
+    let mut in_file = open_gzipped_fastq(&input, phred)?;
+
+    let create = |line_ending| -> Result<Box<dyn FastQFileWriterTrait>, CompressionModelError> {
+        Ok(match output.to_str() {
+            Some("-") => Box::new(
+                FastQFileWriter::gzip(std::io::stdout())
+                    .with_line_ending(line_ending)
+                    .with_phred_offset(phred),
+            ),
+            _ => Box::new(
+                FastQFileWriter::gzip(File::create(&output)?)
+                    .with_line_ending(line_ending)
+                    .with_phred_offset(phred),
+            ),
+        })
+    };
+
+    requality(in_file.as_mut(), create, bins)?;
+    Ok(())
+}
+
+fn open_plain_fastq(
+    path: &OsString,
+    phred: PhredOffset,
+) -> Result<Box<dyn FastQFileReaderTrait + Send>, CompressionModelError> {
+    let source: Box<dyn Read + Send> = match path.to_str() {
+        Some("-") => Box::new(std::io::stdin()),
+        _ => Box::new(File::open(path)?),
+    };
+    Ok(Box::new(
+        FastQFileReader::new(BufReader::new(maybe_decompress(source)?)).with_phred_offset(phred),
+    ))
+}
+
+fn open_pairs(
+    inputs: &[OsString],
+    phred: PhredOffset,
+) -> Result<Box<dyn PairedFastQReader>, CompressionModelError> {
+    Ok(match inputs {
+        [interleaved] => Box::new(FastQInterleavedFileReader::new(
+            open_plain_fastq(interleaved, phred)?,
+            false,
+        )),
+        [r1, r2] => Box::new(FastQPairedFilesReader::new(
+            open_plain_fastq(r1, phred)?,
+            open_plain_fastq(r2, phred)?,
+            false,
+        )),
+        _ => unreachable!("clap takes one or two inputs"),
+    })
+}
+
+/// Print how many records the inputs hold, or the first thing wrong with them and exit 1.
+fn check(inputs: &[OsString]) -> Result<(), CompressionModelError> {
+    match open_pairs(inputs, PhredOffset::Phred33)?.check() {
+        Ok(records) => println!("OK, {} records", records),
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
         }
-        (_, Some(Model::LZMAMulti)) => {
-            writer = Box::new(XZMultiStreamReader::open(&file)?);
+    }
+    Ok(())
+}
+
+/// Copy the pairs of `inputs` to `outputs`, the reads are parsed and written back out unchanged
+/// so the layout is all that changes.
+fn relayout(inputs: &[OsString], outputs: &[OsString]) -> Result<(), CompressionModelError> {
+    let mut reader = open_pairs(inputs, PhredOffset::Phred33)?;
+
+    let mut r1 = FastQRead::default();
+    let mut r2 = FastQRead::default();
+    // the line ending of the input is only known once a read has been parsed
+    let mut more = reader.read_next(&mut r1, &mut r2)?;
+    let line_ending = reader.line_ending();
+    let create = |path| {
+        create_fastq(
+            path,
+            Alphabet::default(),
+            line_ending,
+            PhredOffset::Phred33,
+            None,
+            LetterCase::Preserve,
+        )
+    };
+    let mut writer: Box<dyn PairedFastQWriter> = match outputs {
+        [interleaved] => Box::new(FastQInterleavedFileWriter::new(create(interleaved)?, false)),
+        [r1, r2] => Box::new(FastQPairedFilesWriter::new(create(r1)?, create(r2)?, false)),
+        _ => unreachable!("clap takes one or two outputs"),
+    };
+
+    while more {
+        writer.write_next(&r1, &r2)?;
+        more = reader.read_next(&mut r1, &mut r2)?;
+    }
+    Ok(writer.finish()?)
+}
+
+/// How one model did on the benchmark input.
+struct BenchmarkResult {
+    model: String,
+    fastq_bytes: u64,
+    archive_bytes: u64,
+    compress: Duration,
+    decompress: Duration,
+}
+
+impl BenchmarkResult {
+    fn ratio(&self) -> f64 {
+        self.fastq_bytes as f64 / self.archive_bytes.max(1) as f64
+    }
+}
+
+/// Compress `inputs` with `model` to a temporary file, then decode it back to fastq that is only
+/// counted.
+fn benchmark_model(
+    inputs: &[OsString],
+    phred: PhredOffset,
+    model: &Model,
+    reference: &Option<OsString>,
+    temp_dir: &Path,
+) -> Result<BenchmarkResult, CompressionModelError> {
+    // named as -m takes it
+    let name = model
+        .to_possible_value()
+        .expect("no model is skipped")
+        .get_name()
+        .to_string();
+    let model = model.compression_model();
+    let uses_reference = model == CompressionModel::LZMAReference;
+    let mut options = CompressOptions::new()
+        .with_model(model.clone())
+        .with_phred_offset(phred)
+        .with_temp_dir(temp_dir);
+    if uses_reference {
+        options = options.with_reference(open_reference(reference)?);
+    }
+
+    let mut reader = open_pairs(inputs, phred)?;
+    let mut archive = tempfile::tempfile_in(temp_dir)?;
+    let start = Instant::now();
+    compress_paired(&mut reader, &mut archive, options)?;
+    let compress = start.elapsed();
+    let archive_bytes = archive.stream_position()?;
+    archive.rewind()?;
+
+    let reference = match uses_reference {
+        true => Some(open_reference(reference)?),
+        false => None,
+    };
+    let mut writer = FastQInterleavedFileWriter::new(
+        FastQFileWriter::new(BufWriter::new(CountingWriter::new())).with_phred_offset(phred),
+        false,
+    );
+    let mut r1 = FastQRead::default();
+    let mut r2 = FastQRead::default();
+    let start = Instant::now();
+    let mut decoder = open_decoder(
+        PareArchiveDecoder::new_in(archive, temp_dir)?,
+        Some(model),
+        reference,
+    )?;
+    let mut pairs = decoder.decoder()?;
+    while pairs.read_next(&mut r1, &mut r2)? {
+        writer.write_next(&r1, &r2)?;
+    }
+    let fastq_bytes = writer.into_inner().into_inner()?.bytes();
+    let decompress = start.elapsed();
+
+    Ok(BenchmarkResult {
+        model: name,
+        fastq_bytes,
+        archive_bytes,
+        compress,
+        decompress,
+    })
+}
+
+fn benchmark(
+    inputs: &[OsString],
+    reference: &Option<OsString>,
+    json: bool,
+    temp_dir: &Path,
+) -> Result<(), CompressionModelError> {
+    let mut first = open_plain_fastq(&inputs[0], PhredOffset::Phred33)?;
+    let phred = detect_phred_offset(first.as_mut(), PHRED_DETECTION_READS)?;
+
+    let mut results = Vec::new();
+    for model in Model::value_variants() {
+        if *model == Model::LZMAReference && reference.is_none() {
+            continue;
         }
+        results.push(benchmark_model(inputs, phred, model, reference, temp_dir)?);
     }
-    writer.decompress(&mut sequence_writer)?;
 
+    if json {
+        let results: Vec<serde_json::Value> = results
+            .iter()
+            .map(|result| {
+                serde_json::json!({
+                    "model": result.model,
+                    "fastq_bytes": result.fastq_bytes,
+                    "archive_bytes": result.archive_bytes,
+                    "ratio": result.ratio(),
+                    "compress_seconds": result.compress.as_secs_f64(),
+                    "decompress_seconds": result.decompress.as_secs_f64(),
+                })
+            })
+            .collect();
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&results).map_err(std::io::Error::from)?
+        );
+        return Ok(());
+    }
+
+    println!(
+        "{:<16} {:>14} {:>8} {:>12} {:>14}",
+        "model", "archive bytes", "ratio", "compress s", "decompress s"
+    );
+    for result in &results {
+        println!(
+            "{:<16} {:>14} {:>8.2} {:>12.3} {:>14.3}",
+            result.model,
+            result.archive_bytes,
+            result.ratio(),
+            result.compress.as_secs_f64(),
+            result.decompress.as_secs_f64()
+        );
+    }
     Ok(())
 }
 
 fn main() -> Result<(), CompressionModelError> {
     let args = Cli::parse();
+    if let Some(dir) = &args.temp_dir {
+        if let Err(err) = check_temp_dir(dir) {
+            Cli::command().error(ErrorKind::ValueValidation, err).exit();
+        }
+    } else if matches!(args.command, Commands::Compress(_)) {
+        // inputs whose spools fit in memory never need it
+        if let Err(err) = check_temp_dir(std::env::temp_dir()) {
+            eprintln!("Warning: {}", err);
+        }
+    }
 
+    let temp_dir = args.temp_dir.unwrap_or_else(std::env::temp_dir);
     match args.command {
-        Commands::Compress {
-            files,
-            output,
-            reverse_r2,
-            model,
-        } => compress(&files, output, reverse_r2, model)?,
-        Commands::Decompress {
+        Commands::Compress(args) => {
+            #[cfg(unix)]
+            if args.files.len() > 2 && args.progress_fd.is_some() {
+                Cli::command()
+                    .error(
+                        ErrorKind::ArgumentConflict,
+                        "--progress-fd is only supported for interleaved or paired files",
+                    )
+                    .exit();
+            }
+            if args.config.is_some() && compression_model(&args) != CompressionModel::LZMAMulti {
+                Cli::command()
+                    .error(
+                        ErrorKind::ArgumentConflict,
+                        "--config is only used by the lzma-multi model, add --model lzma-multi",
+                    )
+                    .exit();
+            }
+            if args.single_end && args.files.len() != 1 {
+                Cli::command()
+                    .error(
+                        ErrorKind::WrongNumberOfValues,
+                        "--single-end reads are compressed from a single file",
+                    )
+                    .exit();
+            }
+            let compressed = match &args.manifest {
+                Some(manifest) => compress_manifest(&args, manifest, &temp_dir),
+                None => compress(&args, &temp_dir),
+            };
+            // says what to do about it rather than printing the error
+            if let Err(err @ CompressionModelError::TempDirNotWritable { .. }) = compressed {
+                Cli::command().error(ErrorKind::Io, err).exit();
+            }
+            compressed?
+        }
+        Commands::Decompress(args) => {
+            if args.format == Format::Bam && args.outputs.len() > 1 {
+                Cli::command()
+                    .error(
+                        ErrorKind::ArgumentConflict,
+                        "BAM output holds both reads of a pair, give a single output",
+                    )
+                    .exit();
+            }
+            // two writers on the same fd would garble each other's records
+            if args.outputs.iter().filter(|output| *output == "-").count() > 1 {
+                Cli::command()
+                    .error(
+                        ErrorKind::ArgumentConflict,
+                        "more than one output is stdout, give a single output to write interleaved reads to stdout",
+                    )
+                    .exit();
+            }
+            decompress(&args, &temp_dir)?
+        }
+        Commands::Dump {
+            archive,
+            stream,
+            decompressed,
+            sample,
+        } => dump(archive, &stream, decompressed, sample.as_deref(), &temp_dir)?,
+        Commands::List { file } => list(file, &temp_dir)?,
+        Commands::Verify {
             file,
-            outputs,
-            reverse_r2,
             model,
-        } => decompress(file, outputs, reverse_r2, model)?,
-        Commands::Cite {} => {
-            println!("print out a citation here");
+            reference,
+            sample,
+        } => verify(file, model, reference, sample.as_deref(), &temp_dir)?,
+        Commands::Check { inputs } => check(&inputs)?,
+        Commands::Requality {
+            bins,
+            phred,
+            input,
+            output,
+        } => requality_file(input, output, bins, phred)?,
+        Commands::Relayout {
+            layout,
+            outputs,
+            inputs,
+        } => {
+            if outputs.len() != layout.files() {
+                Cli::command()
+                    .error(
+                        ErrorKind::WrongNumberOfValues,
+                        format!(
+                            "the {} layout is written to {} output(s), {} given",
+                            format!("{:?}", layout).to_lowercase(),
+                            layout.files(),
+                            outputs.len()
+                        ),
+                    )
+                    .exit();
+            }
+            if outputs.iter().filter(|output| *output == "-").count() > 1 {
+                Cli::command()
+                    .error(
+                        ErrorKind::ArgumentConflict,
+                        "more than one output is stdout, use the interleaved layout to write to stdout",
+                    )
+                    .exit();
+            }
+            relayout(&inputs, &outputs)?
+        }
+        Commands::Benchmark {
+            inputs,
+            reference,
+            json,
+        } => {
+            // every model reads the inputs again from the start
+            if inputs.iter().any(is_stream) {
+                Cli::command()
+                    .error(
+                        ErrorKind::InvalidValue,
+                        "benchmark reads its inputs once per model, they can't be stdin or a pipe",
+                    )
+                    .exit();
+            }
+            benchmark(&inputs, &reference, json, &temp_dir)?
         }
+        Commands::Cite { format } => print!("{}", Citation::pare().format(format)),
     }
     Ok(())
 }
@@ -0,0 +1,236 @@
+//! Machine readable progress reporting.
+//!
+//! Progress is written as newline delimited JSON so that a wrapping tool can follow along without
+//! parsing log messages. Each line is a single object with an `event` and the number of `records`
+//! processed so far:
+//!
+//! ```text
+//! {"event":"progress","records":20000}
+//! {"event":"done","records":21042}
+//! ```
+//!
+//! The total number of records is not known up front when reading fastq so no total is reported.
+//!
+//! For people watching a terminal there's [`ProgressBar`] instead, drawn on stderr since stdout may
+//! be the archive. It needs the `progress-bar` feature, without it nothing is drawn.
+
+use std::io::{Read, Write};
+
+use crate::seq_files::fastq::{
+    FastQFileError, FastQRead, LineEnding, PairedFastQReader, PairedFastQWriter, SalvageCounts,
+};
+
+/// How many read pairs go by between progress events.
+const DEFAULT_PROGRESS_INTERVAL: u64 = 10_000;
+
+/// Wraps a paired reader and reports how many records have been read through it.
+pub struct ProgressReader<W: Write> {
+    reader: Box<dyn PairedFastQReader>,
+    sink: W,
+    records: u64,
+    interval: u64,
+    finished: bool,
+}
+
+impl<W: Write> ProgressReader<W> {
+    pub fn new(reader: Box<dyn PairedFastQReader>, sink: W) -> Self {
+        ProgressReader {
+            reader,
+            sink,
+            records: 0,
+            interval: DEFAULT_PROGRESS_INTERVAL,
+            finished: false,
+        }
+    }
+
+    fn emit(&mut self, event: &str) -> Result<(), FastQFileError> {
+        writeln!(
+            self.sink,
+            "{{\"event\":\"{}\",\"records\":{}}}",
+            event, self.records
+        )?;
+        self.sink.flush()?;
+        Ok(())
+    }
+}
+
+impl<W: Write> PairedFastQReader for ProgressReader<W> {
+    fn read_next(
+        &mut self,
+        buf_r1: &mut FastQRead,
+        buf_r2: &mut FastQRead,
+    ) -> Result<bool, FastQFileError> {
+        if !self.reader.read_next(buf_r1, buf_r2)? {
+            if !self.finished {
+                self.finished = true;
+                self.emit("done")?;
+            }
+            return Ok(false);
+        }
+
+        self.records += 2;
+        if (self.records / 2).is_multiple_of(self.interval) {
+            self.emit("progress")?;
+        }
+
+        Ok(true)
+    }
+
+    fn salvage(&self) -> SalvageCounts {
+        self.reader.salvage()
+    }
+
+    fn line_ending(&self) -> LineEnding {
+        self.reader.line_ending()
+    }
+}
+
+/// A progress bar on stderr. It is hidden when stderr isn't a terminal, so logs don't fill up with
+/// redraws, and when pare is built without the `progress-bar` feature.
+#[derive(Clone)]
+pub struct ProgressBar {
+    #[cfg(feature = "progress-bar")]
+    bar: indicatif::ProgressBar,
+}
+
+impl ProgressBar {
+    /// A bar following the bytes read through `wrap_read` out of `total`, when it's known.
+    pub fn bytes(total: Option<u64>) -> Self {
+        ProgressBar::drawn(
+            total,
+            "{spinner} {bytes} read, {binary_bytes_per_sec}",
+            "{spinner} [{bar:40}] {bytes}/{total_bytes} ({eta} left)",
+        )
+    }
+
+    /// A bar following the read pairs counted by `PairProgressWriter` out of `total`, when it's
+    /// known.
+    pub fn pairs(total: Option<u64>) -> Self {
+        ProgressBar::drawn(
+            total,
+            "{spinner} {human_pos} read pairs, {per_sec}",
+            "{spinner} [{bar:40}] {human_pos}/{human_len} read pairs ({eta} left)",
+        )
+    }
+
+    /// A bar that's never drawn, e.g. for --quiet.
+    pub fn hidden() -> Self {
+        ProgressBar {
+            #[cfg(feature = "progress-bar")]
+            bar: indicatif::ProgressBar::hidden(),
+        }
+    }
+
+    #[cfg(feature = "progress-bar")]
+    fn drawn(total: Option<u64>, unknown_total: &str, known_total: &str) -> Self {
+        use std::io::IsTerminal;
+
+        use indicatif::{ProgressDrawTarget, ProgressStyle};
+
+        if !std::io::stderr().is_terminal() {
+            return ProgressBar::hidden();
+        }
+
+        let template = match total {
+            Some(_) => known_total,
+            None => unknown_total,
+        };
+        let bar = indicatif::ProgressBar::with_draw_target(total, ProgressDrawTarget::stderr())
+            .with_style(
+                ProgressStyle::with_template(template)
+                    .expect("Programming error! progress templates should be valid")
+                    .progress_chars("=> "),
+            );
+        ProgressBar { bar }
+    }
+
+    #[cfg(not(feature = "progress-bar"))]
+    fn drawn(_total: Option<u64>, _unknown_total: &str, _known_total: &str) -> Self {
+        ProgressBar::hidden()
+    }
+
+    /// Count the bytes read from `read` on this bar.
+    pub fn wrap_read<R: Read + Send + 'static>(&self, read: R) -> Box<dyn Read + Send> {
+        #[cfg(feature = "progress-bar")]
+        return Box::new(self.bar.wrap_read(read));
+        #[cfg(not(feature = "progress-bar"))]
+        Box::new(read)
+    }
+
+    pub fn inc(&self, delta: u64) {
+        #[cfg(feature = "progress-bar")]
+        self.bar.inc(delta);
+        #[cfg(not(feature = "progress-bar"))]
+        let _ = delta;
+    }
+
+    /// Take the bar off the terminal.
+    pub fn finish(&self) {
+        #[cfg(feature = "progress-bar")]
+        self.bar.finish_and_clear();
+    }
+}
+
+/// Wraps a paired writer and counts the read pairs written through it on a `ProgressBar`.
+pub struct PairProgressWriter {
+    writer: Box<dyn PairedFastQWriter>,
+    bar: ProgressBar,
+}
+
+impl PairProgressWriter {
+    pub fn new(writer: Box<dyn PairedFastQWriter>, bar: ProgressBar) -> Self {
+        PairProgressWriter { writer, bar }
+    }
+}
+
+impl PairedFastQWriter for PairProgressWriter {
+    fn write_next(
+        &mut self,
+        buf_r1: &FastQRead,
+        buf_r2: &FastQRead,
+    ) -> Result<bool, FastQFileError> {
+        let written = self.writer.write_next(buf_r1, buf_r2)?;
+        self.bar.inc(1);
+        Ok(written)
+    }
+
+    fn finish(&mut self) -> Result<(), FastQFileError> {
+        self.writer.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{BufRead, BufReader};
+
+    use crate::compression_models::test_utils::*;
+
+    #[test]
+    fn test_progress_to_pipe() -> Result<(), FastQFileError> {
+        let (pipe_reader, pipe_writer) = std::io::pipe()?;
+
+        let mut reader = ProgressReader::new(interleaved_reader(INTERLEAVED_FASTQ), pipe_writer);
+        reader.interval = 1;
+        let mut r1 = FastQRead::default();
+        let mut r2 = FastQRead::default();
+        while reader.read_next(&mut r1, &mut r2)? {}
+        drop(reader);
+
+        let events: Vec<serde_json::Value> = BufReader::new(pipe_reader)
+            .lines()
+            .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+            .collect();
+
+        assert_eq!(3, events.len());
+        assert_eq!("progress", events[0]["event"]);
+        assert_eq!(2, events[0]["records"]);
+        assert_eq!("progress", events[1]["event"]);
+        assert_eq!(4, events[1]["records"]);
+        assert_eq!("done", events[2]["event"]);
+        assert_eq!(4, events[2]["records"]);
+
+        Ok(())
+    }
+}
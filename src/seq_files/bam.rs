@@ -0,0 +1,170 @@
+use std::path::Path;
+
+use rust_htslib::bam::header::HeaderRecord;
+use rust_htslib::bam::{Format, Header, Record, Writer};
+
+use crate::seq_files::fastq::{
+    nuclotides_upper, reverse_complement_nucleotides, FastQFileError, FastQRead, PairedFastQWriter,
+};
+
+/// The flags of every record: paired, unmapped and the mate unmapped.
+const FLAGS_UNMAPPED_PAIR: u16 = 0x1 | 0x4 | 0x8;
+const FLAG_FIRST_IN_PAIR: u16 = 0x40;
+const FLAG_SECOND_IN_PAIR: u16 = 0x80;
+
+/// The longest read name BAM can hold.
+const MAX_QNAME_LENGTH: usize = 254;
+
+/// Writes read pairs as unaligned BAM records, as an aligner reading uBAM expects them. Both mates
+/// are named after their fragment, the rest of the title is dropped. BAM has no U nor lower case
+/// letters so RNA is written with T and soft masking is lost.
+pub struct UnalignedBamWriter {
+    writer: Writer,
+    record: Record,
+    reverse_complement_r2_nucleotides: bool,
+}
+
+impl UnalignedBamWriter {
+    fn new(writer: Writer, reverse_complement_r2_nucleotides: bool) -> Self {
+        UnalignedBamWriter {
+            writer,
+            record: Record::new(),
+            reverse_complement_r2_nucleotides,
+        }
+    }
+
+    pub fn create<P: AsRef<Path>>(
+        path: &P,
+        reverse_complement_r2_nucleotides: bool,
+    ) -> Result<Self, FastQFileError> {
+        let writer = Writer::from_path(path, &unaligned_header(), Format::Bam)?;
+        Ok(UnalignedBamWriter::new(
+            writer,
+            reverse_complement_r2_nucleotides,
+        ))
+    }
+
+    pub fn to_stdout(reverse_complement_r2_nucleotides: bool) -> Result<Self, FastQFileError> {
+        let writer = Writer::from_stdout(&unaligned_header(), Format::Bam)?;
+        Ok(UnalignedBamWriter::new(
+            writer,
+            reverse_complement_r2_nucleotides,
+        ))
+    }
+
+    fn write_read(
+        &mut self,
+        buf: &FastQRead,
+        flags: u16,
+        reverse_complement: bool,
+    ) -> Result<(), FastQFileError> {
+        let name = buf.fragment_name();
+        if name.len() > MAX_QNAME_LENGTH {
+            return Err(FastQFileError::NameTooLong {
+                name: name.to_string(),
+            });
+        }
+
+        let mut letters = buf.letters.to_owned();
+        if reverse_complement {
+            reverse_complement_nucleotides(&mut letters)?;
+        }
+        nuclotides_upper(&mut letters)?;
+        // stored one above the Phred score, BAM has the score itself
+        let qualities: Vec<u8> = buf.qualities.iter().map(|q| q - 1).collect();
+
+        self.record.set(name.as_bytes(), None, &letters, &qualities);
+        self.record.set_flags(flags);
+        self.writer.write(&self.record)?;
+        Ok(())
+    }
+}
+
+impl PairedFastQWriter for UnalignedBamWriter {
+    fn write_next(
+        &mut self,
+        buf_r1: &FastQRead,
+        buf_r2: &FastQRead,
+    ) -> Result<bool, FastQFileError> {
+        self.write_read(buf_r1, FLAGS_UNMAPPED_PAIR | FLAG_FIRST_IN_PAIR, false)?;
+        self.write_read(
+            buf_r2,
+            FLAGS_UNMAPPED_PAIR | FLAG_SECOND_IN_PAIR,
+            self.reverse_complement_r2_nucleotides,
+        )?;
+        Ok(true)
+    }
+}
+
+fn unaligned_header() -> Header {
+    let mut header = Header::new();
+    header.push_record(
+        HeaderRecord::new(b"HD")
+            .push_tag(b"VN", "1.6")
+            .push_tag(b"SO", "unsorted"),
+    );
+    header.push_record(
+        HeaderRecord::new(b"PG")
+            .push_tag(b"ID", "pare")
+            .push_tag(b"PN", "pare")
+            .push_tag(b"VN", env!("CARGO_PKG_VERSION")),
+    );
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rust_htslib::bam::{Read, Reader};
+
+    fn read(title: &str, letters: &[u8], qualities: &[u8]) -> FastQRead {
+        FastQRead {
+            title: title.to_string(),
+            letters: letters.to_vec(),
+            qualities: qualities.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_unaligned_bam_round_trip() -> Result<(), FastQFileError> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("reads.bam");
+
+        let pairs = [
+            (
+                read("frag1/1", b"acgtn", &[41, 41, 30, 20, 3]),
+                read("frag1/2", b"ttgca", &[41, 40, 39, 38, 37]),
+            ),
+            (
+                read("frag2 1:N:0:ATCACG", b"ggrya", &[1, 2, 3, 4, 94]),
+                read("frag2 2:N:0:ATCACG", b"cc", &[10, 11]),
+            ),
+        ];
+        {
+            let mut writer = UnalignedBamWriter::create(&path, true)?;
+            for (r1, r2) in &pairs {
+                writer.write_next(r1, r2)?;
+            }
+        }
+
+        let mut reader = Reader::from_path(&path)?;
+        let records: Vec<Record> = reader.records().collect::<Result<_, _>>()?;
+        let expected: [(&[u8], u16, &[u8], &[u8]); 4] = [
+            (b"frag1", 77, b"ACGTN", &[40, 40, 29, 19, 2]),
+            (b"frag1", 141, b"TGCAA", &[40, 39, 38, 37, 36]),
+            (b"frag2", 77, b"GGRYA", &[0, 1, 2, 3, 93]),
+            (b"frag2", 141, b"GG", &[9, 10]),
+        ];
+        assert_eq!(expected.len(), records.len());
+        for (record, (name, flags, letters, qualities)) in records.iter().zip(expected) {
+            assert_eq!(name, record.qname());
+            assert_eq!(flags, record.flags());
+            assert_eq!(letters, record.seq().as_bytes());
+            assert_eq!(qualities, record.qual());
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,162 @@
+//! Reading BGZF, the blocked gzip written by bgzip and samtools.
+//!
+//! A BGZF file is a series of gzip members of at most 64 KiB each, with the compressed size of
+//! the member in a `BC` extra field. Any gzip reader can decompress it, reading it a block at a
+//! time with `noodles-bgzf` also gives the virtual offset of every byte: the offset of its block
+//! in the compressed file shifted up 16 bits, plus its offset in the decompressed block.
+
+use std::io::{BufRead, Read};
+
+use noodles_bgzf::VirtualPosition;
+
+/// The bytes of a BGZF header up to and including the `BC` subfield identifier.
+pub const BGZF_HEADER_LEN: usize = 14;
+
+/// Whether `header` starts like a BGZF block: gzip with an extra field whose first subfield is `BC`.
+pub fn is_bgzf(header: &[u8]) -> bool {
+    header.len() >= BGZF_HEADER_LEN
+        && header[..4] == [0x1f, 0x8b, 0x08, 0x04]
+        && header[12..14] == *b"BC"
+}
+
+/// Decompresses a BGZF stream, remembering where the bytes handed out by the last read came from
+/// so the virtual offset can be worked out behind a `BufReader`.
+pub struct BgzfReader<R: Read> {
+    inner: noodles_bgzf::io::Reader<R>,
+    /// The virtual offset of the first byte of the last read, and how many bytes it returned.
+    last_read: (VirtualPosition, usize),
+}
+
+impl<R: Read> BgzfReader<R> {
+    pub fn new(inner: R) -> Self {
+        BgzfReader {
+            inner: noodles_bgzf::io::Reader::new(inner),
+            last_read: (VirtualPosition::default(), 0),
+        }
+    }
+
+    /// The virtual offset of the next byte to be read. Once a block has been read to its end this
+    /// is the start of the next block.
+    pub fn virtual_position(&self) -> u64 {
+        self.inner.virtual_position().into()
+    }
+
+    /// The virtual offset of the next byte a reader on top of this one hands out, when it still
+    /// holds the last `buffered` bytes of the last read.
+    pub fn virtual_position_before(&self, buffered: usize) -> u64 {
+        let (start, length) = self.last_read;
+        if buffered == 0 || buffered > length {
+            return self.virtual_position();
+        }
+        let offset = start.uncompressed() as u64 + (length - buffered) as u64;
+        start.compressed() << 16 | offset
+    }
+}
+
+impl<R: Read> Read for BgzfReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        // reading through the block buffer keeps every read inside one block, so its bytes have
+        // consecutive virtual offsets
+        let available = self.inner.fill_buf()?;
+        let length = available.len().min(buf.len());
+        buf[..length].copy_from_slice(&available[..length]);
+        self.last_read = (self.inner.virtual_position(), length);
+        self.inner.consume(length);
+        Ok(length)
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn bgzip(data: &[u8], block_size: usize) -> Vec<u8> {
+    use std::io::Write;
+
+    let mut writer = noodles_bgzf::io::Writer::new(Vec::new());
+    for block in data.chunks(block_size) {
+        writer.write_all(block).unwrap();
+        writer.flush().unwrap();
+    }
+    writer.finish().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use flate2::read::MultiGzDecoder;
+    use std::io::{BufReader, ErrorKind};
+
+    const TEXT: &[u8] = b"@read1\nACGTACGTAC\n+\nIIIIIIIIII\n@read2\nTTGCA\n+\nIIIII\n";
+
+    #[test]
+    fn test_read_blocks() -> std::io::Result<()> {
+        let bgzf = bgzip(TEXT, 16);
+        assert!(is_bgzf(&bgzf));
+
+        let mut text = Vec::new();
+        BgzfReader::new(bgzf.as_slice()).read_to_end(&mut text)?;
+        assert_eq!(TEXT, text);
+
+        // it is gzip too
+        let mut text = Vec::new();
+        MultiGzDecoder::new(bgzf.as_slice()).read_to_end(&mut text)?;
+        assert_eq!(TEXT, text);
+        Ok(())
+    }
+
+    #[test]
+    fn test_virtual_position() -> std::io::Result<()> {
+        let bgzf = bgzip(TEXT, 16);
+        let mut reader = BgzfReader::new(bgzf.as_slice());
+        assert_eq!(0, reader.virtual_position());
+
+        let mut buf = [0u8; 10];
+        reader.read_exact(&mut buf)?;
+        assert_eq!(10, reader.virtual_position());
+
+        // the rest of the first block, then into the second
+        reader.read_exact(&mut buf[..6])?;
+        let second = reader.virtual_position() >> 16;
+        assert_eq!(0, reader.virtual_position() & 0xffff);
+        reader.read_exact(&mut buf[..3])?;
+        assert_eq!(second << 16 | 3, reader.virtual_position());
+        Ok(())
+    }
+
+    #[test]
+    fn test_virtual_position_buffered() -> std::io::Result<()> {
+        let bgzf = bgzip(TEXT, 16);
+        let mut reader = BufReader::new(BgzfReader::new(bgzf.as_slice()));
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        assert_eq!(
+            7,
+            reader
+                .get_ref()
+                .virtual_position_before(reader.buffer().len())
+        );
+
+        // the second line ends in the second block, which starts after the size of the first
+        reader.read_line(&mut line)?;
+        let second = u16::from_le_bytes([bgzf[16], bgzf[17]]) as u64 + 1;
+        assert_eq!(
+            second << 16 | 2,
+            reader
+                .get_ref()
+                .virtual_position_before(reader.buffer().len())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupt_block() {
+        let mut bgzf = bgzip(TEXT, 16);
+        // the CRC32 of the first block
+        let crc = u16::from_le_bytes([bgzf[16], bgzf[17]]) as usize - 7;
+        bgzf[crc] ^= 0xff;
+
+        let error = BgzfReader::new(bgzf.as_slice())
+            .read_to_end(&mut Vec::new())
+            .unwrap_err();
+        assert_eq!(ErrorKind::InvalidData, error.kind());
+    }
+}
@@ -1,49 +1,186 @@
+use clap::ValueEnum;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
 use thiserror::Error;
 
+use std::borrow::BorrowMut;
 use std::fs::File;
 use std::io::{BufReader, BufWriter};
+use std::ops::DerefMut;
 use std::path::Path;
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::thread::{self, JoinHandle};
 
 use std::io::prelude::*;
 use std::vec::Vec;
 
-fn reverse_complement_nucleotides(nucleotides: &mut Vec<u8>) {
+use crate::compression_models::quality_binning::STORED_QUALITY_SHIFT;
+use crate::seq_files::bgzf::{is_bgzf, BgzfReader, BGZF_HEADER_LEN};
+use crate::seq_files::peek::PeekReader;
+
+pub fn reverse_complement_nucleotides(nucleotides: &mut Vec<u8>) -> Result<(), FastQFileError> {
     nucleotides.reverse();
-    nucleotides.iter_mut().for_each(|n| {
+    for n in nucleotides.iter_mut() {
         *n = match n {
             b'n' => b'n',
             b'a' => b't',
             b't' => b'a',
             b'c' => b'g',
             b'g' => b'c',
-            _ => panic!("Invalid nuceotide {} found!", n),
+            b'r' => b'y',
+            b'y' => b'r',
+            b'k' => b'm',
+            b'm' => b'k',
+            b'b' => b'v',
+            b'v' => b'b',
+            b'd' => b'h',
+            b'h' => b'd',
+            b's' => b's',
+            b'w' => b'w',
+            _ => return Err(FastQFileError::InvalidNucleotideLetter { c: *n as char }),
         }
-    });
+    }
+
+    Ok(())
 }
 
-fn nuclotides_upper(nucleotides: &mut Vec<u8>) {
-    nucleotides.iter_mut().for_each(|n| {
+pub(crate) fn nuclotides_upper(nucleotides: &mut Vec<u8>) -> Result<(), FastQFileError> {
+    for n in nucleotides.iter_mut() {
         *n = match n {
             b'n' => b'N',
             b'a' => b'A',
             b't' => b'T',
             b'c' => b'C',
             b'g' => b'G',
-            _ => panic!("Invalid nuclotide {} found!", n),
+            b'r' | b'y' | b's' | b'w' | b'k' | b'm' | b'b' | b'd' | b'h' | b'v' => {
+                n.to_ascii_uppercase()
+            }
+            _ => return Err(FastQFileError::InvalidNucleotideLetter { c: *n as char }),
         }
-    });
+    }
+
+    Ok(())
 }
 
-#[derive(Debug, Default)]
+/// Move the offsets of `other_letters` to where they are once a read of `length` letters is
+/// reversed, keeping them in order.
+fn reverse_other_letters(other_letters: &mut [(usize, u8)], length: usize) {
+    other_letters.reverse();
+    for (offset, _) in other_letters.iter_mut() {
+        *offset = length - 1 - *offset;
+    }
+}
+
+/// The letters reads are stored with, ACGTN and the IUPAC codes in lower case.
+const STORED_LETTERS: &[u8] = b"acgtnryswkmbdhv";
+
+/// The highest quality score that can be written with Phred+33, `~`.
+pub const MAX_PHRED: u8 = 93;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub struct FastQRead {
     pub letters: Vec<u8>,
+    /// The quality letters minus 32. These are copied byte for byte and never case folded, lower
+    /// case letters are just high scores here.
     pub qualities: Vec<u8>,
     pub title: String,
+    /// The text after the `+` of the third line, empty when it was a bare `+`.
+    pub sub_title: String,
+    /// True for every base that was lower case in the input, e.g. soft masked repeats. Empty
+    /// when the whole read was upper case, which is by far the most common.
+    pub case_mask: Vec<bool>,
+    /// The `.` and `-` letters of a read parsed in permissive mode, by their offset in the read.
+    /// They are stored as an n in `letters`.
+    pub other_letters: Vec<(usize, u8)>,
 }
 
 impl FastQRead {
-    pub fn reverse_complement_nucleotides(&mut self) {
-        reverse_complement_nucleotides(&mut self.letters);
+    pub fn reverse_complement_nucleotides(&mut self) -> Result<(), FastQFileError> {
+        reverse_complement_nucleotides(&mut self.letters)?;
+        self.case_mask.reverse();
+        reverse_other_letters(&mut self.other_letters, self.letters.len());
+        Ok(())
+    }
+
+    /// Check the read is one pare could have parsed: a quality for every base, only the letters
+    /// `nuc_string_to_vec` stores, and qualities within Phred 0 to 93.
+    pub fn validate(&self) -> Result<(), FastQFileError> {
+        if self.letters.len() != self.qualities.len() {
+            return Err(FastQFileError::MismatchedSequenceLength);
+        }
+
+        if let Some(&n) = self.letters.iter().find(|n| !STORED_LETTERS.contains(n)) {
+            return Err(FastQFileError::InvalidNucleotideLetter { c: n as char });
+        }
+
+        // qualities are stored one above their Phred score
+        if self
+            .qualities
+            .iter()
+            .any(|q| !(1..=MAX_PHRED + 1).contains(q))
+        {
+            return Err(FastQFileError::InvalidQualityLetter);
+        }
+
+        Ok(())
+    }
+
+    /// The mean Phred score of the read, 0 for a read with no bases.
+    pub fn mean_quality(&self) -> f64 {
+        if self.qualities.is_empty() {
+            return 0.0;
+        }
+        let total: u64 = self
+            .qualities
+            .iter()
+            .map(|q| u64::from(q.saturating_sub(STORED_QUALITY_SHIFT)))
+            .sum();
+        total as f64 / self.qualities.len() as f64
+    }
+
+    /// The fraction of the bases that are G or C, in either case. Ns are not counted as bases, a
+    /// read with no other bases has a GC content of 0.
+    pub fn gc_content(&self) -> f64 {
+        let bases = self
+            .letters
+            .iter()
+            .filter(|n| !n.eq_ignore_ascii_case(&b'n'))
+            .count();
+        if bases == 0 {
+            return 0.0;
+        }
+        let gc = self
+            .letters
+            .iter()
+            .filter(|n| matches!(n, b'g' | b'G' | b'c' | b'C'))
+            .count();
+        gc as f64 / bases as f64
+    }
+
+    /// The read name without the mate, the first word of the title with any `/1` `/2` suffix
+    /// removed. Both mates of a pair have the same fragment name.
+    pub fn fragment_name(&self) -> &str {
+        let name = self.title.split(char::is_whitespace).next().unwrap_or("");
+        name.strip_suffix("/1")
+            .or_else(|| name.strip_suffix("/2"))
+            .unwrap_or(name)
+    }
+
+    /// The mate number from the title, either from a `/1` `/2` suffix or from a Casava 1.8 style
+    /// ` 1:N:...` comment.
+    pub fn mate(&self) -> Option<u8> {
+        let mut parts = self.title.splitn(2, char::is_whitespace);
+        let name = parts.next()?;
+
+        if let Some([m @ (b'1' | b'2'), b':', ..]) = parts.next().map(|c| c.as_bytes()) {
+            return Some(m - b'0');
+        }
+
+        match name.as_bytes() {
+            [.., b'/', m @ (b'1' | b'2')] => Some(m - b'0'),
+            _ => None,
+        }
     }
 }
 
@@ -55,16 +192,14 @@ pub enum FastQFileError {
         source: std::io::Error,
     },
     #[error("Did not find title (titles should start with '@')")]
-    NoTitleLine { line: u32 },
+    NoTitleLine { line: u64 },
     #[error("Did not find expected line starting with '+'")]
     NoDescriptionLine,
-    #[error("Found + line with extra text. This is not handled by pare.")]
-    SubTitleFound,
     #[error("The quality sequence has unexpected characters")]
     InvalidQualityLetter,
     #[error("The nucleotide sequence and the quality sequence are different lengths")]
     MismatchedSequenceLength,
-    #[error("Found nucleotide {c} that is not |ATCGNatcgn|")]
+    #[error("Found nucleotide {c} that is not ACGTN or an IUPAC ambiguity code")]
     InvalidNucleotideLetter { c: char },
     #[error("EOF caused Incomplete record")]
     IncompleteRecord,
@@ -72,33 +207,248 @@ pub enum FastQFileError {
     FastATitleLine,
     #[error("Missing read from pair, file truncated")]
     MissingPairedRead,
+    #[error("The first file looks like R2 (found read {title}), try swapping the input files")]
+    SwappedMates { title: String },
+    #[error("Paired reads {r1} and {r2} are not from the same fragment")]
+    MismatchedPairNames { r1: String, r2: String },
+    #[error("Read name {name} is too long to be written to BAM")]
+    NameTooLong { name: String },
+    #[error("{source}, in the record starting at line {line}")]
+    AtLine {
+        line: u64,
+        source: Box<FastQFileError>,
+    },
+    #[cfg(feature = "bam")]
+    #[error("Error writing BAM file")]
+    Bam {
+        #[from]
+        source: rust_htslib::errors::Error,
+    },
+}
+
+impl FastQFileError {
+    /// The error without the line of the record it was found in.
+    pub fn cause(&self) -> &FastQFileError {
+        match self {
+            FastQFileError::AtLine { source, .. } => source.cause(),
+            _ => self,
+        }
+    }
+}
+
+/// What to do with a read containing a letter that isn't a nucleotide.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, ValueEnum)]
+pub enum InvalidBasePolicy {
+    /// stop with an error
+    #[default]
+    Fail,
+    /// replace the letter with an N
+    N,
+    /// drop the read, and its mate
+    Skip,
 }
 
-fn nuc_string_to_vec(letters: &str) -> Result<Vec<u8>, FastQFileError> {
-    let mut ret: Vec<u8> = Vec::with_capacity(letters.len());
+impl InvalidBasePolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InvalidBasePolicy::Fail => "fail",
+            InvalidBasePolicy::N => "n",
+            InvalidBasePolicy::Skip => "skip",
+        }
+    }
+}
+
+/// Counts of the lossy fixes made while reading damaged input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SalvageCounts {
+    /// records whose quality line was padded or truncated to fit the sequence
+    pub repaired_records: u64,
+    /// unknown letters replaced with an N
+    pub replaced_bases: u64,
+    /// reads dropped because of an unknown letter
+    pub skipped_reads: u64,
+}
+
+impl std::ops::Add for SalvageCounts {
+    type Output = SalvageCounts;
+
+    fn add(self, other: SalvageCounts) -> SalvageCounts {
+        SalvageCounts {
+            repaired_records: self.repaired_records + other.repaired_records,
+            replaced_bases: self.replaced_bases + other.replaced_bases,
+            skipped_reads: self.skipped_reads + other.skipped_reads,
+        }
+    }
+}
+
+/// Store `letters` in `ret`, false when the read is to be skipped.
+fn nuc_string_to_vec(
+    letters: &str,
+    alphabet: Alphabet,
+    policy: InvalidBasePolicy,
+    replaced: &mut u64,
+    permissive: bool,
+    ret: &mut Vec<u8>,
+    other_letters: &mut Vec<(usize, u8)>,
+) -> Result<bool, FastQFileError> {
+    ret.clear();
+    other_letters.clear();
     for n in letters.chars() {
-        match n {
-            'n' | 'N' => ret.push(b'n'),
-            'a' | 'A' => ret.push(b'a'),
-            't' | 'T' => ret.push(b't'),
-            'c' | 'C' => ret.push(b'c'),
-            'g' | 'G' => ret.push(b'g'),
-            _ => return Err(FastQFileError::InvalidNucleotideLetter { c: n }),
+        match (n, policy) {
+            ('n' | 'N', _) => ret.push(b'n'),
+            ('.' | '-', _) if permissive => {
+                other_letters.push((ret.len(), n as u8));
+                ret.push(b'n');
+            }
+            ('a' | 'A', _) => ret.push(b'a'),
+            ('t' | 'T', _) if alphabet == Alphabet::Dna => ret.push(b't'),
+            ('u' | 'U', _) if alphabet == Alphabet::Rna => ret.push(b't'),
+            ('c' | 'C', _) => ret.push(b'c'),
+            ('g' | 'G', _) => ret.push(b'g'),
+            // IUPAC ambiguity codes
+            (
+                'r' | 'R' | 'y' | 'Y' | 's' | 'S' | 'w' | 'W' | 'k' | 'K' | 'm' | 'M' | 'b' | 'B'
+                | 'd' | 'D' | 'h' | 'H' | 'v' | 'V',
+                _,
+            ) => ret.push(n.to_ascii_lowercase() as u8),
+            (_, InvalidBasePolicy::Fail) => {
+                return Err(FastQFileError::InvalidNucleotideLetter { c: n })
+            }
+            (_, InvalidBasePolicy::N) => {
+                ret.push(b'n');
+                *replaced += 1;
+            }
+            (_, InvalidBasePolicy::Skip) => {
+                ret.clear();
+                other_letters.clear();
+                return Ok(false);
+            }
         }
     }
 
-    Ok(ret)
+    Ok(true)
+}
+
+/// The case of each letter, left empty when there are no lower case letters.
+fn case_mask(letters: &str, mask: &mut Vec<bool>) {
+    mask.clear();
+    if letters.bytes().any(|b| b.is_ascii_lowercase()) {
+        mask.extend(letters.chars().map(|c| c.is_ascii_lowercase()));
+    }
+}
+
+/// Remove the whitespace at the end of `line` and its first `prefix` bytes, without reallocating.
+fn trim_line(line: &mut String, prefix: usize) {
+    line.truncate(line.trim_end().len());
+    line.drain(..prefix.min(line.len()));
 }
 
 /// Fastq file things
 pub trait FastQFileReaderTrait {
     fn read_next(&mut self, buf: &mut FastQRead) -> Result<bool, FastQFileError>;
+
+    /// The line ending of the file, as seen on the first record.
+    fn line_ending(&self) -> LineEnding {
+        LineEnding::Lf
+    }
+
+    /// The lossy fixes made so far.
+    fn salvage(&self) -> SalvageCounts {
+        SalvageCounts::default()
+    }
+
+    /// The records read so far, counting the ones skipped for their `InvalidBasePolicy`. A skipped
+    /// read is never returned, its mates are matched up by this count and dropped with it.
+    fn records(&self) -> u64;
+
+    /// The reads left in the file, a fresh `FastQRead` for each. `read_next` reuses its buffer and
+    /// is quicker.
+    fn iter(&mut self) -> FastQReads<&mut Self>
+    where
+        Self: Sized,
+    {
+        FastQReads::new(self)
+    }
+}
+
+impl<T: FastQFileReaderTrait + ?Sized> FastQFileReaderTrait for &mut T {
+    fn read_next(&mut self, buf: &mut FastQRead) -> Result<bool, FastQFileError> {
+        (**self).read_next(buf)
+    }
+
+    fn line_ending(&self) -> LineEnding {
+        (**self).line_ending()
+    }
+
+    fn salvage(&self) -> SalvageCounts {
+        (**self).salvage()
+    }
+
+    fn records(&self) -> u64 {
+        (**self).records()
+    }
+}
+
+/// Iterator over the reads of a file. Iteration stops after the first error.
+pub struct FastQReads<R: FastQFileReaderTrait> {
+    reader: R,
+    done: bool,
+}
+
+impl<R: FastQFileReaderTrait> FastQReads<R> {
+    pub fn new(reader: R) -> Self {
+        FastQReads {
+            reader,
+            done: false,
+        }
+    }
+}
+
+impl<R: FastQFileReaderTrait> Iterator for FastQReads<R> {
+    type Item = Result<FastQRead, FastQFileError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let mut read = FastQRead::default();
+        match self.reader.read_next(&mut read) {
+            Ok(true) => Some(Ok(read)),
+            Ok(false) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct FastQFileReader<R: Read> {
     stream: BufReader<R>,
-    line: u32,
+    line: u64,
+    /// The line of the title of the record being read.
+    record_line: u64,
+    repair_lengths: bool,
+    invalid_bases: InvalidBasePolicy,
+    permissive: bool,
+    alphabet: Alphabet,
+    salvage: SalvageCounts,
+    records: u64,
+    skipped: bool,
+    line_ending: Option<LineEnding>,
+    phred: PhredOffset,
+    // kept between records so their allocations are reused
+    title: String,
+    nucleotides: String,
+    quality_letters: String,
+    quality_line: String,
+    /// A line read past the end of a short record when repairing lengths, the start of the next.
+    lookahead: Option<String>,
 }
 
 impl<R: Read> FastQFileReader<R> {
@@ -106,22 +456,89 @@ impl<R: Read> FastQFileReader<R> {
         FastQFileReader {
             stream: stream,
             line: 0,
+            record_line: 0,
+            repair_lengths: false,
+            invalid_bases: InvalidBasePolicy::default(),
+            permissive: false,
+            alphabet: Alphabet::default(),
+            salvage: SalvageCounts::default(),
+            records: 0,
+            skipped: false,
+            line_ending: None,
+            phred: PhredOffset::default(),
+            title: String::new(),
+            nucleotides: String::new(),
+            quality_letters: String::new(),
+            quality_line: String::new(),
+            lookahead: None,
         }
     }
-}
 
-impl<R: Read> FastQFileReaderTrait for FastQFileReader<R> {
-    fn read_next(&mut self, buf: &mut FastQRead) -> Result<bool, FastQFileError> {
-        let mut title = String::new();
-        let mut nucleotides = String::new();
-        let mut sub_title = String::new();
-        let mut quality_letters = String::new();
+    /// Pad or truncate quality lines to the length of the sequence instead of failing with
+    /// `MismatchedSequenceLength`. This is lossy and only intended for salvaging damaged files.
+    pub fn with_repair_lengths(mut self) -> Self {
+        self.repair_lengths = true;
+        self
+    }
+
+    pub fn with_invalid_base_policy(mut self, policy: InvalidBasePolicy) -> Self {
+        self.invalid_bases = policy;
+        self
+    }
+
+    /// Accept `.` and `-` as bases, read as an n and kept in `FastQRead::other_letters` so they can
+    /// be written back. Without this they are invalid bases.
+    pub fn with_permissive(mut self, permissive: bool) -> Self {
+        self.permissive = permissive;
+        self
+    }
 
+    /// Read U rather than T. Files are either DNA or RNA, a T in an RNA file is an invalid base.
+    pub fn with_alphabet(mut self, alphabet: Alphabet) -> Self {
+        self.alphabet = alphabet;
+        self
+    }
+
+    /// Read Phred+64 qualities, they are shifted to Phred+33 as they're read.
+    pub fn with_phred_offset(mut self, phred: PhredOffset) -> Self {
+        self.phred = phred;
+        self
+    }
+
+    /// The lines read so far, the line number of the next one counting from 1 is one more.
+    pub fn line(&self) -> u64 {
+        self.line
+    }
+
+    /// Parse the next record into `buf`, reusing the allocations of its fields.
+    fn read_record(&mut self, buf: &mut FastQRead) -> Result<bool, FastQFileError> {
+        // the title is swapped in at the end, so `buf` is left as it was at the end of the file
+        let title = &mut self.title;
+        let nucleotides = &mut self.nucleotides;
+        let quality_letters = &mut self.quality_letters;
+        nucleotides.clear();
+        quality_letters.clear();
+
+        let mut title_line;
         loop {
-            if self.stream.read_line(&mut title)? == 0 {
+            title.clear();
+            let read = match self.lookahead.take() {
+                Some(line) => {
+                    // it was counted when it was read
+                    self.line -= 1;
+                    title.push_str(&line);
+                    line.len()
+                }
+                None => self.stream.read_line(title)?,
+            };
+            self.record_line = self.line;
+            if read == 0 {
                 return Ok(false);
             }
 
+            title_line = self.line;
+            self.line += 1;
+
             // ignore blank lines
             if !title.trim_end().is_empty() {
                 break;
@@ -132,52 +549,168 @@ impl<R: Read> FastQFileReaderTrait for FastQFileReader<R> {
             if title.starts_with(">") {
                 return Err(FastQFileError::FastATitleLine);
             }
-            return Err(FastQFileError::NoTitleLine { line: self.line });
+            return Err(FastQFileError::NoTitleLine { line: title_line });
         }
-        title = title[1..].trim_end().to_string();
-
-        if self.stream.read_line(&mut nucleotides)? == 0 {
-            return Err(FastQFileError::IncompleteRecord);
+        if self.line_ending.is_none() {
+            self.line_ending = Some(match title.ends_with("\r\n") {
+                true => LineEnding::Crlf,
+                false => LineEnding::Lf,
+            });
         }
+        trim_line(title, 1);
+
+        let sub_title = &mut buf.sub_title;
+        sub_title.clear();
+        // the sequence may be wrapped over several lines, it runs up to the + line. Anything but
+        // letters after the first line means the + line is missing rather than the sequence wrapped.
+        // In permissive mode . and - are letters too.
+        let permissive = self.permissive;
+        let is_letter = |c: u8| c.is_ascii_alphabetic() || (permissive && (c == b'.' || c == b'-'));
+        let mut first = true;
+        loop {
+            if self.stream.read_line(sub_title)? == 0 {
+                return Err(FastQFileError::IncompleteRecord);
+            }
+            self.line += 1;
 
-        nucleotides = nucleotides.trim_end().to_string();
-        let letters: Vec<u8> = nuc_string_to_vec(&nucleotides)?;
-        if self.stream.read_line(&mut sub_title)? == 0 {
-            return Err(FastQFileError::IncompleteRecord);
-        }
+            if sub_title.starts_with('+') {
+                break;
+            }
 
-        if !sub_title.starts_with("+") {
-            return Err(FastQFileError::NoDescriptionLine);
+            let line = sub_title.trim_end();
+            if !first && !line.bytes().all(is_letter) {
+                return Err(FastQFileError::NoDescriptionLine);
+            }
+            nucleotides.push_str(line);
+            sub_title.clear();
+            first = false;
         }
 
-        if sub_title.trim_end().len() > 1 {
-            return Err(FastQFileError::SubTitleFound);
-        }
+        // bytes outside of ASCII are a single letter each, replaced by one N or rejected
+        let sequence_length = nucleotides.chars().count();
+        let kept = nuc_string_to_vec(
+            nucleotides,
+            self.alphabet,
+            self.invalid_bases,
+            &mut self.salvage.replaced_bases,
+            self.permissive,
+            &mut buf.letters,
+            &mut buf.other_letters,
+        )?;
+
+        trim_line(sub_title, 1);
+
+        // wrapped qualities are collected until there are as many as there are bases. They can
+        // start with @ so a line is only a title once the record is complete. A damaged record
+        // may be short of qualities, so when repairing lengths a later line starting with @ is
+        // the next title unless it is exactly as long as the qualities still missing.
+        let line = &mut self.quality_line;
+        let mut first = true;
+        loop {
+            line.clear();
+            if self.stream.read_line(line)? == 0 {
+                if first {
+                    return Err(FastQFileError::IncompleteRecord);
+                }
+                break;
+            }
+            self.line += 1;
+
+            let letters = line.trim_end();
+            if self.repair_lengths
+                && !first
+                && (letters.is_empty()
+                    || letters.starts_with('@')
+                        && quality_letters.len() + letters.len() != sequence_length)
+            {
+                self.lookahead = Some(line.clone());
+                break;
+            }
+            first = false;
 
-        if self.stream.read_line(&mut quality_letters)? == 0 {
-            return Err(FastQFileError::IncompleteRecord);
+            quality_letters.push_str(letters);
+            if quality_letters.len() >= sequence_length {
+                break;
+            }
         }
 
-        quality_letters = quality_letters.trim_end().to_string();
         if quality_letters.bytes().any(|c| !c.is_ascii_graphic()) {
             return Err(FastQFileError::InvalidQualityLetter);
         }
 
-        if nucleotides.len() != quality_letters.len() {
-            return Err(FastQFileError::MismatchedSequenceLength);
+        if sequence_length != quality_letters.len() {
+            if !self.repair_lengths {
+                return Err(FastQFileError::MismatchedSequenceLength);
+            }
+
+            // padded with a quality of zero, the letters are all ASCII so this is on a boundary
+            let pad = self.phred.offset() as char;
+            quality_letters.truncate(sequence_length);
+            while quality_letters.len() < sequence_length {
+                quality_letters.push(pad);
+            }
+            self.salvage.repaired_records += 1;
+        }
+        // the letters are all graphic so at least '!', but a Phred+64 file can still have letters
+        // below its offset
+        let shift = self.phred.offset() - 1;
+        buf.qualities.clear();
+        for v in quality_letters.bytes() {
+            buf.qualities.push(
+                v.checked_sub(shift)
+                    .ok_or(FastQFileError::InvalidQualityLetter)?,
+            );
         }
-        let qualities: Vec<u8> = quality_letters.bytes().map(|v| v - 32).collect();
 
-        *buf = FastQRead {
-            letters: letters,
-            qualities: qualities,
-            title: title,
-        };
-        self.line += 4;
+        self.records += 1;
+        self.skipped = !kept;
+        if self.skipped {
+            self.salvage.skipped_reads += 1;
+        }
+
+        case_mask(nucleotides, &mut buf.case_mask);
+        std::mem::swap(title, &mut buf.title);
         return Ok(true);
     }
 }
 
+impl<R: Read> IntoIterator for FastQFileReader<R> {
+    type Item = Result<FastQRead, FastQFileError>;
+    type IntoIter = FastQReads<Self>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        FastQReads::new(self)
+    }
+}
+
+impl<R: Read> FastQFileReaderTrait for FastQFileReader<R> {
+    fn read_next(&mut self, buf: &mut FastQRead) -> Result<bool, FastQFileError> {
+        loop {
+            let more = self
+                .read_record(buf)
+                .map_err(|source| FastQFileError::AtLine {
+                    line: self.record_line + 1,
+                    source: Box::new(source),
+                })?;
+            if !more || !self.skipped {
+                return Ok(more);
+            }
+        }
+    }
+
+    fn salvage(&self) -> SalvageCounts {
+        self.salvage
+    }
+
+    fn line_ending(&self) -> LineEnding {
+        self.line_ending.unwrap_or_default()
+    }
+
+    fn records(&self) -> u64 {
+        self.records
+    }
+}
+
 impl FastQFileReader<File> {
     pub fn open<P: AsRef<Path>>(path: &P) -> Result<Self, std::io::Error> {
         let file = File::open(path)?;
@@ -191,18 +724,379 @@ impl FastQFileReader<std::io::Stdin> {
     }
 }
 
+/// Which letter the file uses for the fourth nucleotide. RNA reads are stored with T internally
+/// and written back out with U.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, ValueEnum)]
+pub enum Alphabet {
+    /// ACGTN
+    #[default]
+    Dna,
+    /// ACGUN
+    Rna,
+}
+
+impl Alphabet {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Alphabet::Dna => "dna",
+            Alphabet::Rna => "rna",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "dna" => Some(Alphabet::Dna),
+            "rna" => Some(Alphabet::Rna),
+            _ => None,
+        }
+    }
+}
+
+/// How the bases are cased when they are written out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, ValueEnum)]
+pub enum LetterCase {
+    /// as they were read, reads that don't know their case are written upper case
+    #[default]
+    Preserve,
+    /// every base upper case
+    Upper,
+    /// every base lower case
+    Lower,
+}
+
+/// The ASCII value of a quality score of zero. Qualities are kept as Phred+33 whatever the file
+/// used, Phred+64 files from old Illumina pipelines are shifted on the way in and back on the way
+/// out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, ValueEnum)]
+pub enum PhredOffset {
+    #[default]
+    #[value(name = "33")]
+    Phred33,
+    #[value(name = "64")]
+    Phred64,
+}
+
+impl PhredOffset {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PhredOffset::Phred33 => "33",
+            PhredOffset::Phred64 => "64",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "33" => Some(PhredOffset::Phred33),
+            "64" => Some(PhredOffset::Phred64),
+            _ => None,
+        }
+    }
+
+    pub fn offset(&self) -> u8 {
+        match self {
+            PhredOffset::Phred33 => 33,
+            PhredOffset::Phred64 => 64,
+        }
+    }
+}
+
+/// Reads `detect_phred_offset` should look at.
+pub const PHRED_DETECTION_READS: usize = 10_000;
+
+/// Guess the offset of a file from the qualities of its first `reads` reads, `reader` has to be
+/// reading them as Phred+33. A letter below ';' can only be Phred+33, and when every letter is '@'
+/// or above the file is most likely Phred+64. Anything else is taken to be the usual Phred+33.
+pub fn detect_phred_offset(
+    reader: &mut dyn FastQFileReaderTrait,
+    reads: usize,
+) -> Result<PhredOffset, FastQFileError> {
+    let mut buf = FastQRead::default();
+    let mut lowest = u8::MAX;
+    for _ in 0..reads {
+        if !reader.read_next(&mut buf)? {
+            break;
+        }
+        // back to the letters as they are in the file
+        if let Some(letter) = buf.qualities.iter().map(|q| q + 32).min() {
+            lowest = lowest.min(letter);
+        }
+        if lowest < b';' {
+            return Ok(PhredOffset::Phred33);
+        }
+    }
+
+    match lowest {
+        u8::MAX => Ok(PhredOffset::Phred33),
+        letter if letter >= b'@' => Ok(PhredOffset::Phred64),
+        _ => Ok(PhredOffset::Phred33),
+    }
+}
+
+/// How the lines of a fastq file end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, ValueEnum)]
+pub enum LineEnding {
+    /// \n
+    #[default]
+    Lf,
+    /// \r\n, from Windows
+    Crlf,
+}
+
+impl LineEnding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "lf",
+            LineEnding::Crlf => "crlf",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "lf" => Some(LineEnding::Lf),
+            "crlf" => Some(LineEnding::Crlf),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &'static [u8] {
+        match self {
+            LineEnding::Lf => b"\n",
+            LineEnding::Crlf => b"\r\n",
+        }
+    }
+}
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// A stream as read from the input, decompressed when it started with the gzip magic.
+pub enum Decompressed<R: Read> {
+    Plain(PeekReader<R>),
+    Gzip(MultiGzDecoder<PeekReader<R>>),
+    Bgzf(BgzfReader<PeekReader<R>>),
+}
+
+impl<R: Read> Read for Decompressed<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Decompressed::Plain(stream) => stream.read(buf),
+            Decompressed::Gzip(stream) => stream.read(buf),
+            Decompressed::Bgzf(stream) => stream.read(buf),
+        }
+    }
+}
+
+/// Wrap `source` in a BGZF decoder if it starts with a BGZF block, in a gzip one when it only
+/// starts with the gzip magic, e.g. a member without the `BC` field. The peeked bytes are put
+/// back in front of the stream so this works on pipes that can't seek.
+pub fn maybe_decompress<R: Read>(source: R) -> Result<Decompressed<R>, std::io::Error> {
+    let stream = PeekReader::new(source, BGZF_HEADER_LEN)?;
+    if is_bgzf(stream.peeked()) {
+        Ok(Decompressed::Bgzf(BgzfReader::new(stream)))
+    } else if stream.peeked().starts_with(&GZIP_MAGIC) {
+        Ok(Decompressed::Gzip(MultiGzDecoder::new(stream)))
+    } else {
+        Ok(Decompressed::Plain(stream))
+    }
+}
+
+impl<R: Read> FastQFileReader<Decompressed<R>> {
+    /// The BGZF virtual offset of the next byte to be read, the start of the next record unless a
+    /// line was read ahead to repair lengths. `None` when the input isn't BGZF.
+    pub fn virtual_position(&self) -> Option<u64> {
+        match self.stream.get_ref() {
+            Decompressed::Bgzf(stream) => {
+                Some(stream.virtual_position_before(self.stream.buffer().len()))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl FastQFileReader<Decompressed<File>> {
+    /// Open a fastq file that may or may not be gzipped.
+    pub fn open_maybe_compressed<P: AsRef<Path>>(path: &P) -> Result<Self, std::io::Error> {
+        let file = File::open(path)?;
+        Ok(FastQFileReader::new(BufReader::new(maybe_decompress(
+            file,
+        )?)))
+    }
+}
+
+impl FastQFileReader<Decompressed<std::io::Stdin>> {
+    /// Read fastq that may or may not be gzipped from stdin.
+    pub fn from_stdin_maybe_compressed() -> Result<Self, std::io::Error> {
+        Ok(FastQFileReader::new(BufReader::new(maybe_decompress(
+            std::io::stdin(),
+        )?)))
+    }
+}
+
 pub trait PairedFastQReader {
     fn read_next(
         &mut self,
         buf_r1: &mut FastQRead,
         buf_r2: &mut FastQRead,
     ) -> Result<bool, FastQFileError>;
+
+    /// The lossy fixes made so far.
+    fn salvage(&self) -> SalvageCounts {
+        SalvageCounts::default()
+    }
+
+    /// The line ending of the input, see `FastQFileReaderTrait::line_ending`.
+    fn line_ending(&self) -> LineEnding {
+        LineEnding::Lf
+    }
+
+    /// The pairs left in the input, see `FastQFileReaderTrait::iter`.
+    fn iter(&mut self) -> ReadPairs<&mut Self>
+    where
+        Self: Sized,
+    {
+        ReadPairs::new(self)
+    }
+
+    /// Read every pair left without keeping them, to check the input is well formed. Returns how
+    /// many records were read.
+    fn check(&mut self) -> Result<u64, FastQFileError> {
+        let mut r1 = FastQRead::default();
+        let mut r2 = FastQRead::default();
+        let mut records = 0;
+        while self.read_next(&mut r1, &mut r2)? {
+            records += 2;
+        }
+        Ok(records)
+    }
+}
+
+impl<T: PairedFastQReader + ?Sized> PairedFastQReader for &mut T {
+    fn read_next(
+        &mut self,
+        buf_r1: &mut FastQRead,
+        buf_r2: &mut FastQRead,
+    ) -> Result<bool, FastQFileError> {
+        (**self).read_next(buf_r1, buf_r2)
+    }
+
+    fn salvage(&self) -> SalvageCounts {
+        (**self).salvage()
+    }
+
+    fn line_ending(&self) -> LineEnding {
+        (**self).line_ending()
+    }
+}
+
+/// Iterator over the read pairs of an input. Iteration stops after the first error.
+pub struct ReadPairs<R: PairedFastQReader> {
+    reader: R,
+    done: bool,
+}
+
+impl<R: PairedFastQReader> ReadPairs<R> {
+    pub fn new(reader: R) -> Self {
+        ReadPairs {
+            reader,
+            done: false,
+        }
+    }
+}
+
+impl<R: PairedFastQReader> Iterator for ReadPairs<R> {
+    type Item = Result<(FastQRead, FastQRead), FastQFileError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let mut r1 = FastQRead::default();
+        let mut r2 = FastQRead::default();
+        match self.reader.read_next(&mut r1, &mut r2) {
+            Ok(true) => Some(Ok((r1, r2))),
+            Ok(false) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// How many pairs are inspected when checking the mate order of paired files.
+const MATE_ORDER_CHECK_READS: u32 = 8;
+
+/// Read the next record kept by every one of `readers` into the buffer of the same index. A read
+/// skipped by one reader drops the reads of the same record in the others. False once every file
+/// has ended, or has only skipped reads left, a missing paired read when one ends before the others.
+fn read_matching_records<R, B>(readers: &mut [R], bufs: &mut [B]) -> Result<bool, FastQFileError>
+where
+    R: DerefMut<Target = dyn FastQFileReaderTrait>,
+    B: BorrowMut<FastQRead>,
+{
+    // the readers are all at the last record returned, or at the end of their file
+    let mut record = readers
+        .iter()
+        .map(|reader| reader.records())
+        .max()
+        .unwrap_or(0)
+        + 1;
+    'fragment: loop {
+        for (index, (reader, buf)) in readers.iter_mut().zip(bufs.iter_mut()).enumerate() {
+            while reader.records() < record {
+                if !reader.read_next(buf.borrow_mut())? {
+                    return end_of_records(readers, bufs, index);
+                }
+            }
+            if reader.records() > record {
+                // this reader skipped past the record, the others catch up with it
+                record = reader.records();
+                continue 'fragment;
+            }
+        }
+        return Ok(true);
+    }
+}
+
+/// The reader at `ended` ran out of records, the others have to run out at the same record. Their
+/// records past it are only allowed when they would be skipped anyway.
+fn end_of_records<R, B>(
+    readers: &mut [R],
+    bufs: &mut [B],
+    ended: usize,
+) -> Result<bool, FastQFileError>
+where
+    R: DerefMut<Target = dyn FastQFileReaderTrait>,
+    B: BorrowMut<FastQRead>,
+{
+    let last = readers[ended].records();
+    for (index, (reader, buf)) in readers.iter_mut().zip(bufs.iter_mut()).enumerate() {
+        if index == ended {
+            continue;
+        }
+        // the readers before `ended` already hold the record it is missing
+        if reader.records() > last {
+            return Err(FastQFileError::MissingPairedRead);
+        }
+        while reader.read_next(buf.borrow_mut())? {
+            if reader.records() > last {
+                return Err(FastQFileError::MissingPairedRead);
+            }
+        }
+    }
+    Ok(false)
 }
 
 pub struct FastQPairedFilesReader {
     r1_reader: Box<dyn FastQFileReaderTrait>,
     r2_reader: Box<dyn FastQFileReaderTrait>,
     reverse_complement_r2_nucleotides: bool,
+    mate_order_checks_left: u32,
+    validate_names: bool,
+    pairs: u64,
 }
 
 impl FastQPairedFilesReader {
@@ -210,13 +1104,63 @@ impl FastQPairedFilesReader {
         stream_r1: Box<dyn FastQFileReaderTrait>,
         stream_r2: Box<dyn FastQFileReaderTrait>,
         reverse_complement_r2_nucleotides: bool,
+    ) -> Self {
+        FastQPairedFilesReader::new_with_validation(
+            stream_r1,
+            stream_r2,
+            reverse_complement_r2_nucleotides,
+            false,
+        )
+    }
+
+    /// When `validate` is set every pair is checked to have the same fragment name, so a
+    /// truncated or misordered file fails instead of pairing up unrelated reads.
+    pub fn new_with_validation(
+        stream_r1: Box<dyn FastQFileReaderTrait>,
+        stream_r2: Box<dyn FastQFileReaderTrait>,
+        reverse_complement_r2_nucleotides: bool,
+        validate: bool,
     ) -> Self {
         FastQPairedFilesReader {
             r1_reader: stream_r1,
             r2_reader: stream_r2,
-            reverse_complement_r2_nucleotides: reverse_complement_r2_nucleotides,
+            reverse_complement_r2_nucleotides,
+            mate_order_checks_left: 0,
+            validate_names: validate,
+            pairs: 0,
         }
     }
+
+    /// Like `new_with_validation` but each file is parsed on its own thread, so reading R1 and R2
+    /// overlap. The pairs come out in the same order.
+    pub fn new_threaded(
+        stream_r1: Box<dyn FastQFileReaderTrait + Send>,
+        stream_r2: Box<dyn FastQFileReaderTrait + Send>,
+        reverse_complement_r2_nucleotides: bool,
+        validate: bool,
+    ) -> Self {
+        FastQPairedFilesReader::new_with_validation(
+            Box::new(ThreadedFastQFileReader::new(stream_r1)),
+            Box::new(ThreadedFastQFileReader::new(stream_r2)),
+            reverse_complement_r2_nucleotides,
+            validate,
+        )
+    }
+
+    /// Fail if the first few reads of the R1 file are marked as mate 2.
+    pub fn with_mate_order_check(mut self) -> Self {
+        self.mate_order_checks_left = MATE_ORDER_CHECK_READS;
+        self
+    }
+}
+
+impl IntoIterator for FastQPairedFilesReader {
+    type Item = Result<(FastQRead, FastQRead), FastQFileError>;
+    type IntoIter = ReadPairs<Self>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        ReadPairs::new(self)
+    }
 }
 
 impl PairedFastQReader for FastQPairedFilesReader {
@@ -225,25 +1169,179 @@ impl PairedFastQReader for FastQPairedFilesReader {
         buf_r1: &mut FastQRead,
         buf_r2: &mut FastQRead,
     ) -> Result<bool, FastQFileError> {
-        if !self.r1_reader.read_next(buf_r1)? {
+        if !read_matching_records(
+            &mut [&mut *self.r1_reader, &mut *self.r2_reader],
+            &mut [&mut *buf_r1, &mut *buf_r2],
+        )? {
             return Ok(false);
         }
+        self.pairs += 1;
 
-        if !self.r2_reader.read_next(buf_r2)? {
-            return Err(FastQFileError::MissingPairedRead);
+        if self.validate_names && buf_r1.fragment_name() != buf_r2.fragment_name() {
+            return Err(FastQFileError::MismatchedPairNames {
+                r1: buf_r1.title.clone(),
+                r2: buf_r2.title.clone(),
+            });
+        }
+
+        if self.mate_order_checks_left > 0 {
+            self.mate_order_checks_left -= 1;
+            if buf_r1.mate() == Some(2) {
+                return Err(FastQFileError::SwappedMates {
+                    title: buf_r1.title.clone(),
+                });
+            }
         }
 
         if self.reverse_complement_r2_nucleotides {
-            buf_r2.reverse_complement_nucleotides();
+            buf_r2.reverse_complement_nucleotides()?;
         }
 
         Ok(true)
     }
+
+    fn salvage(&self) -> SalvageCounts {
+        let records = self.r1_reader.records().max(self.r2_reader.records());
+        SalvageCounts {
+            skipped_reads: 2 * (records - self.pairs),
+            ..self.r1_reader.salvage() + self.r2_reader.salvage()
+        }
+    }
+
+    fn line_ending(&self) -> LineEnding {
+        self.r1_reader.line_ending()
+    }
+}
+
+/// Reads handed over from a reader thread at a time.
+const THREADED_BATCH_SIZE: usize = 1024;
+/// Batches a reader thread can get ahead by.
+const THREADED_BATCHES_IN_FLIGHT: usize = 4;
+
+/// Reads parsed on the reader thread along with the record each was, and the state of the reader
+/// after the last of them.
+struct ReadBatch {
+    reads: Vec<(FastQRead, u64)>,
+    records: u64,
+    salvage: SalvageCounts,
+    line_ending: LineEnding,
+    error: Option<FastQFileError>,
+}
+
+/// Parses a fastq file on a thread of its own, ahead of the reads being asked for.
+pub struct ThreadedFastQFileReader {
+    batches: Receiver<ReadBatch>,
+    handle: Option<JoinHandle<()>>,
+    reads: std::vec::IntoIter<(FastQRead, u64)>,
+    salvage: SalvageCounts,
+    line_ending: LineEnding,
+    records: u64,
+    // the records of the reader thread after the last batch, which counts any skipped at the end
+    batch_records: u64,
+    error: Option<FastQFileError>,
+}
+
+impl ThreadedFastQFileReader {
+    pub fn new(mut reader: Box<dyn FastQFileReaderTrait + Send>) -> Self {
+        let (sender, batches) = sync_channel(THREADED_BATCHES_IN_FLIGHT);
+        let handle = thread::spawn(move || loop {
+            let mut reads = Vec::with_capacity(THREADED_BATCH_SIZE);
+            let mut error = None;
+            let mut done = false;
+            while reads.len() < THREADED_BATCH_SIZE {
+                let mut read = FastQRead::default();
+                match reader.read_next(&mut read) {
+                    Ok(true) => reads.push((read, reader.records())),
+                    Ok(false) => {
+                        done = true;
+                        break;
+                    }
+                    Err(e) => {
+                        error = Some(e);
+                        done = true;
+                        break;
+                    }
+                }
+            }
+
+            let batch = ReadBatch {
+                reads,
+                records: reader.records(),
+                salvage: reader.salvage(),
+                line_ending: reader.line_ending(),
+                error,
+            };
+            // the receiving end is gone when the reader is dropped early
+            if sender.send(batch).is_err() || done {
+                return;
+            }
+        });
+
+        ThreadedFastQFileReader {
+            batches,
+            handle: Some(handle),
+            reads: Vec::new().into_iter(),
+            salvage: SalvageCounts::default(),
+            line_ending: LineEnding::default(),
+            records: 0,
+            batch_records: 0,
+            error: None,
+        }
+    }
+}
+
+impl FastQFileReaderTrait for ThreadedFastQFileReader {
+    fn read_next(&mut self, buf: &mut FastQRead) -> Result<bool, FastQFileError> {
+        loop {
+            if let Some((read, record)) = self.reads.next() {
+                *buf = read;
+                self.records = record;
+                return Ok(true);
+            }
+            // the reads before an error are handed out first
+            if let Some(error) = self.error.take() {
+                return Err(error);
+            }
+
+            let batch = match self.batches.recv() {
+                Ok(batch) => batch,
+                Err(_) => {
+                    // the thread has finished, either after the last batch or by panicking
+                    if let Some(handle) = self.handle.take() {
+                        if let Err(panic) = handle.join() {
+                            std::panic::resume_unwind(panic);
+                        }
+                    }
+                    self.records = self.batch_records;
+                    return Ok(false);
+                }
+            };
+            self.salvage = batch.salvage;
+            self.batch_records = batch.records;
+            self.line_ending = batch.line_ending;
+            self.reads = batch.reads.into_iter();
+            self.error = batch.error;
+        }
+    }
+
+    fn salvage(&self) -> SalvageCounts {
+        self.salvage
+    }
+
+    fn records(&self) -> u64 {
+        self.records
+    }
+
+    fn line_ending(&self) -> LineEnding {
+        self.line_ending
+    }
 }
 
 pub struct FastQInterleavedFileReader {
     reader: Box<dyn FastQFileReaderTrait>,
     reverse_complement_r2_nucleotides: bool,
+    mate_order_checks_left: u32,
+    pairs: u64,
 }
 
 impl FastQInterleavedFileReader {
@@ -254,8 +1352,25 @@ impl FastQInterleavedFileReader {
         FastQInterleavedFileReader {
             reader: stream,
             reverse_complement_r2_nucleotides: reverse_complement_r2_nucleotides,
+            mate_order_checks_left: 0,
+            pairs: 0,
         }
     }
+
+    /// Fail if the first few reads taken as R1 are marked as mate 2.
+    pub fn with_mate_order_check(mut self) -> Self {
+        self.mate_order_checks_left = MATE_ORDER_CHECK_READS;
+        self
+    }
+}
+
+impl IntoIterator for FastQInterleavedFileReader {
+    type Item = Result<(FastQRead, FastQRead), FastQFileError>;
+    type IntoIter = ReadPairs<Self>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        ReadPairs::new(self)
+    }
 }
 
 impl PairedFastQReader for FastQInterleavedFileReader {
@@ -264,20 +1379,236 @@ impl PairedFastQReader for FastQInterleavedFileReader {
         buf_r1: &mut FastQRead,
         buf_r2: &mut FastQRead,
     ) -> Result<bool, FastQFileError> {
-        if !self.reader.read_next(buf_r1)? {
+        // R1 is an odd record and R2 the even one after it, a skipped read drops its mate
+        let mut r1_record = 0;
+        loop {
+            if r1_record == 0 {
+                if !self.reader.read_next(buf_r1)? {
+                    // the last record is an R1, kept or skipped, without an R2
+                    if self.reader.records() % 2 == 1 {
+                        return Err(FastQFileError::MissingPairedRead);
+                    }
+                    return Ok(false);
+                }
+                if self.reader.records() % 2 == 1 {
+                    r1_record = self.reader.records();
+                }
+                continue;
+            }
+
+            if !self.reader.read_next(buf_r2)? {
+                if self.reader.records() > r1_record {
+                    return Ok(false);
+                }
+                return Err(FastQFileError::MissingPairedRead);
+            }
+            let record = self.reader.records();
+            if record == r1_record + 1 {
+                break;
+            }
+            // the R2 was skipped, this is the R1 of a later pair or an R2 that lost its R1 too
+            if record % 2 == 1 {
+                std::mem::swap(buf_r1, buf_r2);
+                r1_record = record;
+            } else {
+                r1_record = 0;
+            }
+        }
+        self.pairs += 1;
+
+        if self.mate_order_checks_left > 0 {
+            self.mate_order_checks_left -= 1;
+            if buf_r1.mate() == Some(2) {
+                return Err(FastQFileError::SwappedMates {
+                    title: buf_r1.title.clone(),
+                });
+            }
+        }
+
+        if self.reverse_complement_r2_nucleotides {
+            buf_r2.reverse_complement_nucleotides()?;
+        }
+
+        Ok(true)
+    }
+
+    fn salvage(&self) -> SalvageCounts {
+        SalvageCounts {
+            skipped_reads: 2 * (self.reader.records().div_ceil(2) - self.pairs),
+            ..self.reader.salvage()
+        }
+    }
+
+    fn line_ending(&self) -> LineEnding {
+        self.reader.line_ending()
+    }
+}
+
+/// Reads fragments sequenced as more than two reads, e.g. R1 and R2 plus the I1 and I2 index
+/// reads, one record from each file at a time. A pair is the case of two reads.
+pub trait MultiFastQReader {
+    /// The reads of every fragment, `read_next` is given this many buffers.
+    fn reads_per_fragment(&self) -> usize;
+
+    fn read_next(&mut self, bufs: &mut [FastQRead]) -> Result<bool, FastQFileError>;
+
+    /// The lossy fixes made so far.
+    fn salvage(&self) -> SalvageCounts {
+        SalvageCounts::default()
+    }
+
+    /// The line ending of the input, see `FastQFileReaderTrait::line_ending`.
+    fn line_ending(&self) -> LineEnding {
+        LineEnding::Lf
+    }
+}
+
+pub struct FastQMultiFilesReader {
+    readers: Vec<Box<dyn FastQFileReaderTrait>>,
+    reverse_complement_r2_nucleotides: bool,
+    mate_order_checks_left: u32,
+    validate_names: bool,
+    fragments: u64,
+}
+
+impl FastQMultiFilesReader {
+    /// `readers` are in the order of the reads of a fragment, R2 is the second. When `validate` is
+    /// set every read of a fragment is checked to have the same fragment name.
+    pub fn new(
+        readers: Vec<Box<dyn FastQFileReaderTrait>>,
+        reverse_complement_r2_nucleotides: bool,
+        validate: bool,
+    ) -> Self {
+        FastQMultiFilesReader {
+            readers,
+            reverse_complement_r2_nucleotides,
+            mate_order_checks_left: 0,
+            validate_names: validate,
+            fragments: 0,
+        }
+    }
+
+    /// Like `new` but each file is parsed on its own thread, see `FastQPairedFilesReader::new_threaded`.
+    pub fn new_threaded(
+        readers: Vec<Box<dyn FastQFileReaderTrait + Send>>,
+        reverse_complement_r2_nucleotides: bool,
+        validate: bool,
+    ) -> Self {
+        FastQMultiFilesReader::new(
+            readers
+                .into_iter()
+                .map(|reader| {
+                    Box::new(ThreadedFastQFileReader::new(reader)) as Box<dyn FastQFileReaderTrait>
+                })
+                .collect(),
+            reverse_complement_r2_nucleotides,
+            validate,
+        )
+    }
+
+    /// Fail if the first few reads of the first file are marked as mate 2.
+    pub fn with_mate_order_check(mut self) -> Self {
+        self.mate_order_checks_left = MATE_ORDER_CHECK_READS;
+        self
+    }
+}
+
+impl MultiFastQReader for FastQMultiFilesReader {
+    fn reads_per_fragment(&self) -> usize {
+        self.readers.len()
+    }
+
+    fn read_next(&mut self, bufs: &mut [FastQRead]) -> Result<bool, FastQFileError> {
+        debug_assert_eq!(bufs.len(), self.readers.len());
+
+        if !read_matching_records(&mut self.readers, bufs)? {
             return Ok(false);
         }
+        self.fragments += 1;
+
+        if self.validate_names {
+            let (first, rest) = bufs
+                .split_first()
+                .expect("a fragment has at least one read");
+            if let Some(read) = rest
+                .iter()
+                .find(|read| read.fragment_name() != first.fragment_name())
+            {
+                return Err(FastQFileError::MismatchedPairNames {
+                    r1: first.title.clone(),
+                    r2: read.title.clone(),
+                });
+            }
+        }
 
-        if !self.reader.read_next(buf_r2)? {
-            return Err(FastQFileError::MissingPairedRead);
+        if self.mate_order_checks_left > 0 {
+            self.mate_order_checks_left -= 1;
+            if bufs[0].mate() == Some(2) {
+                return Err(FastQFileError::SwappedMates {
+                    title: bufs[0].title.clone(),
+                });
+            }
         }
 
-        if self.reverse_complement_r2_nucleotides {
-            buf_r2.reverse_complement_nucleotides();
+        if self.reverse_complement_r2_nucleotides && bufs.len() > 1 {
+            bufs[1].reverse_complement_nucleotides()?;
         }
 
         Ok(true)
     }
+
+    fn salvage(&self) -> SalvageCounts {
+        let salvage = self
+            .readers
+            .iter()
+            .fold(SalvageCounts::default(), |total, reader| {
+                total + reader.salvage()
+            });
+        let records = self
+            .readers
+            .iter()
+            .map(|reader| reader.records())
+            .max()
+            .unwrap_or(0);
+        SalvageCounts {
+            skipped_reads: self.readers.len() as u64 * (records - self.fragments),
+            ..salvage
+        }
+    }
+
+    fn line_ending(&self) -> LineEnding {
+        self.readers[0].line_ending()
+    }
+}
+
+/// Reads single-end reads, each fragment is a single read with no mate.
+pub struct SingleEndReader {
+    reader: Box<dyn FastQFileReaderTrait>,
+}
+
+impl SingleEndReader {
+    pub fn new(reader: Box<dyn FastQFileReaderTrait>) -> Self {
+        SingleEndReader { reader }
+    }
+}
+
+impl MultiFastQReader for SingleEndReader {
+    fn reads_per_fragment(&self) -> usize {
+        1
+    }
+
+    fn read_next(&mut self, bufs: &mut [FastQRead]) -> Result<bool, FastQFileError> {
+        debug_assert_eq!(bufs.len(), 1);
+        self.reader.read_next(&mut bufs[0])
+    }
+
+    fn salvage(&self) -> SalvageCounts {
+        self.reader.salvage()
+    }
+
+    fn line_ending(&self) -> LineEnding {
+        self.reader.line_ending()
+    }
 }
 
 //writers
@@ -287,11 +1618,35 @@ pub trait FastQFileWriterTrait {
         buf: &FastQRead,
         reverse_complement: bool,
     ) -> Result<bool, FastQFileError>;
+
+    /// Write out whatever is still buffered. Dropping the writer does this too but loses any error.
+    fn finish(&mut self) -> Result<(), FastQFileError>;
+}
+
+impl<T: FastQFileWriterTrait + ?Sized> FastQFileWriterTrait for Box<T> {
+    fn write_next(
+        &mut self,
+        buf: &FastQRead,
+        reverse_complement: bool,
+    ) -> Result<bool, FastQFileError> {
+        (**self).write_next(buf, reverse_complement)
+    }
+
+    fn finish(&mut self) -> Result<(), FastQFileError> {
+        (**self).finish()
+    }
 }
 
 pub struct FastQFileWriter<W: Write> {
     stream: BufWriter<W>,
-    line: u32,
+    line: u64,
+    alphabet: Alphabet,
+    line_ending: LineEnding,
+    phred: PhredOffset,
+    quality_fill: Option<u8>,
+    case: LetterCase,
+    /// Ends the stream underneath once it has been flushed, e.g. the gzip trailer.
+    end_stream: fn(&mut W) -> std::io::Result<()>,
 }
 
 impl<W: Write> FastQFileWriter<W> {
@@ -299,8 +1654,50 @@ impl<W: Write> FastQFileWriter<W> {
         FastQFileWriter {
             stream: stream,
             line: 0,
+            alphabet: Alphabet::default(),
+            line_ending: LineEnding::default(),
+            phred: PhredOffset::default(),
+            quality_fill: None,
+            case: LetterCase::default(),
+            end_stream: |_| Ok(()),
         }
     }
+
+    /// Write U rather than T.
+    pub fn with_alphabet(mut self, alphabet: Alphabet) -> Self {
+        self.alphabet = alphabet;
+        self
+    }
+
+    pub fn with_line_ending(mut self, line_ending: LineEnding) -> Self {
+        self.line_ending = line_ending;
+        self
+    }
+
+    /// Write the qualities back out as Phred+64.
+    pub fn with_phred_offset(mut self, phred: PhredOffset) -> Self {
+        self.phred = phred;
+        self
+    }
+
+    /// Write every quality as `letter`, e.g. for reads from an archive made without qualities.
+    pub fn with_quality_fill(mut self, letter: Option<u8>) -> Self {
+        self.quality_fill = letter;
+        self
+    }
+
+    /// Write every base in `case`, rather than the case each read was read in.
+    pub fn with_case(mut self, case: LetterCase) -> Self {
+        self.case = case;
+        self
+    }
+
+    /// Flush what's buffered and hand back the writer underneath.
+    pub fn into_inner(self) -> Result<W, FastQFileError> {
+        self.stream
+            .into_inner()
+            .map_err(|e| FastQFileError::from(e.into_error()))
+    }
 }
 
 impl<W: Write> FastQFileWriterTrait for FastQFileWriter<W> {
@@ -309,25 +1706,65 @@ impl<W: Write> FastQFileWriterTrait for FastQFileWriter<W> {
         buf: &FastQRead,
         reverse_complement: bool,
     ) -> Result<bool, FastQFileError> {
-        write!(self.stream, "@{}\n", buf.title)?;
+        let ending = self.line_ending.as_bytes();
+        write!(self.stream, "@{}", buf.title)?;
+        self.stream.write_all(ending)?;
 
         let mut letters = buf.letters.to_owned();
+        let mut case_mask = buf.case_mask.to_owned();
+        let mut other_letters = buf.other_letters.to_owned();
 
         if reverse_complement {
-            reverse_complement_nucleotides(&mut letters);
+            reverse_complement_nucleotides(&mut letters)?;
+            case_mask.reverse();
+            reverse_other_letters(&mut other_letters, letters.len());
+        }
+        nuclotides_upper(&mut letters)?;
+        if self.alphabet == Alphabet::Rna {
+            letters
+                .iter_mut()
+                .filter(|l| **l == b'T')
+                .for_each(|l| *l = b'U');
+        }
+        match self.case {
+            LetterCase::Preserve => {
+                for (letter, lower) in letters.iter_mut().zip(case_mask) {
+                    if lower {
+                        letter.make_ascii_lowercase();
+                    }
+                }
+            }
+            LetterCase::Upper => {}
+            LetterCase::Lower => letters.make_ascii_lowercase(),
+        }
+        for (offset, letter) in other_letters {
+            if let Some(stored) = letters.get_mut(offset) {
+                *stored = letter;
+            }
         }
-        nuclotides_upper(&mut letters);
         self.stream.write(&letters)?;
+        self.stream.write_all(ending)?;
 
-        self.stream.write(b"\n+\n")?;
+        write!(self.stream, "+{}", buf.sub_title)?;
+        self.stream.write_all(ending)?;
 
-        let quals: Vec<u8> = buf.qualities.iter().map(|q| q + 32).collect();
+        let shift = self.phred.offset() - 1;
+        let quals: Vec<u8> = match self.quality_fill {
+            Some(letter) => vec![letter; buf.qualities.len()],
+            None => buf.qualities.iter().map(|q| q + shift).collect(),
+        };
 
         self.stream.write(&quals)?;
-        self.stream.write(b"\n")?;
+        self.stream.write_all(ending)?;
         self.line += 4;
         return Ok(true);
     }
+
+    fn finish(&mut self) -> Result<(), FastQFileError> {
+        self.stream.flush()?;
+        (self.end_stream)(self.stream.get_mut())?;
+        Ok(())
+    }
 }
 
 impl FastQFileWriter<File> {
@@ -343,74 +1780,272 @@ impl FastQFileWriter<std::io::Stdout> {
     }
 }
 
+/// A fastq file being written, gzipped when its name ends in `.gz`.
+pub enum FastQOutputFile {
+    Plain(File),
+    Gzip(GzEncoder<File>),
+}
+
+impl FastQOutputFile {
+    pub fn create<P: AsRef<Path>>(path: &P) -> Result<Self, std::io::Error> {
+        let path = path.as_ref();
+        let file = File::create(path)?;
+        match path.extension().is_some_and(|extension| extension == "gz") {
+            true => Ok(FastQOutputFile::Gzip(GzEncoder::new(
+                file,
+                Compression::default(),
+            ))),
+            false => Ok(FastQOutputFile::Plain(file)),
+        }
+    }
+
+    /// Write the end of the gzip stream, dropping the file does this too but can't report errors.
+    pub fn finish(self) -> Result<File, std::io::Error> {
+        match self {
+            FastQOutputFile::Plain(file) => Ok(file),
+            FastQOutputFile::Gzip(encoder) => encoder.finish(),
+        }
+    }
+
+    /// As `finish` but the file is kept, nothing more should be written to it.
+    pub fn try_finish(&mut self) -> Result<(), std::io::Error> {
+        match self {
+            FastQOutputFile::Plain(file) => file.flush(),
+            FastQOutputFile::Gzip(encoder) => encoder.try_finish(),
+        }
+    }
+}
+
+impl Write for FastQOutputFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            FastQOutputFile::Plain(file) => file.write(buf),
+            FastQOutputFile::Gzip(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            FastQOutputFile::Plain(file) => file.flush(),
+            FastQOutputFile::Gzip(encoder) => encoder.flush(),
+        }
+    }
+}
+
+impl FastQFileWriter<FastQOutputFile> {
+    /// Create a fastq file that is gzipped if its name ends in `.gz`.
+    pub fn create_maybe_compressed<P: AsRef<Path>>(path: &P) -> Result<Self, std::io::Error> {
+        let mut writer = FastQFileWriter::new(BufWriter::new(FastQOutputFile::create(path)?));
+        writer.end_stream = FastQOutputFile::try_finish;
+        Ok(writer)
+    }
+}
+
+impl<W: Write> FastQFileWriter<GzEncoder<W>> {
+    /// Gzip the records into `stream`, `finish` writes the end of the gzip stream.
+    pub fn gzip(stream: W) -> Self {
+        let mut writer = FastQFileWriter::new(BufWriter::new(GzEncoder::new(
+            stream,
+            Compression::default(),
+        )));
+        writer.end_stream = GzEncoder::try_finish;
+        writer
+    }
+}
+
 pub trait PairedFastQWriter {
     fn write_next(
         &mut self,
         buf_r1: &FastQRead,
         buf_r2: &FastQRead,
     ) -> Result<bool, FastQFileError>;
+
+    /// Write out whatever the writer still holds, see `FastQFileWriterTrait::finish`.
+    fn finish(&mut self) -> Result<(), FastQFileError> {
+        Ok(())
+    }
+}
+
+pub struct FastQPairedFilesWriter<W: FastQFileWriterTrait = Box<dyn FastQFileWriterTrait>> {
+    r1_stream: W,
+    r2_stream: W,
+    reverse_complement_r2_nucleotides: bool,
+}
+
+impl<W: FastQFileWriterTrait> FastQPairedFilesWriter<W> {
+    pub fn new(stream_r1: W, stream_r2: W, reverse_complement_r2_nucleotides: bool) -> Self {
+        FastQPairedFilesWriter {
+            r1_stream: stream_r1,
+            r2_stream: stream_r2,
+            reverse_complement_r2_nucleotides: reverse_complement_r2_nucleotides,
+        }
+    }
+
+    /// The R1 and R2 writers back.
+    pub fn into_inner(self) -> (W, W) {
+        (self.r1_stream, self.r2_stream)
+    }
+}
+
+impl<W: FastQFileWriterTrait> PairedFastQWriter for FastQPairedFilesWriter<W> {
+    fn write_next(
+        &mut self,
+        buf_r1: &FastQRead,
+        buf_r2: &FastQRead,
+    ) -> Result<bool, FastQFileError> {
+        self.r1_stream.write_next(&buf_r1, false)?;
+        self.r2_stream
+            .write_next(&buf_r2, self.reverse_complement_r2_nucleotides)?;
+        Ok(true)
+    }
+
+    fn finish(&mut self) -> Result<(), FastQFileError> {
+        self.r1_stream.finish()?;
+        self.r2_stream.finish()
+    }
 }
 
-pub struct FastQPairedFilesWriter {
-    r1_stream: Box<dyn FastQFileWriterTrait>,
-    r2_stream: Box<dyn FastQFileWriterTrait>,
+/// Writes R1 then R2 of each pair to one stream, in the order the pairs are given.
+pub struct FastQInterleavedFileWriter<W: FastQFileWriterTrait = Box<dyn FastQFileWriterTrait>> {
+    stream: W,
     reverse_complement_r2_nucleotides: bool,
 }
 
-impl FastQPairedFilesWriter {
-    pub fn new(
-        stream_r1: Box<dyn FastQFileWriterTrait>,
-        stream_r2: Box<dyn FastQFileWriterTrait>,
-        reverse_complement_r2_nucleotides: bool,
-    ) -> Self {
-        FastQPairedFilesWriter {
-            r1_stream: stream_r1,
-            r2_stream: stream_r2,
+impl<W: FastQFileWriterTrait> FastQInterleavedFileWriter<W> {
+    pub fn new(stream: W, reverse_complement_r2_nucleotides: bool) -> Self {
+        FastQInterleavedFileWriter {
+            stream: stream,
             reverse_complement_r2_nucleotides: reverse_complement_r2_nucleotides,
         }
     }
+
+    /// The writer back.
+    pub fn into_inner(self) -> W {
+        self.stream
+    }
 }
 
-impl PairedFastQWriter for FastQPairedFilesWriter {
+impl<W: FastQFileWriterTrait> PairedFastQWriter for FastQInterleavedFileWriter<W> {
     fn write_next(
         &mut self,
         buf_r1: &FastQRead,
         buf_r2: &FastQRead,
     ) -> Result<bool, FastQFileError> {
-        self.r1_stream.write_next(&buf_r1, false)?;
-        self.r2_stream
+        self.stream.write_next(&buf_r1, false)?;
+        self.stream
             .write_next(&buf_r2, self.reverse_complement_r2_nucleotides)?;
+
         Ok(true)
     }
+
+    fn finish(&mut self) -> Result<(), FastQFileError> {
+        self.stream.finish()
+    }
+}
+
+/// Writes each read of a fragment to its own file, see `MultiFastQReader`.
+pub trait MultiFastQWriter {
+    fn reads_per_fragment(&self) -> usize;
+
+    fn write_next(&mut self, bufs: &[FastQRead]) -> Result<bool, FastQFileError>;
+
+    /// Write out whatever the writers still hold, see `FastQFileWriterTrait::finish`.
+    fn finish(&mut self) -> Result<(), FastQFileError>;
 }
 
-pub struct FastQInterleavedFileWriter {
-    stream: Box<dyn FastQFileWriterTrait>,
+pub struct FastQMultiFilesWriter {
+    writers: Vec<Box<dyn FastQFileWriterTrait>>,
     reverse_complement_r2_nucleotides: bool,
 }
 
-impl FastQInterleavedFileWriter {
+impl FastQMultiFilesWriter {
     pub fn new(
-        stream: Box<dyn FastQFileWriterTrait>,
+        writers: Vec<Box<dyn FastQFileWriterTrait>>,
         reverse_complement_r2_nucleotides: bool,
     ) -> Self {
-        FastQInterleavedFileWriter {
-            stream: stream,
-            reverse_complement_r2_nucleotides: reverse_complement_r2_nucleotides,
+        FastQMultiFilesWriter {
+            writers,
+            reverse_complement_r2_nucleotides,
+        }
+    }
+}
+
+impl MultiFastQWriter for FastQMultiFilesWriter {
+    fn reads_per_fragment(&self) -> usize {
+        self.writers.len()
+    }
+
+    fn write_next(&mut self, bufs: &[FastQRead]) -> Result<bool, FastQFileError> {
+        debug_assert_eq!(bufs.len(), self.writers.len());
+
+        for (i, (writer, buf)) in self.writers.iter_mut().zip(bufs).enumerate() {
+            writer.write_next(buf, i == 1 && self.reverse_complement_r2_nucleotides)?;
         }
+        Ok(true)
+    }
+
+    fn finish(&mut self) -> Result<(), FastQFileError> {
+        self.writers
+            .iter_mut()
+            .try_for_each(|writer| writer.finish())
+    }
+}
+
+/// Writes single-end reads, see `SingleEndReader`.
+pub struct SingleEndWriter {
+    writer: Box<dyn FastQFileWriterTrait>,
+}
+
+impl SingleEndWriter {
+    pub fn new(writer: Box<dyn FastQFileWriterTrait>) -> Self {
+        SingleEndWriter { writer }
+    }
+}
+
+impl MultiFastQWriter for SingleEndWriter {
+    fn reads_per_fragment(&self) -> usize {
+        1
+    }
+
+    fn write_next(&mut self, bufs: &[FastQRead]) -> Result<bool, FastQFileError> {
+        debug_assert_eq!(bufs.len(), 1);
+        self.writer.write_next(&bufs[0], false)
+    }
+
+    fn finish(&mut self) -> Result<(), FastQFileError> {
+        self.writer.finish()
+    }
+}
+
+/// Counts what would have been written without writing it anywhere.
+#[derive(Debug, Default)]
+pub struct CountingFastQWriter {
+    pairs: u64,
+    bases: u64,
+}
+
+impl CountingFastQWriter {
+    pub fn pairs(&self) -> u64 {
+        self.pairs
+    }
+
+    pub fn records(&self) -> u64 {
+        2 * self.pairs
+    }
+
+    pub fn bases(&self) -> u64 {
+        self.bases
     }
 }
 
-impl PairedFastQWriter for FastQInterleavedFileWriter {
+impl PairedFastQWriter for CountingFastQWriter {
     fn write_next(
         &mut self,
         buf_r1: &FastQRead,
         buf_r2: &FastQRead,
     ) -> Result<bool, FastQFileError> {
-        self.stream.write_next(&buf_r1, false)?;
-        self.stream
-            .write_next(&buf_r2, self.reverse_complement_r2_nucleotides)?;
-
+        self.pairs += 1;
+        self.bases += (buf_r1.letters.len() + buf_r2.letters.len()) as u64;
         Ok(true)
     }
 }
@@ -419,16 +2054,52 @@ impl PairedFastQWriter for FastQInterleavedFileWriter {
 mod tests {
     use super::*;
 
+    use std::io::Cursor;
+
+    use crate::compression_models::test_utils::{file_reader, SyntheticFastq};
+    use crate::seq_files::bgzf::bgzip;
+    use flate2::read::MultiGzDecoder;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    /// The line of the record an error was found in, and the error itself.
+    fn at_line(err: FastQFileError) -> (u64, FastQFileError) {
+        match err {
+            FastQFileError::AtLine { line, source } => (line, *source),
+            err => panic!("{:?} doesn't say which line", err),
+        }
+    }
+
     #[test]
-    fn test_reverse_complement_nucleotides() {
+    fn test_reverse_complement_nucleotides() -> Result<(), FastQFileError> {
         let mut read = FastQRead::default();
         read.letters = b"ttaattggtaaataaatctcctaatagcttagatnttaccttnnnnnnnnnntagtttcttgagatttgttgggggagacatttttgtgattgccttgat".to_vec();
 
-        read.reverse_complement_nucleotides();
+        read.reverse_complement_nucleotides()?;
         assert_eq!(
             read.letters,
             b"atcaaggcaatcacaaaaatgtctcccccaacaaatctcaagaaactannnnnnnnnnaaggtaanatctaagctattaggagatttatttaccaattaa"
         );
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_letter_is_an_error() {
+        let mut read = FastQRead {
+            letters: b"acgxt".to_vec(),
+            ..Default::default()
+        };
+        assert!(matches!(
+            read.reverse_complement_nucleotides(),
+            Err(FastQFileError::InvalidNucleotideLetter { c: 'x' })
+        ));
+
+        read.letters = b"acgxt".to_vec();
+        let mut writer = FastQFileWriter::new(BufWriter::new(Vec::new()));
+        assert!(matches!(
+            writer.write_next(&read, false),
+            Err(FastQFileError::InvalidNucleotideLetter { c: 'x' })
+        ));
     }
 
     const FASTQ_RECORD: &str = concat!(
@@ -481,6 +2152,58 @@ mod tests {
         "efcfffffcfeefffcffffffddf`feed]`]_B__^__[YBBBBBBBBBBRTT\\]][]dddd`ddd^dddadd^BBBBBBBBBBBBBBBBBBBBBBBB\n"
     );
 
+    #[test]
+    fn test_maybe_decompress() -> Result<(), FastQFileError> {
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        gz.write_all(FASTQ_RECORD.as_bytes())?;
+        let gz = gz.finish()?;
+        let bgzf = bgzip(FASTQ_RECORD.as_bytes(), 64);
+
+        for input in [gz, bgzf, FASTQ_RECORD.as_bytes().to_vec()] {
+            let mut reader =
+                FastQFileReader::new(BufReader::new(maybe_decompress(Cursor::new(input))?));
+            let mut seq = FastQRead::default();
+            assert!(reader.read_next(&mut seq)?);
+
+            let mut writer = FastQFileWriter::new(BufWriter::new(Vec::new()));
+            writer.write_next(&seq, false)?;
+            assert_eq!(FASTQ_RECORD.as_bytes(), writer.stream.into_inner().unwrap());
+            assert!(!reader.read_next(&mut seq)?);
+        }
+
+        // shorter than the magic
+        let mut short = String::new();
+        maybe_decompress(Cursor::new(b"@".to_vec()))?.read_to_string(&mut short)?;
+        assert_eq!("@", short);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_virtual_position() -> Result<(), FastQFileError> {
+        let text = FASTQ_RECORD.repeat(3);
+        let bgzf = bgzip(text.as_bytes(), 64);
+        let mut reader =
+            FastQFileReader::new(BufReader::new(maybe_decompress(Cursor::new(bgzf.clone()))?));
+        assert_eq!(Some(0), reader.virtual_position());
+
+        let mut seq = FastQRead::default();
+        for records in 1..=3 {
+            assert!(reader.read_next(&mut seq)?);
+            // where a plain BGZF reader is after the same number of bytes
+            let mut expected = BgzfReader::new(bgzf.as_slice());
+            std::io::copy(
+                &mut (&mut expected).take((FASTQ_RECORD.len() * records) as u64),
+                &mut std::io::sink(),
+            )?;
+            assert_eq!(Some(expected.virtual_position()), reader.virtual_position());
+        }
+
+        let plain = FastQFileReader::new(BufReader::new(maybe_decompress(Cursor::new(text))?));
+        assert_eq!(None, plain.virtual_position());
+        Ok(())
+    }
+
     #[test]
     fn test_invalid_sequence_length() {
         let mut reader = FastQFileReader::new(BufReader::new(
@@ -491,8 +2214,8 @@ mod tests {
         let actual = reader.read_next(&mut seq);
         assert!(actual.is_err());
         assert!(matches!(
-            actual.unwrap_err(),
-            FastQFileError::MismatchedSequenceLength
+            at_line(actual.unwrap_err()),
+            (1, FastQFileError::MismatchedSequenceLength)
         ));
     }
 
@@ -513,9 +2236,225 @@ mod tests {
         let actual = reader.read_next(&mut seq);
         assert!(actual.is_err());
         assert!(matches!(
-            actual.unwrap_err(),
-            FastQFileError::MismatchedSequenceLength
+            at_line(actual.unwrap_err()),
+            (1, FastQFileError::MismatchedSequenceLength)
+        ));
+    }
+
+    const PHRED64_RECORD: &str = "@old/1\nACGTNA\n+\nhh`O@B\n";
+    const PHRED33_RECORD: &str = "@old/1\nACGTNA\n+\nIIA0!#\n";
+
+    fn read_record(record: &str, phred: PhredOffset) -> Result<FastQRead, FastQFileError> {
+        let mut reader =
+            FastQFileReader::new(BufReader::new(record.as_bytes())).with_phred_offset(phred);
+        let mut seq = FastQRead::default();
+        reader.read_next(&mut seq)?;
+        Ok(seq)
+    }
+
+    #[test]
+    fn test_phred64_record() -> Result<(), FastQFileError> {
+        let phred64 = read_record(PHRED64_RECORD, PhredOffset::Phred64)?;
+        let phred33 = read_record(PHRED33_RECORD, PhredOffset::Phred33)?;
+        assert_eq!(phred33.qualities, phred64.qualities);
+
+        let mut buffer: Vec<u8> = Vec::new();
+        FastQFileWriter::new(BufWriter::new(&mut buffer))
+            .with_phred_offset(PhredOffset::Phred64)
+            .write_next(&phred33, false)?;
+        assert_eq!(PHRED64_RECORD, String::from_utf8(buffer).unwrap());
+
+        // '0' is below the lowest Phred+64 letter
+        assert!(matches!(
+            at_line(read_record(PHRED33_RECORD, PhredOffset::Phred64).unwrap_err()),
+            (1, FastQFileError::InvalidQualityLetter)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_detect_phred_offset() -> Result<(), FastQFileError> {
+        for (record, expected) in [
+            (PHRED64_RECORD, PhredOffset::Phred64),
+            (PHRED33_RECORD, PhredOffset::Phred33),
+            // between the two ranges, this could be either
+            ("@new/1\nACGT\n+\n?=?;\n", PhredOffset::Phred33),
+            ("", PhredOffset::Phred33),
+        ] {
+            let mut reader = FastQFileReader::new(BufReader::new(record.as_bytes()));
+            assert_eq!(
+                expected,
+                detect_phred_offset(&mut reader, PHRED_DETECTION_READS)?,
+                "{}",
+                record
+            );
+        }
+
+        Ok(())
+    }
+
+    const FASTQ_AT_QUALITIES: &str = concat!(
+        "@full/1\n",
+        "ACGTACGT\n",
+        "+\n",
+        "@IIIIIII\n",
+        "@short/1\n",
+        "ACGTACGT\n",
+        "+\n",
+        "IIII\n",
+        "@wrapped/1\n",
+        "ACGTACGT\n",
+        "+\n",
+        "IIII\n",
+        "@III\n",
+    );
+
+    #[test]
+    fn test_quality_line_starting_with_at() -> Result<(), FastQFileError> {
+        let mut seq = FastQRead::default();
+
+        // the line after a complete record is always a title
+        let mut reader = FastQFileReader::new(BufReader::new(FASTQ_AT_QUALITIES.as_bytes()));
+        assert!(reader.read_next(&mut seq)?);
+        assert_eq!("full/1", seq.title);
+        assert_eq!(b'@' - 32, seq.qualities[0]);
+        // a short record takes the next title as qualities, which are then too long
+        assert!(matches!(
+            at_line(reader.read_next(&mut seq).unwrap_err()),
+            (5, FastQFileError::MismatchedSequenceLength)
+        ));
+
+        // a line starting with @ that completes the qualities belongs to them, one that doesn't
+        // is the next title
+        let mut reader = FastQFileReader::new(BufReader::new(FASTQ_AT_QUALITIES.as_bytes()))
+            .with_repair_lengths();
+        assert!(reader.read_next(&mut seq)?);
+        assert_eq!("full/1", seq.title);
+        assert!(reader.read_next(&mut seq)?);
+        assert_eq!("short/1", seq.title);
+        assert_eq!(8, seq.qualities.len());
+        assert_eq!(Some(&(b'!' - 32)), seq.qualities.last());
+        assert!(reader.read_next(&mut seq)?);
+        assert_eq!("wrapped/1", seq.title);
+        assert_eq!(b'@' - 32, seq.qualities[4]);
+        assert_eq!(13, reader.line());
+        assert!(!reader.read_next(&mut seq)?);
+        assert_eq!(1, reader.salvage().repaired_records);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_repair_lengths() -> Result<(), FastQFileError> {
+        let input = format!(
+            "{}{}",
+            FASTQ_RECORD_INVALID_SEQUENCE_LENGTH, FASTQ_RECORD_INVALID_QUALITY_LENGTH
+        );
+
+        let mut reader = FastQFileReader::new(BufReader::new(input.as_bytes()));
+        let mut seq = FastQRead::default();
+        assert!(matches!(
+            at_line(reader.read_next(&mut seq).unwrap_err()),
+            (1, FastQFileError::MismatchedSequenceLength)
+        ));
+
+        let mut reader =
+            FastQFileReader::new(BufReader::new(input.as_bytes())).with_repair_lengths();
+        assert!(reader.read_next(&mut seq)?);
+        assert_eq!(seq.letters.len(), seq.qualities.len());
+        assert_eq!(Some(&(b'B' - 32)), seq.qualities.last());
+
+        assert!(reader.read_next(&mut seq)?);
+        assert_eq!(seq.letters.len(), seq.qualities.len());
+        assert_eq!(Some(&(b'!' - 32)), seq.qualities.last());
+
+        assert!(!reader.read_next(&mut seq)?);
+        assert_eq!(2, reader.salvage().repaired_records);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_threaded_paired_reader() -> Result<(), FastQFileError> {
+        // several batches and a partial one
+        let r1 = SyntheticFastq::default().mate(5000, 1);
+        let r2 = SyntheticFastq::default().mate(5000, 2);
+
+        let mut serial = FastQPairedFilesReader::new_with_validation(
+            file_reader(&r1),
+            file_reader(&r2),
+            true,
+            true,
+        );
+        let mut threaded =
+            FastQPairedFilesReader::new_threaded(file_reader(&r1), file_reader(&r2), true, true);
+
+        let (mut s1, mut s2) = (FastQRead::default(), FastQRead::default());
+        let (mut t1, mut t2) = (FastQRead::default(), FastQRead::default());
+        let mut pairs = 0;
+        while serial.read_next(&mut s1, &mut s2)? {
+            assert!(threaded.read_next(&mut t1, &mut t2)?);
+            assert_eq!(
+                (&s1.title, &s1.letters, &s1.qualities),
+                (&t1.title, &t1.letters, &t1.qualities)
+            );
+            assert_eq!(
+                (&s2.title, &s2.letters, &s2.qualities),
+                (&t2.title, &t2.letters, &t2.qualities)
+            );
+            pairs += 1;
+        }
+        assert_eq!(5000, pairs);
+        assert!(!threaded.read_next(&mut t1, &mut t2)?);
+
+        // an error comes after the reads before it
+        let mut reader = ThreadedFastQFileReader::new(file_reader(&format!(
+            "{}@broken\nACGT\n+\nII\n",
+            SyntheticFastq::default().mate(1500, 1)
+        )));
+        for _ in 0..1500 {
+            assert!(reader.read_next(&mut t1)?);
+        }
+        assert!(matches!(
+            at_line(reader.read_next(&mut t1).unwrap_err()),
+            (6001, FastQFileError::MismatchedSequenceLength)
         ));
+
+        Ok(())
+    }
+
+    const FASTQ_WRAPPED: &str = concat!(
+        "@wrapped/1\n",
+        "ACGTACGTAC\n",
+        "GTACGT\n",
+        "+\n",
+        "@IIIIIIIII\n",
+        "@HHHHH\n",
+        "@unwrapped/2\n",
+        "TTGCA\n",
+        "+\n",
+        "@@@@#\n",
+    );
+
+    #[test]
+    fn test_wrapped_record() -> Result<(), FastQFileError> {
+        let mut reader = FastQFileReader::new(BufReader::new(FASTQ_WRAPPED.as_bytes()));
+        let mut seq = FastQRead::default();
+
+        assert!(reader.read_next(&mut seq)?);
+        assert_eq!("wrapped/1", seq.title);
+        assert_eq!(b"acgtacgtacgtacgt".to_vec(), seq.letters);
+        // the second quality line starting with @ is not mistaken for a title
+        assert_eq!(16, seq.qualities.len());
+        assert_eq!(b'@' - 32, seq.qualities[10]);
+
+        assert!(reader.read_next(&mut seq)?);
+        assert_eq!("unwrapped/2", seq.title);
+        assert_eq!(b"ttgca".to_vec(), seq.letters);
+
+        assert!(!reader.read_next(&mut seq)?);
+        Ok(())
     }
 
     const FASTQ_RECORD_NO_DESCRIPTION: &str = concat!(
@@ -534,8 +2473,8 @@ mod tests {
         let actual = reader.read_next(&mut seq);
         assert!(actual.is_err());
         assert!(matches!(
-            actual.unwrap_err(),
-            FastQFileError::NoDescriptionLine
+            at_line(actual.unwrap_err()),
+            (1, FastQFileError::NoDescriptionLine)
         ));
     }
 
@@ -554,8 +2493,8 @@ mod tests {
         let actual = reader.read_next(&mut seq);
         assert!(actual.is_err());
         assert!(matches!(
-            actual.unwrap_err(),
-            FastQFileError::NoTitleLine { line: 0 }
+            at_line(actual.unwrap_err()),
+            (1, FastQFileError::NoTitleLine { line: 0 })
         ));
     }
 
@@ -575,9 +2514,184 @@ mod tests {
         let actual = reader.read_next(&mut seq);
         assert!(actual.is_err());
         assert!(matches!(
-            actual.unwrap_err(),
-            FastQFileError::InvalidNucleotideLetter { c: 'z' }
+            at_line(actual.unwrap_err()),
+            (1, FastQFileError::InvalidNucleotideLetter { c: 'z' })
+        ));
+    }
+
+    const FASTQ_PAIRS_INVALID_NUCLEOTIDE: &str = concat!(
+        "@read1/1\n",
+        "ACGTACGT\n",
+        "+\n",
+        "IIIIIIII\n",
+        "@read1/2\n",
+        "ACGTXCGT\n",
+        "+\n",
+        "IIIIIIII\n",
+        "@read2/1\n",
+        "TTTTGGGG\n",
+        "+\n",
+        "IIIIIIII\n",
+        "@read2/2\n",
+        "CCCCAAAA\n",
+        "+\n",
+        "IIIIIIII\n",
+    );
+
+    fn interleaved_with_policy(policy: InvalidBasePolicy) -> FastQInterleavedFileReader {
+        FastQInterleavedFileReader::new(
+            Box::new(
+                FastQFileReader::new(BufReader::new(FASTQ_PAIRS_INVALID_NUCLEOTIDE.as_bytes()))
+                    .with_invalid_base_policy(policy),
+            ),
+            false,
+        )
+    }
+
+    #[test]
+    fn test_iupac_codes() -> Result<(), FastQFileError> {
+        let mut reader = FastQFileReader::new(BufReader::new(
+            "@iupac\nRYSWKMBDHVN\n+\nIIIIIIIIIII\n".as_bytes(),
+        ));
+        let mut seq = FastQRead::default();
+        assert!(reader.read_next(&mut seq)?);
+        assert_eq!(b"ryswkmbdhvn", &seq.letters[..]);
+
+        let mut letters = b"ryswkm".to_vec();
+        reverse_complement_nucleotides(&mut letters)?;
+        assert_eq!(b"kmwsry", &letters[..]);
+
+        let mut letters = b"bdhv".to_vec();
+        reverse_complement_nucleotides(&mut letters)?;
+        assert_eq!(b"bdhv", &letters[..]);
+
+        let mut writer = FastQFileWriter::new(BufWriter::new(Vec::new()));
+        writer.write_next(&seq, true)?;
+        let result = String::from_utf8(writer.stream.into_inner().unwrap()).unwrap();
+        assert_eq!("NBDHVKMWSRY", result.lines().nth(1).unwrap());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_base_policies() -> Result<(), FastQFileError> {
+        let mut r1 = FastQRead::default();
+        let mut r2 = FastQRead::default();
+
+        let mut reader = interleaved_with_policy(InvalidBasePolicy::Fail);
+        assert!(matches!(
+            at_line(reader.read_next(&mut r1, &mut r2).unwrap_err()),
+            (5, FastQFileError::InvalidNucleotideLetter { c: 'X' })
+        ));
+
+        let mut reader = interleaved_with_policy(InvalidBasePolicy::N);
+        assert!(reader.read_next(&mut r1, &mut r2)?);
+        assert_eq!(b"acgtncgt".to_vec(), r2.letters);
+        assert!(reader.read_next(&mut r1, &mut r2)?);
+        assert!(!reader.read_next(&mut r1, &mut r2)?);
+        assert_eq!(1, reader.salvage().replaced_bases);
+        assert_eq!(0, reader.salvage().skipped_reads);
+
+        let mut reader = interleaved_with_policy(InvalidBasePolicy::Skip);
+        assert!(reader.read_next(&mut r1, &mut r2)?);
+        assert_eq!("read2/1", r1.title);
+        assert_eq!("read2/2", r2.title);
+        assert!(!reader.read_next(&mut r1, &mut r2)?);
+        assert_eq!(0, reader.salvage().replaced_bases);
+        assert_eq!(2, reader.salvage().skipped_reads);
+
+        // a single file never hands out the skipped read
+        let titles: Vec<String> =
+            FastQFileReader::new(BufReader::new(FASTQ_PAIRS_INVALID_NUCLEOTIDE.as_bytes()))
+                .with_invalid_base_policy(InvalidBasePolicy::Skip)
+                .into_iter()
+                .map(|read| read.map(|read| read.title))
+                .collect::<Result<_, _>>()?;
+        assert_eq!(vec!["read1/1", "read2/1", "read2/2"], titles);
+
+        Ok(())
+    }
+
+    fn skipping_reader(fastq: &'static str) -> FastQFileReader<&'static [u8]> {
+        FastQFileReader::new(BufReader::new(fastq.as_bytes()))
+            .with_invalid_base_policy(InvalidBasePolicy::Skip)
+    }
+
+    #[test]
+    fn test_skipped_reads_drop_their_mates() -> Result<(), FastQFileError> {
+        let r1_file =
+            "@a/1\nACGT\n+\nIIII\n@b/1\nAXGT\n+\nIIII\n@c/1\nACGT\n+\nIIII\n@d/1\nACGT\n+\nIIII\n";
+        let r2_file =
+            "@a/2\nACGT\n+\nIIII\n@b/2\nACGT\n+\nIIII\n@c/2\nACXT\n+\nIIII\n@d/2\nACGT\n+\nIIII\n";
+
+        let paired = |threaded: bool| match threaded {
+            false => FastQPairedFilesReader::new_with_validation(
+                Box::new(skipping_reader(r1_file)),
+                Box::new(skipping_reader(r2_file)),
+                false,
+                true,
+            ),
+            true => FastQPairedFilesReader::new_threaded(
+                Box::new(skipping_reader(r1_file)),
+                Box::new(skipping_reader(r2_file)),
+                false,
+                true,
+            ),
+        };
+        for threaded in [false, true] {
+            let mut reader = paired(threaded);
+            let pairs: Vec<String> = reader
+                .iter()
+                .map(|pair| pair.map(|(r1, _)| r1.title))
+                .collect::<Result<_, _>>()?;
+            assert_eq!(vec!["a/1", "d/1"], pairs);
+            assert_eq!(4, reader.salvage().skipped_reads);
+        }
+
+        // interleaved, an R2 that is skipped drops the R1 before it and a skipped R1 the R2 after
+        let interleaved = "@a/1\nACGT\n+\nIIII\n@a/2\nAXGT\n+\nIIII\n@b/1\nACGT\n+\nIIII\n@b/2\nACGT\n+\nIIII\n@c/1\nAXGT\n+\nIIII\n@c/2\nACGT\n+\nIIII\n";
+        let mut reader =
+            FastQInterleavedFileReader::new(Box::new(skipping_reader(interleaved)), false);
+        let pairs: Vec<(String, String)> = reader
+            .iter()
+            .map(|pair| pair.map(|(r1, r2)| (r1.title, r2.title)))
+            .collect::<Result<_, _>>()?;
+        assert_eq!(vec![("b/1".to_string(), "b/2".to_string())], pairs);
+        assert_eq!(4, reader.salvage().skipped_reads);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_skipped_last_read_keeps_checking_mates() -> Result<(), FastQFileError> {
+        let r1_file = "@a/1\nACGT\n+\nIIII\n@b/1\nACGT\n+\nIIII\n@c/1\nACGT\n+\nIIII\n";
+        let r2_file = "@a/2\nACGT\n+\nIIII\n@b/2\nAXGT\n+\nIIII\n";
+
+        // R2 ends on a skipped read while R1 still has one after it
+        let mut reader = FastQPairedFilesReader::new(
+            Box::new(skipping_reader(r1_file)),
+            Box::new(skipping_reader(r2_file)),
+            false,
+        );
+        let mut r1 = FastQRead::default();
+        let mut r2 = FastQRead::default();
+        assert!(reader.read_next(&mut r1, &mut r2)?);
+        assert!(matches!(
+            reader.read_next(&mut r1, &mut r2),
+            Err(FastQFileError::MissingPairedRead)
         ));
+
+        // both ending together is fine, even when the last pair is dropped
+        let r1_file = "@a/1\nACGT\n+\nIIII\n@b/1\nACGT\n+\nIIII\n";
+        let mut reader = FastQPairedFilesReader::new(
+            Box::new(skipping_reader(r1_file)),
+            Box::new(skipping_reader(r2_file)),
+            false,
+        );
+        assert!(reader.read_next(&mut r1, &mut r2)?);
+        assert!(!reader.read_next(&mut r1, &mut r2)?);
+
+        Ok(())
     }
 
     const FASTQ_RECORD_SUBTITLE_INCLUDED: &str = concat!(
@@ -593,9 +2707,15 @@ mod tests {
             FastQFileReader::new(BufReader::new(FASTQ_RECORD_SUBTITLE_INCLUDED.as_bytes()));
         let mut seq = FastQRead::default();
 
-        let actual = reader.read_next(&mut seq);
-        assert!(actual.is_err());
-        assert!(matches!(actual.unwrap_err(), FastQFileError::SubTitleFound));
+        assert!(reader.read_next(&mut seq).unwrap());
+        assert_eq!(seq.title, seq.sub_title);
+
+        let mut writer = FastQFileWriter::new(BufWriter::new(Vec::new()));
+        writer.write_next(&seq, false).unwrap();
+        assert_eq!(
+            FASTQ_RECORD_SUBTITLE_INCLUDED,
+            String::from_utf8(writer.stream.into_inner().unwrap()).unwrap()
+        );
     }
 
     const FASTQ_RECORD_INVALID_QUALITY: &str = concat!(
@@ -614,9 +2734,44 @@ mod tests {
         let actual = reader.read_next(&mut seq);
         assert!(actual.is_err());
         assert!(matches!(
-            actual.unwrap_err(),
-            FastQFileError::InvalidQualityLetter
+            at_line(actual.unwrap_err()),
+            (1, FastQFileError::InvalidQualityLetter)
+        ));
+    }
+
+    #[test]
+    fn test_validate() -> Result<(), FastQFileError> {
+        let mut reader = FastQFileReader::new(BufReader::new(FASTQ_RECORD.as_bytes()));
+        let mut seq = FastQRead::default();
+        reader.read_next(&mut seq)?;
+        seq.validate()?;
+
+        let mut short = seq.clone();
+        short.qualities.pop();
+        assert!(matches!(
+            short.validate(),
+            Err(FastQFileError::MismatchedSequenceLength)
         ));
+
+        let mut letter = seq.clone();
+        letter.letters[3] = b'T';
+        assert!(matches!(
+            letter.validate(),
+            Err(FastQFileError::InvalidNucleotideLetter { c: 'T' })
+        ));
+
+        for quality in [0, MAX_PHRED + 2] {
+            let mut invalid = seq.clone();
+            invalid.qualities[0] = quality;
+            assert!(matches!(
+                invalid.validate(),
+                Err(FastQFileError::InvalidQualityLetter)
+            ));
+        }
+
+        let mut highest = seq.clone();
+        highest.qualities[0] = MAX_PHRED + 1;
+        highest.validate()
     }
 
     const FASTQ_RECORD_TRUNCATED_QUALITY: &str = concat!(
@@ -633,6 +2788,79 @@ mod tests {
     const FASTQ_RECORD_TRUNCATED_NUCLEOTIDES: &str =
         concat!("@HWI-EAS209_0006_FC706VJ:5:58:5894:21141#ATCACG/1\n",);
 
+    /// Random bytes, mostly laid out as fastq records with random fields so that the parser gets
+    /// past the title line often enough to be tested.
+    fn fuzz_buffer(state: &mut u64) -> Vec<u8> {
+        const PIECES: [&[u8]; 10] = [
+            b"ACGT",
+            b"acgtn",
+            b"RYK",
+            b"IIII",
+            b"!!#",
+            b"~",
+            b"@",
+            b"+",
+            b"\xc3\xa9",
+            b"\xff",
+        ];
+        let mut next = || {
+            // xorshift, the same buffers every run
+            *state ^= *state << 13;
+            *state ^= *state >> 7;
+            *state ^= *state << 17;
+            *state
+        };
+        let mut field = |buffer: &mut Vec<u8>| {
+            for _ in 0..next() % 4 {
+                let choice = next();
+                match choice % 4 {
+                    0 => buffer.push((choice >> 8) as u8),
+                    _ => buffer.extend_from_slice(PIECES[(choice >> 8) as usize % PIECES.len()]),
+                }
+            }
+        };
+
+        let mut buffer = Vec::new();
+        for _ in 0..3 {
+            for line in [&b"@"[..], b"\n", b"\n+", b"\n", b"\n"] {
+                buffer.extend_from_slice(line);
+                field(&mut buffer);
+            }
+        }
+        buffer
+    }
+
+    #[test]
+    fn test_arbitrary_bytes_never_panic() {
+        let mut state = 0x2545_f491_4f6c_dd1d;
+        for _ in 0..5000 {
+            let buffer = fuzz_buffer(&mut state);
+            for policy in [
+                InvalidBasePolicy::Fail,
+                InvalidBasePolicy::N,
+                InvalidBasePolicy::Skip,
+            ] {
+                for phred in [PhredOffset::Phred33, PhredOffset::Phred64] {
+                    let mut reader = FastQFileReader::new(BufReader::new(buffer.as_slice()))
+                        .with_invalid_base_policy(policy)
+                        .with_phred_offset(phred);
+                    if policy == InvalidBasePolicy::N {
+                        reader = reader.with_repair_lengths();
+                    }
+
+                    for read in reader {
+                        match read {
+                            Ok(read) if !read.letters.is_empty() => {
+                                assert_eq!(read.letters.len(), read.qualities.len())
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+        }
+    }
+
     #[test]
     fn test_truncated() {
         let mut reader =
@@ -642,8 +2870,8 @@ mod tests {
         let actual = reader.read_next(&mut seq);
         assert!(actual.is_err());
         assert!(matches!(
-            actual.unwrap_err(),
-            FastQFileError::IncompleteRecord
+            at_line(actual.unwrap_err()),
+            (1, FastQFileError::IncompleteRecord)
         ));
 
         let mut reader = FastQFileReader::new(BufReader::new(
@@ -654,8 +2882,8 @@ mod tests {
         let actual = reader.read_next(&mut seq);
         assert!(actual.is_err());
         assert!(matches!(
-            actual.unwrap_err(),
-            FastQFileError::IncompleteRecord
+            at_line(actual.unwrap_err()),
+            (1, FastQFileError::IncompleteRecord)
         ));
 
         let mut reader = FastQFileReader::new(BufReader::new(
@@ -666,9 +2894,36 @@ mod tests {
         let actual = reader.read_next(&mut seq);
         assert!(actual.is_err());
         assert!(matches!(
-            actual.unwrap_err(),
-            FastQFileError::IncompleteRecord
+            at_line(actual.unwrap_err()),
+            (1, FastQFileError::IncompleteRecord)
+        ));
+    }
+
+    #[test]
+    fn test_truncated_deep_in_file() -> Result<(), FastQFileError> {
+        let fastq = format!(
+            "{}@cut/1\nACGTACGT\n+\n",
+            SyntheticFastq::default().mate(1000, 1)
+        );
+        let mut reader = FastQFileReader::new(BufReader::new(fastq.as_bytes()));
+        let mut seq = FastQRead::default();
+        for _ in 0..1000 {
+            assert!(reader.read_next(&mut seq)?);
+        }
+        assert_eq!(4000, reader.line());
+
+        let err = reader.read_next(&mut seq).unwrap_err();
+        assert_eq!(
+            "EOF caused Incomplete record, in the record starting at line 4001",
+            err.to_string()
+        );
+        assert!(matches!(err.cause(), FastQFileError::IncompleteRecord));
+        assert!(matches!(
+            at_line(err),
+            (4001, FastQFileError::IncompleteRecord)
         ));
+
+        Ok(())
     }
 
     const FASTQ_RECORD_FASTA_RECORD: &str = concat!(
@@ -694,8 +2949,8 @@ mod tests {
         let actual = reader.read_next(&mut seq);
         assert!(actual.is_err());
         assert!(matches!(
-            actual.unwrap_err(),
-            FastQFileError::FastATitleLine
+            at_line(actual.unwrap_err()),
+            (5, FastQFileError::FastATitleLine)
         ));
 
         Ok(())
@@ -714,6 +2969,45 @@ mod tests {
         "efcfffffcfeefffcffffffddf`feed]`]_B__^__[YBBBBBBBBBBRTT\\]][]dddd`ddd^dddadd^BBBBBBBBBBBBBBBBBBBBBBBB\n"
     );
 
+    #[test]
+    fn test_check() -> Result<(), FastQFileError> {
+        let check = |fastq: String| {
+            let reader = FastQFileReader::new(BufReader::new(Cursor::new(fastq.into_bytes())));
+            FastQInterleavedFileReader::new(Box::new(reader), false).check()
+        };
+        assert_eq!(4, check(FASTQ_RECORD_INTERLEAVED.repeat(2))?);
+
+        // the second pair starts at line 9
+        let after_a_pair =
+            |malformed: &str| check(format!("{FASTQ_RECORD_INTERLEAVED}{malformed}"));
+        assert!(matches!(
+            at_line(after_a_pair(FASTQ_RECORD_NO_DESCRIPTION).unwrap_err()),
+            (9, FastQFileError::NoDescriptionLine)
+        ));
+        assert!(matches!(
+            at_line(after_a_pair(FASTQ_RECORD_NO_TITLE).unwrap_err()),
+            (9, FastQFileError::NoTitleLine { .. })
+        ));
+        assert!(matches!(
+            at_line(after_a_pair(FASTQ_RECORD_INVALID_NUCLEOTIDE).unwrap_err()),
+            (9, FastQFileError::InvalidNucleotideLetter { c: 'z' })
+        ));
+        assert!(matches!(
+            at_line(after_a_pair(FASTQ_RECORD_INVALID_QUALITY_LENGTH).unwrap_err()),
+            (9, FastQFileError::MismatchedSequenceLength)
+        ));
+        assert!(matches!(
+            at_line(after_a_pair(FASTQ_RECORD_TRUNCATED_QUALITY).unwrap_err()),
+            (9, FastQFileError::IncompleteRecord)
+        ));
+        assert!(matches!(
+            after_a_pair(FASTQ_RECORD),
+            Err(FastQFileError::MissingPairedRead)
+        ));
+
+        Ok(())
+    }
+
     #[test]
     fn test_fastq_interleaved_file_read() -> Result<(), FastQFileError> {
         let str_reader = Box::new(FastQFileReader::new(BufReader::new(
@@ -733,12 +3027,67 @@ mod tests {
             seq2.title,
             "HWI-EAS209_0006_FC706VJ:5:58:5894:21141#ATCACG/2"
         );
-        seq2.reverse_complement_nucleotides();
+        seq2.reverse_complement_nucleotides()?;
         assert_eq!(seq1.letters, seq2.letters);
 
         Ok(())
     }
 
+    #[test]
+    fn test_iterate_reads() -> Result<(), FastQFileError> {
+        let reader = FastQFileReader::new(BufReader::new(FASTQ_RECORD_INTERLEAVED.as_bytes()));
+        let titles: Vec<String> = reader
+            .into_iter()
+            .map(|read| read.map(|read| read.title))
+            .collect::<Result<_, _>>()?;
+        assert_eq!(
+            vec![
+                "HWI-EAS209_0006_FC706VJ:5:58:5894:21141#ATCACG/1",
+                "HWI-EAS209_0006_FC706VJ:5:58:5894:21141#ATCACG/2",
+            ],
+            titles
+        );
+
+        let mut reader = FastQFileReader::new(BufReader::new(FASTQ_RECORD_INTERLEAVED.as_bytes()));
+        assert_eq!(1, reader.iter().take(1).count());
+        // take left the second read to be read
+        assert_eq!(1, reader.iter().count());
+
+        // a bad record ends the iteration
+        let mut reads = FastQFileReader::new(BufReader::new(
+            "@read1\nACGT\n+\nIII\n@read2\nACGT\n+\nIIII\n".as_bytes(),
+        ))
+        .into_iter();
+        assert!(matches!(
+            reads.next().map(|read| at_line(read.unwrap_err())),
+            Some((1, FastQFileError::MismatchedSequenceLength))
+        ));
+        assert!(reads.next().is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_iterate_pairs() -> Result<(), FastQFileError> {
+        let str_reader = Box::new(FastQFileReader::new(BufReader::new(
+            FASTQ_RECORD_INTERLEAVED.as_bytes(),
+        )));
+        let reader = FastQInterleavedFileReader::new(str_reader, false);
+        let titles: Vec<(String, String)> = reader
+            .into_iter()
+            .map(|pair| pair.map(|(r1, r2)| (r1.title, r2.title)))
+            .collect::<Result<_, _>>()?;
+        assert_eq!(
+            vec![(
+                "HWI-EAS209_0006_FC706VJ:5:58:5894:21141#ATCACG/1".to_string(),
+                "HWI-EAS209_0006_FC706VJ:5:58:5894:21141#ATCACG/2".to_string()
+            )],
+            titles
+        );
+
+        Ok(())
+    }
+
     const FASTQ_RECORD_PAIR_R1: &str = concat!(
         "@HWI-EAS209_0006_FC706VJ:5:58:5894:21141#ATCACG/1\n",
         "TTAATTGGTAAATAAATCTCCTAATAGCTTAGATNTTACCTTNNNNNNNNNNTAGTTTCTTGAGATTTGTTGGGGGAGACATTTTTGTGATTGCCTTGAT\n",
@@ -776,12 +3125,363 @@ mod tests {
             seq2.title,
             "HWI-EAS209_0006_FC706VJ:5:58:5894:21141#ATCACG/2"
         );
-        seq2.reverse_complement_nucleotides();
+        seq2.reverse_complement_nucleotides()?;
         assert_eq!(seq1.letters, seq2.letters);
 
         Ok(())
     }
 
+    #[test]
+    fn test_mate() {
+        let mut read = FastQRead::default();
+        for (title, mate) in [
+            ("HWI-EAS209_0006_FC706VJ:5:58:5894:21141#ATCACG/1", Some(1)),
+            ("HWI-EAS209_0006_FC706VJ:5:58:5894:21141#ATCACG/2", Some(2)),
+            (
+                "EAS139:136:FC706VJ:2:2104:15343:197393 1:Y:18:ATCACG",
+                Some(1),
+            ),
+            (
+                "EAS139:136:FC706VJ:2:2104:15343:197393 2:N:18:ATCACG",
+                Some(2),
+            ),
+            ("EAS139:136:FC706VJ:2:2104:15343:197393", None),
+            ("read/3", None),
+        ] {
+            read.title = title.to_string();
+            assert_eq!(mate, read.mate(), "{}", title);
+        }
+    }
+
+    #[test]
+    fn test_fastq_paired_files_swapped() -> Result<(), FastQFileError> {
+        let mut reader = FastQPairedFilesReader::new(
+            Box::new(FastQFileReader::new(BufReader::new(
+                FASTQ_RECORD_PAIR_R2.as_bytes(),
+            ))),
+            Box::new(FastQFileReader::new(BufReader::new(
+                FASTQ_RECORD_PAIR_R1.as_bytes(),
+            ))),
+            false,
+        )
+        .with_mate_order_check();
+        let mut seq1 = FastQRead::default();
+        let mut seq2 = FastQRead::default();
+
+        let actual = reader.read_next(&mut seq1, &mut seq2);
+        assert!(matches!(
+            actual.unwrap_err(),
+            FastQFileError::SwappedMates { title } if title.ends_with("/2")
+        ));
+
+        // without the check the swapped files are read as given
+        let mut reader = FastQPairedFilesReader::new(
+            Box::new(FastQFileReader::new(BufReader::new(
+                FASTQ_RECORD_PAIR_R2.as_bytes(),
+            ))),
+            Box::new(FastQFileReader::new(BufReader::new(
+                FASTQ_RECORD_PAIR_R1.as_bytes(),
+            ))),
+            false,
+        );
+        assert!(reader.read_next(&mut seq1, &mut seq2)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fastq_interleaved_file_swapped() -> Result<(), FastQFileError> {
+        let swapped = format!("{}{}", FASTQ_RECORD_PAIR_R2, FASTQ_RECORD_PAIR_R1);
+        let mut reader = FastQInterleavedFileReader::new(
+            Box::new(FastQFileReader::new(BufReader::new(Cursor::new(swapped)))),
+            false,
+        )
+        .with_mate_order_check();
+        let mut seq1 = FastQRead::default();
+        let mut seq2 = FastQRead::default();
+
+        let actual = reader.read_next(&mut seq1, &mut seq2);
+        assert!(matches!(
+            actual.unwrap_err(),
+            FastQFileError::SwappedMates { title } if title.ends_with("/2")
+        ));
+
+        let ordered = format!("{}{}", FASTQ_RECORD_PAIR_R1, FASTQ_RECORD_PAIR_R2);
+        let mut reader = FastQInterleavedFileReader::new(
+            Box::new(FastQFileReader::new(BufReader::new(Cursor::new(ordered)))),
+            false,
+        )
+        .with_mate_order_check();
+        assert!(reader.read_next(&mut seq1, &mut seq2)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fragment_name() {
+        let mut read = FastQRead::default();
+        for (title, name) in [
+            ("read7/1", "read7"),
+            ("read7/2 extra", "read7"),
+            (
+                "EAS139:136:FC706VJ:2:2104:15343:197393 1:Y:18:ATCACG",
+                "EAS139:136:FC706VJ:2:2104:15343:197393",
+            ),
+            ("read/3", "read/3"),
+        ] {
+            read.title = title.to_string();
+            assert_eq!(name, read.fragment_name(), "{}", title);
+        }
+    }
+
+    #[test]
+    fn test_fastq_paired_files_validation() -> Result<(), FastQFileError> {
+        let r1 = numbered_records(3, 1).concat();
+        // R2 lost its first record
+        let r2 = numbered_records(3, 2)[1..].concat();
+
+        let mut reader = FastQPairedFilesReader::new_with_validation(
+            Box::new(FastQFileReader::new(BufReader::new(Cursor::new(
+                r1.clone(),
+            )))),
+            Box::new(FastQFileReader::new(BufReader::new(Cursor::new(r2)))),
+            false,
+            true,
+        );
+        let mut seq1 = FastQRead::default();
+        let mut seq2 = FastQRead::default();
+
+        let actual = reader.read_next(&mut seq1, &mut seq2);
+        assert!(matches!(
+            actual.unwrap_err(),
+            FastQFileError::MismatchedPairNames { r1, r2 } if r1 == "read0/1" && r2 == "read1/2"
+        ));
+
+        let r2 = numbered_records(3, 2).concat();
+        let mut reader = FastQPairedFilesReader::new_with_validation(
+            Box::new(FastQFileReader::new(BufReader::new(Cursor::new(
+                r1.clone(),
+            )))),
+            Box::new(FastQFileReader::new(BufReader::new(Cursor::new(r2)))),
+            false,
+            true,
+        );
+        while reader.read_next(&mut seq1, &mut seq2)? {}
+
+        Ok(())
+    }
+
+    fn numbered_records(count: usize, mate: u8) -> Vec<String> {
+        (0..count)
+            .map(|i| {
+                format!(
+                    "@read{}/{}\n{}\n+\n{}\n",
+                    i,
+                    mate,
+                    "ACGT".repeat(i % 7 + 1),
+                    "IIII".repeat(i % 7 + 1)
+                )
+            })
+            .collect()
+    }
+
+    fn multi_files_reader(files: &[String]) -> FastQMultiFilesReader {
+        let readers: Vec<Box<dyn FastQFileReaderTrait>> = files
+            .iter()
+            .map(|file| -> Box<dyn FastQFileReaderTrait> {
+                Box::new(FastQFileReader::new(BufReader::new(Cursor::new(
+                    file.clone().into_bytes(),
+                ))))
+            })
+            .collect();
+        FastQMultiFilesReader::new(readers, false, false)
+    }
+
+    #[test]
+    fn test_fastq_multi_files_read() -> Result<(), FastQFileError> {
+        let files: Vec<String> = (1..=3)
+            .map(|mate| numbered_records(3, mate).concat())
+            .collect();
+        let mut reader = multi_files_reader(&files);
+        assert_eq!(3, reader.reads_per_fragment());
+
+        let mut reads = vec![FastQRead::default(); 3];
+        for i in 0..3 {
+            assert!(reader.read_next(&mut reads)?);
+            let titles: Vec<&str> = reads.iter().map(|read| read.title.as_str()).collect();
+            assert_eq!(
+                vec![
+                    format!("read{}/1", i),
+                    format!("read{}/2", i),
+                    format!("read{}/3", i)
+                ],
+                titles
+            );
+        }
+        assert!(!reader.read_next(&mut reads)?);
+
+        // the index reads stop a fragment short
+        let mut files = files;
+        files[2] = numbered_records(2, 3).concat();
+        let mut reader = multi_files_reader(&files);
+        assert!(reader.read_next(&mut reads)?);
+        assert!(reader.read_next(&mut reads)?);
+        assert!(matches!(
+            reader.read_next(&mut reads),
+            Err(FastQFileError::MissingPairedRead)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fastq_multi_files_threaded() -> Result<(), FastQFileError> {
+        let files: Vec<String> = [2, 1, 3]
+            .into_iter()
+            .map(|mate| numbered_records(3, mate).concat())
+            .collect();
+        let threaded = |files: &[String]| {
+            FastQMultiFilesReader::new_threaded(
+                files
+                    .iter()
+                    .map(|file| -> Box<dyn FastQFileReaderTrait + Send> {
+                        Box::new(FastQFileReader::new(BufReader::new(Cursor::new(
+                            file.clone().into_bytes(),
+                        ))))
+                    })
+                    .collect(),
+                false,
+                false,
+            )
+        };
+
+        // read as they are the mates are only out of order, the check catches it
+        let mut reads = vec![FastQRead::default(); 3];
+        let mut reader = threaded(&files);
+        for _ in 0..3 {
+            assert!(reader.read_next(&mut reads)?);
+        }
+        assert!(!reader.read_next(&mut reads)?);
+        assert!(matches!(
+            threaded(&files)
+                .with_mate_order_check()
+                .read_next(&mut reads),
+            Err(FastQFileError::SwappedMates { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_gzip_output() -> Result<(), FastQFileError> {
+        let dir = tempfile::tempdir()?;
+        let mut seq = FastQRead::default();
+        FastQFileReader::new(BufReader::new(FASTQ_RECORD.as_bytes())).read_next(&mut seq)?;
+
+        for (name, gzipped) in [("reads.fastq.gz", true), ("reads.fastq", false)] {
+            let path = dir.path().join(name);
+            let mut writer = FastQFileWriter::create_maybe_compressed(&path)?;
+            writer.write_next(&seq, false)?;
+            // the file is whole before the writer is dropped
+            writer.finish()?;
+
+            assert_eq!(
+                gzipped,
+                std::fs::read(&path)?.starts_with(&GZIP_MAGIC),
+                "{}",
+                name
+            );
+            let mut read_back = FastQRead::default();
+            assert!(FastQFileReader::open_maybe_compressed(&path)?.read_next(&mut read_back)?);
+            assert_eq!(seq.title, read_back.title);
+            assert_eq!(seq.letters, read_back.letters);
+            assert_eq!(seq.qualities, read_back.qualities);
+        }
+
+        Ok(())
+    }
+
+    /// Takes writes but fails to flush them, like a full disk.
+    struct FailingFlush;
+
+    impl Write for FailingFlush {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Err(std::io::Error::other("disk full"))
+        }
+    }
+
+    #[test]
+    fn test_finish_reports_flush_error() -> Result<(), FastQFileError> {
+        let mut seq = FastQRead::default();
+        FastQFileReader::new(BufReader::new(FASTQ_RECORD.as_bytes())).read_next(&mut seq)?;
+
+        let writer = FastQFileWriter::new(BufWriter::new(FailingFlush));
+        let mut paired = FastQPairedFilesWriter::new(
+            writer,
+            FastQFileWriter::new(BufWriter::new(FailingFlush)),
+            false,
+        );
+        paired.write_next(&seq, &seq)?;
+        assert!(
+            matches!(paired.finish(), Err(FastQFileError::IO { source }) if source.to_string() == "disk full")
+        );
+
+        let mut interleaved = FastQInterleavedFileWriter::new(
+            FastQFileWriter::new(BufWriter::new(FailingFlush)),
+            false,
+        );
+        interleaved.write_next(&seq, &seq)?;
+        assert!(
+            matches!(interleaved.finish(), Err(FastQFileError::IO { source }) if source.to_string() == "disk full")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fastq_paired_gzip_files_read() -> Result<(), FastQFileError> {
+        // R1 is a single gzip member at a fast level, R2 is one member per record at the highest
+        // level, so the block boundaries in the two files never line up.
+        let r1_records = numbered_records(50, 1);
+        let mut r1_gz = GzEncoder::new(Vec::new(), Compression::fast());
+        r1_gz.write_all(r1_records.concat().as_bytes())?;
+        let r1_gz = r1_gz.finish()?;
+
+        let mut r2_gz: Vec<u8> = Vec::new();
+        for record in numbered_records(50, 2) {
+            let mut member = GzEncoder::new(Vec::new(), Compression::best());
+            member.write_all(record.as_bytes())?;
+            r2_gz.extend(member.finish()?);
+        }
+
+        let mut reader = FastQPairedFilesReader::new(
+            Box::new(FastQFileReader::new(BufReader::new(MultiGzDecoder::new(
+                Cursor::new(r1_gz),
+            )))),
+            Box::new(FastQFileReader::new(BufReader::new(MultiGzDecoder::new(
+                Cursor::new(r2_gz),
+            )))),
+            false,
+        );
+        let mut seq1 = FastQRead::default();
+        let mut seq2 = FastQRead::default();
+
+        for i in 0..50 {
+            assert!(reader.read_next(&mut seq1, &mut seq2)?);
+            assert_eq!(seq1.title, format!("read{}/1", i));
+            assert_eq!(seq2.title, format!("read{}/2", i));
+            assert_eq!(seq1.letters.len(), 4 * (i % 7 + 1));
+            assert_eq!(seq1.letters, seq2.letters);
+        }
+        assert!(!reader.read_next(&mut seq1, &mut seq2)?);
+
+        Ok(())
+    }
+
     #[test]
     fn test_correct_write() -> Result<(), FastQFileError> {
         let mut reader = FastQFileReader::new(BufReader::new(FASTQ_RECORD.as_bytes()));
@@ -799,6 +3499,190 @@ mod tests {
         Ok(())
     }
 
+    const FASTQ_RECORD_LOWERCASE_QUALITIES: &str = concat!(
+        "@HWI-EAS209_0006_FC706VJ:5:58:5894:21141#ATCACG/1\n",
+        "ACGTNACGTNACGTNACGTNACGTNACGTN\n",
+        "+\n",
+        "abcdefghijklmnopqrstuvwxyz{|}~\n",
+    );
+
+    #[test]
+    fn test_lowercase_qualities_write() -> Result<(), FastQFileError> {
+        let mut reader =
+            FastQFileReader::new(BufReader::new(FASTQ_RECORD_LOWERCASE_QUALITIES.as_bytes()));
+        let mut seq = FastQRead::default();
+
+        assert!(reader.read_next(&mut seq)?);
+        let mut writer = FastQFileWriter::new(BufWriter::new(Vec::new()));
+        writer.write_next(&seq, false)?;
+        writer.write_next(&seq, true)?;
+
+        let result = String::from_utf8(writer.stream.into_inner().unwrap()).unwrap();
+        let lines: Vec<&str> = result.lines().collect();
+        assert_eq!("abcdefghijklmnopqrstuvwxyz{|}~", lines[3]);
+        assert_eq!("abcdefghijklmnopqrstuvwxyz{|}~", lines[7]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mixed_case_write() -> Result<(), FastQFileError> {
+        let mut reader = FastQFileReader::new(BufReader::new(
+            "@masked\nacGTnNAtcg\n+\nIIIIIIIIII\n".as_bytes(),
+        ));
+        let mut seq = FastQRead::default();
+
+        assert!(reader.read_next(&mut seq)?);
+        assert_eq!(b"acgtnnatcg", &seq.letters[..]);
+        let mut writer = FastQFileWriter::new(BufWriter::new(Vec::new()));
+        writer.write_next(&seq, false)?;
+        writer.write_next(&seq, true)?;
+
+        let result = String::from_utf8(writer.stream.into_inner().unwrap()).unwrap();
+        let lines: Vec<&str> = result.lines().collect();
+        assert_eq!("acGTnNAtcg", lines[1]);
+        assert_eq!("cgaTNnACgt", lines[5]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_statistics() -> Result<(), FastQFileError> {
+        let mut read = FastQRead::default();
+        assert_eq!(0.0, read.mean_quality());
+        assert_eq!(0.0, read.gc_content());
+
+        // 4 of the 8 bases that aren't N are G or C. Qualities of Q40, Q30, Q20, Q10, Q0 and
+        // five of Q2 add up to 110 over 10 bases
+        let mut reader = FastQFileReader::new(BufReader::new(
+            "@stats\nGCgcATatNn\n+\nI?5+!#####\n".as_bytes(),
+        ));
+        assert!(reader.read_next(&mut read)?);
+        assert_eq!(0.5, read.gc_content());
+        assert_eq!(11.0, read.mean_quality());
+
+        read.letters = b"nnn".to_vec();
+        assert_eq!(0.0, read.gc_content());
+        Ok(())
+    }
+
+    #[test]
+    fn test_permissive_letters() -> Result<(), FastQFileError> {
+        let record = "@gaps\nAC.GT-n\n+\nIIIIIII\n";
+        let mut seq = FastQRead::default();
+        let mut reader = FastQFileReader::new(BufReader::new(record.as_bytes()));
+        assert!(matches!(
+            at_line(reader.read_next(&mut seq).unwrap_err()),
+            (1, FastQFileError::InvalidNucleotideLetter { c: '.' })
+        ));
+
+        let mut reader =
+            FastQFileReader::new(BufReader::new(record.as_bytes())).with_permissive(true);
+        assert!(reader.read_next(&mut seq)?);
+        assert_eq!(b"acngtnn", &seq.letters[..]);
+        assert_eq!(vec![(2, b'.'), (5, b'-')], seq.other_letters);
+
+        let mut writer = FastQFileWriter::new(BufWriter::new(Vec::new()));
+        writer.write_next(&seq, false)?;
+        writer.write_next(&seq, true)?;
+        let result = String::from_utf8(writer.into_inner()?).unwrap();
+        let lines: Vec<&str> = result.lines().collect();
+        assert_eq!("AC.GT-n", lines[1]);
+        assert_eq!("n-AC.GT", lines[5]);
+
+        // the gap letters can be on any line of a wrapped sequence
+        let wrapped = "@r1/1\nACGT\nAC-T\n.A\n+\nIIIIIIIIII\n";
+        let mut reader = FastQFileReader::new(BufReader::new(wrapped.as_bytes()));
+        assert!(matches!(
+            at_line(reader.read_next(&mut seq).unwrap_err()),
+            (1, FastQFileError::NoDescriptionLine)
+        ));
+
+        let mut reader =
+            FastQFileReader::new(BufReader::new(wrapped.as_bytes())).with_permissive(true);
+        assert!(reader.read_next(&mut seq)?);
+        assert_eq!(b"acgtacntna", &seq.letters[..]);
+        assert_eq!(vec![(6, b'-'), (8, b'.')], seq.other_letters);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_letter_case_write() -> Result<(), FastQFileError> {
+        let mut reader = FastQFileReader::new(BufReader::new(
+            "@masked\nacGTnNAtcg\n+\nIIIIIIIIII\n".as_bytes(),
+        ));
+        let mut seq = FastQRead::default();
+        assert!(reader.read_next(&mut seq)?);
+
+        for (case, expected) in [
+            (LetterCase::Preserve, "acGTnNAtcg"),
+            (LetterCase::Upper, "ACGTNNATCG"),
+            (LetterCase::Lower, "acgtnnatcg"),
+        ] {
+            let mut writer = FastQFileWriter::new(BufWriter::new(Vec::new())).with_case(case);
+            writer.write_next(&seq, false)?;
+            let result = String::from_utf8(writer.into_inner()?).unwrap();
+            assert_eq!(Some(expected), result.lines().nth(1), "{:?}", case);
+        }
+
+        // without a case mask preserving it is upper case
+        seq.case_mask.clear();
+        let mut writer = FastQFileWriter::new(BufWriter::new(Vec::new()));
+        writer.write_next(&seq, false)?;
+        let result = String::from_utf8(writer.into_inner()?).unwrap();
+        assert_eq!(Some("ACGTNNATCG"), result.lines().nth(1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lowercase_n_write() -> Result<(), FastQFileError> {
+        // n and N are both stored as n, the case mask tells them apart again
+        let mut reader = FastQFileReader::new(BufReader::new("@n\natcgn\n+\nIIIII\n".as_bytes()));
+        let mut seq = FastQRead::default();
+
+        assert!(reader.read_next(&mut seq)?);
+        let mut writer = FastQFileWriter::new(BufWriter::new(Vec::new()));
+        writer.write_next(&seq, false)?;
+
+        let result = String::from_utf8(writer.stream.into_inner().unwrap()).unwrap();
+        assert_eq!(Some("atcgn"), result.lines().nth(1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_line_counter_past_u32() -> Result<(), FastQFileError> {
+        // a billion records is four billion lines, start just short of the u32 limit
+        let start = u64::from(u32::MAX) - 1;
+
+        let mut reader = FastQFileReader::new(BufReader::new(FASTQ_RECORD_FASTA_RECORD.as_bytes()));
+        reader.line = start;
+        let mut seq = FastQRead::default();
+
+        assert!(reader.read_next(&mut seq)?);
+        assert_eq!(start + 4, reader.line);
+        assert!(matches!(
+            at_line(reader.read_next(&mut seq).unwrap_err()),
+            (line, FastQFileError::FastATitleLine) if line == start + 5
+        ));
+
+        let mut writer = FastQFileWriter::new(BufWriter::new(Vec::new()));
+        writer.line = start;
+        writer.write_next(&seq, false)?;
+        assert_eq!(start + 4, writer.line);
+
+        let mut reader = FastQFileReader::new(BufReader::new(FASTQ_RECORD_NO_TITLE.as_bytes()));
+        reader.line = start + 4;
+        assert!(matches!(
+            at_line(reader.read_next(&mut seq).unwrap_err()),
+            (record, FastQFileError::NoTitleLine { line }) if line == start + 4 && record == start + 5
+        ));
+
+        Ok(())
+    }
+
     #[test]
     fn test_fastq_paired_files_write() -> Result<(), FastQFileError> {
         let str_reader1 = Box::new(FastQFileReader::new(BufReader::new(
@@ -824,8 +3708,9 @@ mod tests {
         );
 
         writer.write_next(&seq1, &seq2)?;
-        // really need to find a way to get to the underlying data. the type checker/lifetime
-        // checker isn't happy with normal methods.
+        let (r1, r2) = writer.into_inner();
+        assert_eq!(FASTQ_RECORD_PAIR_R1.as_bytes(), r1.into_inner()?);
+        assert_eq!(FASTQ_RECORD_PAIR_R2.as_bytes(), r2.into_inner()?);
 
         Ok(())
     }
@@ -848,6 +3733,10 @@ mod tests {
         );
 
         writer.write_next(&seq1, &seq2)?;
+        assert_eq!(
+            FASTQ_RECORD_INTERLEAVED.as_bytes(),
+            writer.into_inner().into_inner()?
+        );
 
         Ok(())
     }
@@ -1 +1,5 @@
+#[cfg(feature = "bam")]
+pub mod bam;
+pub mod bgzf;
 pub mod fastq;
+pub mod peek;
@@ -0,0 +1,83 @@
+//! Look at the start of a stream that can't seek, e.g. stdin, before reading it.
+
+use std::io::{ErrorKind, Read};
+
+/// Reads the first bytes of a stream up front so they can be looked at, e.g. for a magic number,
+/// then reads them again followed by the rest of the stream.
+pub struct PeekReader<R: Read> {
+    peeked: Vec<u8>,
+    /// How many of the peeked bytes have been read back out.
+    position: usize,
+    inner: R,
+}
+
+impl<R: Read> PeekReader<R> {
+    /// Read up to `length` bytes of `inner`, fewer only when it ends first.
+    pub fn new(mut inner: R, length: usize) -> Result<Self, std::io::Error> {
+        let mut peeked = vec![0; length];
+        let mut filled = 0;
+        while filled < length {
+            match inner.read(&mut peeked[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        peeked.truncate(filled);
+
+        Ok(PeekReader {
+            peeked,
+            position: 0,
+            inner,
+        })
+    }
+
+    /// The bytes read up front, all of them whatever has been read since.
+    pub fn peeked(&self) -> &[u8] {
+        &self.peeked
+    }
+}
+
+impl<R: Read> Read for PeekReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position < self.peeked.len() {
+            let n = (&self.peeked[self.position..]).read(buf)?;
+            self.position += n;
+            return Ok(n);
+        }
+        self.inner.read(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Hands out a few bytes at a time, like a pipe.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = buf.len().min(self.0.len()).min(3);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_peek_then_read_all() -> std::io::Result<()> {
+        let data: Vec<u8> = (0..=255).collect();
+        for length in [0, 1, 2, 10, 256, 1000] {
+            let mut reader = PeekReader::new(Trickle(&data), length)?;
+            assert_eq!(&data[..length.min(data.len())], reader.peeked());
+
+            let mut all = Vec::new();
+            reader.read_to_end(&mut all)?;
+            assert_eq!(data, all);
+            assert_eq!(length.min(data.len()), reader.peeked().len());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,64 @@
+//! Counts the allocations made while parsing, in a binary of its own so the allocator only sees
+//! this test.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::BufReader;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use pare::seq_files::fastq::{FastQFileReader, FastQFileReaderTrait, FastQRead};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+#[test]
+fn test_read_next_reuses_the_buffer() {
+    const RECORDS: u64 = 10_000;
+    let mut fastq = String::new();
+    for i in 0..RECORDS {
+        // soft masked so the case mask is filled too
+        fastq.push_str(&format!(
+            "@read{}/1 sample\nACGTacgtNNACGTACGTAC\n+read{}\nIIIIIIIIII##########\n",
+            i, i
+        ));
+    }
+
+    let mut reader = FastQFileReader::new(BufReader::new(fastq.as_bytes()));
+    let mut read = FastQRead::default();
+    // the first record sizes the buffers
+    assert!(reader.read_next(&mut read).unwrap());
+
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let mut records = 1;
+    while reader.read_next(&mut read).unwrap() {
+        records += 1;
+    }
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+
+    assert_eq!(RECORDS, records);
+    assert_eq!(format!("read{}/1 sample", RECORDS - 1), read.title);
+    assert_eq!(format!("read{}", RECORDS - 1), read.sub_title);
+    assert_eq!(b"acgtacgtnnacgtacgtac".to_vec(), read.letters);
+    assert_eq!(20, read.case_mask.len());
+    // only the titles growing by a digit may need more room
+    assert!(allocations < 10, "{} allocations", allocations);
+}
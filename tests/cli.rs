@@ -0,0 +1,1109 @@
+use std::process::Command;
+
+#[test]
+fn test_paired_outputs_both_stdout() {
+    let output = Command::new(env!("CARGO_BIN_EXE_pare"))
+        .args(["decompress", "archive.pare", "-", "-"])
+        .output()
+        .expect("failed to run pare");
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("give a single output to write interleaved reads to stdout"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn test_reverse_r2_is_read_from_archive() {
+    let dir = tempfile::tempdir().expect("failed to create a temp dir");
+    let r1 = dir.path().join("r1.fastq");
+    let r2 = dir.path().join("r2.fastq");
+    let archive = dir.path().join("reads.pare");
+    let out_r1 = dir.path().join("out_r1.fastq");
+    let out_r2 = dir.path().join("out_r2.fastq");
+    std::fs::write(
+        &r1,
+        "@read1/1\nACGTTG\n+\nIIIIII\n@read2/1\nGGCA\n+\nIIII\n",
+    )
+    .unwrap();
+    std::fs::write(
+        &r2,
+        "@read1/2\nAACCGT\n+\nIIIIII\n@read2/2\nTTGA\n+\nIIII\n",
+    )
+    .unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_pare"))
+        .arg("compress")
+        .arg("--reverse-r2")
+        .arg("-o")
+        .arg(&archive)
+        .args([&r1, &r2])
+        .status()
+        .expect("failed to run pare");
+    assert!(status.success());
+
+    let status = Command::new(env!("CARGO_BIN_EXE_pare"))
+        .arg("decompress")
+        .args([&archive, &out_r1, &out_r2])
+        .status()
+        .expect("failed to run pare");
+    assert!(status.success());
+
+    assert_eq!(
+        std::fs::read_to_string(&r2).unwrap(),
+        std::fs::read_to_string(&out_r2).unwrap()
+    );
+}
+
+#[test]
+fn test_quiet_has_no_stderr() {
+    let dir = tempfile::tempdir().expect("failed to create a temp dir");
+    let reads = dir.path().join("reads.fastq");
+    let archive = dir.path().join("reads.pare");
+    let decompressed = dir.path().join("decompressed.fastq");
+    std::fs::write(
+        &reads,
+        "@read1/1\nACGTTG\n+\nIIIIII\n@read1/2\nAACCGT\n+\nIIIIII\n",
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_pare"))
+        .args(["compress", "--quiet", "-o"])
+        .arg(&archive)
+        .arg(&reads)
+        .output()
+        .expect("failed to run pare");
+    assert!(output.status.success());
+    assert_eq!("", String::from_utf8_lossy(&output.stderr));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_pare"))
+        .args(["decompress", "--quiet"])
+        .args([&archive, &decompressed])
+        .output()
+        .expect("failed to run pare");
+    assert!(output.status.success());
+    assert_eq!("", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(
+        std::fs::read_to_string(&reads).unwrap(),
+        std::fs::read_to_string(&decompressed).unwrap()
+    );
+}
+
+#[test]
+fn test_small_input_is_spooled_in_memory() {
+    let dir = tempfile::tempdir().expect("failed to create a temp dir");
+    let reads = dir.path().join("reads.fastq");
+    let archive = dir.path().join("reads.pare");
+    std::fs::write(
+        &reads,
+        "@read1/1\nACGTTG\n+\nIIIIII\n@read1/2\nAACCGT\n+\nIIIIII\n",
+    )
+    .unwrap();
+    // nothing can be created in a directory that doesn't exist, not even by root
+    let missing = dir.path().join("missing");
+
+    let compress = |spool_memory: &str| {
+        Command::new(env!("CARGO_BIN_EXE_pare"))
+            .env("TMPDIR", &missing)
+            .args(["compress", "--quiet", "--spool-memory", spool_memory, "-o"])
+            .arg(&archive)
+            .arg(&reads)
+            .output()
+            .expect("failed to run pare")
+    };
+
+    let output = compress("1048576");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let output = compress("0");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Can't write temporary files to"),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("--temp-dir"), "{}", stderr);
+}
+
+#[test]
+fn test_dry_run_size_matches_archive() {
+    let dir = tempfile::tempdir().expect("failed to create a temp dir");
+    let reads = dir.path().join("reads.fastq");
+    let archive = dir.path().join("reads.pare");
+    std::fs::write(
+        &reads,
+        "@read1/1\nACGTTG\n+\nIIIIII\n@read1/2\nAACCGT\n+\nIIIIII\n\
+         @read2/1\nGGCA\n+\nIIII\n@read2/2\nTTGA\n+\nIIII\n",
+    )
+    .unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_pare"))
+        .args(["compress", "--quiet", "-o"])
+        .arg(&archive)
+        .arg(&reads)
+        .status()
+        .expect("failed to run pare");
+    assert!(status.success());
+
+    let output = Command::new(env!("CARGO_BIN_EXE_pare"))
+        .args(["compress", "--quiet", "--dry-run"])
+        .arg(&reads)
+        .output()
+        .expect("failed to run pare");
+    assert!(output.status.success());
+    assert_eq!(
+        format!("{} bytes\n", std::fs::metadata(&archive).unwrap().len()),
+        String::from_utf8_lossy(&output.stdout)
+    );
+}
+
+#[test]
+fn test_append_samples() {
+    let dir = tempfile::tempdir().expect("failed to create a temp dir");
+    let sample_a = dir.path().join("a.fastq");
+    let sample_b = dir.path().join("b.fastq");
+    let archive = dir.path().join("samples.pare");
+    let decompressed = dir.path().join("decompressed.fastq");
+    std::fs::write(
+        &sample_a,
+        "@a1/1\nACGTTG\n+\nIIIIII\n@a1/2\nAACCGT\n+\nIIIIII\n",
+    )
+    .unwrap();
+    std::fs::write(&sample_b, "@b1/1\nGGCA\n+\nIIII\n@b1/2\nTTGA\n+\nIIII\n").unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_pare"))
+        .args(["compress", "--quiet", "--sample-name", "a", "-o"])
+        .arg(&archive)
+        .arg(&sample_a)
+        .status()
+        .expect("failed to run pare");
+    assert!(status.success());
+    let status = Command::new(env!("CARGO_BIN_EXE_pare"))
+        .args(["compress", "--quiet", "--sample-name", "b", "--append"])
+        .arg(&archive)
+        .arg(&sample_b)
+        .status()
+        .expect("failed to run pare");
+    assert!(status.success());
+
+    let output = Command::new(env!("CARGO_BIN_EXE_pare"))
+        .args(["decompress", "--quiet", "--sample", "b"])
+        .args([&archive, &decompressed])
+        .output()
+        .expect("failed to run pare");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        std::fs::read_to_string(&sample_b).unwrap(),
+        std::fs::read_to_string(&decompressed).unwrap()
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_pare"))
+        .args(["decompress", "--quiet"])
+        .args([&archive, &decompressed])
+        .output()
+        .expect("failed to run pare");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("a, b"));
+
+    // verify and dump choose a sample the same way
+    let output = Command::new(env!("CARGO_BIN_EXE_pare"))
+        .args(["verify", "--sample", "a"])
+        .arg(&archive)
+        .output()
+        .expect("failed to run pare");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(String::from_utf8_lossy(&output.stdout).contains("OK, 2 records"));
+    let output = Command::new(env!("CARGO_BIN_EXE_pare"))
+        .args(["dump", "--decompressed", "--sample", "b"])
+        .arg(&archive)
+        .arg("data")
+        .output()
+        .expect("failed to run pare");
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("b1/1"));
+}
+
+#[test]
+fn test_paired_files_to_interleaved() {
+    let dir = tempfile::tempdir().expect("failed to create a temp dir");
+    let r1 = dir.path().join("r1.fastq");
+    let r2 = dir.path().join("r2.fastq");
+    let archive = dir.path().join("reads.pare");
+    let decompressed = dir.path().join("interleaved.fastq");
+    let relaid = dir.path().join("relaid.fastq");
+    std::fs::write(
+        &r1,
+        "@read1/1\nACGTTG\n+\nIIIIII\n@read2/1\nGGCA\n+\nIIII\n",
+    )
+    .unwrap();
+    std::fs::write(
+        &r2,
+        "@read1/2\nAACCGT\n+\nIIIIII\n@read2/2\nTTGA\n+\nIIII\n",
+    )
+    .unwrap();
+    let interleaved = concat!(
+        "@read1/1\nACGTTG\n+\nIIIIII\n@read1/2\nAACCGT\n+\nIIIIII\n",
+        "@read2/1\nGGCA\n+\nIIII\n@read2/2\nTTGA\n+\nIIII\n",
+    );
+
+    let status = Command::new(env!("CARGO_BIN_EXE_pare"))
+        .args(["compress", "--quiet", "-o"])
+        .arg(&archive)
+        .args([&r1, &r2])
+        .status()
+        .expect("failed to run pare");
+    assert!(status.success());
+    let status = Command::new(env!("CARGO_BIN_EXE_pare"))
+        .args(["decompress", "--quiet"])
+        .args([&archive, &decompressed])
+        .status()
+        .expect("failed to run pare");
+    assert!(status.success());
+    assert_eq!(interleaved, std::fs::read_to_string(&decompressed).unwrap());
+
+    let status = Command::new(env!("CARGO_BIN_EXE_pare"))
+        .args(["relayout", "--layout", "interleaved", "-o"])
+        .arg(&relaid)
+        .args([&r1, &r2])
+        .status()
+        .expect("failed to run pare");
+    assert!(status.success());
+    assert_eq!(interleaved, std::fs::read_to_string(&relaid).unwrap());
+
+    let (out_r1, out_r2) = (
+        dir.path().join("out_r1.fastq"),
+        dir.path().join("out_r2.fastq"),
+    );
+    let status = Command::new(env!("CARGO_BIN_EXE_pare"))
+        .args(["relayout", "--layout", "paired", "-o"])
+        .arg(&out_r1)
+        .arg("-o")
+        .arg(&out_r2)
+        .arg(&relaid)
+        .status()
+        .expect("failed to run pare");
+    assert!(status.success());
+    assert_eq!(
+        std::fs::read_to_string(&r2).unwrap(),
+        std::fs::read_to_string(&out_r2).unwrap()
+    );
+}
+
+/// A named pipe at `path` that `contents` is written into from another thread, like the
+/// `<(...)` of a shell process substitution.
+fn fifo(path: &std::path::Path, contents: Vec<u8>) -> std::thread::JoinHandle<()> {
+    let status = Command::new("mkfifo")
+        .arg(path)
+        .status()
+        .expect("failed to run mkfifo");
+    assert!(status.success());
+    let path = path.to_path_buf();
+    std::thread::spawn(move || std::fs::write(path, contents).unwrap())
+}
+
+#[test]
+fn test_named_pipes() {
+    let dir = tempfile::tempdir().expect("failed to create a temp dir");
+    let fastq = "@read1/1\nACGTTG\n+\nIIIIII\n@read1/2\nAACCGT\n+\nIIIIII\n";
+    let archive = dir.path().join("reads.pare");
+    let decompressed = dir.path().join("decompressed.fastq");
+
+    let input = dir.path().join("input.fifo");
+    let writer = fifo(&input, fastq.as_bytes().to_vec());
+    let output = Command::new(env!("CARGO_BIN_EXE_pare"))
+        .args(["compress", "--quiet", "-o"])
+        .arg(&archive)
+        .arg(&input)
+        .output()
+        .expect("failed to run pare");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    writer.join().unwrap();
+
+    let piped_archive = dir.path().join("archive.fifo");
+    let writer = fifo(&piped_archive, std::fs::read(&archive).unwrap());
+    let output = Command::new(env!("CARGO_BIN_EXE_pare"))
+        .args(["decompress", "--quiet"])
+        .args([&piped_archive, &decompressed])
+        .output()
+        .expect("failed to run pare");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    writer.join().unwrap();
+    assert_eq!(fastq, std::fs::read_to_string(&decompressed).unwrap());
+}
+
+#[test]
+fn test_temp_dir() {
+    let dir = tempfile::tempdir().expect("failed to create a temp dir");
+    let reads = dir.path().join("reads.fastq");
+    let archive = dir.path().join("reads.pare");
+    let out = dir.path().join("out.fastq");
+    let scratch = dir.path().join("scratch");
+    std::fs::create_dir(&scratch).unwrap();
+    let fastq = "@read1/1\nACGTTG\n+\nIIIIII\n@read1/2\nAACCGT\n+\nIIIIII\n";
+    std::fs::write(&reads, fastq).unwrap();
+    // the system temp can't be used, everything has to land in scratch
+    let missing = dir.path().join("missing");
+
+    let pare = |args: &[&std::ffi::OsStr], temp_dir: &std::path::Path| {
+        Command::new(env!("CARGO_BIN_EXE_pare"))
+            .env("TMPDIR", &missing)
+            .arg("--temp-dir")
+            .arg(temp_dir)
+            .args(args)
+            .output()
+            .expect("failed to run pare")
+    };
+
+    let output = pare(
+        &[
+            "compress".as_ref(),
+            "--quiet".as_ref(),
+            "--spool-memory".as_ref(),
+            "0".as_ref(),
+            "-o".as_ref(),
+            archive.as_os_str(),
+            reads.as_os_str(),
+        ],
+        &scratch,
+    );
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let output = pare(
+        &["decompress".as_ref(), archive.as_os_str(), out.as_os_str()],
+        &scratch,
+    );
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(fastq, std::fs::read_to_string(&out).unwrap());
+    assert_eq!(0, std::fs::read_dir(&scratch).unwrap().count());
+
+    let output = pare(
+        &["decompress".as_ref(), archive.as_os_str(), out.as_os_str()],
+        &missing,
+    );
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Can't write temporary files to"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn test_keep_temp() {
+    let dir = tempfile::tempdir().expect("failed to create a temp dir");
+    let reads = dir.path().join("reads.fastq");
+    let archive = dir.path().join("reads.pare");
+    let scratch = dir.path().join("scratch");
+    std::fs::create_dir(&scratch).unwrap();
+    std::fs::write(
+        &reads,
+        "@read1/1\nACGTTG\n+\nIIIIII\n@read1/2\nAACCGT\n+\nIIIIII\n",
+    )
+    .unwrap();
+    let status = Command::new(env!("CARGO_BIN_EXE_pare"))
+        .args(["compress", "-q", "-o"])
+        .arg(&archive)
+        .arg(&reads)
+        .status()
+        .expect("failed to run pare");
+    assert!(status.success());
+
+    let decompress = |keep_temp: bool| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_pare"));
+        command
+            .arg("--temp-dir")
+            .arg(&scratch)
+            .args(["decompress", "-q"]);
+        if keep_temp {
+            command.arg("--keep-temp");
+        }
+        command.arg(&archive).output().expect("failed to run pare")
+    };
+
+    let output = decompress(false);
+    assert!(output.status.success());
+    assert_eq!(0, std::fs::read_dir(&scratch).unwrap().count());
+
+    let output = decompress(true);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let kept = stderr
+        .lines()
+        .find_map(|line| line.strip_prefix("Kept the unpacked archive in "))
+        .unwrap_or_else(|| panic!("{}", stderr));
+    let kept = std::path::Path::new(kept);
+    assert!(kept.starts_with(&scratch));
+    assert!(kept.join("metadata").is_file());
+}
+
+#[test]
+fn test_pair_order_is_kept() {
+    const PAIRS: usize = 10_000;
+    let dir = tempfile::tempdir().expect("failed to create a temp dir");
+    let reads = dir.path().join("reads.fastq");
+    let archive = dir.path().join("reads.pare");
+    let out = dir.path().join("out.fastq");
+
+    let bases = ["ACGT", "TTGA", "GGCA", "CATG", "AATC"];
+    let mut fastq = String::new();
+    for i in 0..PAIRS {
+        for read in 1..=2 {
+            let letters = bases[(i + read) % bases.len()].repeat(1 + i % 7);
+            fastq.push_str(&format!(
+                "@pair{i}/{read}\n{letters}\n+\n{}\n",
+                "I".repeat(letters.len())
+            ));
+        }
+    }
+    std::fs::write(&reads, &fastq).unwrap();
+
+    // small blocks so the block models have plenty of them to get out of order
+    for model in ["lzma", "lzma-multi", "lzma-block", "lzma-columnar", "zstd"] {
+        let status = Command::new(env!("CARGO_BIN_EXE_pare"))
+            .args([
+                "compress",
+                "--quiet",
+                "--block-size",
+                "97",
+                "-m",
+                model,
+                "-o",
+            ])
+            .arg(&archive)
+            .arg(&reads)
+            .status()
+            .expect("failed to run pare");
+        assert!(status.success(), "{}", model);
+
+        let status = Command::new(env!("CARGO_BIN_EXE_pare"))
+            .arg("decompress")
+            .args([&archive, &out])
+            .status()
+            .expect("failed to run pare");
+        assert!(status.success(), "{}", model);
+
+        let text = std::fs::read_to_string(&out).unwrap();
+        let titles: Vec<&str> = text.lines().step_by(4).collect();
+        assert_eq!(2 * PAIRS, titles.len(), "{}", model);
+        let mut last = None;
+        for pair in titles.chunks(2) {
+            let index = |title: &str, read: &str| -> usize {
+                let number = title.strip_prefix("@pair").unwrap();
+                let (number, suffix) = number.split_once('/').unwrap();
+                assert_eq!(read, suffix, "{}", model);
+                number.parse().unwrap()
+            };
+            let r1 = index(pair[0], "1");
+            assert_eq!(r1, index(pair[1], "2"), "{}", model);
+            assert!(last.is_none_or(|last| r1 > last), "{}: {}", model, r1);
+            last = Some(r1);
+        }
+        assert_eq!(fastq, text, "{}", model);
+    }
+}
+
+#[test]
+fn test_benchmark_json() {
+    let dir = tempfile::tempdir().expect("failed to create a temp dir");
+    let reads = dir.path().join("reads.fastq");
+    let reference = dir.path().join("reference.fasta");
+    std::fs::write(
+        &reads,
+        "@read1/1\nGGATCCTAGGATAGCTAACGG\n+\nIIIIIIIIIIIIIIIIIIIII\n\
+         @read1/2\nGGATGCTAAGCCGATTGACC\n+\nFFFFFFFFFFFFFFFFFFFF\n",
+    )
+    .unwrap();
+    std::fs::write(
+        &reference,
+        ">chr1\nACGTTGCAACGTTACGGATCCTAGGCTAGCTAACGGTTCAGTCAGGTACCATGCATGCAAGTC\n",
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_pare"))
+        .args(["benchmark", "--json", "--reference"])
+        .arg(&reference)
+        .arg(&reads)
+        .output()
+        .expect("failed to run pare");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let results: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let models: Vec<&str> = results
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|result| result["model"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        vec![
+            "lzma",
+            "lzma-multi",
+            "lzma-reference",
+            "lzma-block",
+            "lzma-columnar",
+            "zstd"
+        ],
+        models
+    );
+    // every model decodes back to the input
+    let fastq_bytes = std::fs::metadata(&reads).unwrap().len();
+    for result in results.as_array().unwrap() {
+        assert_eq!(
+            Some(fastq_bytes),
+            result["fastq_bytes"].as_u64(),
+            "{}",
+            result
+        );
+        assert!(result["archive_bytes"].as_u64().unwrap() > 0);
+    }
+}
+
+#[test]
+fn test_decompress_to_gzip() {
+    use std::io::Read;
+
+    let dir = tempfile::tempdir().expect("failed to create a temp dir");
+    let reads = dir.path().join("reads.fastq");
+    let archive = dir.path().join("reads.pare");
+    let out_r1 = dir.path().join("out_r1.fastq.gz");
+    let out_r2 = dir.path().join("out_r2.fastq.gz");
+    std::fs::write(
+        &reads,
+        "@read1/1\nACGTTG\n+\nIIIIII\n@read1/2\nAACCGT\n+\nIIIIII\n",
+    )
+    .unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_pare"))
+        .args(["compress", "--quiet", "-o"])
+        .arg(&archive)
+        .arg(&reads)
+        .status()
+        .expect("failed to run pare");
+    assert!(status.success());
+
+    let status = Command::new(env!("CARGO_BIN_EXE_pare"))
+        .arg("decompress")
+        .args([&archive, &out_r1, &out_r2])
+        .status()
+        .expect("failed to run pare");
+    assert!(status.success());
+
+    for (path, expected) in [
+        (&out_r1, "@read1/1\nACGTTG\n+\nIIIIII\n"),
+        (&out_r2, "@read1/2\nAACCGT\n+\nIIIIII\n"),
+    ] {
+        let mut text = String::new();
+        flate2::read::GzDecoder::new(std::fs::File::open(path).unwrap())
+            .read_to_string(&mut text)
+            .expect("not a valid gzip file");
+        assert_eq!(expected, text);
+    }
+}
+
+#[test]
+fn test_requality_output_is_complete_gzip() {
+    use std::io::{Read, Write};
+
+    let dir = tempfile::tempdir().expect("failed to create a temp dir");
+    let input = dir.path().join("reads.fastq.gz");
+    let output = dir.path().join("binned.fastq.gz");
+    let mut encoder = flate2::write::GzEncoder::new(
+        std::fs::File::create(&input).unwrap(),
+        flate2::Compression::default(),
+    );
+    encoder
+        .write_all(b"@read1/1\nACGTTG\n+\n!+5?AI\n@read2/1\nGGCA\n+\nIIII\n")
+        .unwrap();
+    encoder.finish().unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_pare"))
+        .args(["requality", "--bins", "illumina8"])
+        .args([&input, &output])
+        .status()
+        .expect("failed to run pare");
+    assert!(status.success());
+
+    let mut text = String::new();
+    flate2::read::MultiGzDecoder::new(std::fs::File::open(&output).unwrap())
+        .read_to_string(&mut text)
+        .expect("not a complete gzip file");
+    assert_eq!(
+        "@read1/1\nACGTTG\n+\n!07BBI\n@read2/1\nGGCA\n+\nIIII\n",
+        text
+    );
+}
+
+#[test]
+fn test_compress_manifest() {
+    let dir = tempfile::tempdir().expect("failed to create a temp dir");
+    let mut manifest = String::from("# r1\tr2\toutput\n");
+    for sample in ["a", "b"] {
+        let r1 = dir.path().join(format!("{}_r1.fastq", sample));
+        let r2 = dir.path().join(format!("{}_r2.fastq", sample));
+        std::fs::write(&r1, format!("@{}/1\nACGTTG\n+\nIIIIII\n", sample)).unwrap();
+        std::fs::write(&r2, format!("@{}/2\nAACCGT\n+\nIIIIII\n", sample)).unwrap();
+        let archive = dir.path().join(format!("{}.pare", sample));
+        manifest.push_str(&format!(
+            "{}\t{}\t{}\n",
+            r1.display(),
+            r2.display(),
+            archive.display()
+        ));
+    }
+    let path = dir.path().join("samples.tsv");
+    std::fs::write(&path, &manifest).unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_pare"))
+        .args(["compress", "--quiet", "--manifest"])
+        .arg(&path)
+        .status()
+        .expect("failed to run pare");
+    assert!(status.success());
+
+    for sample in ["a", "b"] {
+        let output = Command::new(env!("CARGO_BIN_EXE_pare"))
+            .arg("decompress")
+            .arg(dir.path().join(format!("{}.pare", sample)))
+            .output()
+            .expect("failed to run pare");
+        assert!(output.status.success());
+        assert_eq!(
+            format!(
+                "@{0}/1\nACGTTG\n+\nIIIIII\n@{0}/2\nAACCGT\n+\nIIIIII\n",
+                sample
+            ),
+            String::from_utf8_lossy(&output.stdout)
+        );
+    }
+
+    // a missing input fails its row, with --keep-going the rows after it are still compressed
+    let missing = dir.path().join("missing.fastq");
+    let kept = dir.path().join("kept.pare");
+    let rows = format!(
+        "{0}\t{0}\t{1}\n{2}\t{3}\t{4}\n",
+        missing.display(),
+        dir.path().join("missing.pare").display(),
+        dir.path().join("a_r1.fastq").display(),
+        dir.path().join("a_r2.fastq").display(),
+        kept.display()
+    );
+    std::fs::write(&path, rows).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_pare"))
+        .args(["compress", "--quiet", "--keep-going", "--manifest"])
+        .arg(&path)
+        .output()
+        .expect("failed to run pare");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("1 of the 2 rows"), "{}", stderr);
+    assert!(kept.exists());
+}
+
+#[test]
+fn test_compress_config() {
+    let dir = tempfile::tempdir().expect("failed to create a temp dir");
+    let reads = dir.path().join("reads.fastq");
+    let archive = dir.path().join("reads.pare");
+    let config = dir.path().join("config.json");
+    let fastq = "@read1/1\nACGTTG\n+\nIIIIII\n@read1/2\nAACCGT\n+\nIIIIII\n";
+    std::fs::write(&reads, fastq).unwrap();
+    std::fs::write(
+        &config,
+        r#"{ "nucleotides": { "level": 1, "packed": true }, "qualities": { "codec": "bzip2" } }"#,
+    )
+    .unwrap();
+
+    let compress = |extra: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_pare"))
+            .args(["compress", "--quiet", "--model", "lzma-multi", "--config"])
+            .arg(&config)
+            .args(extra)
+            .arg("-o")
+            .arg(&archive)
+            .arg(&reads)
+            .output()
+            .expect("failed to run pare")
+    };
+
+    let output = compress(&[]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let output = Command::new(env!("CARGO_BIN_EXE_pare"))
+        .args(["decompress", "--quiet"])
+        .arg(&archive)
+        .output()
+        .expect("failed to run pare");
+    assert!(output.status.success());
+    assert_eq!(fastq, String::from_utf8_lossy(&output.stdout));
+
+    // the stream is decoded with the codec from the config
+    let output = Command::new(env!("CARGO_BIN_EXE_pare"))
+        .args(["dump", "--decompressed"])
+        .arg(&archive)
+        .arg("qualities")
+        .output()
+        .expect("failed to run pare");
+    assert!(output.status.success());
+    assert_eq!(12, output.stdout.len());
+
+    // the config says what the codecs are
+    assert!(!compress(&["--quality-codec", "brotli"]).status.success());
+
+    // and only the multi stream model has streams to configure
+    let output = Command::new(env!("CARGO_BIN_EXE_pare"))
+        .args(["compress", "--quiet", "--model", "zstd", "--config"])
+        .arg(&config)
+        .arg("-o")
+        .arg(&archive)
+        .arg(&reads)
+        .output()
+        .expect("failed to run pare");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--model lzma-multi"));
+}
+
+#[test]
+fn test_no_qualities() {
+    let dir = tempfile::tempdir().expect("failed to create a temp dir");
+    let reads = dir.path().join("reads.fastq");
+    let archive = dir.path().join("reads.pare");
+    std::fs::write(
+        &reads,
+        "@read1/1\nACGTTG\n+\n#+5?DF\n@read1/2\nAACCGTAA\n+\n!!!!!!!!\n",
+    )
+    .unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_pare"))
+        .args(["compress", "--quiet", "--no-qualities", "-o"])
+        .arg(&archive)
+        .arg(&reads)
+        .status()
+        .expect("failed to run pare");
+    assert!(status.success());
+
+    for (fill, expected) in [
+        (
+            None,
+            "@read1/1\nACGTTG\n+\nIIIIII\n@read1/2\nAACCGTAA\n+\nIIIIIIII\n",
+        ),
+        (
+            Some("--quality-fill=#"),
+            "@read1/1\nACGTTG\n+\n######\n@read1/2\nAACCGTAA\n+\n########\n",
+        ),
+    ] {
+        let output = Command::new(env!("CARGO_BIN_EXE_pare"))
+            .args(["decompress", "--quiet"])
+            .args(fill)
+            .arg(&archive)
+            .output()
+            .expect("failed to run pare");
+        assert!(output.status.success());
+        assert_eq!(expected, String::from_utf8_lossy(&output.stdout));
+    }
+}
+
+#[test]
+fn test_check() {
+    let dir = tempfile::tempdir().expect("failed to create a temp dir");
+    let good = dir.path().join("good.fastq");
+    let bad = dir.path().join("bad.fastq");
+    std::fs::write(&good, "@read1/1\nACGT\n+\nIIII\n@read1/2\nTTGA\n+\nIIII\n").unwrap();
+    std::fs::write(
+        &bad,
+        "@read1/1\nACGT\n+\nIIII\n@read1/2\nTTGA\n+\nIIII\n@read2/1\nACZT\n+\nIIII\n@read2/2\nTTGA\n+\nIIII\n",
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_pare"))
+        .arg("check")
+        .arg(&good)
+        .output()
+        .expect("failed to run pare");
+    assert!(output.status.success());
+    assert_eq!("OK, 2 records\n", String::from_utf8_lossy(&output.stdout));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_pare"))
+        .arg("check")
+        .arg(&bad)
+        .output()
+        .expect("failed to run pare");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("line 9"), "{}", stderr);
+}
+
+#[test]
+fn test_decompress_case() {
+    let dir = tempfile::tempdir().expect("failed to create a temp dir");
+    let fastq = dir.path().join("reads.fastq");
+    let archive = dir.path().join("reads.pare");
+    let block = dir.path().join("block.pare");
+    std::fs::write(
+        &fastq,
+        "@read1/1\nacGTnN\n+\nIIIIII\n@read1/2\nTTgaAt\n+\nIIIIII\n",
+    )
+    .unwrap();
+    for (model, path) in [("lzma-multi", &archive), ("lzma-block", &block)] {
+        let status = Command::new(env!("CARGO_BIN_EXE_pare"))
+            .args(["compress", "-q", "-m", model, "-o"])
+            .arg(path)
+            .arg(&fastq)
+            .status()
+            .expect("failed to run pare");
+        assert!(status.success());
+    }
+
+    let decompress = |archive: &std::path::Path, case: &str| {
+        Command::new(env!("CARGO_BIN_EXE_pare"))
+            .args(["decompress", "-q", "--case", case])
+            .arg(archive)
+            .output()
+            .expect("failed to run pare")
+    };
+    for (case, r1, r2) in [
+        ("preserve", "acGTnN", "TTgaAt"),
+        ("upper", "ACGTNN", "TTGAAT"),
+        ("lower", "acgtnn", "ttgaat"),
+    ] {
+        let output = decompress(&archive, case);
+        assert!(output.status.success());
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let lines: Vec<&str> = stdout.lines().collect();
+        assert_eq!([r1, r2], [lines[1], lines[5]], "{}", case);
+    }
+
+    // the block model doesn't keep the case
+    let output = decompress(&block, "preserve");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("CaseNotKept"), "{}", stderr);
+    assert!(decompress(&block, "lower").status.success());
+}
+
+#[test]
+fn test_permissive() {
+    let dir = tempfile::tempdir().expect("failed to create a temp dir");
+    let fastq = dir.path().join("reads.fastq");
+    let archive = dir.path().join("reads.pare");
+    let reads = "@read1/1\nAC.GTN\n+\nIIIIII\n@read1/2\n-TGAAT\n+\nIIIIII\n";
+    std::fs::write(&fastq, reads).unwrap();
+
+    let compress = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_pare"))
+            .args(["compress", "-q"])
+            .args(args)
+            .arg("-o")
+            .arg(&archive)
+            .arg(&fastq)
+            .output()
+            .expect("failed to run pare")
+    };
+
+    // . and - are invalid bases by default
+    let output = compress(&[]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("InvalidNucleotideLetter"), "{}", stderr);
+
+    for model in ["lzma", "lzma-multi"] {
+        assert!(compress(&["--permissive", "-m", model]).status.success());
+        let output = Command::new(env!("CARGO_BIN_EXE_pare"))
+            .args(["decompress", "-q"])
+            .arg(&archive)
+            .output()
+            .expect("failed to run pare");
+        assert!(output.status.success());
+        assert_eq!(reads, String::from_utf8_lossy(&output.stdout), "{}", model);
+    }
+
+    // the block model has nowhere to keep them
+    let output = compress(&["--permissive", "-m", "lzma-block"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("OtherLettersNotStored"), "{}", stderr);
+}
+
+#[test]
+fn test_single_end() {
+    let dir = tempfile::tempdir().expect("failed to create a temp dir");
+    let fastq = dir.path().join("reads.fastq");
+    let archive = dir.path().join("reads.pare");
+    let out = dir.path().join("out.fastq");
+    let reads = "@se1\nACGTTG\n+\nIIIIII\n@se2\nGGCA\n+\nIIII\n@se3\nTTGACC\n+\nFFFFFF\n";
+    std::fs::write(&fastq, reads).unwrap();
+
+    // as interleaved pairs the third read has no mate
+    let status = Command::new(env!("CARGO_BIN_EXE_pare"))
+        .args(["compress", "-q", "-o"])
+        .arg(&archive)
+        .arg(&fastq)
+        .status()
+        .expect("failed to run pare");
+    assert!(!status.success());
+
+    let status = Command::new(env!("CARGO_BIN_EXE_pare"))
+        .args(["compress", "-q", "--single-end", "-o"])
+        .arg(&archive)
+        .arg(&fastq)
+        .status()
+        .expect("failed to run pare");
+    assert!(status.success());
+
+    let status = Command::new(env!("CARGO_BIN_EXE_pare"))
+        .args(["decompress", "-q"])
+        .args([&archive, &out])
+        .status()
+        .expect("failed to run pare");
+    assert!(status.success());
+    assert_eq!(reads, std::fs::read_to_string(&out).unwrap());
+}
+
+#[test]
+fn test_multi_outputs_are_lzma_multi() {
+    let dir = tempfile::tempdir().expect("failed to create a temp dir");
+    let archive = dir.path().join("reads.pare");
+    let mut inputs = Vec::new();
+    for mate in 1..=3 {
+        let path = dir.path().join(format!("r{}.fastq", mate));
+        std::fs::write(&path, format!("@read1/{}\nACGT\n+\nIIII\n", mate)).unwrap();
+        inputs.push(path);
+    }
+
+    let status = Command::new(env!("CARGO_BIN_EXE_pare"))
+        .args([
+            "compress",
+            "--quiet",
+            "--model",
+            "lzma-multi",
+            "--threads",
+            "2",
+        ])
+        .arg("--check-mate-order")
+        .arg("-o")
+        .arg(&archive)
+        .args(&inputs)
+        .status()
+        .expect("failed to run pare");
+    assert!(status.success());
+
+    let outputs: Vec<_> = (1..=3)
+        .map(|mate| dir.path().join(format!("out{}.fastq", mate)))
+        .collect();
+    let output = Command::new(env!("CARGO_BIN_EXE_pare"))
+        .args(["decompress", "--quiet", "--model", "zstd"])
+        .arg(&archive)
+        .args(&outputs)
+        .output()
+        .expect("failed to run pare");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("always lzma-multi"));
+
+    let status = Command::new(env!("CARGO_BIN_EXE_pare"))
+        .args(["decompress", "--quiet", "--model", "lzma-multi"])
+        .arg(&archive)
+        .args(&outputs)
+        .status()
+        .expect("failed to run pare");
+    assert!(status.success());
+    assert_eq!(
+        std::fs::read_to_string(&inputs[2]).unwrap(),
+        std::fs::read_to_string(&outputs[2]).unwrap()
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_progress_fd_must_be_open_and_not_stdout() {
+    let dir = tempfile::tempdir().expect("failed to create a temp dir");
+    let reads = dir.path().join("reads.fastq");
+    std::fs::write(&reads, "@read1/1\nACGT\n+\nIIII\n@read1/2\nACGT\n+\nIIII\n").unwrap();
+
+    for (fd, message) in [
+        ("1", "use 2 for stderr"),
+        ("200", "isn't an open descriptor"),
+    ] {
+        let output = Command::new(env!("CARGO_BIN_EXE_pare"))
+            .args(["compress", "--quiet", "--progress-fd", fd, "-o"])
+            .arg(dir.path().join("reads.pare"))
+            .arg(&reads)
+            .output()
+            .expect("failed to run pare");
+        assert!(!output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains(message), "{}", stderr);
+    }
+}
+
+#[test]
+fn test_list_sizes() {
+    let dir = tempfile::tempdir().expect("failed to create a temp dir");
+    let input = dir.path().join("reads.fastq");
+    let archive = dir.path().join("reads.pare");
+    std::fs::write(&input, "@a/1\nACGT\n+\nIIII\n@a/2\nACGA\n+\nIIII\n").unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_pare"))
+        .args(["compress", "--model", "lzma-block", "-o"])
+        .arg(&archive)
+        .arg(&input)
+        .status()
+        .expect("failed to run pare");
+    assert!(status.success());
+
+    let output = Command::new(env!("CARGO_BIN_EXE_pare"))
+        .arg("list")
+        .arg(&archive)
+        .output()
+        .expect("failed to run pare");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut lines = stdout.lines();
+    assert_eq!(Some("stream\tcompressed\tuncompressed"), lines.next());
+    // the read count, two lengths, the titles, letters and qualities
+    let block: Vec<&str> = lines
+        .find(|line| line.starts_with("block_00000000\t"))
+        .expect("the block is listed")
+        .split('\t')
+        .collect();
+    assert_eq!("36", block[2]);
+}